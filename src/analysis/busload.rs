@@ -0,0 +1,226 @@
+//! Bus load calculation
+//!
+//! The load is the fraction of time the bus was occupied by frames. The
+//! length of a frame on the wire is computed exactly, including the stuff
+//! bits inserted after five consecutive bits of equal level, the CRC, the
+//! acknowledge slot, the end of frame and the interframe space.
+
+use std::io;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+use {telemetry, CanFrame, CanTransport};
+use util::read_until;
+
+/// Bits following the CRC, which are not subject to bit stuffing: CRC
+/// delimiter, ACK slot, ACK delimiter, 7 end of frame and 3 interframe space
+/// bits
+const UNSTUFFED_TRAILER_BITS: u32 = 13;
+
+/// CAN CRC-15 polynomial
+const CRC15_POLY: u16 = 0x4599;
+
+struct BitStream {
+    bits: Vec<bool>,
+}
+
+impl BitStream {
+    fn push(&mut self, value: u32, len: u32) {
+        for i in (0..len).rev() {
+            self.bits.push(value >> i & 1 != 0);
+        }
+    }
+
+    fn crc15(&self) -> u32 {
+        let mut crc: u16 = 0;
+        for &bit in &self.bits {
+            let next = bit ^ (crc >> 14 & 1 != 0);
+            crc = (crc << 1) & 0x7fff;
+            if next {
+                crc ^= CRC15_POLY;
+            }
+        }
+        crc as u32
+    }
+
+    fn stuff_bits(&self) -> u32 {
+        let mut stuffed = 0;
+        let mut run = 0;
+        let mut level = None;
+
+        for &bit in &self.bits {
+            if Some(bit) == level {
+                run += 1;
+            } else {
+                level = Some(bit);
+                run = 1;
+            }
+
+            // the stuff bit has the opposite level and starts a new run
+            if run == 5 {
+                stuffed += 1;
+                level = Some(!bit);
+                run = 1;
+            }
+        }
+
+        stuffed
+    }
+}
+
+/// Number of bits `frame` occupies on the bus
+///
+/// Error frames are reports generated by the local controller and take no
+/// time on the bus as far as this calculation is concerned.
+pub fn frame_bits(frame: &CanFrame) -> u32 {
+    if frame.is_error() {
+        return 0;
+    }
+
+    let mut s = BitStream { bits: Vec::with_capacity(160) };
+    let rtr = frame.is_rtr() as u32;
+    let dlc = frame.data().len() as u32;
+
+    // start of frame
+    s.push(0, 1);
+    if frame.is_extended() {
+        // base ID, SRR, IDE, ID extension, RTR, r1, r0
+        s.push(frame.id() >> 18, 11);
+        s.push(0b11, 2);
+        s.push(frame.id(), 18);
+        s.push(rtr, 1);
+        s.push(0, 2);
+    } else {
+        // ID, RTR, IDE, r0
+        s.push(frame.id(), 11);
+        s.push(rtr, 1);
+        s.push(0, 2);
+    }
+    s.push(dlc, 4);
+
+    if !frame.is_rtr() {
+        for &byte in frame.data() {
+            s.push(byte as u32, 8);
+        }
+    }
+
+    let crc = s.crc15();
+    s.push(crc, 15);
+
+    s.bits.len() as u32 + s.stuff_bits() + UNSTUFFED_TRAILER_BITS
+}
+
+/// Rolling bus load
+///
+/// Sums the bits of the frames received within a sliding window.
+#[derive(Debug)]
+pub struct BusLoad {
+    bitrate: u32,
+    window: Duration,
+    frames: VecDeque<(Instant, u32)>,
+    bits: u64,
+}
+
+impl BusLoad {
+    /// Create a bus load calculator for a bus running at `bitrate` bits per
+    /// second, averaging over `window`.
+    pub fn new(bitrate: u32, window: Duration) -> BusLoad {
+        BusLoad {
+            bitrate: bitrate,
+            window: window,
+            frames: VecDeque::new(),
+            bits: 0,
+        }
+    }
+
+    /// Account for `frame` received at `now`.
+    pub fn process_frame(&mut self, frame: &CanFrame, now: Instant) {
+        let bits = frame_bits(frame);
+        if bits > 0 {
+            self.frames.push_back((now, bits));
+            self.bits += bits as u64;
+        }
+    }
+
+    fn expire(&mut self, now: Instant) {
+        while let Some(&(t, bits)) = self.frames.front() {
+            if now.duration_since(t) < self.window {
+                break;
+            }
+            self.frames.pop_front();
+            self.bits -= bits as u64;
+        }
+    }
+
+    /// Bits transferred within the window ending at `now`
+    pub fn bits(&mut self, now: Instant) -> u64 {
+        self.expire(now);
+        self.bits
+    }
+
+    /// Bus load within the window ending at `now`, in percent
+    pub fn load(&mut self, now: Instant) -> f64 {
+        let window_s = self.window.as_secs() as f64 + self.window.subsec_nanos() as f64 * 1e-9;
+        let capacity = self.bitrate as f64 * window_s;
+        if capacity <= 0.0 {
+            return 0.0;
+        }
+
+        self.bits(now) as f64 * 100.0 / capacity
+    }
+
+    /// Read frames from `socket` for `interval`, then return the bus load.
+    ///
+    /// Call repeatedly to sample the load periodically. Changes the socket's
+    /// read timeout.
+    pub fn sample<T: CanTransport>(&mut self, socket: &T, interval: Duration) -> io::Result<f64> {
+        read_until(socket, interval, |frame| {
+            self.process_frame(frame, Instant::now());
+            false
+        })?;
+
+        let load = self.load(Instant::now());
+        telemetry::bus_load(load);
+        Ok(load)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+    use CanFrame;
+    use super::{frame_bits, BusLoad};
+
+    #[test]
+    fn test_frame_bits() {
+        // a standard frame takes 47 + 8 * dlc bits before stuffing
+        let f = CanFrame::new(0x0aa, &[0x55], false, false).unwrap();
+        assert!(frame_bits(&f) >= 47 + 8);
+
+        // 3 stuff bits within the 15 zeros up to r0, 13 within the 67 zeros
+        // of DLC and data
+        let zeros = CanFrame::new(0x000, &[0; 8], false, false).unwrap();
+        assert_eq!(frame_bits(&zeros) - 47 - 64, 16);
+
+        let ext = CanFrame::new(0x12345, &[], false, false).unwrap();
+        assert!(frame_bits(&ext) > frame_bits(&CanFrame::new(0x123, &[], false, false).unwrap()));
+    }
+
+    #[test]
+    fn test_rolling_load() {
+        let start = Instant::now();
+        let mut load = BusLoad::new(125_000, Duration::from_secs(1));
+        let frame = CanFrame::new(0x000, &[0; 8], false, false).unwrap();
+        let bits = frame_bits(&frame) as u64;
+
+        for i in 0..10 {
+            load.process_frame(&frame, start + Duration::from_millis(i * 100));
+        }
+
+        let now = start + Duration::from_millis(950);
+        assert_eq!(load.bits(now), 10 * bits);
+        assert!((load.load(now) - (10 * bits) as f64 / 1250.0).abs() < 1e-9);
+
+        // the first five frames left the window
+        assert_eq!(load.bits(start + Duration::from_millis(1450)), 5 * bits);
+    }
+}
@@ -0,0 +1,344 @@
+//! Gap and dropout analysis
+//!
+//! Checks periodic traffic for timing problems after the fact, typically
+//! on a log recorded during a test drive. For every ID, the period is
+//! either given or inferred as the median time between frames. Intervals
+//! longer than `tolerance` periods count as dropouts, the others make up
+//! the jitter statistics.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use socketcan::analysis::GapAnalysis;
+//! use socketcan::dump;
+//!
+//! let mut analysis = GapAnalysis::new().expect_period(0x123, false, Duration::from_millis(10));
+//! analysis.process_log(dump::open("drive.log").unwrap()).unwrap();
+//!
+//! for report in analysis.report() {
+//!     for dropout in &report.dropouts {
+//!         println!("{:03X}: {} frames missing after {} us",
+//!                  report.id, dropout.missed, dropout.start_us);
+//!     }
+//! }
+//! ```
+
+use std::cmp;
+use std::collections::BTreeMap;
+use std::time::Duration;
+use {CanFrame, EFF_FLAG};
+use dump::{ParseError, TimestampedFrame};
+use util::{duration_from_us, duration_to_us};
+
+/// A stretch of time in which frames of an ID were missing
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Dropout {
+    /// Time of the last frame before the dropout, or the start of the log
+    pub start_us: u64,
+    /// Time of the first frame after the dropout, or the end of the log
+    pub end_us: u64,
+    /// Estimated number of missing frames
+    pub missed: u64,
+}
+
+/// Timing of a single ID
+#[derive(Clone, Debug)]
+pub struct GapReport {
+    /// CAN ID, without flags
+    pub id: u32,
+    /// Whether the ID uses the extended frame format
+    pub extended: bool,
+    /// Number of frames received
+    pub count: u64,
+    /// Expected or inferred period, unknown if it was not given and fewer
+    /// than two frames were received
+    pub period: Option<Duration>,
+    /// Whether `period` was inferred from the traffic
+    pub period_inferred: bool,
+    /// Longest time between two consecutive frames
+    pub max_gap: Option<Duration>,
+    /// Mean deviation of the intervals from the period, dropouts excluded
+    pub mean_jitter: Option<Duration>,
+    /// Largest deviation of an interval from the period, dropouts excluded
+    pub max_jitter: Option<Duration>,
+    /// Detected dropouts, in order
+    pub dropouts: Vec<Dropout>,
+}
+
+impl GapReport {
+    /// Estimated number of missing frames over all dropouts
+    pub fn missed(&self) -> u64 {
+        self.dropouts.iter().map(|d| d.missed).sum()
+    }
+}
+
+/// Collector of frame timestamps for gap analysis
+#[derive(Debug)]
+pub struct GapAnalysis {
+    expected: BTreeMap<u32, Duration>,
+    tolerance: f64,
+    seen: BTreeMap<u32, Vec<u64>>,
+    log_start: Option<u64>,
+    log_end: Option<u64>,
+}
+
+impl Default for GapAnalysis {
+    fn default() -> GapAnalysis {
+        GapAnalysis::new()
+    }
+}
+
+impl GapAnalysis {
+    /// Create an analysis inferring all periods, with a tolerance of 1.5
+    pub fn new() -> GapAnalysis {
+        GapAnalysis {
+            expected: BTreeMap::new(),
+            tolerance: 1.5,
+            seen: BTreeMap::new(),
+            log_start: None,
+            log_end: None,
+        }
+    }
+
+    // standard and extended IDs are kept apart
+    fn key(id: u32, extended: bool) -> u32 {
+        if extended { id | EFF_FLAG } else { id }
+    }
+
+    /// Expect frames with `id` every `period`.
+    ///
+    /// Expected IDs are reported even if they never appear, and dropouts at
+    /// the start and end of the log are detected for them.
+    pub fn expect_period(mut self, id: u32, extended: bool, period: Duration) -> GapAnalysis {
+        self.expected.insert(GapAnalysis::key(id, extended), period);
+        self
+    }
+
+    /// Count intervals longer than `factor` periods as dropouts.
+    pub fn tolerance(mut self, factor: f64) -> GapAnalysis {
+        self.tolerance = factor;
+        self
+    }
+
+    /// Account for `frame` received at `t_us`. Error frames are ignored.
+    ///
+    /// Frames need not be processed in chronological order, as happens
+    /// with merged logs or a clock stepping backwards.
+    pub fn process_frame(&mut self, frame: &CanFrame, t_us: u64) {
+        self.log_start = Some(self.log_start.map_or(t_us, |start| cmp::min(start, t_us)));
+        self.log_end = Some(self.log_end.map_or(t_us, |end| cmp::max(end, t_us)));
+
+        if frame.is_error() {
+            return;
+        }
+
+        self.seen
+            .entry(GapAnalysis::key(frame.id(), frame.is_extended()))
+            .or_insert_with(Vec::new)
+            .push(t_us);
+    }
+
+    /// Process all frames of a log.
+    pub fn process_log<I>(&mut self, frames: I) -> Result<(), ParseError>
+        where I: IntoIterator<Item = Result<TimestampedFrame, ParseError>>
+    {
+        for rec in frames {
+            let rec = rec?;
+            self.process_frame(&rec.frame, rec.t_us);
+        }
+        Ok(())
+    }
+
+    /// Analyze the frames processed so far, one report per ID, standard IDs
+    /// first, in ascending order.
+    pub fn report(&self) -> Vec<GapReport> {
+        let mut keys: Vec<u32> = self.seen.keys().chain(self.expected.keys()).cloned().collect();
+        keys.sort();
+        keys.dedup();
+
+        keys.into_iter().map(|key| self.report_id(key)).collect()
+    }
+
+    fn report_id(&self, key: u32) -> GapReport {
+        let mut times = self.seen.get(&key).cloned().unwrap_or_default();
+        times.sort();
+        let intervals: Vec<u64> = times.windows(2).map(|w| w[1] - w[0]).collect();
+
+        let expected = self.expected.get(&key).map(|&p| duration_to_us(p));
+        let period_us = expected.or_else(|| median(&intervals));
+
+        let mut report = GapReport {
+            id: key & !EFF_FLAG,
+            extended: key & EFF_FLAG != 0,
+            count: times.len() as u64,
+            period: period_us.map(duration_from_us),
+            period_inferred: expected.is_none() && period_us.is_some(),
+            max_gap: intervals.iter().max().map(|&us| duration_from_us(us)),
+            mean_jitter: None,
+            max_jitter: None,
+            dropouts: Vec::new(),
+        };
+
+        let period_us = match period_us {
+            Some(period_us) if period_us > 0 => period_us,
+            _ => return report,
+        };
+        let limit = (period_us as f64 * self.tolerance) as u64;
+        let missed = |gap: u64| cmp::max((gap + period_us / 2) / period_us, 2) - 1;
+
+        // leading and trailing dropouts are only known for expected IDs
+        let bounds = match (self.log_start, self.log_end) {
+            (Some(start), Some(end)) if expected.is_some() => Some((start, end)),
+            _ => None,
+        };
+
+        if let Some((start, end)) = bounds {
+            let first = times.first().cloned().unwrap_or(end);
+            if first - start > limit {
+                report.dropouts.push(Dropout {
+                    start_us: start,
+                    end_us: first,
+                    missed: (first - start) / period_us,
+                });
+            }
+        }
+
+        let mut jitter_sum = 0;
+        let mut jitter_count = 0;
+        let mut jitter_max = 0;
+        for (w, &gap) in times.windows(2).zip(&intervals) {
+            if gap > limit {
+                report.dropouts.push(Dropout {
+                    start_us: w[0],
+                    end_us: w[1],
+                    missed: missed(gap),
+                });
+            } else {
+                let jitter = if gap > period_us { gap - period_us } else { period_us - gap };
+                jitter_sum += jitter;
+                jitter_count += 1;
+                jitter_max = cmp::max(jitter_max, jitter);
+            }
+        }
+
+        if let Some((_, end)) = bounds {
+            if let Some(&last) = times.last() {
+                if end - last > limit {
+                    report.dropouts.push(Dropout {
+                        start_us: last,
+                        end_us: end,
+                        missed: (end - last) / period_us,
+                    });
+                }
+            }
+        }
+
+        if jitter_count > 0 {
+            report.mean_jitter = Some(duration_from_us(jitter_sum / jitter_count));
+            report.max_jitter = Some(duration_from_us(jitter_max));
+        }
+
+        report
+    }
+}
+
+fn median(values: &[u64]) -> Option<u64> {
+    if values.is_empty() {
+        return None;
+    }
+
+    let mut sorted = values.to_vec();
+    sorted.sort();
+    Some(sorted[sorted.len() / 2])
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use CanFrame;
+    use util::duration_from_us;
+    use super::{Dropout, GapAnalysis};
+
+    #[test]
+    fn test_inferred_period() {
+        let frame = CanFrame::new(0x123, &[], false, false).unwrap();
+        let mut analysis = GapAnalysis::new();
+
+        // 10 ms period with jitter and three missing frames after 40 ms
+        for &t in &[0, 10_100, 20_000, 30_000, 40_000, 80_200, 90_000] {
+            analysis.process_frame(&frame, t);
+        }
+
+        let report = analysis.report();
+        assert_eq!(report.len(), 1);
+        let report = &report[0];
+        assert_eq!(report.count, 7);
+        assert_eq!(report.period, Some(Duration::from_millis(10)));
+        assert!(report.period_inferred);
+        assert_eq!(report.max_gap, Some(duration_from_us(40_200)));
+        assert_eq!(report.max_jitter, Some(duration_from_us(200)));
+        assert_eq!(report.dropouts,
+                   vec![Dropout {
+                            start_us: 40_000,
+                            end_us: 80_200,
+                            missed: 3,
+                        }]);
+        assert_eq!(report.missed(), 3);
+    }
+
+    #[test]
+    fn test_expected_period() {
+        let a = CanFrame::new(0x100, &[], false, false).unwrap();
+        let b = CanFrame::new(0x200, &[], false, false).unwrap();
+        let mut analysis = GapAnalysis::new()
+            .expect_period(0x100, false, Duration::from_millis(10))
+            .expect_period(0x300, false, Duration::from_millis(10));
+
+        // 0x100 only appears after 30 ms, 0x300 never does
+        for t in 0..10 {
+            analysis.process_frame(&b, t * 10_000);
+            if t >= 3 {
+                analysis.process_frame(&a, t * 10_000);
+            }
+        }
+
+        let report = analysis.report();
+        assert_eq!(report.iter().map(|r| r.id).collect::<Vec<_>>(), vec![0x100, 0x200, 0x300]);
+
+        assert_eq!(report[0].dropouts,
+                   vec![Dropout {
+                            start_us: 0,
+                            end_us: 30_000,
+                            missed: 3,
+                        }]);
+        assert!(report[1].dropouts.is_empty());
+        assert_eq!(report[2].count, 0);
+        assert_eq!(report[2].missed(), 9);
+    }
+
+    #[test]
+    fn test_backwards_timestamps() {
+        let frame = CanFrame::new(0x123, &[], false, false).unwrap();
+        let other = CanFrame::new(0x200, &[], false, false).unwrap();
+        let mut analysis =
+            GapAnalysis::new().expect_period(0x123, false, Duration::from_millis(10));
+
+        // a merged log, with the other ID's timestamps before and after
+        for &t in &[20_000, 30_000, 10_000, 40_000] {
+            analysis.process_frame(&frame, t);
+        }
+        analysis.process_frame(&other, 0);
+        analysis.process_frame(&other, 80_000);
+        analysis.process_frame(&other, 50_000);
+
+        let report = analysis.report();
+        assert_eq!(report[0].count, 4);
+        assert_eq!(report[0].max_gap, Some(duration_from_us(10_000)));
+        assert_eq!(report[0].dropouts,
+                   vec![Dropout {
+                            start_us: 40_000,
+                            end_us: 80_000,
+                            missed: 4,
+                        }]);
+        assert_eq!(report[1].period, Some(Duration::from_millis(50)));
+    }
+}
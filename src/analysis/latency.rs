@@ -0,0 +1,229 @@
+//! Round-trip latency measurement
+//!
+//! An `EchoTest` sends marker frames and waits until they are observed
+//! again, either as the local echo of the sending socket (enable
+//! `set_recv_own_msgs`) or as a reflection sent back by a peer. The
+//! measured round-trip times are collected in a `Histogram`, e.g. to
+//! qualify adapters and drivers.
+
+use std::{cmp, io};
+use std::time::{Duration, Instant};
+use {CanFrame, CanSocket, CanTransport};
+use util::{duration_from_us, duration_to_us, read_until};
+
+/// Latency histogram with buckets of equal width
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    bucket_width: Duration,
+    buckets: Vec<u64>,
+    overflow: u64,
+    count: u64,
+    sum_us: u64,
+    min: Option<Duration>,
+    max: Option<Duration>,
+}
+
+impl Histogram {
+    /// Create a histogram of `buckets` buckets of `bucket_width` each.
+    /// Larger values are counted as overflow.
+    pub fn new(bucket_width: Duration, buckets: usize) -> Histogram {
+        Histogram {
+            bucket_width: bucket_width,
+            buckets: vec![0; buckets],
+            overflow: 0,
+            count: 0,
+            sum_us: 0,
+            min: None,
+            max: None,
+        }
+    }
+
+    /// Add a measurement.
+    pub fn record(&mut self, latency: Duration) {
+        let width_us = cmp::max(duration_to_us(self.bucket_width), 1);
+        let idx = (duration_to_us(latency) / width_us) as usize;
+        match self.buckets.get_mut(idx) {
+            Some(bucket) => *bucket += 1,
+            None => self.overflow += 1,
+        }
+
+        self.count += 1;
+        self.sum_us += duration_to_us(latency);
+        self.min = Some(self.min.map_or(latency, |m| cmp::min(m, latency)));
+        self.max = Some(self.max.map_or(latency, |m| cmp::max(m, latency)));
+    }
+
+    /// Number of measurements
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// Counts per bucket, bucket `n` covering `n * width..(n + 1) * width`
+    pub fn buckets(&self) -> &[u64] {
+        &self.buckets
+    }
+
+    /// Number of measurements beyond the last bucket
+    pub fn overflow(&self) -> u64 {
+        self.overflow
+    }
+
+    pub fn min(&self) -> Option<Duration> {
+        self.min
+    }
+
+    pub fn max(&self) -> Option<Duration> {
+        self.max
+    }
+
+    pub fn mean(&self) -> Option<Duration> {
+        if self.count == 0 { None } else { Some(duration_from_us(self.sum_us / self.count)) }
+    }
+
+    /// Upper bound of the bucket containing the `p`th percentile
+    /// (`0.0..=100.0`)
+    ///
+    /// Returns `None` if there are no measurements or the percentile lies
+    /// in the overflow.
+    pub fn percentile(&self, p: f64) -> Option<Duration> {
+        if self.count == 0 {
+            return None;
+        }
+
+        let rank = cmp::max((p / 100.0 * self.count as f64).ceil() as u64, 1);
+        let mut seen = 0;
+        for (i, &n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= rank {
+                return Some(self.bucket_width * (i as u32 + 1));
+            }
+        }
+        None
+    }
+}
+
+/// Outcome of an echo test
+#[derive(Clone, Debug)]
+pub struct EchoReport {
+    /// Number of marker frames sent
+    pub sent: u64,
+    /// Number of markers that were not observed within the timeout
+    pub lost: u64,
+    /// Round-trip times of the observed markers
+    pub histogram: Histogram,
+}
+
+/// Echo test measuring round-trip times
+#[derive(Debug)]
+pub struct EchoTest<'a, T: 'a = CanSocket> {
+    tx: &'a T,
+    rx: &'a T,
+    id: u32,
+    reply_id: u32,
+    timeout: Duration,
+    interval: Duration,
+}
+
+impl<'a, T: CanTransport> EchoTest<'a, T> {
+    /// Send markers with `id` on `tx` and wait for them on `rx`.
+    ///
+    /// `tx` and `rx` may be the same socket if it receives its own
+    /// messages. Markers are matched by ID and payload, which carries a
+    /// sequence number.
+    pub fn new(tx: &'a T, rx: &'a T, id: u32) -> EchoTest<'a, T> {
+        EchoTest {
+            tx: tx,
+            rx: rx,
+            id: id,
+            reply_id: id,
+            timeout: Duration::from_millis(100),
+            interval: Duration::from_millis(0),
+        }
+    }
+
+    /// Expect the reflected markers with `reply_id` instead of the
+    /// original ID, for peers answering with a different ID.
+    pub fn reply_id(mut self, reply_id: u32) -> EchoTest<'a, T> {
+        self.reply_id = reply_id;
+        self
+    }
+
+    /// Consider a marker lost if it was not observed within `timeout`.
+    pub fn timeout(mut self, timeout: Duration) -> EchoTest<'a, T> {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Wait for `interval` between two markers.
+    pub fn interval(mut self, interval: Duration) -> EchoTest<'a, T> {
+        self.interval = interval;
+        self
+    }
+
+    /// Send `count` markers and collect their round-trip times into
+    /// `histogram`.
+    ///
+    /// Changes the read timeout of the receiving socket.
+    pub fn run(&self, count: u64, histogram: Histogram) -> io::Result<EchoReport> {
+        let mut report = EchoReport {
+            sent: 0,
+            lost: 0,
+            histogram: histogram,
+        };
+
+        for seq in 0..count {
+            if seq > 0 && self.interval > Duration::from_millis(0) {
+                ::std::thread::sleep(self.interval);
+            }
+
+            let marker = [(seq >> 56) as u8,
+                          (seq >> 48) as u8,
+                          (seq >> 40) as u8,
+                          (seq >> 32) as u8,
+                          (seq >> 24) as u8,
+                          (seq >> 16) as u8,
+                          (seq >> 8) as u8,
+                          seq as u8];
+            let frame = CanFrame::new(self.id, &marker, false, false)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+
+            let start = Instant::now();
+            self.tx.write_frame_insist(&frame)?;
+            report.sent += 1;
+
+            let echo = read_until(self.rx, self.timeout, |f| {
+                !f.is_error() && f.id() == self.reply_id && f.data() == marker
+            })?;
+
+            match echo {
+                Some(_) => report.histogram.record(start.elapsed()),
+                None => report.lost += 1,
+            }
+        }
+
+        Ok(report)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use super::Histogram;
+
+    #[test]
+    fn test_histogram() {
+        let mut h = Histogram::new(Duration::from_millis(1), 10);
+        for &us in &[200, 500, 1500, 2500, 2600, 50_000] {
+            h.record(Duration::new(0, us * 1000));
+        }
+
+        assert_eq!(h.count(), 6);
+        assert_eq!(&h.buckets()[..3], &[2, 1, 2]);
+        assert_eq!(h.overflow(), 1);
+        assert_eq!(h.min(), Some(Duration::new(0, 200_000)));
+        assert_eq!(h.max(), Some(Duration::from_millis(50)));
+        assert_eq!(h.mean(), Some(Duration::new(0, 57_300 / 6 * 1000)));
+        assert_eq!(h.percentile(50.0), Some(Duration::from_millis(2)));
+        assert_eq!(h.percentile(99.0), None);
+    }
+}
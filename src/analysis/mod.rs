@@ -0,0 +1,18 @@
+//! Bus analysis
+//!
+//! Components that observe traffic and derive information about the bus,
+//! similar to the `canbusload` and `cansniffer` tools from can-utils. All
+//! components are fed frames through a `process_frame` method taking the
+//! time of reception, so they work on live sockets and recorded logs alike.
+
+pub mod busload;
+pub mod gaps;
+pub mod latency;
+pub mod sniffer;
+pub mod stats;
+
+pub use self::busload::{frame_bits, BusLoad};
+pub use self::gaps::{Dropout, GapAnalysis, GapReport};
+pub use self::latency::{EchoReport, EchoTest, Histogram};
+pub use self::sniffer::{Change, Sniffer};
+pub use self::stats::{IdStats, Stats};
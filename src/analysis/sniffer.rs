@@ -0,0 +1,186 @@
+//! Change detection
+//!
+//! Keeps the latest payload of every CAN ID and reports which bytes and
+//! bits changed, like the `cansniffer` tool. Changed bytes stay highlighted
+//! for a configurable duration, which makes slowly changing signals stand
+//! out when reverse engineering a bus.
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use {CanFrame, EFF_FLAG};
+
+/// A payload change of a single ID
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Change {
+    /// CAN ID, without flags
+    pub id: u32,
+    /// Whether the ID uses the extended frame format
+    pub extended: bool,
+    /// Payload before the change, `None` if the ID was not seen before
+    pub previous: Option<Vec<u8>>,
+    /// New payload
+    pub data: Vec<u8>,
+    /// Bit mask of the changed bytes, bit `n` standing for byte `n`
+    pub changed_bytes: u8,
+    /// Flipped bits of every byte. Bytes present in only one of the
+    /// payloads count as fully flipped.
+    pub flipped_bits: [u8; 8],
+}
+
+#[derive(Debug)]
+struct Entry {
+    data: Vec<u8>,
+    last_seen: Instant,
+    last_change: [Option<Instant>; 8],
+}
+
+/// Tracker of payload changes
+#[derive(Debug)]
+pub struct Sniffer {
+    highlight: Duration,
+    entries: BTreeMap<u32, Entry>,
+}
+
+impl Sniffer {
+    /// Create a sniffer keeping changed bytes highlighted for `highlight`.
+    pub fn new(highlight: Duration) -> Sniffer {
+        Sniffer {
+            highlight: highlight,
+            entries: BTreeMap::new(),
+        }
+    }
+
+    // standard and extended IDs are kept apart
+    fn key(id: u32, extended: bool) -> u32 {
+        if extended { id | EFF_FLAG } else { id }
+    }
+
+    /// Process `frame` received at `now`, returning the change it caused.
+    ///
+    /// Returns `None` if the payload did not change. Error frames are
+    /// ignored.
+    pub fn process_frame(&mut self, frame: &CanFrame, now: Instant) -> Option<Change> {
+        if frame.is_error() {
+            return None;
+        }
+
+        let data = frame.data();
+        let key = Sniffer::key(frame.id(), frame.is_extended());
+        let is_new = !self.entries.contains_key(&key);
+        let entry = self.entries.entry(key).or_insert_with(|| {
+            Entry {
+                data: Vec::new(),
+                last_seen: now,
+                last_change: [None; 8],
+            }
+        });
+
+        // a new ID counts as a change of all its bytes
+        let previous = if is_new { None } else { Some(entry.data.clone()) };
+        entry.last_seen = now;
+
+        let mut flipped_bits = [0; 8];
+        let mut changed_bytes = 0;
+        for i in 0..8 {
+            let (old, new) = (entry.data.get(i), data.get(i));
+            flipped_bits[i] = match (old, new) {
+                (Some(&a), Some(&b)) => a ^ b,
+                (None, None) => 0,
+                _ => 0xff,
+            };
+
+            if flipped_bits[i] != 0 || (is_new && new.is_some()) {
+                changed_bytes |= 1 << i;
+                entry.last_change[i] = Some(now);
+            }
+        }
+
+        if changed_bytes == 0 && !is_new {
+            return None;
+        }
+
+        entry.data.clear();
+        entry.data.extend_from_slice(data);
+
+        Some(Change {
+            id: frame.id(),
+            extended: frame.is_extended(),
+            previous: previous,
+            data: data.to_vec(),
+            changed_bytes: changed_bytes,
+            flipped_bits: flipped_bits,
+        })
+    }
+
+    /// Latest payload of an ID
+    pub fn data(&self, id: u32, extended: bool) -> Option<&[u8]> {
+        self.entries.get(&Sniffer::key(id, extended)).map(|e| &e.data[..])
+    }
+
+    /// Bit mask of the bytes of an ID that changed within the highlight
+    /// duration before `now`
+    pub fn highlighted(&self, id: u32, extended: bool, now: Instant) -> u8 {
+        let entry = match self.entries.get(&Sniffer::key(id, extended)) {
+            Some(entry) => entry,
+            None => return 0,
+        };
+
+        entry.last_change
+            .iter()
+            .enumerate()
+            .filter(|&(_, t)| t.map_or(false, |t| now.duration_since(t) < self.highlight))
+            .fold(0, |mask, (i, _)| mask | 1 << i)
+    }
+
+    /// Iterate over all IDs as `(id, extended)`, standard IDs first
+    pub fn ids<'a>(&'a self) -> Box<Iterator<Item = (u32, bool)> + 'a> {
+        Box::new(self.entries.keys().map(|&key| (key & !EFF_FLAG, key & EFF_FLAG != 0)))
+    }
+
+    /// Forget IDs not seen for `timeout` before `now`.
+    pub fn forget_stale(&mut self, now: Instant, timeout: Duration) {
+        let stale: Vec<_> = self.entries
+            .iter()
+            .filter(|&(_, e)| now.duration_since(e.last_seen) >= timeout)
+            .map(|(&key, _)| key)
+            .collect();
+
+        for key in stale {
+            self.entries.remove(&key);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+    use CanFrame;
+    use super::Sniffer;
+
+    #[test]
+    fn test_changes() {
+        let start = Instant::now();
+        let ms = |ms| start + Duration::from_millis(ms);
+        let mut sniffer = Sniffer::new(Duration::from_millis(500));
+
+        let frame = |data: &[u8]| CanFrame::new(0x321, data, false, false).unwrap();
+
+        let c = sniffer.process_frame(&frame(&[1, 2, 3]), ms(0)).unwrap();
+        assert_eq!(c.previous, None);
+        assert_eq!(c.changed_bytes, 0b111);
+
+        assert_eq!(sniffer.process_frame(&frame(&[1, 2, 3]), ms(100)), None);
+
+        let c = sniffer.process_frame(&frame(&[1, 6, 3, 4]), ms(600)).unwrap();
+        assert_eq!(c.previous, Some(vec![1, 2, 3]));
+        assert_eq!(c.changed_bytes, 0b1010);
+        assert_eq!(&c.flipped_bits[..4], &[0, 0x04, 0, 0xff]);
+
+        assert_eq!(sniffer.highlighted(0x321, false, ms(700)), 0b1010);
+        assert_eq!(sniffer.highlighted(0x321, false, ms(1100)), 0);
+        assert_eq!(sniffer.data(0x321, false), Some(&[1, 6, 3, 4][..]));
+
+        sniffer.forget_stale(ms(5600), Duration::from_secs(5));
+        assert_eq!(sniffer.ids().count(), 0);
+    }
+}
@@ -0,0 +1,236 @@
+//! Per-ID statistics
+//!
+//! Tracks frame counts, rates and periods for every CAN ID seen on a bus,
+//! the core of a "what's on this bus" tool. Snapshots can be exported as
+//! JSON or CSV:
+//!
+//! ```text
+//! id,extended,count,rate_hz,min_period_us,max_period_us,mean_period_us,last_data
+//! 123,false,100,10.000,99871,100130,100002,DEADBEEF
+//! ```
+
+use std::io;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use {CanFrame, EFF_FLAG};
+use util::duration_to_us;
+
+/// Statistics of a single CAN ID
+#[derive(Clone, Debug)]
+pub struct IdStats {
+    /// CAN ID, without flags
+    pub id: u32,
+    /// Whether the ID uses the extended frame format
+    pub extended: bool,
+    /// Number of frames received
+    pub count: u64,
+    /// Reception time of the first frame
+    pub first_seen: Instant,
+    /// Reception time of the latest frame
+    pub last_seen: Instant,
+    /// Shortest time between two consecutive frames
+    pub min_period: Option<Duration>,
+    /// Longest time between two consecutive frames
+    pub max_period: Option<Duration>,
+    /// Payload of the latest frame
+    pub last_data: Vec<u8>,
+}
+
+impl IdStats {
+    /// Average time between two consecutive frames
+    pub fn mean_period(&self) -> Option<Duration> {
+        if self.count < 2 {
+            return None;
+        }
+
+        let span_us = duration_to_us(self.last_seen.duration_since(self.first_seen));
+        let mean_us = span_us / (self.count - 1);
+        Some(Duration::new(mean_us / 1_000_000, (mean_us % 1_000_000) as u32 * 1000))
+    }
+
+    /// Average frame rate in frames per second
+    pub fn rate(&self) -> Option<f64> {
+        self.mean_period().and_then(|p| {
+            let us = duration_to_us(p);
+            if us == 0 { None } else { Some(1e6 / us as f64) }
+        })
+    }
+
+    fn payload_hex(&self) -> String {
+        self.last_data.iter().map(|b| format!("{:02X}", b)).collect()
+    }
+
+    fn period_fields(&self) -> [Option<u64>; 3] {
+        [self.min_period.map(duration_to_us),
+         self.max_period.map(duration_to_us),
+         self.mean_period().map(duration_to_us)]
+    }
+}
+
+/// Collector of per-ID statistics
+#[derive(Debug, Default)]
+pub struct Stats {
+    ids: BTreeMap<u32, IdStats>,
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Stats { ids: BTreeMap::new() }
+    }
+
+    // standard and extended IDs are kept apart
+    fn key(id: u32, extended: bool) -> u32 {
+        if extended { id | EFF_FLAG } else { id }
+    }
+
+    /// Account for `frame` received at `now`. Error frames are ignored.
+    pub fn process_frame(&mut self, frame: &CanFrame, now: Instant) {
+        if frame.is_error() {
+            return;
+        }
+
+        let key = Stats::key(frame.id(), frame.is_extended());
+        let stats = self.ids.entry(key).or_insert_with(|| {
+            IdStats {
+                id: frame.id(),
+                extended: frame.is_extended(),
+                count: 0,
+                first_seen: now,
+                last_seen: now,
+                min_period: None,
+                max_period: None,
+                last_data: Vec::new(),
+            }
+        });
+
+        if stats.count > 0 {
+            let period = now.duration_since(stats.last_seen);
+            stats.min_period = Some(stats.min_period.map_or(period, |p| p.min(period)));
+            stats.max_period = Some(stats.max_period.map_or(period, |p| p.max(period)));
+        }
+
+        stats.count += 1;
+        stats.last_seen = now;
+        stats.last_data.clear();
+        stats.last_data.extend_from_slice(frame.data());
+    }
+
+    /// Statistics of a single ID
+    pub fn get(&self, id: u32, extended: bool) -> Option<&IdStats> {
+        self.ids.get(&Stats::key(id, extended))
+    }
+
+    /// Iterate over all IDs seen, standard IDs first, in ascending order
+    pub fn iter(&self) -> ::std::collections::btree_map::Values<u32, IdStats> {
+        self.ids.values()
+    }
+
+    /// Copy of the current statistics
+    pub fn snapshot(&self) -> Vec<IdStats> {
+        self.iter().cloned().collect()
+    }
+
+    /// Forget all statistics.
+    pub fn reset(&mut self) {
+        self.ids.clear();
+    }
+
+    /// Export the statistics as CSV, one line per ID.
+    ///
+    /// Periods are given in microseconds, fields that are not known yet
+    /// are left empty.
+    pub fn write_csv<W: io::Write>(&self, mut wtr: W) -> io::Result<()> {
+        writeln!(wtr,
+                 "id,extended,count,rate_hz,min_period_us,max_period_us,mean_period_us,last_data")?;
+
+        for s in self.iter() {
+            write!(wtr, "{:X},{},{},", s.id, s.extended, s.count)?;
+            if let Some(rate) = s.rate() {
+                write!(wtr, "{:.3}", rate)?;
+            }
+            for period in &s.period_fields() {
+                wtr.write_all(b",")?;
+                if let Some(us) = *period {
+                    write!(wtr, "{}", us)?;
+                }
+            }
+            writeln!(wtr, ",{}", s.payload_hex())?;
+        }
+
+        Ok(())
+    }
+
+    /// Export the statistics as a JSON array, one object per ID.
+    ///
+    /// Periods are given in microseconds, fields that are not known yet
+    /// are `null`.
+    pub fn write_json<W: io::Write>(&self, mut wtr: W) -> io::Result<()> {
+        fn opt<T: ::std::fmt::Display>(v: Option<T>) -> String {
+            v.map_or_else(|| "null".to_owned(), |v| v.to_string())
+        }
+
+        wtr.write_all(b"[")?;
+        for (i, s) in self.iter().enumerate() {
+            let periods = s.period_fields();
+            write!(wtr,
+                   "{}{{\"id\":{},\"extended\":{},\"count\":{},\"rate_hz\":{},\
+                    \"min_period_us\":{},\"max_period_us\":{},\"mean_period_us\":{},\
+                    \"last_data\":\"{}\"}}",
+                   if i == 0 { "" } else { "," },
+                   s.id,
+                   s.extended,
+                   s.count,
+                   opt(s.rate().map(|r| format!("{:.3}", r))),
+                   opt(periods[0]),
+                   opt(periods[1]),
+                   opt(periods[2]),
+                   s.payload_hex())?;
+        }
+        wtr.write_all(b"]")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+    use {CanFrame, EFF_FLAG};
+    use super::Stats;
+
+    #[test]
+    fn test_periods_and_export() {
+        let start = Instant::now();
+        let mut stats = Stats::new();
+
+        for &(ms, data) in &[(0, 1u8), (90, 2), (200, 3), (300, 4)] {
+            let frame = CanFrame::new(0x123, &[data], false, false).unwrap();
+            stats.process_frame(&frame, start + Duration::from_millis(ms));
+        }
+
+        // same ID in the extended frame format
+        let mut ext = CanFrame::new(0x123, &[], false, false).unwrap();
+        ext._id |= EFF_FLAG;
+        stats.process_frame(&ext, start);
+
+        let s = stats.get(0x123, false).unwrap();
+        assert_eq!(s.count, 4);
+        assert_eq!(s.min_period, Some(Duration::from_millis(90)));
+        assert_eq!(s.max_period, Some(Duration::from_millis(110)));
+        assert_eq!(s.mean_period(), Some(Duration::from_millis(100)));
+        assert_eq!(s.rate(), Some(10.0));
+        assert_eq!(s.last_data, vec![4]);
+        assert_eq!(stats.get(0x123, true).unwrap().count, 1);
+
+        let mut csv = Vec::new();
+        stats.write_csv(&mut csv).unwrap();
+        let csv = String::from_utf8(csv).unwrap();
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines[1], "123,false,4,10.000,90000,110000,100000,04");
+        assert_eq!(lines[2], "123,true,1,,,,,");
+
+        let mut json = Vec::new();
+        stats.write_json(&mut json).unwrap();
+        assert!(String::from_utf8(json)
+            .unwrap()
+            .starts_with("[{\"id\":291,\"extended\":false,\"count\":4,\"rate_hz\":10.000,"));
+    }
+}
@@ -0,0 +1,348 @@
+//! Asynchronous transports for tokio
+//!
+//! `AsyncCanTransport` is the non-blocking flavor of `CanTransport`: reads
+//! and writes are futures, so many transports can be served by a single
+//! tokio runtime instead of a thread each. It is implemented by
+//! `AsyncCanSocket`, a `CanSocket` registered with the tokio reactor, and
+//! by `testing::MockSocket`.
+//!
+//! Only available with the `tokio` feature. The socket has to be created
+//! within the runtime:
+//!
+//! ```no_run
+//! extern crate socketcan;
+//! extern crate tokio;
+//!
+//! use socketcan::async_transport::{AsyncCanSocket, AsyncCanTransport};
+//!
+//! fn main() {
+//!     let rt = tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap();
+//!     let _runtime = rt.enter();
+//!
+//!     let socket = AsyncCanSocket::open("vcan0").unwrap();
+//!     let frame = rt.block_on(socket.read_frame_async()).unwrap();
+//!     println!("{:X}", frame.id());
+//! }
+//! ```
+//!
+//! `run_read_loop` is the asynchronous variant of `socketcan::run_read_loop`,
+//! stopping once a `CancellationToken` is cancelled:
+//!
+//! ```no_run
+//! extern crate socketcan;
+//! extern crate tokio;
+//! extern crate tokio_util;
+//!
+//! use socketcan::async_transport::{run_read_loop, AsyncCanSocket};
+//! use tokio_util::sync::CancellationToken;
+//!
+//! fn main() {
+//!     let rt = tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap();
+//!     let _runtime = rt.enter();
+//!
+//!     let token = CancellationToken::new();
+//!     let socket = AsyncCanSocket::open("vcan0").unwrap();
+//!     let reader = rt.spawn(run_read_loop(socket,
+//!                                         |frame| Ok(println!("{:X}", frame.id())),
+//!                                         token.clone()));
+//!
+//!     token.cancel();
+//!     let count = rt.block_on(reader).unwrap().unwrap();
+//!     println!("{} frames", count);
+//! }
+//! ```
+
+use std::io;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::unix::AsyncFd;
+use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
+use {CanFrame, CanSocket, CanSocketOpenError};
+use testing::MockSocket;
+
+/// A non-blocking frame transport
+pub trait AsyncCanTransport {
+    /// Read a single frame if one is available.
+    ///
+    /// Otherwise returns `Poll::Pending` and wakes the task of `cx` once a
+    /// frame can be read.
+    fn poll_read_frame(&self, cx: &mut Context) -> Poll<io::Result<CanFrame>>;
+
+    /// Write a single frame if the transport is writable.
+    ///
+    /// Otherwise returns `Poll::Pending` and wakes the task of `cx` once it
+    /// becomes writable.
+    fn poll_write_frame(&self, cx: &mut Context, frame: &CanFrame) -> Poll<io::Result<()>>;
+
+    /// Read a single frame.
+    fn read_frame_async(&self) -> ReadFrame<Self> {
+        ReadFrame { transport: self }
+    }
+
+    /// Write a single frame.
+    fn write_frame_async(&self, frame: &CanFrame) -> WriteFrame<Self> {
+        WriteFrame {
+            transport: self,
+            frame: *frame,
+        }
+    }
+}
+
+/// Future returned by `AsyncCanTransport::read_frame_async`
+#[derive(Debug)]
+pub struct ReadFrame<'a, T: 'a + ?Sized> {
+    transport: &'a T,
+}
+
+impl<'a, T: AsyncCanTransport + ?Sized> Future for ReadFrame<'a, T> {
+    type Output = io::Result<CanFrame>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<CanFrame>> {
+        self.transport.poll_read_frame(cx)
+    }
+}
+
+/// Future returned by `AsyncCanTransport::write_frame_async`
+#[derive(Debug)]
+pub struct WriteFrame<'a, T: 'a + ?Sized> {
+    transport: &'a T,
+    frame: CanFrame,
+}
+
+impl<'a, T: AsyncCanTransport + ?Sized> Future for WriteFrame<'a, T> {
+    type Output = io::Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        self.transport.poll_write_frame(cx, &self.frame)
+    }
+}
+
+/// A `CanSocket` registered with the tokio reactor
+#[derive(Debug)]
+pub struct AsyncCanSocket {
+    inner: AsyncFd<CanSocket>,
+}
+
+impl AsyncCanSocket {
+    /// Open a named CAN device, see `CanSocket::open`.
+    ///
+    /// # Panics
+    ///
+    /// If called outside of a tokio runtime with IO enabled.
+    pub fn open(ifname: &str) -> Result<AsyncCanSocket, CanSocketOpenError> {
+        let socket = CanSocket::open(ifname)?;
+        Ok(AsyncCanSocket::new(socket)?)
+    }
+
+    /// Register `socket` with the reactor, making it non-blocking.
+    ///
+    /// # Panics
+    ///
+    /// If called outside of a tokio runtime with IO enabled.
+    pub fn new(socket: CanSocket) -> io::Result<AsyncCanSocket> {
+        socket.set_nonblocking(true)?;
+        Ok(AsyncCanSocket { inner: AsyncFd::new(socket)? })
+    }
+
+    /// The underlying socket, e.g. to set filters
+    pub fn get_ref(&self) -> &CanSocket {
+        self.inner.get_ref()
+    }
+
+    /// Deregister the socket from the reactor.
+    ///
+    /// The socket stays non-blocking.
+    pub fn into_inner(self) -> CanSocket {
+        self.inner.into_inner()
+    }
+}
+
+impl AsyncCanTransport for AsyncCanSocket {
+    fn poll_read_frame(&self, cx: &mut Context) -> Poll<io::Result<CanFrame>> {
+        loop {
+            let mut guard = match self.inner.poll_read_ready(cx) {
+                Poll::Ready(guard) => guard?,
+                Poll::Pending => return Poll::Pending,
+            };
+
+            // a spurious readiness clears the guard and polls again
+            if let Ok(result) = guard.try_io(|inner| inner.get_ref().read_frame()) {
+                return Poll::Ready(result);
+            }
+        }
+    }
+
+    fn poll_write_frame(&self, cx: &mut Context, frame: &CanFrame) -> Poll<io::Result<()>> {
+        loop {
+            let mut guard = match self.inner.poll_write_ready(cx) {
+                Poll::Ready(guard) => guard?,
+                Poll::Pending => return Poll::Pending,
+            };
+
+            if let Ok(result) = guard.try_io(|inner| inner.get_ref().write_frame(frame)) {
+                return Poll::Ready(result);
+            }
+        }
+    }
+}
+
+impl AsyncCanTransport for MockSocket {
+    fn poll_read_frame(&self, cx: &mut Context) -> Poll<io::Result<CanFrame>> {
+        MockSocket::poll_read_frame(self, cx)
+    }
+
+    /// Writes never block on the mock bus.
+    fn poll_write_frame(&self, _cx: &mut Context, frame: &CanFrame) -> Poll<io::Result<()>> {
+        Poll::Ready(MockSocket::write_frame(self, frame))
+    }
+}
+
+impl<'a, T: AsyncCanTransport + ?Sized> AsyncCanTransport for &'a T {
+    fn poll_read_frame(&self, cx: &mut Context) -> Poll<io::Result<CanFrame>> {
+        (**self).poll_read_frame(cx)
+    }
+
+    fn poll_write_frame(&self, cx: &mut Context, frame: &CanFrame) -> Poll<io::Result<()>> {
+        (**self).poll_write_frame(cx, frame)
+    }
+}
+
+/// Number of frames `ReadLoop` handles before yielding to other tasks
+const FRAMES_PER_POLL: u32 = 64;
+
+/// Pass frames read from `transport` to `handler` until `token` is
+/// cancelled.
+///
+/// Interrupted reads are retried, any other read error ends the loop, as
+/// does an error returned by `handler`. Unlike with the blocking variant,
+/// cancelling takes effect right away, also while waiting for a frame.
+/// Resolves to the number of frames handled.
+pub fn run_read_loop<T, F>(transport: T, handler: F, token: CancellationToken) -> ReadLoop<T, F>
+    where T: AsyncCanTransport,
+          F: FnMut(CanFrame) -> io::Result<()>
+{
+    ReadLoop {
+        transport: transport,
+        handler: handler,
+        cancelled: Box::pin(token.cancelled_owned()),
+        count: 0,
+    }
+}
+
+/// Future returned by `run_read_loop`
+pub struct ReadLoop<T, F> {
+    transport: T,
+    handler: F,
+    cancelled: Pin<Box<WaitForCancellationFutureOwned>>,
+    count: u64,
+}
+
+impl<T, F> Future for ReadLoop<T, F>
+    where T: AsyncCanTransport + Unpin,
+          F: FnMut(CanFrame) -> io::Result<()> + Unpin
+{
+    type Output = io::Result<u64>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<u64>> {
+        let this = self.get_mut();
+        for _ in 0..FRAMES_PER_POLL {
+            if this.cancelled.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(Ok(this.count));
+            }
+
+            let frame = match this.transport.poll_read_frame(cx) {
+                Poll::Ready(Ok(frame)) => frame,
+                Poll::Ready(Err(ref e)) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            };
+
+            if let Err(e) = (this.handler)(frame) {
+                return Poll::Ready(Err(e));
+            }
+            this.count += 1;
+        }
+
+        // a busy bus must not starve the other tasks of the runtime
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io, thread};
+    use std::time::Duration;
+    use tokio::runtime::Builder;
+    use tokio_util::sync::CancellationToken;
+    use CanFrame;
+    use testing::MockBus;
+    use super::{run_read_loop, AsyncCanTransport};
+
+    #[test]
+    fn test_mock_transport() {
+        let rt = Builder::new_current_thread().build().unwrap();
+        let bus = MockBus::new();
+        let (socket, peer) = (bus.endpoint(), bus.endpoint());
+
+        // the read is pending until the frame is written on another thread
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            peer.write_frame(&CanFrame::new(0x123, &[1], false, false).unwrap()).unwrap();
+            peer
+        });
+        let frame = rt.block_on(socket.read_frame_async()).unwrap();
+        assert_eq!((frame.id(), frame.data()), (0x123, &[1][..]));
+
+        let peer = writer.join().unwrap();
+        rt.block_on(socket.write_frame_async(&CanFrame::new(0x321, &[], false, false).unwrap()))
+            .unwrap();
+        assert_eq!(peer.read_frame().unwrap().id(), 0x321);
+
+        socket.inject_read_error(io::Error::from_raw_os_error(::libc::ENETDOWN));
+        assert!(rt.block_on((&socket).read_frame_async()).is_err());
+    }
+
+    #[test]
+    fn test_read_loop() {
+        let rt = Builder::new_current_thread().build().unwrap();
+        let bus = MockBus::new();
+        let (socket, peer) = (bus.endpoint(), bus.endpoint());
+        for id in 1..5 {
+            peer.write_frame(&CanFrame::new(id, &[], false, false).unwrap()).unwrap();
+        }
+
+        let token = CancellationToken::new();
+        let mut ids = Vec::new();
+        let count = {
+            let handle = |frame: CanFrame| {
+                ids.push(frame.id());
+                if ids.len() == 3 {
+                    token.cancel();
+                }
+                Ok(())
+            };
+            rt.block_on(run_read_loop(&socket, handle, token.clone())).unwrap()
+        };
+        assert_eq!((count, ids), (3, vec![1, 2, 3]));
+
+        // cancelling ends a loop waiting for frames, after the one left over
+        let token = CancellationToken::new();
+        let canceller = {
+            let token = token.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(20));
+                token.cancel();
+            })
+        };
+        let count = rt.block_on(run_read_loop(&socket, |_| Ok(()), token.clone())).unwrap();
+        assert_eq!(count, 1);
+        canceller.join().unwrap();
+
+        peer.write_frame(&CanFrame::new(0x10, &[], false, false).unwrap()).unwrap();
+        let stop = |_| Err(io::Error::new(io::ErrorKind::Other, "stop"));
+        let err = rt.block_on(run_read_loop(&socket, stop, CancellationToken::new())).unwrap_err();
+        assert_eq!(err.to_string(), "stop");
+    }
+}
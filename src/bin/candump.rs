@@ -0,0 +1,58 @@
+//! candump-like sniffer
+//!
+//! ```text
+//! candump <interface> [filter]
+//! ```
+//!
+//! Prints all frames received on `interface` in the log format of
+//! `candump -L`. The optional filter is either a `candump` filter list
+//! such as `123:7FF,#FFFFFFFF` or a filter expression such as
+//! `id >= 0x100 && data[0] == 0x42`.
+
+extern crate socketcan;
+
+use std::{env, error, io, process};
+use std::time::UNIX_EPOCH;
+use socketcan::CanSocket;
+use socketcan::dump::Writer;
+use socketcan::filter::FilterSpec;
+
+fn run(interface: &str, filter: Option<&str>) -> Result<(), Box<error::Error>> {
+    let spec = match filter {
+        Some(filter) => Some(filter.parse::<FilterSpec>()?),
+        None => None,
+    };
+
+    let mut socket = CanSocket::open(interface)?;
+    if let Some(ref spec) = spec {
+        spec.apply(&socket)?;
+    }
+
+    let stdout = io::stdout();
+    let mut writer = Writer::from_writer(stdout.lock());
+
+    loop {
+        let (frame, t) = socket.read_frame_with_timestamp()?;
+        if !spec.as_ref().map_or(true, |spec| spec.matches(&frame)) {
+            continue;
+        }
+
+        let t = t.duration_since(UNIX_EPOCH)?;
+        let t_us = t.as_secs() * 1_000_000 + (t.subsec_nanos() / 1000) as u64;
+        writer.write_frame(t_us, interface, &frame)?;
+        writer.flush()?;
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 || args.len() > 3 {
+        eprintln!("usage: {} <interface> [filter]", args[0]);
+        process::exit(2);
+    }
+
+    if let Err(e) = run(&args[1], args.get(2).map(|s| s.as_str())) {
+        eprintln!("{}: {}", args[1], e);
+        process::exit(1);
+    }
+}
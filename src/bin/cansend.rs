@@ -0,0 +1,38 @@
+//! cansend-like frame sender
+//!
+//! ```text
+//! cansend <interface> <frame>...
+//! ```
+//!
+//! Sends frames written in `cansend` syntax, e.g. `123#DEADBEEF`,
+//! `1F334455#1122` (extended) or `123#R` (remote frame).
+
+extern crate socketcan;
+
+use std::{env, error, process};
+use socketcan::{CanFrame, CanSocket};
+
+fn run(interface: &str, frames: &[String]) -> Result<(), Box<error::Error>> {
+    let frames = frames.iter()
+        .map(|s| s.parse::<CanFrame>().map_err(|e| format!("invalid frame {}: {:?}", s, e)))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let socket = CanSocket::open(interface)?;
+    for frame in &frames {
+        socket.write_frame_insist(frame)?;
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        eprintln!("usage: {} <interface> <frame>...", args[0]);
+        process::exit(2);
+    }
+
+    if let Err(e) = run(&args[1], &args[2..]) {
+        eprintln!("{}: {}", args[1], e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,92 @@
+//! cansniffer-like change view
+//!
+//! ```text
+//! cansniffer <interface> [interval in ms]
+//! ```
+//!
+//! Shows the latest payload of every ID seen on `interface`, redrawn every
+//! interval (100 ms by default). Bytes that changed within the last second
+//! are highlighted; IDs not seen for five seconds are removed.
+
+extern crate socketcan;
+
+use std::{env, error, process};
+use std::io::{self, Write};
+use std::time::{Duration, Instant};
+use socketcan::{CanSocket, ShouldRetry};
+use socketcan::analysis::Sniffer;
+
+fn draw<W: Write>(out: &mut W, sniffer: &Sniffer, now: Instant) -> io::Result<()> {
+    // clear the screen and move the cursor home
+    write!(out, "\x1b[2J\x1b[H")?;
+
+    for (id, extended) in sniffer.ids() {
+        let data = sniffer.data(id, extended).unwrap_or(&[]);
+        let highlighted = sniffer.highlighted(id, extended, now);
+
+        if extended {
+            write!(out, "{:08X} ", id)?;
+        } else {
+            write!(out, "     {:03X} ", id)?;
+        }
+
+        for (i, byte) in data.iter().enumerate() {
+            if highlighted & 1 << i != 0 {
+                write!(out, " \x1b[1;31m{:02X}\x1b[0m", byte)?;
+            } else {
+                write!(out, " {:02X}", byte)?;
+            }
+        }
+        writeln!(out)?;
+    }
+
+    out.flush()
+}
+
+fn run(interface: &str, interval: Duration) -> Result<(), Box<error::Error>> {
+    let socket = CanSocket::open(interface)?;
+    socket.set_read_timeout(interval)?;
+
+    let mut sniffer = Sniffer::new(Duration::from_secs(1));
+    let mut next_draw = Instant::now();
+    let stdout = io::stdout();
+
+    loop {
+        match socket.read_frame() {
+            Ok(frame) => {
+                sniffer.process_frame(&frame, Instant::now());
+            }
+            Err(ref e) if e.should_retry() => {}
+            Err(e) => return Err(e.into()),
+        }
+
+        let now = Instant::now();
+        if now >= next_draw {
+            sniffer.forget_stale(now, Duration::from_secs(5));
+            draw(&mut stdout.lock(), &sniffer, now)?;
+            next_draw = now + interval;
+        }
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let interval = match args.get(2).map(|s| s.parse::<u64>()) {
+        None => Some(100),
+        Some(Ok(ms)) if ms > 0 => Some(ms),
+        Some(_) => None,
+    };
+
+    let interval = match interval {
+        Some(ms) if args.len() == 2 || args.len() == 3 => Duration::from_millis(ms),
+        _ => {
+            eprintln!("usage: {} <interface> [interval in ms]", args[0]);
+            process::exit(2);
+        }
+    };
+
+    if let Err(e) = run(&args[1], interval) {
+        eprintln!("{}: {}", args[1], e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,613 @@
+//! A single handle for the common CAN tasks
+//!
+//! `CanBus` covers what most applications need from a bus: sending,
+//! periodically sending, receiving selected IDs, request/response
+//! exchanges and error reporting, all through one object. It is built on
+//! a `Router`, which can be used directly for finer control.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use socketcan::{CanBus, CanFrame};
+//!
+//! let bus = CanBus::open("vcan0").unwrap();
+//!
+//! // heartbeat every 100 ms until `heartbeat` is dropped
+//! let heartbeat = bus.send_periodic(CanFrame::new(0x701, &[0x05], false, false).unwrap(),
+//!                                   Duration::from_millis(100));
+//!
+//! let request = CanFrame::new(0x7df, &[0x02, 0x01, 0x0d], false, false).unwrap();
+//! let response = bus.request(&request, 0x7e8, Duration::from_millis(50)).unwrap();
+//! println!("speed: {} km/h", response.data()[3]);
+//!
+//! for error in bus.errors() {
+//!     println!("{:?}", error.error());
+//! }
+//! ```
+
+use std::{fmt, io, thread};
+use std::ffi::CStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use libc::{self, c_char, c_uint};
+use {CanFrame, CanSocket, CanSocketOpenError, CanTransport, ShouldRetry, ERR_MASK_ALL};
+use clock::{Clock, SystemClock};
+use router::{Overflow, Router, Subscriber};
+use schedule::CyclicTable;
+
+/// Queue depth of subscriptions made through `CanBus`
+pub const DEFAULT_CAPACITY: usize = 256;
+
+/// Longest time an event-driven transmission waits before noticing a new
+/// frame
+pub const EVENT_POLL_INTERVAL_MS: u64 = 1;
+
+/// Identifies the bus a frame was received on
+///
+/// Used wherever frames of several buses come together, so their origin
+/// is not lost.
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BusId {
+    /// Interface name, e.g. `can0`
+    Name(String),
+    /// Kernel interface index
+    Index(u32),
+}
+
+impl BusId {
+    /// ID of the interface `socket` is bound to
+    pub fn of(socket: &CanSocket) -> io::Result<BusId> {
+        Ok(BusId::Index(socket.if_index()?))
+    }
+
+    /// Interface name, looked up for an index.
+    ///
+    /// Returns `None` if no interface has the index.
+    pub fn name(&self) -> Option<String> {
+        match *self {
+            BusId::Name(ref name) => Some(name.clone()),
+            BusId::Index(index) => {
+                let mut buf = [0 as c_char; libc::IF_NAMESIZE];
+                let name = unsafe { libc::if_indextoname(index as c_uint, buf.as_mut_ptr()) };
+                if name.is_null() {
+                    return None;
+                }
+                Some(unsafe { CStr::from_ptr(name) }.to_string_lossy().into_owned())
+            }
+        }
+    }
+}
+
+impl fmt::Display for BusId {
+    /// Formats the name, or the index prefixed with `#`.
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BusId::Name(ref name) => f.write_str(name),
+            BusId::Index(index) => write!(f, "#{}", index),
+        }
+    }
+}
+
+impl<'a> From<&'a str> for BusId {
+    fn from(name: &'a str) -> BusId {
+        BusId::Name(name.to_owned())
+    }
+}
+
+impl From<String> for BusId {
+    fn from(name: String) -> BusId {
+        BusId::Name(name)
+    }
+}
+
+impl From<u32> for BusId {
+    fn from(index: u32) -> BusId {
+        BusId::Index(index)
+    }
+}
+
+/// A frame tagged with the bus it was received on
+#[derive(Clone, Debug)]
+pub struct TaggedFrame {
+    pub bus: BusId,
+    pub frame: CanFrame,
+}
+
+/// A CAN bus with routing, periodic sending and request/response
+#[derive(Debug)]
+pub struct CanBus<T = CanSocket, C = SystemClock> {
+    router: Arc<Router<T>>,
+    clock: C,
+}
+
+impl CanBus<CanSocket> {
+    /// Open the named interface with all error frames enabled.
+    pub fn open(ifname: &str) -> Result<CanBus<CanSocket>, CanSocketOpenError> {
+        let socket = CanSocket::open(ifname)?;
+        socket.set_error_mask(ERR_MASK_ALL)?;
+        Ok(CanBus::new(socket)?)
+    }
+}
+
+impl<T: CanTransport + Send + Sync + 'static> CanBus<T> {
+    /// Use `transport` as the bus.
+    ///
+    /// Error frames are only reported if the transport's error mask lets
+    /// them through.
+    pub fn new(transport: T) -> io::Result<CanBus<T>> {
+        CanBus::with_clock(transport, SystemClock)
+    }
+}
+
+impl<T, C> CanBus<T, C>
+    where T: CanTransport + Send + Sync + 'static,
+          C: Clock + Clone + Send + 'static
+{
+    /// Use `transport` as the bus, timing periodic transmissions with
+    /// `clock`.
+    pub fn with_clock(transport: T, clock: C) -> io::Result<CanBus<T, C>> {
+        Ok(CanBus {
+            router: Arc::new(Router::new(transport)?),
+            clock: clock,
+        })
+    }
+
+    /// Send a single frame.
+    pub fn send(&self, frame: &CanFrame) -> io::Result<()> {
+        self.router.transport().write_frame(frame)
+    }
+
+    /// Send `frame` every `interval`, starting now, until the returned
+    /// handle is dropped or stopped.
+    pub fn send_periodic(&self, frame: CanFrame, interval: Duration) -> Periodic {
+        self.send_periodic_offset(frame, interval, Duration::from_secs(0))
+    }
+
+    /// Send `frame` every `interval`, starting after `offset`.
+    ///
+    /// Offsets spread frames of the same interval over the period instead
+    /// of sending them in bursts.
+    pub fn send_periodic_offset(&self,
+                                frame: CanFrame,
+                                interval: Duration,
+                                offset: Duration)
+                                -> Periodic {
+        let frame = Arc::new(Mutex::new(frame));
+        let interval = Arc::new(Mutex::new(interval));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread = {
+            let (router, frame, running) = (self.router.clone(), frame.clone(), running.clone());
+            let (interval, clock) = (interval.clone(), self.clock.clone());
+            thread::spawn(move || {
+                let mut next = clock.now() + offset;
+                if offset > Duration::from_secs(0) {
+                    clock.sleep(offset);
+                }
+                while running.load(Ordering::SeqCst) {
+                    let current = *frame.lock().expect("periodic frame lock poisoned");
+                    match router.transport().write_frame(&current) {
+                        Err(ref e) if e.should_retry() => {}
+                        Err(e) => return Err(e),
+                        Ok(()) => {}
+                    }
+
+                    next += *interval.lock().expect("periodic interval lock poisoned");
+                    let now = clock.now();
+                    if next > now {
+                        clock.sleep(next - now);
+                    } else {
+                        // fell behind, skip the missed periods
+                        next = now;
+                    }
+                }
+                Ok(())
+            })
+        };
+
+        Periodic {
+            frame: frame,
+            interval: interval,
+            running: running,
+            thread: Some(thread),
+        }
+    }
+
+    /// Send `frame` now and whenever a new frame is passed to the returned
+    /// handle, until it is dropped or stopped.
+    ///
+    /// Transmissions are at least `inhibit_time` apart; a frame passed
+    /// earlier waits for the inhibit time to elapse, and only the latest of
+    /// the frames passed meanwhile is sent. Unless `event_timer` is zero,
+    /// the current frame is repeated once no transmission happened for
+    /// that long. This is the transmission of event-driven CANopen TPDOs.
+    pub fn send_on_event(&self,
+                         frame: CanFrame,
+                         inhibit_time: Duration,
+                         event_timer: Duration)
+                         -> EventDriven {
+        let state = Arc::new(Mutex::new(EventState {
+                                            frame: frame,
+                                            pending: true,
+                                            inhibit_time: inhibit_time,
+                                            event_timer: event_timer,
+                                        }));
+        let running = Arc::new(AtomicBool::new(true));
+
+        let thread = {
+            let (router, state, running) = (self.router.clone(), state.clone(), running.clone());
+            let clock = self.clock.clone();
+            thread::spawn(move || {
+                let poll_interval = Duration::from_millis(EVENT_POLL_INTERVAL_MS);
+                let mut last = None;
+                while running.load(Ordering::SeqCst) {
+                    let now = clock.now();
+                    let (due, frame) = {
+                        let mut state = state.lock().expect("event state lock poisoned");
+                        let due = match last {
+                            Some(last) => state.due(last),
+                            None => Some(now),
+                        };
+                        match due {
+                            Some(due) if due <= now => {
+                                state.pending = false;
+                                (None, Some(state.frame))
+                            }
+                            _ => (due, None),
+                        }
+                    };
+
+                    if let Some(frame) = frame {
+                        match router.transport().write_frame(&frame) {
+                            Err(ref e) if e.should_retry() => {}
+                            Err(e) => return Err(e),
+                            Ok(()) => {}
+                        }
+                        last = Some(now);
+                        continue;
+                    }
+
+                    // new frames are only noticed between the slices
+                    clock.sleep(match due {
+                                    Some(due) if due - now < poll_interval => due - now,
+                                    _ => poll_interval,
+                                });
+                }
+                Ok(())
+            })
+        };
+
+        EventDriven {
+            state: state,
+            running: running,
+            thread: Some(thread),
+        }
+    }
+
+    /// Send all frames of `table` at their periods and offsets.
+    ///
+    /// Dropping the returned handles stops the transmissions.
+    pub fn send_table(&self, table: &CyclicTable) -> Vec<Periodic> {
+        table.frames()
+            .iter()
+            .map(|cyclic| self.send_periodic_offset(cyclic.frame, cyclic.period, cyclic.offset))
+            .collect()
+    }
+
+    /// Receive frames with IDs in `first..=last`.
+    ///
+    /// Up to `DEFAULT_CAPACITY` frames are queued, older ones are dropped
+    /// if the subscriber falls behind.
+    pub fn subscribe(&self, first: u32, last: u32) -> Subscriber {
+        self.router.subscribe(first, last, DEFAULT_CAPACITY, Overflow::DropOldest)
+    }
+
+    /// Send `request` and wait for the next frame with ID `response_id`.
+    ///
+    /// Fails with `TimedOut` if no response arrives within `timeout`.
+    pub fn request(&self,
+                   request: &CanFrame,
+                   response_id: u32,
+                   timeout: Duration)
+                   -> io::Result<CanFrame> {
+        // subscribe before sending, so a fast response is not missed
+        let responses = self.router.subscribe(response_id, response_id, 1, Overflow::DropNewest);
+        self.send(request)?;
+        responses.recv_timeout(timeout)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::TimedOut, "no response received"))
+    }
+
+    /// Receive error frames.
+    pub fn errors(&self) -> Subscriber {
+        self.router.subscribe_errors(DEFAULT_CAPACITY, Overflow::DropOldest)
+    }
+
+    /// The underlying router
+    pub fn router(&self) -> &Router<T> {
+        &self.router
+    }
+}
+
+/// Handle of a periodic transmission
+///
+/// Dropping the handle stops the transmission.
+#[derive(Debug)]
+pub struct Periodic {
+    frame: Arc<Mutex<CanFrame>>,
+    interval: Arc<Mutex<Duration>>,
+    running: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<io::Result<()>>>,
+}
+
+impl Periodic {
+    /// Send `frame` instead from the next period on.
+    pub fn set_frame(&self, frame: CanFrame) {
+        *self.frame.lock().expect("periodic frame lock poisoned") = frame;
+    }
+
+    /// Send every `interval` instead, from the next period on.
+    ///
+    /// The period already waited for is not cut short.
+    pub fn set_interval(&self, interval: Duration) {
+        *self.interval.lock().expect("periodic interval lock poisoned") = interval;
+    }
+
+    /// Stop sending.
+    ///
+    /// Returns the error that stopped the transmission early, if any.
+    pub fn stop(mut self) -> io::Result<()> {
+        self.running.store(false, Ordering::SeqCst);
+        match self.thread.take() {
+            Some(thread) => thread.join().expect("periodic sender panicked"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Periodic {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[derive(Debug)]
+struct EventState {
+    frame: CanFrame,
+    pending: bool,
+    inhibit_time: Duration,
+    event_timer: Duration,
+}
+
+impl EventState {
+    /// When the next transmission is due after the one at `last`, `None`
+    /// if it waits for a new frame
+    fn due(&self, last: Instant) -> Option<Instant> {
+        if self.pending {
+            Some(last + self.inhibit_time)
+        } else if self.event_timer > Duration::from_secs(0) {
+            Some(last + self.event_timer)
+        } else {
+            None
+        }
+    }
+}
+
+/// Handle of an event-driven transmission
+///
+/// Dropping the handle stops the transmission.
+#[derive(Debug)]
+pub struct EventDriven {
+    state: Arc<Mutex<EventState>>,
+    running: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<io::Result<()>>>,
+}
+
+impl EventDriven {
+    fn lock(&self) -> MutexGuard<EventState> {
+        self.state.lock().expect("event state lock poisoned")
+    }
+
+    /// Send `frame` as soon as the inhibit time allows.
+    pub fn send(&self, frame: CanFrame) {
+        let mut state = self.lock();
+        state.frame = frame;
+        state.pending = true;
+    }
+
+    /// Repeat `frame` on the event timer instead, without sending it
+    /// right away.
+    pub fn set_frame(&self, frame: CanFrame) {
+        self.lock().frame = frame;
+    }
+
+    /// Keep transmissions at least `inhibit_time` apart from now on.
+    pub fn set_inhibit_time(&self, inhibit_time: Duration) {
+        self.lock().inhibit_time = inhibit_time;
+    }
+
+    /// Repeat the frame after `event_timer` without transmission, never
+    /// if zero.
+    pub fn set_event_timer(&self, event_timer: Duration) {
+        self.lock().event_timer = event_timer;
+    }
+
+    /// Stop sending.
+    ///
+    /// Returns the error that stopped the transmission early, if any.
+    pub fn stop(mut self) -> io::Result<()> {
+        self.running.store(false, Ordering::SeqCst);
+        match self.thread.take() {
+            Some(thread) => thread.join().expect("event-driven sender panicked"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for EventDriven {
+    fn drop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::time::{Duration, UNIX_EPOCH};
+    use {CanFrame, ERR_MASK_ALL};
+    use schedule::CyclicTable;
+    use testing::MockBus;
+    use super::{BusId, CanBus};
+
+    #[test]
+    fn test_request() {
+        let mock = MockBus::new();
+        let ecu = mock.endpoint();
+        let bus = CanBus::new(mock.endpoint()).unwrap();
+
+        let responder = thread::spawn(move || {
+            let request = ecu.read_frame().unwrap();
+            assert_eq!(request.id(), 0x7df);
+            ecu.write_frame(&CanFrame::new(0x7e9, &[0], false, false).unwrap()).unwrap();
+            ecu.write_frame(&CanFrame::new(0x7e8, &[1], false, false).unwrap()).unwrap();
+        });
+
+        let request = CanFrame::new(0x7df, &[0x02, 0x01, 0x0d], false, false).unwrap();
+        let response = bus.request(&request, 0x7e8, Duration::from_secs(5)).unwrap();
+        assert_eq!(response.data(), &[1]);
+        responder.join().unwrap();
+
+        let err = bus.request(&request, 0x7e8, Duration::from_millis(1)).unwrap_err();
+        assert_eq!(err.kind(), ::std::io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn test_periodic_and_errors() {
+        let mock = MockBus::new();
+        let peer = mock.endpoint();
+        let socket = mock.endpoint();
+        socket.set_error_mask(ERR_MASK_ALL).unwrap();
+        let bus = CanBus::new(socket).unwrap();
+
+        let heartbeat = bus.send_periodic(CanFrame::new(0x701, &[0x05], false, false).unwrap(),
+                                          Duration::from_millis(1));
+        assert_eq!(peer.read_frame().unwrap().id(), 0x701);
+        heartbeat.set_frame(CanFrame::new(0x701, &[0x7f], false, false).unwrap());
+        while peer.read_frame().unwrap().data() != &[0x7f] {}
+        heartbeat.stop().unwrap();
+
+        let errors = bus.errors();
+        let data = bus.subscribe(0x100, 0x100);
+        peer.write_frame(&CanFrame::new(0x040, &[0; 8], false, true).unwrap()).unwrap();
+        peer.write_frame(&CanFrame::new(0x100, &[], false, false).unwrap()).unwrap();
+        assert_eq!(data.recv().unwrap().id(), 0x100);
+        assert!(errors.recv().unwrap().is_error());
+    }
+
+    #[test]
+    fn test_periodic_timing() {
+        let mock = MockBus::new();
+        let mut peer = mock.endpoint();
+        let bus = CanBus::with_clock(mock.endpoint(), mock.clone()).unwrap();
+
+        // the mock clock advances on sleep, so this runs at full speed
+        let heartbeat = bus.send_periodic(CanFrame::new(0x701, &[0x05], false, false).unwrap(),
+                                          Duration::from_millis(100));
+        for n in 0..5 {
+            let (_, t) = peer.read_frame_with_timestamp().unwrap();
+            assert_eq!(t.duration_since(UNIX_EPOCH).unwrap(), Duration::from_millis(100 * n));
+        }
+
+        heartbeat.set_interval(Duration::from_millis(30));
+        let mut last = peer.read_frame_with_timestamp().unwrap().1;
+        loop {
+            let (_, t) = peer.read_frame_with_timestamp().unwrap();
+            if t.duration_since(last).unwrap() == Duration::from_millis(30) {
+                break;
+            }
+            assert_eq!(t.duration_since(last).unwrap(), Duration::from_millis(100));
+            last = t;
+        }
+        heartbeat.stop().unwrap();
+    }
+
+    #[test]
+    fn test_event_driven() {
+        let mock = MockBus::new();
+        let mut peer = mock.endpoint();
+        let bus = CanBus::with_clock(mock.endpoint(), mock.clone()).unwrap();
+        let pdo = |data| CanFrame::new(0x185, &[data], false, false).unwrap();
+
+        // repeated on the event timer while nothing happens
+        let tpdo = bus.send_on_event(pdo(1),
+                                     Duration::from_millis(30),
+                                     Duration::from_millis(100));
+        let start = peer.read_frame_with_timestamp().unwrap().1;
+        for n in 1..3 {
+            let (frame, t) = peer.read_frame_with_timestamp().unwrap();
+            assert_eq!(frame.data(), &[1]);
+            assert_eq!(t.duration_since(start).unwrap(), Duration::from_millis(100 * n));
+        }
+
+        // a new frame keeps the inhibit time and restarts the event timer
+        tpdo.send(pdo(2));
+        let mut last = start;
+        let sent = loop {
+            let (frame, t) = peer.read_frame_with_timestamp().unwrap();
+            if frame.data() == &[2] {
+                break t;
+            }
+            last = t;
+        };
+        assert!(sent.duration_since(last).unwrap() >= Duration::from_millis(30));
+        let (frame, t) = peer.read_frame_with_timestamp().unwrap();
+        assert_eq!(frame.data(), &[2]);
+        assert_eq!(t.duration_since(sent).unwrap(), Duration::from_millis(100));
+
+        // without event timer, frames are only sent when passed
+        tpdo.set_event_timer(Duration::from_secs(0));
+        tpdo.set_inhibit_time(Duration::from_secs(60));
+        tpdo.send(pdo(3));
+        let sent = loop {
+            let (frame, t) = peer.read_frame_with_timestamp().unwrap();
+            if frame.data() == &[3] {
+                break t;
+            }
+        };
+        tpdo.send(pdo(4));
+        let (frame, t) = peer.read_frame_with_timestamp().unwrap();
+        assert_eq!(frame.data(), &[4]);
+        assert!(t.duration_since(sent).unwrap() >= Duration::from_secs(60));
+        tpdo.stop().unwrap();
+    }
+
+    #[test]
+    fn test_send_table() {
+        let mock = MockBus::new();
+        let mut peer = mock.endpoint();
+        let bus = CanBus::with_clock(mock.endpoint(), mock.clone()).unwrap();
+
+        let table: CyclicTable = "123#01 100ms 30ms".parse().unwrap();
+        let cyclic = bus.send_table(&table);
+        for n in 0..3 {
+            let (frame, t) = peer.read_frame_with_timestamp().unwrap();
+            assert_eq!(frame.id(), 0x123);
+            assert_eq!(t.duration_since(UNIX_EPOCH).unwrap(),
+                       Duration::from_millis(30 + 100 * n));
+        }
+        drop(cyclic);
+    }
+
+    #[test]
+    fn test_bus_id() {
+        assert_eq!(BusId::from("can0").to_string(), "can0");
+        assert_eq!(BusId::from(3).to_string(), "#3");
+        assert_eq!(BusId::from("can0").name(), Some("can0".to_owned()));
+        assert_eq!(BusId::Index(0).name(), None);
+    }
+}
@@ -0,0 +1,349 @@
+//! cannelloni UDP tunneling
+//!
+//! [cannelloni](https://github.com/mguentner/cannelloni) bridges CAN
+//! buses between machines by tunneling frames over UDP. Every datagram
+//! starts with a header, followed by the frames:
+//!
+//! ```text
+//! header: version (u8) | op code (u8) | sequence number (u8) | count (u16, BE)
+//! frame:  CAN ID incl. EFF/RTR/ERR flags (u32, BE) | length (u8) | data
+//! ```
+//!
+//! RTR frames carry no data bytes. CAN FD frames set the top bit of the
+//! length byte and add a flags byte; they are rejected when received.
+//!
+//! `CannelloniSocket` implements `CanTransport`, so it can take the place
+//! of a `CanSocket` to exchange frames with a remote cannelloni instance.
+//! Only the UDP flavor of cannelloni is supported, not SCTP.
+
+use std::{cmp, error, fmt, io};
+use std::collections::VecDeque;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use {CanFrame, CanTransport, RTR_FLAG};
+
+/// Protocol version spoken by cannelloni
+pub const VERSION: u8 = 2;
+
+/// Op code of datagrams carrying frames
+pub const OP_DATA: u8 = 0;
+
+/// Largest datagram sent, to avoid IP fragmentation on an Ethernet link
+pub const MAX_PACKET_LEN: usize = 1472;
+
+const HEADER_LEN: usize = 5;
+const FRAME_HEADER_LEN: usize = 5;
+const FD_FLAG: u8 = 0x80;
+
+/// Error decoding a cannelloni datagram
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum PacketError {
+    /// The datagram ended in the middle of the header or a frame
+    Truncated,
+
+    /// The header carried an unknown protocol version
+    UnsupportedVersion(u8),
+
+    /// The datagram does not carry frames (ACK/NACK)
+    UnsupportedOpCode(u8),
+
+    /// The datagram contained a CAN FD frame
+    UnsupportedFdFrame,
+
+    /// A frame length larger than 8
+    InvalidLength(u8),
+}
+
+impl fmt::Display for PacketError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PacketError::UnsupportedVersion(v) => write!(f, "unsupported version {}", v),
+            PacketError::UnsupportedOpCode(op) => write!(f, "unsupported op code {}", op),
+            PacketError::InvalidLength(len) => write!(f, "invalid frame length {}", len),
+            _ => write!(f, "{}", error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for PacketError {
+    fn description(&self) -> &str {
+        match *self {
+            PacketError::Truncated => "truncated packet",
+            PacketError::UnsupportedVersion(_) => "unsupported version",
+            PacketError::UnsupportedOpCode(_) => "unsupported op code",
+            PacketError::UnsupportedFdFrame => "CAN FD frames are not supported",
+            PacketError::InvalidLength(_) => "invalid frame length",
+        }
+    }
+}
+
+impl From<PacketError> for io::Error {
+    fn from(e: PacketError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
+/// Number of bytes `frame` occupies in a datagram
+fn encoded_len(frame: &CanFrame) -> usize {
+    FRAME_HEADER_LEN + if frame.is_rtr() { 0 } else { frame.data().len() }
+}
+
+/// Encode `frames` into a single datagram.
+///
+/// # Panics
+///
+/// If there are more than 65535 frames.
+pub fn encode(seq_no: u8, frames: &[CanFrame]) -> Vec<u8> {
+    assert!(frames.len() <= 0xffff, "too many frames for a single packet");
+
+    let len = HEADER_LEN + frames.iter().map(encoded_len).sum::<usize>();
+    let mut buf = Vec::with_capacity(len);
+    buf.extend_from_slice(&[VERSION,
+                            OP_DATA,
+                            seq_no,
+                            (frames.len() >> 8) as u8,
+                            frames.len() as u8]);
+
+    for frame in frames {
+        let id = frame._id;
+        buf.extend_from_slice(&[(id >> 24) as u8,
+                                (id >> 16) as u8,
+                                (id >> 8) as u8,
+                                id as u8,
+                                frame._data_len]);
+        if !frame.is_rtr() {
+            buf.extend_from_slice(frame.data());
+        }
+    }
+
+    buf
+}
+
+/// Decode the frames of a datagram.
+pub fn decode(packet: &[u8]) -> Result<Vec<CanFrame>, PacketError> {
+    if packet.len() < HEADER_LEN {
+        return Err(PacketError::Truncated);
+    }
+    if packet[0] != VERSION {
+        return Err(PacketError::UnsupportedVersion(packet[0]));
+    }
+    if packet[1] != OP_DATA {
+        return Err(PacketError::UnsupportedOpCode(packet[1]));
+    }
+
+    let count = (packet[3] as usize) << 8 | packet[4] as usize;
+    let mut frames = Vec::with_capacity(count);
+    let mut rest = &packet[HEADER_LEN..];
+
+    for _ in 0..count {
+        if rest.len() < FRAME_HEADER_LEN {
+            return Err(PacketError::Truncated);
+        }
+
+        let id = (rest[0] as u32) << 24 | (rest[1] as u32) << 16 | (rest[2] as u32) << 8 |
+                 rest[3] as u32;
+        let len = rest[4];
+        if len & FD_FLAG != 0 {
+            return Err(PacketError::UnsupportedFdFrame);
+        }
+        if len > 8 {
+            return Err(PacketError::InvalidLength(len));
+        }
+        rest = &rest[FRAME_HEADER_LEN..];
+
+        let len = len as usize;
+        let mut data = [0; 8];
+        if id & RTR_FLAG == 0 {
+            if rest.len() < len {
+                return Err(PacketError::Truncated);
+            }
+            data[..len].copy_from_slice(&rest[..len]);
+            rest = &rest[len..];
+        }
+
+        let mut frame = CanFrame::new(0, &data[..len], false, false)
+            .expect("length was checked above");
+        frame._id = id;
+        frames.push(frame);
+    }
+
+    Ok(frames)
+}
+
+/// A UDP socket exchanging frames with a cannelloni peer
+///
+/// Frames are read one at a time; the remaining frames of a datagram are
+/// buffered for the following reads.
+#[derive(Debug)]
+pub struct CannelloniSocket {
+    socket: UdpSocket,
+    seq_no: AtomicUsize,
+    pending: Mutex<VecDeque<CanFrame>>,
+}
+
+impl CannelloniSocket {
+    /// Bind to `local` and exchange frames with the cannelloni instance at
+    /// `remote`. Datagrams from other addresses are ignored.
+    pub fn bind<A: ToSocketAddrs, B: ToSocketAddrs>(local: A,
+                                                    remote: B)
+                                                    -> io::Result<CannelloniSocket> {
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(remote)?;
+        Ok(CannelloniSocket::from_udp(socket))
+    }
+
+    /// Use an already connected UDP socket.
+    pub fn from_udp(socket: UdpSocket) -> CannelloniSocket {
+        CannelloniSocket {
+            socket: socket,
+            seq_no: AtomicUsize::new(0),
+            pending: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// The underlying UDP socket
+    pub fn udp_socket(&self) -> &UdpSocket {
+        &self.socket
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.peer_addr()
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.socket.set_nonblocking(nonblocking)
+    }
+
+    /// Set the read timeout. A zero duration disables the timeout.
+    pub fn set_read_timeout(&self, duration: Duration) -> io::Result<()> {
+        self.socket.set_read_timeout(non_zero(duration))
+    }
+
+    /// Set the write timeout. A zero duration disables the timeout.
+    pub fn set_write_timeout(&self, duration: Duration) -> io::Result<()> {
+        self.socket.set_write_timeout(non_zero(duration))
+    }
+
+    fn pop_pending(&self) -> Option<CanFrame> {
+        self.pending.lock().expect("pending frames lock poisoned").pop_front()
+    }
+
+    /// Blocking read of a single frame.
+    ///
+    /// Fails with `InvalidData` if a datagram cannot be decoded.
+    pub fn read_frame(&self) -> io::Result<CanFrame> {
+        let mut buf = [0; 65536];
+        loop {
+            if let Some(frame) = self.pop_pending() {
+                return Ok(frame);
+            }
+
+            let n = self.socket.recv(&mut buf)?;
+            let frames = decode(&buf[..n])?;
+            self.pending.lock().expect("pending frames lock poisoned").extend(frames);
+        }
+    }
+
+    /// Send a single frame in its own datagram.
+    pub fn write_frame(&self, frame: &CanFrame) -> io::Result<()> {
+        self.write_frames(&[*frame])
+    }
+
+    /// Send `frames`, packing as many into each datagram as fit into
+    /// `MAX_PACKET_LEN` bytes.
+    pub fn write_frames(&self, frames: &[CanFrame]) -> io::Result<()> {
+        let mut rest = frames;
+        while !rest.is_empty() {
+            let mut len = HEADER_LEN;
+            let count = rest.iter()
+                .take_while(|frame| {
+                    len += encoded_len(frame);
+                    len <= MAX_PACKET_LEN
+                })
+                .count();
+            let count = cmp::max(count, 1);
+
+            let seq_no = self.seq_no.fetch_add(1, Ordering::Relaxed) as u8;
+            self.socket.send(&encode(seq_no, &rest[..count]))?;
+            rest = &rest[count..];
+        }
+        Ok(())
+    }
+}
+
+fn non_zero(duration: Duration) -> Option<Duration> {
+    if duration == Duration::from_millis(0) { None } else { Some(duration) }
+}
+
+impl CanTransport for CannelloniSocket {
+    fn read_frame(&self) -> io::Result<CanFrame> {
+        CannelloniSocket::read_frame(self)
+    }
+
+    fn write_frame(&self, frame: &CanFrame) -> io::Result<()> {
+        CannelloniSocket::write_frame(self, frame)
+    }
+
+    fn set_read_timeout(&self, duration: Duration) -> io::Result<()> {
+        CannelloniSocket::set_read_timeout(self, duration)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::UdpSocket;
+    use {CanFrame, EFF_FLAG};
+    use super::{decode, encode, CannelloniSocket, PacketError};
+
+    #[test]
+    fn test_codec() {
+        let mut ext = CanFrame::new(0x12, &[0xaa], false, false).unwrap();
+        ext._id |= EFF_FLAG;
+        let frames = [CanFrame::new(0x123, &[1, 2, 3], false, false).unwrap(),
+                      ext,
+                      CanFrame::new(0x7ff, &[0; 4], true, false).unwrap()];
+
+        let packet = encode(7, &frames);
+        assert_eq!(packet,
+                   vec![2, 0, 7, 0, 3,
+                        0x00, 0x00, 0x01, 0x23, 3, 1, 2, 3,
+                        0x80, 0x00, 0x00, 0x12, 1, 0xaa,
+                        0x40, 0x00, 0x07, 0xff, 4]);
+
+        let decoded = decode(&packet).unwrap();
+        assert_eq!(decoded.len(), 3);
+        assert_eq!(decoded[0].data(), &[1, 2, 3]);
+        assert!(decoded[1].is_extended());
+        assert_eq!(decoded[1].id(), 0x12);
+        assert!(decoded[2].is_rtr());
+        assert_eq!(decoded[2].data().len(), 4);
+
+        assert_eq!(decode(&packet[..10]).unwrap_err(), PacketError::Truncated);
+        assert_eq!(decode(&[2, 0, 0, 0, 1, 0, 0, 1, 0, 0x88, 0]).unwrap_err(),
+                   PacketError::UnsupportedFdFrame);
+    }
+
+    #[test]
+    fn test_socket() {
+        let a = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let b = UdpSocket::bind("127.0.0.1:0").unwrap();
+        a.connect(b.local_addr().unwrap()).unwrap();
+        b.connect(a.local_addr().unwrap()).unwrap();
+        let (a, b) = (CannelloniSocket::from_udp(a), CannelloniSocket::from_udp(b));
+
+        let frames: Vec<_> = (0..300)
+            .map(|i| CanFrame::new(i, &[i as u8; 8], false, false).unwrap())
+            .collect();
+        a.write_frames(&frames).unwrap();
+
+        for i in 0..300 {
+            assert_eq!(b.read_frame().unwrap().id(), i);
+        }
+    }
+}
@@ -0,0 +1,331 @@
+//! Boot-up handling
+//!
+//! A CiA 302 NMT master brings up every node that boots before starting
+//! it: it checks the node's identity against the expected one, configures
+//! its PDOs and finally starts it. `NodeConfig` describes this for a
+//! single node; `bring_up` runs it, and `BootMaster` runs it whenever a
+//! configured node sends its boot-up message (`0x700 + node` with a
+//! payload of 0).
+//!
+//! ```no_run
+//! use std::sync::atomic::AtomicBool;
+//! use std::time::Duration;
+//! use socketcan::CanSocket;
+//! use socketcan::canopen::{COB_TPDO, SdoClient};
+//! use socketcan::canopen::boot::{BootMaster, NodeConfig};
+//! use socketcan::canopen::mapping::{Pdo, PdoMapping, TRANSMISSION_SYNC};
+//!
+//! let mut master = BootMaster::new();
+//! master.add_node(5,
+//!                 NodeConfig::new()
+//!                     .vendor_id(0x0000_0195)
+//!                     .pdo(PdoMapping::new(Pdo::Tpdo(1), COB_TPDO[0] + 5, TRANSMISSION_SYNC)
+//!                              .map(0x6041, 0, 16)));
+//!
+//! let listener = CanSocket::open("can0").unwrap();
+//! let socket = CanSocket::open("can0").unwrap();
+//! let client = SdoClient::new(&socket, Duration::from_millis(100));
+//! let shutdown = AtomicBool::new(false);
+//! master.run(&listener, &client, &shutdown, |node_id, result| {
+//!     println!("node {}: {:?}", node_id, result);
+//! }).unwrap();
+//! ```
+
+use std::{error, fmt, io};
+use std::collections::HashMap;
+use std::sync::atomic::AtomicBool;
+use {CanFrame, CanTransport};
+use transport::run_read_loop;
+use super::{Heartbeat, NmtCommand, NmtMessage};
+use super::mapping::{configure_pdo, MappingError, PdoMapping};
+use super::scan::OBJ_IDENTITY;
+use super::sdo::{SdoClient, SdoError};
+
+/// Bring-up of a single node
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NodeConfig {
+    /// Expected entries of the identity object, by subindex
+    identity: Vec<(u8, u32)>,
+
+    /// PDOs to configure, in order
+    pdos: Vec<PdoMapping>,
+
+    /// Whether to start the node afterwards
+    start: bool,
+}
+
+impl Default for NodeConfig {
+    fn default() -> NodeConfig {
+        NodeConfig::new()
+    }
+}
+
+impl NodeConfig {
+    /// Create a configuration that accepts any identity, configures no
+    /// PDOs and starts the node.
+    pub fn new() -> NodeConfig {
+        NodeConfig {
+            identity: Vec::new(),
+            pdos: Vec::new(),
+            start: true,
+        }
+    }
+
+    /// Expect the vendor ID `vendor_id`.
+    pub fn vendor_id(self, vendor_id: u32) -> NodeConfig {
+        self.expect_identity(1, vendor_id)
+    }
+
+    /// Expect the product code `product_code`.
+    pub fn product_code(self, product_code: u32) -> NodeConfig {
+        self.expect_identity(2, product_code)
+    }
+
+    /// Expect the revision number `revision`.
+    pub fn revision(self, revision: u32) -> NodeConfig {
+        self.expect_identity(3, revision)
+    }
+
+    /// Configure the PDO described by `mapping`.
+    pub fn pdo(mut self, mapping: PdoMapping) -> NodeConfig {
+        self.pdos.push(mapping);
+        self
+    }
+
+    /// Leave the node in pre-operational state instead of starting it.
+    pub fn keep_pre_operational(mut self) -> NodeConfig {
+        self.start = false;
+        self
+    }
+
+    fn expect_identity(mut self, subindex: u8, value: u32) -> NodeConfig {
+        self.identity.retain(|&(s, _)| s != subindex);
+        self.identity.push((subindex, value));
+        self
+    }
+}
+
+/// Error bringing up a node
+#[derive(Debug)]
+pub enum BootError {
+    /// An entry of the identity object did not match, the subindex and the
+    /// value read are given
+    IdentityMismatch(u8, u32),
+
+    /// Configuring a PDO failed
+    Mapping(MappingError),
+
+    /// Reading the identity or starting the node failed
+    Sdo(SdoError),
+}
+
+impl fmt::Display for BootError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            BootError::IdentityMismatch(subindex, value) => {
+                write!(f, "identity mismatch at subindex {} ({:08X})", subindex, value)
+            }
+            BootError::Mapping(ref e) => write!(f, "PDO mapping: {}", e),
+            BootError::Sdo(ref e) => write!(f, "SDO: {}", e),
+        }
+    }
+}
+
+impl error::Error for BootError {
+    fn description(&self) -> &str {
+        match *self {
+            BootError::IdentityMismatch(..) => "identity mismatch",
+            BootError::Mapping(ref e) => e.description(),
+            BootError::Sdo(ref e) => e.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            BootError::Mapping(ref e) => Some(e),
+            BootError::Sdo(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<MappingError> for BootError {
+    fn from(e: MappingError) -> BootError {
+        BootError::Mapping(e)
+    }
+}
+
+impl From<SdoError> for BootError {
+    fn from(e: SdoError) -> BootError {
+        BootError::Sdo(e)
+    }
+}
+
+impl From<io::Error> for BootError {
+    fn from(e: io::Error) -> BootError {
+        BootError::Sdo(SdoError::Io(e))
+    }
+}
+
+/// Bring up node `node_id` as described by `config`.
+///
+/// Stops at the first failing step, in which case the node is not started.
+pub fn bring_up<T>(client: &SdoClient<T>, node_id: u8, config: &NodeConfig) -> Result<(), BootError>
+    where T: CanTransport
+{
+    for &(subindex, expected) in &config.identity {
+        let value = client.read_u32(node_id, OBJ_IDENTITY, subindex)?;
+        if value != expected {
+            return Err(BootError::IdentityMismatch(subindex, value));
+        }
+    }
+
+    for mapping in &config.pdos {
+        configure_pdo(client, node_id, mapping)?;
+    }
+
+    if config.start {
+        let frame = NmtMessage::new(NmtCommand::Start, Some(node_id))
+            .and_then(|msg| msg.to_frame())
+            .map_err(|_| SdoError::InvalidNodeId(node_id))?;
+        client.socket().write_frame_insist(&frame)?;
+    }
+    Ok(())
+}
+
+/// Brings up configured nodes when they boot
+#[derive(Debug, Default)]
+pub struct BootMaster {
+    nodes: HashMap<u8, NodeConfig>,
+}
+
+impl BootMaster {
+    /// Create a master without configured nodes.
+    pub fn new() -> BootMaster {
+        BootMaster { nodes: HashMap::new() }
+    }
+
+    /// Bring up `node_id` as described by `config` whenever it boots.
+    pub fn add_node(&mut self, node_id: u8, config: NodeConfig) {
+        self.nodes.insert(node_id, config);
+    }
+
+    /// Configuration of `node_id`
+    pub fn node(&self, node_id: u8) -> Option<&NodeConfig> {
+        self.nodes.get(&node_id)
+    }
+
+    /// Bring up the node if `frame` is the boot-up message of a configured
+    /// node, returning its node ID and the result.
+    pub fn process_frame<T>(&self,
+                            client: &SdoClient<T>,
+                            frame: &CanFrame)
+                            -> Option<(u8, Result<(), BootError>)>
+        where T: CanTransport
+    {
+        let node_id = match Heartbeat::from_frame(frame) {
+            Ok(ref hb) if hb.is_boot_up() => hb.node_id,
+            _ => return None,
+        };
+
+        self.nodes.get(&node_id).map(|config| (node_id, bring_up(client, node_id, config)))
+    }
+
+    /// Bring up configured nodes as they boot until `shutdown` is set,
+    /// passing every result to `report`.
+    ///
+    /// Boot-up messages are read from `socket`, which must be a socket of
+    /// its own: the client discards frames while waiting for responses,
+    /// while `socket` queues the boot-up messages of nodes booting during
+    /// a bring-up. Returns the number of frames read from `socket`.
+    pub fn run<T, U, F>(&self,
+                        socket: &T,
+                        client: &SdoClient<U>,
+                        shutdown: &AtomicBool,
+                        mut report: F)
+                        -> io::Result<u64>
+        where T: CanTransport,
+              U: CanTransport,
+              F: FnMut(u8, Result<(), BootError>)
+    {
+        let handle = |frame: CanFrame| {
+            if let Some((node_id, result)) = self.process_frame(client, &frame) {
+                report(node_id, result);
+            }
+            Ok(())
+        };
+        run_read_loop(socket, handle, shutdown)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+    use canopen::{AccessType, Entry, NmtState, ObjectDictionary, SdoClient, Value, COB_TPDO};
+    use canopen::mapping::{insert_pdo_objects, Pdo, PdoMapping, TRANSMISSION_SYNC};
+    use canopen::scan::OBJ_IDENTITY;
+    use canopen::sim::{SimNode, Simulator};
+    use testing::MockBus;
+    use super::{BootError, BootMaster, NodeConfig};
+
+    fn node(node_id: u8, vendor_id: u32) -> SimNode {
+        let mut od = ObjectDictionary::new();
+        od.insert(OBJ_IDENTITY, 1, Entry::new(Value::Unsigned32(vendor_id), AccessType::Const));
+        od.insert(OBJ_IDENTITY, 2, Entry::new(Value::Unsigned32(0x42), AccessType::Const));
+        insert_pdo_objects(&mut od, Pdo::Tpdo(1));
+        SimNode::new(node_id, od).unwrap()
+    }
+
+    #[test]
+    fn test_boot_master() {
+        let bus = MockBus::new();
+        let (listener, socket, devices) = (bus.endpoint(), bus.endpoint(), bus.endpoint());
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let flag = shutdown.clone();
+        let simulator = thread::spawn(move || {
+            let mut sim = Simulator::new(devices);
+            sim.add_node(node(5, 0x195));
+            sim.add_node(node(6, 0x999));
+            sim.start().unwrap();
+            while !flag.load(Ordering::SeqCst) {
+                sim.run_for(Duration::from_millis(10)).unwrap();
+            }
+            sim.nodes()
+                .iter()
+                .map(|n| (n.state(), n.od().get(0x1800, 2).unwrap().clone()))
+                .collect::<Vec<_>>()
+        });
+
+        let config = NodeConfig::new()
+            .vendor_id(0x195)
+            .product_code(0x42)
+            .pdo(PdoMapping::new(Pdo::Tpdo(1), COB_TPDO[0] + 5, TRANSMISSION_SYNC)
+                     .map(0x6041, 0, 16));
+        let mut master = BootMaster::new();
+        master.add_node(5, config.clone());
+        master.add_node(6, config);
+
+        let client = SdoClient::new(&socket, Duration::from_secs(5));
+        let mut results = Vec::new();
+        master.run(&listener, &client, &shutdown, |node_id, result| {
+                results.push((node_id, result));
+                if results.len() == 2 {
+                    shutdown.store(true, Ordering::SeqCst);
+                }
+            })
+            .unwrap();
+        let nodes = simulator.join().unwrap();
+
+        results.sort_by_key(|&(node_id, _)| node_id);
+        assert!(results[0].1.is_ok());
+        match results[1] {
+            (6, Err(BootError::IdentityMismatch(1, 0x999))) => {}
+            ref r => panic!("unexpected result: {:?}", r),
+        }
+        assert_eq!(nodes[0], (NmtState::Operational, Value::Unsigned8(TRANSMISSION_SYNC)));
+        assert_eq!(nodes[1].0, NmtState::PreOperational);
+    }
+}
@@ -0,0 +1,177 @@
+//! Emergency objects (EMCY)
+//!
+//! A node transmits an emergency object on COB-ID `0x080 + node` whenever
+//! an internal error occurs or is cleared. The 8 byte payload consists of
+//! the error code (CiA 301, table 21), the current value of the error
+//! register (object `0x1001`) and five bytes of manufacturer-specific data.
+
+use std::io;
+use {CanFrame, CanTransport};
+use super::{check_data_frame, check_node_id, split_cob_id, FrameError, COB_EMCY};
+
+/// Error register bit: generic error
+pub const ERR_REG_GENERIC: u8 = 0x01;
+
+/// Error register bit: current
+pub const ERR_REG_CURRENT: u8 = 0x02;
+
+/// Error register bit: voltage
+pub const ERR_REG_VOLTAGE: u8 = 0x04;
+
+/// Error register bit: temperature
+pub const ERR_REG_TEMPERATURE: u8 = 0x08;
+
+/// Error register bit: communication error (overrun, error state)
+pub const ERR_REG_COMMUNICATION: u8 = 0x10;
+
+/// Error register bit: device profile specific
+pub const ERR_REG_DEVICE_PROFILE: u8 = 0x20;
+
+/// Error register bit: manufacturer specific
+pub const ERR_REG_MANUFACTURER: u8 = 0x80;
+
+/// A decoded emergency object.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Emcy {
+    /// Node that emitted the emergency
+    pub node_id: u8,
+
+    /// Emergency error code, `0x0000` signals an error reset
+    pub error_code: u16,
+
+    /// Contents of the error register (object `0x1001`)
+    pub error_register: u8,
+
+    /// Manufacturer-specific error information
+    pub manufacturer_data: [u8; 5],
+}
+
+impl Emcy {
+    /// Create a new emergency object for the node `node_id`.
+    pub fn new(node_id: u8,
+               error_code: u16,
+               error_register: u8,
+               manufacturer_data: [u8; 5])
+               -> Result<Emcy, FrameError> {
+        Ok(Emcy {
+            node_id: check_node_id(node_id)?,
+            error_code: error_code,
+            error_register: error_register,
+            manufacturer_data: manufacturer_data,
+        })
+    }
+
+    /// Decode an emergency object from a received frame.
+    ///
+    /// Some devices do not transmit the manufacturer-specific part. Frames
+    /// containing at least the error code and error register are accepted,
+    /// missing manufacturer bytes are set to zero.
+    pub fn from_frame(frame: &CanFrame) -> Result<Emcy, FrameError> {
+        check_data_frame(frame)?;
+
+        let (function, node_id) = split_cob_id(frame.id());
+        if function != COB_EMCY || node_id == 0 {
+            return Err(FrameError::UnexpectedCobId(frame.id()));
+        }
+
+        let data = frame.data();
+        if data.len() < 3 {
+            return Err(FrameError::NotEnoughData(data.len()));
+        }
+
+        let mut manufacturer_data = [0; 5];
+        for (dst, src) in manufacturer_data.iter_mut().zip(data[3..].iter()) {
+            *dst = *src;
+        }
+
+        Ok(Emcy {
+            node_id: node_id,
+            error_code: data[0] as u16 | (data[1] as u16) << 8,
+            error_register: data[2],
+            manufacturer_data: manufacturer_data,
+        })
+    }
+
+    /// Encode the emergency object into a frame.
+    pub fn to_frame(&self) -> Result<CanFrame, FrameError> {
+        let mut data = [0; 8];
+        data[0] = self.error_code as u8;
+        data[1] = (self.error_code >> 8) as u8;
+        data[2] = self.error_register;
+        data[3..].copy_from_slice(&self.manufacturer_data);
+
+        let cob_id = COB_EMCY + check_node_id(self.node_id)? as u32;
+        Ok(CanFrame::new(cob_id, &data, false, false)?)
+    }
+
+    /// Check if this emergency signals that all errors were cleared
+    #[inline]
+    pub fn is_reset(&self) -> bool {
+        self.error_code == 0
+    }
+
+    /// The error class, i.e. the upper byte of the error code
+    ///
+    /// Examples are `0x10` (generic), `0x23` (output current) or `0x81`
+    /// (communication).
+    #[inline]
+    pub fn error_class(&self) -> u8 {
+        (self.error_code >> 8) as u8
+    }
+}
+
+/// Transmit an emergency object.
+///
+/// Intended for slave implementations that need to signal error conditions
+/// to the rest of the network.
+pub fn send_emcy<T: CanTransport>(socket: &T, emcy: &Emcy) -> io::Result<()> {
+    let frame = emcy.to_frame()
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+    socket.write_frame(&frame)
+}
+
+#[cfg(test)]
+mod test {
+    use CanFrame;
+    use testing::MockBus;
+    use super::{send_emcy, Emcy};
+
+    #[test]
+    fn test_emcy_roundtrip() {
+        let frame = CanFrame::new(0x085, &[0x10, 0x23, 0x05, 1, 2, 3, 4, 5], false, false)
+            .unwrap();
+        let emcy = Emcy::from_frame(&frame).unwrap();
+
+        assert_eq!(emcy.node_id, 5);
+        assert_eq!(emcy.error_code, 0x2310);
+        assert_eq!(emcy.error_class(), 0x23);
+        assert_eq!(emcy.error_register, 0x05);
+        assert_eq!(emcy.manufacturer_data, [1, 2, 3, 4, 5]);
+
+        assert_eq!(emcy.to_frame().unwrap().data(), frame.data());
+    }
+
+    #[test]
+    fn test_emcy_rejects_sync() {
+        let frame = CanFrame::new(0x080, &[], false, false).unwrap();
+        assert!(Emcy::from_frame(&frame).is_err());
+    }
+
+    #[test]
+    fn test_send_emcy_mock() {
+        let bus = MockBus::new();
+        let (node, master) = (bus.endpoint(), bus.endpoint());
+
+        let emcy = Emcy {
+            node_id: 3,
+            error_code: 0x8130,
+            error_register: 0x11,
+            manufacturer_data: [0; 5],
+        };
+        send_emcy(&node, &emcy).unwrap();
+
+        let frame = master.read_frame().unwrap();
+        assert_eq!(frame.id(), 0x083);
+        assert_eq!(Emcy::from_frame(&frame).unwrap(), emcy);
+    }
+}
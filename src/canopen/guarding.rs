@@ -0,0 +1,213 @@
+//! Node guarding
+//!
+//! Node guarding is the legacy alternative to heartbeats: the master polls
+//! a node by sending a remote frame on `0x700 + node`, the node answers
+//! with a single byte containing its NMT state and a toggle bit (bit 7).
+//! The toggle bit starts at 0 after the node's communication was reset and
+//! alternates with every response; a response carrying the wrong toggle
+//! bit indicates a lost or duplicated message.
+
+use std::{error, fmt, io};
+use std::time::{Duration, Instant};
+use {CanFrame, CanTransport};
+use util::read_until;
+use super::{check_node_id, FrameError, NmtState, COB_HEARTBEAT};
+
+/// Error during a node guarding cycle
+#[derive(Debug)]
+pub enum GuardError {
+    /// The node did not answer within the given timeout
+    Timeout,
+
+    /// The node answered with the wrong toggle bit
+    ToggleMismatch,
+
+    /// The response did not contain a valid NMT state
+    InvalidState(u8),
+
+    /// Socket error while sending or receiving
+    Io(io::Error),
+}
+
+impl fmt::Display for GuardError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            GuardError::InvalidState(s) => write!(f, "invalid node state {:02X}", s),
+            GuardError::Io(ref e) => write!(f, "IO: {}", e),
+            _ => write!(f, "{}", error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for GuardError {
+    fn description(&self) -> &str {
+        match *self {
+            GuardError::Timeout => "node guarding timeout",
+            GuardError::ToggleMismatch => "toggle bit mismatch",
+            GuardError::InvalidState(_) => "invalid node state",
+            GuardError::Io(ref e) => e.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            GuardError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for GuardError {
+    fn from(e: io::Error) -> GuardError {
+        GuardError::Io(e)
+    }
+}
+
+/// Guarding state of a single node
+///
+/// Mirrors the guard time (object `0x100C`) and life time factor (object
+/// `0x100D`) configured on the node. Call `guard` once every guard time; if
+/// no valid response was received for `guard_time * life_time_factor`, the
+/// node is considered lost.
+#[derive(Debug)]
+pub struct NodeGuard {
+    node_id: u8,
+    guard_time: Duration,
+    life_time_factor: u32,
+    toggle: bool,
+    last_response: Option<Instant>,
+}
+
+impl NodeGuard {
+    /// Create guarding state for `node_id`.
+    pub fn new(node_id: u8,
+               guard_time: Duration,
+               life_time_factor: u32)
+               -> Result<NodeGuard, FrameError> {
+        Ok(NodeGuard {
+            node_id: check_node_id(node_id)?,
+            guard_time: guard_time,
+            life_time_factor: life_time_factor,
+            toggle: false,
+            last_response: None,
+        })
+    }
+
+    /// Guarded node
+    #[inline]
+    pub fn node_id(&self) -> u8 {
+        self.node_id
+    }
+
+    /// Interval at which the node should be polled
+    #[inline]
+    pub fn guard_time(&self) -> Duration {
+        self.guard_time
+    }
+
+    /// Node life time, i.e. guard time multiplied by the life time factor
+    pub fn life_time(&self) -> Duration {
+        self.guard_time * self.life_time_factor
+    }
+
+    /// Reset the expected toggle bit.
+    ///
+    /// Must be called after the node's communication was reset (e.g. after
+    /// sending an NMT reset or receiving a boot-up message).
+    pub fn reset_toggle(&mut self) {
+        self.toggle = false;
+    }
+
+    /// The remote frame polling the node
+    pub fn request_frame(&self) -> CanFrame {
+        CanFrame::new(COB_HEARTBEAT + self.node_id as u32, &[0], true, false)
+            .expect("node id validated on construction")
+    }
+
+    /// Check if `frame` is a guarding response of this node
+    pub fn is_response(&self, frame: &CanFrame) -> bool {
+        !frame.is_rtr() && !frame.is_extended() && !frame.is_error() &&
+        frame.id() == COB_HEARTBEAT + self.node_id as u32 && frame.data().len() == 1
+    }
+
+    /// Evaluate a guarding response received at `now`.
+    ///
+    /// The expected toggle bit advances on every response, even a
+    /// mismatching one, so a single lost message is reported only once.
+    pub fn process_response(&mut self,
+                            frame: &CanFrame,
+                            now: Instant)
+                            -> Result<NmtState, GuardError> {
+        let byte = frame.data()[0];
+        let toggle = byte & 0x80 != 0;
+        let expected = self.toggle;
+        self.toggle = !toggle;
+
+        if toggle != expected {
+            return Err(GuardError::ToggleMismatch);
+        }
+
+        let state = NmtState::from_byte(byte).ok_or(GuardError::InvalidState(byte))?;
+        self.last_response = Some(now);
+        Ok(state)
+    }
+
+    /// Check if the node's life time expired at `now`.
+    ///
+    /// A node that never answered is not considered lost.
+    pub fn is_lost(&self, now: Instant) -> bool {
+        match self.last_response {
+            Some(t) => now.duration_since(t) > self.life_time(),
+            None => false,
+        }
+    }
+
+    /// Perform a single guarding cycle.
+    ///
+    /// Sends the remote request and waits up to `timeout` for the response.
+    /// Unrelated frames received in the meantime are discarded, so this
+    /// should be used on a socket dedicated to guarding (ideally with a
+    /// filter for `0x700 + node`). Note that the socket's read timeout is
+    /// changed by this call.
+    pub fn guard<T: CanTransport>(&mut self,
+                                  socket: &T,
+                                  timeout: Duration)
+                                  -> Result<NmtState, GuardError> {
+        socket.write_frame_insist(&self.request_frame())?;
+
+        match read_until(socket, timeout, |frame| self.is_response(frame))? {
+            Some(frame) => self.process_response(&frame, Instant::now()),
+            None => Err(GuardError::Timeout),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+    use CanFrame;
+    use super::{GuardError, NodeGuard};
+    use canopen::NmtState;
+
+    #[test]
+    fn test_toggle_bit() {
+        let mut guard = NodeGuard::new(4, Duration::from_millis(100), 3).unwrap();
+        let now = Instant::now();
+
+        let r0 = CanFrame::new(0x704, &[0x05], false, false).unwrap();
+        let r1 = CanFrame::new(0x704, &[0x85], false, false).unwrap();
+
+        assert!(guard.is_response(&r0));
+        assert_eq!(guard.process_response(&r0, now).unwrap(), NmtState::Operational);
+        assert_eq!(guard.process_response(&r1, now).unwrap(), NmtState::Operational);
+
+        // a response with toggle 0 got lost, the node answers with toggle 1
+        match guard.process_response(&r1, now) {
+            Err(GuardError::ToggleMismatch) => (),
+            r => panic!("unexpected result {:?}", r),
+        }
+
+        assert!(!guard.is_lost(now + Duration::from_millis(300)));
+        assert!(guard.is_lost(now + Duration::from_millis(301)));
+    }
+}
@@ -0,0 +1,399 @@
+//! Heartbeat protocol
+//!
+//! Every node periodically transmits its current NMT state on COB-ID
+//! `0x700 + node`. The first message after a reset carries the state
+//! `0x00` and doubles as the boot-up message.
+//!
+//! A heartbeat consumer watches these messages and considers a node lost if
+//! no heartbeat was received within its configured consumer time. As in CiA
+//! 301, monitoring of a node starts with the first heartbeat received from
+//! it.
+//!
+//! A `HeartbeatProducer` transmits the heartbeat of a device implemented
+//! in software, as a periodic transmission of a `CanBus`:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use socketcan::CanBus;
+//! use socketcan::canopen::{HeartbeatProducer, NmtState};
+//!
+//! let bus = CanBus::open("can0").unwrap();
+//! let mut heartbeat = HeartbeatProducer::new(&bus, 5, NmtState::PreOperational).unwrap();
+//! heartbeat.set_period(Duration::from_millis(100));
+//!
+//! // the next heartbeat reports the new state
+//! heartbeat.set_state(NmtState::Operational);
+//! ```
+
+use std::io;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use {CanFrame, CanTransport};
+use bus::{CanBus, Periodic};
+use clock::Clock;
+use super::{check_data_frame, check_node_id, split_cob_id, FrameError, COB_HEARTBEAT};
+
+/// NMT state as reported by heartbeat messages
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum NmtState {
+    /// Node has just booted (boot-up message)
+    BootUp,
+
+    /// Stopped, only NMT and heartbeat/node guarding are active
+    Stopped,
+
+    /// Operational, all communication objects are active
+    Operational,
+
+    /// Pre-operational, SDO communication only
+    PreOperational,
+}
+
+impl NmtState {
+    /// Decode the state byte of a heartbeat message.
+    ///
+    /// The toggle bit used by node guarding (bit 7) is ignored.
+    pub fn from_byte(val: u8) -> Option<NmtState> {
+        match val & 0x7f {
+            0x00 => Some(NmtState::BootUp),
+            0x04 => Some(NmtState::Stopped),
+            0x05 => Some(NmtState::Operational),
+            0x7f => Some(NmtState::PreOperational),
+            _ => None,
+        }
+    }
+
+    /// The state byte transmitted in heartbeat messages
+    pub fn as_byte(&self) -> u8 {
+        match *self {
+            NmtState::BootUp => 0x00,
+            NmtState::Stopped => 0x04,
+            NmtState::Operational => 0x05,
+            NmtState::PreOperational => 0x7f,
+        }
+    }
+}
+
+/// A heartbeat (or boot-up) message.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct Heartbeat {
+    /// Node that transmitted the heartbeat
+    pub node_id: u8,
+
+    /// Reported NMT state
+    pub state: NmtState,
+}
+
+impl Heartbeat {
+    /// Decode a heartbeat message.
+    ///
+    /// Returns `FrameError::UnexpectedCobId` if the frame is not a heartbeat
+    /// and `FrameError::NotEnoughData` if the state byte is missing or
+    /// invalid.
+    pub fn from_frame(frame: &CanFrame) -> Result<Heartbeat, FrameError> {
+        check_data_frame(frame)?;
+
+        let (function, node_id) = split_cob_id(frame.id());
+        if function != COB_HEARTBEAT || node_id == 0 {
+            return Err(FrameError::UnexpectedCobId(frame.id()));
+        }
+
+        let data = frame.data();
+        let state = data.first()
+            .and_then(|&b| NmtState::from_byte(b))
+            .ok_or(FrameError::NotEnoughData(data.len()))?;
+
+        Ok(Heartbeat {
+            node_id: node_id,
+            state: state,
+        })
+    }
+
+    /// Encode the heartbeat into a frame.
+    pub fn to_frame(&self) -> Result<CanFrame, FrameError> {
+        let cob_id = COB_HEARTBEAT + check_node_id(self.node_id)? as u32;
+        Ok(CanFrame::new(cob_id, &[self.state.as_byte()], false, false)?)
+    }
+
+    /// Check if this is a boot-up message
+    #[inline]
+    pub fn is_boot_up(&self) -> bool {
+        self.state == NmtState::BootUp
+    }
+}
+
+/// Heartbeat producer
+///
+/// Sends the heartbeat of a node every producer time, as configured in
+/// object 0x1017, carrying the NMT state set by the application. A
+/// producer time of zero disables the heartbeat, which is also the initial
+/// setting. Dropping the producer stops the heartbeat.
+#[derive(Debug)]
+pub struct HeartbeatProducer<'a, T: 'a, C: 'a> {
+    bus: &'a CanBus<T, C>,
+    heartbeat: Heartbeat,
+    period: Duration,
+    periodic: Option<Periodic>,
+}
+
+impl<'a, T, C> HeartbeatProducer<'a, T, C>
+    where T: CanTransport + Send + Sync + 'static,
+          C: Clock + Clone + Send + 'static
+{
+    /// Produce the heartbeat of `node_id` on `bus`, reporting `state`.
+    pub fn new(bus: &'a CanBus<T, C>,
+               node_id: u8,
+               state: NmtState)
+               -> Result<HeartbeatProducer<'a, T, C>, FrameError> {
+        Ok(HeartbeatProducer {
+            bus: bus,
+            heartbeat: Heartbeat {
+                node_id: check_node_id(node_id)?,
+                state: state,
+            },
+            period: Duration::from_secs(0),
+            periodic: None,
+        })
+    }
+
+    /// Report `state` from the next heartbeat on.
+    pub fn set_state(&mut self, state: NmtState) {
+        self.heartbeat.state = state;
+        if let Some(ref periodic) = self.periodic {
+            periodic.set_frame(self.frame());
+        }
+    }
+
+    /// The reported NMT state
+    pub fn state(&self) -> NmtState {
+        self.heartbeat.state
+    }
+
+    /// Change the producer time, zero stops the heartbeat.
+    ///
+    /// A stopped heartbeat starts right away, a running one keeps the
+    /// current period and continues with the new one.
+    pub fn set_period(&mut self, period: Duration) {
+        self.period = period;
+        if period == Duration::from_secs(0) {
+            self.periodic = None;
+            return;
+        }
+
+        match self.periodic {
+            Some(ref periodic) => periodic.set_interval(period),
+            None => self.periodic = Some(self.bus.send_periodic(self.frame(), period)),
+        }
+    }
+
+    /// The producer time, zero if the heartbeat is stopped
+    pub fn period(&self) -> Duration {
+        self.period
+    }
+
+    /// Stop the heartbeat.
+    ///
+    /// Returns the error that stopped the transmission early, if any.
+    pub fn stop(&mut self) -> io::Result<()> {
+        self.period = Duration::from_secs(0);
+        match self.periodic.take() {
+            Some(periodic) => periodic.stop(),
+            None => Ok(()),
+        }
+    }
+
+    fn frame(&self) -> CanFrame {
+        self.heartbeat.to_frame().expect("node ID is checked")
+    }
+}
+
+/// Event reported by the `HeartbeatConsumer`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum HeartbeatEvent {
+    /// No heartbeat was received from the node within its consumer time
+    NodeLost(u8),
+
+    /// A previously lost node started sending heartbeats again
+    NodeRecovered(u8),
+}
+
+#[derive(Debug)]
+struct ConsumerEntry {
+    consumer_time: Duration,
+    last_seen: Option<Instant>,
+    state: Option<NmtState>,
+    lost: bool,
+}
+
+/// Heartbeat consumer
+///
+/// Tracks the last heartbeat of every monitored node. Feed all received
+/// frames into `process_frame` and call `check_timeouts` periodically
+/// (at least as often as the shortest consumer time) to detect lost nodes.
+#[derive(Debug, Default)]
+pub struct HeartbeatConsumer {
+    nodes: HashMap<u8, ConsumerEntry>,
+}
+
+impl HeartbeatConsumer {
+    /// Create a consumer without any monitored nodes.
+    pub fn new() -> HeartbeatConsumer {
+        HeartbeatConsumer { nodes: HashMap::new() }
+    }
+
+    /// Start monitoring `node_id` with the given consumer time.
+    ///
+    /// Reconfiguring an already monitored node keeps its last-seen time.
+    pub fn set_consumer_time(&mut self,
+                             node_id: u8,
+                             consumer_time: Duration)
+                             -> Result<(), FrameError> {
+        let entry = self.nodes.entry(check_node_id(node_id)?).or_insert(ConsumerEntry {
+            consumer_time: consumer_time,
+            last_seen: None,
+            state: None,
+            lost: false,
+        });
+        entry.consumer_time = consumer_time;
+        Ok(())
+    }
+
+    /// Stop monitoring `node_id`.
+    pub fn remove(&mut self, node_id: u8) {
+        self.nodes.remove(&node_id);
+    }
+
+    /// Process a received frame.
+    ///
+    /// Frames that are not heartbeats of monitored nodes are ignored. If the
+    /// heartbeat belongs to a node that was previously reported as lost,
+    /// `NodeRecovered` is returned.
+    pub fn process_frame(&mut self, frame: &CanFrame, now: Instant) -> Option<HeartbeatEvent> {
+        let hb = match Heartbeat::from_frame(frame) {
+            Ok(hb) => hb,
+            Err(_) => return None,
+        };
+
+        let entry = match self.nodes.get_mut(&hb.node_id) {
+            Some(entry) => entry,
+            None => return None,
+        };
+
+        entry.last_seen = Some(now);
+        entry.state = Some(hb.state);
+
+        if entry.lost {
+            entry.lost = false;
+            Some(HeartbeatEvent::NodeRecovered(hb.node_id))
+        } else {
+            None
+        }
+    }
+
+    /// Check all monitored nodes for expired consumer times.
+    ///
+    /// Every loss is reported only once, until the node recovers.
+    pub fn check_timeouts(&mut self, now: Instant) -> Vec<HeartbeatEvent> {
+        let mut events = Vec::new();
+
+        for (&node_id, entry) in &mut self.nodes {
+            if entry.lost {
+                continue;
+            }
+
+            if let Some(last_seen) = entry.last_seen {
+                if now.duration_since(last_seen) > entry.consumer_time {
+                    entry.lost = true;
+                    events.push(HeartbeatEvent::NodeLost(node_id));
+                }
+            }
+        }
+
+        events
+    }
+
+    /// Time the last heartbeat of `node_id` was received
+    pub fn last_seen(&self, node_id: u8) -> Option<Instant> {
+        self.nodes.get(&node_id).and_then(|e| e.last_seen)
+    }
+
+    /// Last NMT state reported by `node_id`
+    pub fn state(&self, node_id: u8) -> Option<NmtState> {
+        self.nodes.get(&node_id).and_then(|e| e.state)
+    }
+
+    /// Check if `node_id` is currently considered lost
+    pub fn is_lost(&self, node_id: u8) -> bool {
+        self.nodes.get(&node_id).map_or(false, |e| e.lost)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant, UNIX_EPOCH};
+    use {CanBus, CanFrame};
+    use testing::MockBus;
+    use super::{HeartbeatConsumer, HeartbeatEvent, HeartbeatProducer, NmtState};
+
+    #[test]
+    fn test_producer() {
+        let mock = MockBus::new();
+        let mut peer = mock.endpoint();
+        let bus = CanBus::with_clock(mock.endpoint(), mock.clone()).unwrap();
+        let mut producer = HeartbeatProducer::new(&bus, 5, NmtState::PreOperational).unwrap();
+        assert!(HeartbeatProducer::new(&bus, 0, NmtState::PreOperational).is_err());
+
+        // the mock clock advances on sleep, so this runs at full speed
+        producer.set_period(Duration::from_millis(100));
+        for n in 0..3 {
+            let (frame, t) = peer.read_frame_with_timestamp().unwrap();
+            assert_eq!((frame.id(), frame.data()), (0x705, &[0x7f][..]));
+            assert_eq!(t.duration_since(UNIX_EPOCH).unwrap(), Duration::from_millis(100 * n));
+        }
+
+        producer.set_state(NmtState::Operational);
+        producer.set_period(Duration::from_millis(20));
+        assert_eq!(producer.period(), Duration::from_millis(20));
+        let mut last = peer.read_frame_with_timestamp().unwrap().1;
+        loop {
+            let (frame, t) = peer.read_frame_with_timestamp().unwrap();
+            let elapsed = t.duration_since(last).unwrap();
+            if frame.data() == &[0x05] && elapsed == Duration::from_millis(20) {
+                break;
+            }
+            assert_eq!(elapsed, Duration::from_millis(100));
+            last = t;
+        }
+
+        producer.stop().unwrap();
+        assert_eq!(producer.period(), Duration::from_secs(0));
+        while peer.pending() > 0 {
+            peer.read_frame().unwrap();
+        }
+        mock.advance(Duration::from_secs(1));
+        assert_eq!(peer.pending(), 0);
+    }
+
+    #[test]
+    fn test_consumer_lost_and_recovered() {
+        let mut consumer = HeartbeatConsumer::new();
+        consumer.set_consumer_time(3, Duration::from_millis(100)).unwrap();
+
+        let hb = CanFrame::new(0x703, &[0x05], false, false).unwrap();
+        let t0 = Instant::now();
+
+        // not monitored until the first heartbeat arrives
+        assert!(consumer.check_timeouts(t0 + Duration::from_secs(1)).is_empty());
+
+        assert_eq!(consumer.process_frame(&hb, t0), None);
+        assert_eq!(consumer.state(3), Some(NmtState::Operational));
+        assert!(consumer.check_timeouts(t0 + Duration::from_millis(50)).is_empty());
+
+        let t1 = t0 + Duration::from_millis(150);
+        assert_eq!(consumer.check_timeouts(t1), vec![HeartbeatEvent::NodeLost(3)]);
+        assert!(consumer.check_timeouts(t1).is_empty());
+
+        assert_eq!(consumer.process_frame(&hb, t1),
+                   Some(HeartbeatEvent::NodeRecovered(3)));
+    }
+}
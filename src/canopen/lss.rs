@@ -0,0 +1,278 @@
+//! Layer setting services (LSS, CiA 305)
+//!
+//! LSS allows configuring node ID and bit timing of devices that are not
+//! (yet) reachable through regular CANopen services. The LSS master sends
+//! requests on COB-ID `0x7E5`, slaves answer on `0x7E4`. Every LSS message
+//! carries 8 bytes, the first one being the command specifier.
+//!
+//! A slave is addressed by its LSS address, the four entries of the
+//! identity object `0x1018`. Only slaves in the configuration state accept
+//! configuration requests; either switch all slaves at once (if only a
+//! single unconfigured device is connected) or select one by address.
+
+use std::{error, fmt, io};
+use std::time::Duration;
+use {CanFrame, CanSocket, CanTransport};
+use util::read_until;
+use super::MAX_NODE_ID;
+
+/// COB-ID of LSS requests (master to slave)
+pub const COB_LSS_REQUEST: u32 = 0x7e5;
+
+/// COB-ID of LSS responses (slave to master)
+pub const COB_LSS_RESPONSE: u32 = 0x7e4;
+
+// command specifiers
+const CS_SWITCH_GLOBAL: u8 = 0x04;
+const CS_CONFIGURE_NODE_ID: u8 = 0x11;
+const CS_CONFIGURE_BIT_TIMING: u8 = 0x13;
+const CS_ACTIVATE_BIT_TIMING: u8 = 0x15;
+const CS_STORE_CONFIGURATION: u8 = 0x17;
+const CS_SWITCH_SELECTIVE_VENDOR: u8 = 0x40;
+const CS_SWITCH_SELECTIVE_RESPONSE: u8 = 0x44;
+const CS_INQUIRE_VENDOR: u8 = 0x5a;
+const CS_INQUIRE_NODE_ID: u8 = 0x5e;
+
+/// Node ID that marks a device as unconfigured
+pub const UNCONFIGURED_NODE_ID: u8 = 0xff;
+
+/// LSS address of a device (identity object `0x1018`)
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct LssAddress {
+    pub vendor_id: u32,
+    pub product_code: u32,
+    pub revision: u32,
+    pub serial: u32,
+}
+
+/// LSS state of the slaves
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LssMode {
+    /// Normal operation, configuration requests are ignored
+    Waiting,
+
+    /// Configuration requests are accepted
+    Configuration,
+}
+
+/// Bit rates of the standard CiA bit timing table
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum BitRate {
+    Kbit1000,
+    Kbit800,
+    Kbit500,
+    Kbit250,
+    Kbit125,
+    Kbit50,
+    Kbit20,
+    Kbit10,
+
+    /// Automatic bit rate detection
+    Auto,
+}
+
+impl BitRate {
+    /// Index into the standard bit timing table
+    pub fn table_index(&self) -> u8 {
+        match *self {
+            BitRate::Kbit1000 => 0,
+            BitRate::Kbit800 => 1,
+            BitRate::Kbit500 => 2,
+            BitRate::Kbit250 => 3,
+            BitRate::Kbit125 => 4,
+            BitRate::Kbit50 => 6,
+            BitRate::Kbit20 => 7,
+            BitRate::Kbit10 => 8,
+            BitRate::Auto => 9,
+        }
+    }
+}
+
+/// Error performing an LSS service
+#[derive(Debug)]
+pub enum LssError {
+    /// No slave answered within the timeout
+    Timeout,
+
+    /// The slave rejected the request with the given error code and
+    /// manufacturer-specific error
+    Rejected(u8, u8),
+
+    /// Node ID outside of 1..127 (and not `UNCONFIGURED_NODE_ID`)
+    InvalidNodeId(u8),
+
+    /// Socket error while sending or receiving
+    Io(io::Error),
+}
+
+impl fmt::Display for LssError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LssError::Rejected(code, spec) => {
+                write!(f, "request rejected (error {}, specific {})", code, spec)
+            }
+            LssError::InvalidNodeId(id) => write!(f, "invalid node ID {}", id),
+            LssError::Io(ref e) => write!(f, "IO: {}", e),
+            _ => write!(f, "{}", error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for LssError {
+    fn description(&self) -> &str {
+        match *self {
+            LssError::Timeout => "lss timeout",
+            LssError::Rejected(..) => "lss request rejected",
+            LssError::InvalidNodeId(_) => "invalid node id",
+            LssError::Io(ref e) => e.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            LssError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for LssError {
+    fn from(e: io::Error) -> LssError {
+        LssError::Io(e)
+    }
+}
+
+/// LSS master
+///
+/// Performs LSS services over a socket, waiting up to `timeout` for every
+/// confirmation. Responses are read from the same socket, so other traffic
+/// received in the meantime is discarded.
+#[derive(Debug)]
+pub struct LssMaster<'a, T: 'a = CanSocket> {
+    socket: &'a T,
+    timeout: Duration,
+}
+
+impl<'a, T: CanTransport> LssMaster<'a, T> {
+    /// Create a new LSS master.
+    pub fn new(socket: &'a T, timeout: Duration) -> LssMaster<'a, T> {
+        LssMaster {
+            socket: socket,
+            timeout: timeout,
+        }
+    }
+
+    fn send(&self, cs: u8, payload: &[u8]) -> io::Result<()> {
+        let mut data = [0; 8];
+        data[0] = cs;
+        data[1..1 + payload.len()].copy_from_slice(payload);
+
+        let frame = CanFrame::new(COB_LSS_REQUEST, &data, false, false)
+            .expect("LSS frame is always valid");
+        self.socket.write_frame_insist(&frame)
+    }
+
+    fn request(&self, cs: u8, payload: &[u8], response_cs: u8) -> Result<[u8; 8], LssError> {
+        self.send(cs, payload)?;
+
+        let response = read_until(self.socket, self.timeout, |frame| {
+            frame.id() == COB_LSS_RESPONSE && !frame.is_extended() && !frame.is_rtr() &&
+            frame.data().first() == Some(&response_cs)
+        })?;
+
+        match response {
+            Some(frame) => {
+                let mut data = [0; 8];
+                data[..frame.data().len()].copy_from_slice(frame.data());
+                Ok(data)
+            }
+            None => Err(LssError::Timeout),
+        }
+    }
+
+    fn configure(&self, cs: u8, payload: &[u8]) -> Result<(), LssError> {
+        let data = self.request(cs, payload, cs)?;
+        match data[1] {
+            0 => Ok(()),
+            code => Err(LssError::Rejected(code, data[2])),
+        }
+    }
+
+    /// Switch all slaves into `mode`. Not confirmed.
+    pub fn switch_global(&self, mode: LssMode) -> io::Result<()> {
+        self.send(CS_SWITCH_GLOBAL, &[(mode == LssMode::Configuration) as u8])
+    }
+
+    /// Switch the slave with the given address into configuration state.
+    pub fn switch_selective(&self, addr: &LssAddress) -> Result<(), LssError> {
+        let parts = [addr.vendor_id, addr.product_code, addr.revision, addr.serial];
+
+        for (i, part) in parts.iter().enumerate() {
+            let bytes = [*part as u8, (*part >> 8) as u8, (*part >> 16) as u8, (*part >> 24) as u8];
+
+            if i < 3 {
+                self.send(CS_SWITCH_SELECTIVE_VENDOR + i as u8, &bytes)?;
+            } else {
+                self.request(CS_SWITCH_SELECTIVE_VENDOR + i as u8,
+                             &bytes,
+                             CS_SWITCH_SELECTIVE_RESPONSE)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Configure the node ID of the slave in configuration state.
+    ///
+    /// The new node ID becomes active after the next NMT reset
+    /// communication.
+    pub fn configure_node_id(&self, node_id: u8) -> Result<(), LssError> {
+        if (node_id == 0 || node_id > MAX_NODE_ID) && node_id != UNCONFIGURED_NODE_ID {
+            return Err(LssError::InvalidNodeId(node_id));
+        }
+
+        self.configure(CS_CONFIGURE_NODE_ID, &[node_id])
+    }
+
+    /// Configure the bit rate of the slave in configuration state.
+    pub fn configure_bit_timing(&self, rate: BitRate) -> Result<(), LssError> {
+        self.configure(CS_CONFIGURE_BIT_TIMING, &[0, rate.table_index()])
+    }
+
+    /// Make all slaves in configuration state switch to the configured bit
+    /// rate after `delay`. Not confirmed.
+    pub fn activate_bit_timing(&self, delay: Duration) -> io::Result<()> {
+        let ms = delay.as_secs() * 1000 + (delay.subsec_nanos() / 1_000_000) as u64;
+        let ms = if ms > 0xffff { 0xffff } else { ms as u16 };
+        self.send(CS_ACTIVATE_BIT_TIMING, &[ms as u8, (ms >> 8) as u8])
+    }
+
+    /// Store the configured node ID and bit timing in non-volatile memory.
+    pub fn store_configuration(&self) -> Result<(), LssError> {
+        self.configure(CS_STORE_CONFIGURATION, &[])
+    }
+
+    /// Read the LSS address of the slave in configuration state.
+    pub fn inquire_address(&self) -> Result<LssAddress, LssError> {
+        let mut parts = [0u32; 4];
+
+        for (i, part) in parts.iter_mut().enumerate() {
+            let cs = CS_INQUIRE_VENDOR + i as u8;
+            let data = self.request(cs, &[], cs)?;
+            *part = data[1] as u32 | (data[2] as u32) << 8 | (data[3] as u32) << 16 |
+                    (data[4] as u32) << 24;
+        }
+
+        Ok(LssAddress {
+            vendor_id: parts[0],
+            product_code: parts[1],
+            revision: parts[2],
+            serial: parts[3],
+        })
+    }
+
+    /// Read the active node ID of the slave in configuration state.
+    pub fn inquire_node_id(&self) -> Result<u8, LssError> {
+        Ok(self.request(CS_INQUIRE_NODE_ID, &[], CS_INQUIRE_NODE_ID)?[1])
+    }
+}
@@ -0,0 +1,413 @@
+//! PDO mapping
+//!
+//! Every PDO of a node is described by two objects. The communication
+//! parameters (`0x1400` for RPDO1, `0x1800` for TPDO1, one index per PDO)
+//! hold the COB-ID and the transmission type. The mapping parameters
+//! (`0x1600` and `0x1A00`) list the objects whose values make up the PDO
+//! data, each as `index << 16 | subindex << 8 | bits`.
+//!
+//! `configure_pdo` writes a `PdoMapping` to a node in the sequence of
+//! CiA 301: the PDO is disabled through bit 31 of its COB-ID, the mapping
+//! is cleared, rewritten and activated by writing the number of entries,
+//! then the PDO is enabled again. Nodes usually accept mapping changes
+//! only in pre-operational state.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use socketcan::CanSocket;
+//! use socketcan::canopen::{COB_TPDO, SdoClient};
+//! use socketcan::canopen::mapping::{configure_pdo, Pdo, PdoMapping, TRANSMISSION_SYNC};
+//!
+//! let socket = CanSocket::open("can0").unwrap();
+//! let client = SdoClient::new(&socket, Duration::from_millis(100));
+//!
+//! // statusword and position actual value of node 5 on every SYNC
+//! let tpdo = PdoMapping::new(Pdo::Tpdo(1), COB_TPDO[0] + 5, TRANSMISSION_SYNC)
+//!     .map(0x6041, 0, 16)
+//!     .map(0x6064, 0, 32);
+//! configure_pdo(&client, 5, &tpdo).unwrap();
+//! ```
+
+use std::{error, fmt};
+use std::time::Duration;
+use CanTransport;
+use super::od::{AccessType, Entry, ObjectDictionary, Value};
+use super::sdo::{SdoClient, SdoError};
+
+/// Index of the communication parameters of RPDO1
+pub const OBJ_RPDO_COMMUNICATION: u16 = 0x1400;
+
+/// Index of the mapping parameters of RPDO1
+pub const OBJ_RPDO_MAPPING: u16 = 0x1600;
+
+/// Index of the communication parameters of TPDO1
+pub const OBJ_TPDO_COMMUNICATION: u16 = 0x1800;
+
+/// Index of the mapping parameters of TPDO1
+pub const OBJ_TPDO_MAPPING: u16 = 0x1A00;
+
+/// Bit of the COB-ID marking a PDO as disabled
+pub const PDO_DISABLED: u32 = 0x8000_0000;
+
+/// Transmission type of PDOs exchanged on every SYNC. Types 2 to 240 send
+/// on every n-th SYNC.
+pub const TRANSMISSION_SYNC: u8 = 1;
+
+/// Transmission type of event-driven PDOs, with events defined by the
+/// device profile
+pub const TRANSMISSION_EVENT: u8 = 0xff;
+
+/// Highest number of objects mapped into a PDO
+pub const MAX_MAPPED_OBJECTS: usize = 8;
+
+/// Highest PDO number
+pub const MAX_PDO: u16 = 512;
+
+/// Unit of the inhibit time of TPDOs in microseconds
+pub const INHIBIT_TIME_UNIT_US: u64 = 100;
+
+/// A PDO of a node, numbered from 1
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Pdo {
+    /// Receive PDO, master to node
+    Rpdo(u16),
+
+    /// Transmit PDO, node to master
+    Tpdo(u16),
+}
+
+impl Pdo {
+    /// PDO number, starting at 1
+    pub fn number(&self) -> u16 {
+        match *self {
+            Pdo::Rpdo(n) | Pdo::Tpdo(n) => n,
+        }
+    }
+
+    /// Index of the communication parameters
+    pub fn communication_index(&self) -> u16 {
+        match *self {
+            Pdo::Rpdo(n) => OBJ_RPDO_COMMUNICATION + n - 1,
+            Pdo::Tpdo(n) => OBJ_TPDO_COMMUNICATION + n - 1,
+        }
+    }
+
+    /// Index of the mapping parameters
+    pub fn mapping_index(&self) -> u16 {
+        match *self {
+            Pdo::Rpdo(n) => OBJ_RPDO_MAPPING + n - 1,
+            Pdo::Tpdo(n) => OBJ_TPDO_MAPPING + n - 1,
+        }
+    }
+}
+
+/// An object mapped into a PDO
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct MappedObject {
+    /// Index of the object
+    pub index: u16,
+
+    /// Subindex of the object
+    pub subindex: u8,
+
+    /// Length of the value in bits
+    pub bits: u8,
+}
+
+impl MappedObject {
+    /// Value of the mapping entry describing the object
+    pub fn to_u32(&self) -> u32 {
+        (self.index as u32) << 16 | (self.subindex as u32) << 8 | self.bits as u32
+    }
+
+    /// Decode a mapping entry.
+    pub fn from_u32(entry: u32) -> MappedObject {
+        MappedObject {
+            index: (entry >> 16) as u16,
+            subindex: (entry >> 8) as u8,
+            bits: entry as u8,
+        }
+    }
+}
+
+/// Communication and mapping parameters of a PDO
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PdoMapping {
+    /// The PDO to configure
+    pub pdo: Pdo,
+
+    /// COB-ID of the PDO, `PDO_DISABLED` keeps it disabled
+    pub cob_id: u32,
+
+    /// When the PDO is sent or applied, e.g. `TRANSMISSION_SYNC`
+    pub transmission_type: u8,
+
+    /// Mapped objects, in the order of their values in the PDO data
+    pub objects: Vec<MappedObject>,
+}
+
+impl PdoMapping {
+    /// Describe `pdo` without any mapped objects.
+    pub fn new(pdo: Pdo, cob_id: u32, transmission_type: u8) -> PdoMapping {
+        PdoMapping {
+            pdo: pdo,
+            cob_id: cob_id,
+            transmission_type: transmission_type,
+            objects: Vec::new(),
+        }
+    }
+
+    /// Append the object `index`:`subindex` of `bits` length.
+    pub fn map(mut self, index: u16, subindex: u8, bits: u8) -> PdoMapping {
+        self.objects.push(MappedObject {
+                              index: index,
+                              subindex: subindex,
+                              bits: bits,
+                          });
+        self
+    }
+
+    /// Total length of the mapped values in bits
+    pub fn bits(&self) -> u32 {
+        self.objects.iter().map(|o| o.bits as u32).sum()
+    }
+
+    fn validate(&self) -> Result<(), MappingError> {
+        let number = self.pdo.number();
+        if number == 0 || number > MAX_PDO {
+            return Err(MappingError::InvalidPdo(self.pdo));
+        }
+        if self.objects.len() > MAX_MAPPED_OBJECTS || self.bits() > 64 {
+            return Err(MappingError::TooLong(self.bits()));
+        }
+        Ok(())
+    }
+}
+
+/// Timing of an event-driven TPDO
+///
+/// Kept in subindices 3 and 5 of the communication parameters, and used
+/// by `TpdoProducer`. Zero disables either.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct TpdoTiming {
+    /// Shortest time between two transmissions, in units of 100 µs
+    pub inhibit_time: u16,
+
+    /// Time in milliseconds after which the PDO is sent without an event
+    pub event_timer: u16,
+}
+
+impl TpdoTiming {
+    /// Read the timing of `pdo` from `od`, taking missing entries as zero.
+    pub fn from_od(od: &ObjectDictionary, pdo: Pdo) -> TpdoTiming {
+        let u16_entry = |subindex| match od.get(pdo.communication_index(), subindex) {
+            Ok(&Value::Unsigned16(val)) => val,
+            _ => 0,
+        };
+        TpdoTiming {
+            inhibit_time: u16_entry(3),
+            event_timer: u16_entry(5),
+        }
+    }
+
+    /// The inhibit time as a duration
+    pub fn inhibit_duration(&self) -> Duration {
+        Duration::from_micros(self.inhibit_time as u64 * INHIBIT_TIME_UNIT_US)
+    }
+
+    /// The event timer as a duration
+    pub fn event_duration(&self) -> Duration {
+        Duration::from_millis(self.event_timer as u64)
+    }
+}
+
+/// Error configuring a PDO
+#[derive(Debug)]
+pub enum MappingError {
+    /// PDO number outside of 1..512
+    InvalidPdo(Pdo),
+
+    /// The mapped objects do not fit into a PDO, the length in bits is
+    /// given
+    TooLong(u32),
+
+    /// The SDO transfer failed, e.g. with an abort code of `0x06040041`
+    /// if the node cannot map an object
+    Sdo(SdoError),
+}
+
+impl fmt::Display for MappingError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MappingError::InvalidPdo(pdo) => write!(f, "invalid PDO {:?}", pdo),
+            MappingError::TooLong(bits) => write!(f, "mapping too long ({} bits)", bits),
+            MappingError::Sdo(ref e) => write!(f, "SDO: {}", e),
+        }
+    }
+}
+
+impl error::Error for MappingError {
+    fn description(&self) -> &str {
+        match *self {
+            MappingError::InvalidPdo(_) => "invalid pdo",
+            MappingError::TooLong(_) => "mapping too long",
+            MappingError::Sdo(ref e) => e.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            MappingError::Sdo(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<SdoError> for MappingError {
+    fn from(e: SdoError) -> MappingError {
+        MappingError::Sdo(e)
+    }
+}
+
+/// Write the communication and mapping parameters of `mapping` to node
+/// `node_id`.
+///
+/// The PDO is disabled while its mapping changes and enabled afterwards,
+/// unless the COB-ID of `mapping` has `PDO_DISABLED` set.
+pub fn configure_pdo<T>(client: &SdoClient<T>,
+                        node_id: u8,
+                        mapping: &PdoMapping)
+                        -> Result<(), MappingError>
+    where T: CanTransport
+{
+    mapping.validate()?;
+    let communication = mapping.pdo.communication_index();
+    let index = mapping.pdo.mapping_index();
+
+    client.write_u32(node_id, communication, 1, mapping.cob_id | PDO_DISABLED)?;
+    client.write_u8(node_id, communication, 2, mapping.transmission_type)?;
+
+    client.write_u8(node_id, index, 0, 0)?;
+    for (subindex, object) in mapping.objects.iter().enumerate() {
+        client.write_u32(node_id, index, subindex as u8 + 1, object.to_u32())?;
+    }
+    client.write_u8(node_id, index, 0, mapping.objects.len() as u8)?;
+
+    client.write_u32(node_id, communication, 1, mapping.cob_id)?;
+    Ok(())
+}
+
+/// Read the communication and mapping parameters of `pdo` from node
+/// `node_id`.
+pub fn read_pdo<T>(client: &SdoClient<T>, node_id: u8, pdo: Pdo) -> Result<PdoMapping, MappingError>
+    where T: CanTransport
+{
+    let communication = pdo.communication_index();
+    let index = pdo.mapping_index();
+
+    let mut mapping = PdoMapping::new(pdo,
+                                      client.read_u32(node_id, communication, 1)?,
+                                      client.read_u8(node_id, communication, 2)?);
+    for subindex in 1..client.read_u8(node_id, index, 0)? + 1 {
+        let entry = client.read_u32(node_id, index, subindex)?;
+        mapping.objects.push(MappedObject::from_u32(entry));
+    }
+    Ok(mapping)
+}
+
+/// Add the communication and mapping parameters of `pdo` to `od`, as a
+/// node implementing it provides them: disabled, event-driven and without
+/// mapped objects. TPDOs also get an inhibit time and an event timer,
+/// both zero.
+pub fn insert_pdo_objects(od: &mut ObjectDictionary, pdo: Pdo) {
+    let communication = pdo.communication_index();
+    let index = pdo.mapping_index();
+    let read_write = |value| Entry::new(value, AccessType::ReadWrite);
+
+    let highest = match pdo {
+        Pdo::Rpdo(_) => 2,
+        Pdo::Tpdo(_) => {
+            od.insert(communication, 3, read_write(Value::Unsigned16(0)));
+            od.insert(communication, 5, read_write(Value::Unsigned16(0)));
+            5
+        }
+    };
+    od.insert(communication, 0, Entry::new(Value::Unsigned8(highest), AccessType::Const));
+    od.insert(communication, 1, read_write(Value::Unsigned32(PDO_DISABLED)));
+    od.insert(communication, 2, read_write(Value::Unsigned8(TRANSMISSION_EVENT)));
+
+    let count = read_write(Value::Unsigned8(0))
+        .with_limits(Value::Unsigned8(0), Value::Unsigned8(MAX_MAPPED_OBJECTS as u8));
+    od.insert(index, 0, count);
+    for subindex in 1..MAX_MAPPED_OBJECTS as u8 + 1 {
+        od.insert(index, subindex, read_write(Value::Unsigned32(0)));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+    use canopen::{ObjectDictionary, SdoClient, SdoServer, Value, COB_RPDO, COB_TPDO};
+    use testing::MockBus;
+    use super::{configure_pdo, insert_pdo_objects, read_pdo, MappedObject, MappingError, Pdo,
+                PdoMapping, TpdoTiming, TRANSMISSION_SYNC};
+
+    #[test]
+    fn test_mapping() {
+        assert_eq!(Pdo::Rpdo(2).communication_index(), 0x1401);
+        assert_eq!(Pdo::Tpdo(1).mapping_index(), 0x1A00);
+
+        let object = MappedObject::from_u32(0x60410010);
+        assert_eq!((object.index, object.subindex, object.bits), (0x6041, 0, 16));
+        assert_eq!(object.to_u32(), 0x60410010);
+
+        let mut od = ObjectDictionary::new();
+        assert_eq!(TpdoTiming::from_od(&od, Pdo::Tpdo(2)), TpdoTiming::default());
+        insert_pdo_objects(&mut od, Pdo::Tpdo(2));
+        od.set(0x1801, 3, Value::Unsigned16(15)).unwrap();
+        od.set(0x1801, 5, Value::Unsigned16(100)).unwrap();
+        let timing = TpdoTiming::from_od(&od, Pdo::Tpdo(2));
+        assert_eq!(timing.inhibit_duration(), Duration::from_micros(1500));
+        assert_eq!(timing.event_duration(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn test_configure_pdo() {
+        let bus = MockBus::new();
+        let (socket, server) = (bus.endpoint(), bus.endpoint());
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let flag = shutdown.clone();
+        let responder = thread::spawn(move || {
+            let mut od = ObjectDictionary::new();
+            insert_pdo_objects(&mut od, Pdo::Tpdo(1));
+            insert_pdo_objects(&mut od, Pdo::Rpdo(1));
+            SdoServer::new(5).unwrap().serve(&server, &mut od, &flag).unwrap();
+        });
+        let client = SdoClient::new(&socket, Duration::from_secs(5));
+
+        let tpdo = PdoMapping::new(Pdo::Tpdo(1), COB_TPDO[0] + 5, TRANSMISSION_SYNC)
+            .map(0x6041, 0, 16)
+            .map(0x6064, 0, 32);
+        configure_pdo(&client, 5, &tpdo).unwrap();
+        assert_eq!(read_pdo(&client, 5, Pdo::Tpdo(1)).unwrap(), tpdo);
+
+        let mut rpdo = PdoMapping::new(Pdo::Rpdo(1), COB_RPDO[0] + 5, TRANSMISSION_SYNC);
+        for subindex in 0..9 {
+            rpdo = rpdo.map(0x2000, subindex, 1);
+        }
+        match configure_pdo(&client, 5, &rpdo) {
+            Err(MappingError::TooLong(9)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match configure_pdo(&client, 5, &PdoMapping::new(Pdo::Rpdo(2), 0x305, 1)) {
+            Err(MappingError::Sdo(_)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        shutdown.store(true, Ordering::SeqCst);
+        responder.join().unwrap();
+    }
+}
@@ -0,0 +1,315 @@
+//! Decoded CANopen messages
+//!
+//! `Message::from_frame` tells the services of the predefined connection
+//! set apart by their COB-IDs and decodes the frame accordingly. PDOs and
+//! SDOs are left as frames, as their contents depend on the mapping and
+//! the state of the transfer.
+//!
+//! With the `tokio` feature, a `MessageStream` reads decoded messages from
+//! an `AsyncCanTransport`, so a task can match on the messages of all
+//! nodes instead of running its own read loop.
+
+#[cfg(feature = "tokio")]
+use std::io;
+#[cfg(feature = "tokio")]
+use std::future::Future;
+#[cfg(feature = "tokio")]
+use std::pin::Pin;
+#[cfg(feature = "tokio")]
+use std::task::{Context, Poll};
+use CanFrame;
+#[cfg(feature = "tokio")]
+use async_transport::AsyncCanTransport;
+use super::{check_data_frame, split_cob_id, Emcy, FrameError, Heartbeat, NmtMessage,
+            TimeOfDay, COB_EMCY, COB_HEARTBEAT, COB_NMT, COB_RPDO, COB_SDO_RX, COB_SDO_TX,
+            COB_SYNC, COB_TIME, COB_TPDO};
+
+/// A CANopen message of the predefined connection set
+#[derive(Debug, Copy, Clone)]
+pub enum Message {
+    /// NMT command
+    Nmt(NmtMessage),
+
+    /// SYNC, carrying the counter if the producer has one
+    Sync(Option<u8>),
+
+    /// Emergency object
+    Emcy(Emcy),
+
+    /// TIME stamp object
+    Time(TimeOfDay),
+
+    /// One of the four default TPDOs of a node, numbered from 1
+    Tpdo {
+        node_id: u8,
+        pdo: u8,
+        frame: CanFrame,
+    },
+
+    /// One of the four default RPDOs of a node, numbered from 1
+    Rpdo {
+        node_id: u8,
+        pdo: u8,
+        frame: CanFrame,
+    },
+
+    /// SDO response of the server of a node
+    SdoResponse { node_id: u8, frame: CanFrame },
+
+    /// SDO request to the server of a node
+    SdoRequest { node_id: u8, frame: CanFrame },
+
+    /// Heartbeat or boot-up message
+    Heartbeat(Heartbeat),
+
+    /// A standard data frame of no service above, e.g. LSS
+    Other(CanFrame),
+}
+
+impl Message {
+    /// Decode `frame` as the service its COB-ID belongs to.
+    ///
+    /// Fails with `FrameError::UnexpectedFrameType` for extended, RTR and
+    /// error frames, and with the error of the service if the frame does
+    /// not decode.
+    pub fn from_frame(frame: &CanFrame) -> Result<Message, FrameError> {
+        check_data_frame(frame)?;
+
+        let (function, node_id) = split_cob_id(frame.id());
+        let pdo = |functions: &[u32; 4]| functions.iter().position(|&f| f == function);
+        let message = match frame.id() {
+            COB_NMT => Message::Nmt(NmtMessage::from_frame(frame)?),
+            COB_SYNC => Message::Sync(frame.data().first().cloned()),
+            COB_TIME => Message::Time(TimeOfDay::from_frame(frame)?),
+            _ if node_id == 0 => Message::Other(*frame),
+            _ if function == COB_EMCY => Message::Emcy(Emcy::from_frame(frame)?),
+            _ if function == COB_HEARTBEAT => Message::Heartbeat(Heartbeat::from_frame(frame)?),
+            _ if function == COB_SDO_TX => {
+                Message::SdoResponse {
+                    node_id: node_id,
+                    frame: *frame,
+                }
+            }
+            _ if function == COB_SDO_RX => {
+                Message::SdoRequest {
+                    node_id: node_id,
+                    frame: *frame,
+                }
+            }
+            _ => {
+                match (pdo(&COB_TPDO), pdo(&COB_RPDO)) {
+                    (Some(n), _) => {
+                        Message::Tpdo {
+                            node_id: node_id,
+                            pdo: n as u8 + 1,
+                            frame: *frame,
+                        }
+                    }
+                    (_, Some(n)) => {
+                        Message::Rpdo {
+                            node_id: node_id,
+                            pdo: n as u8 + 1,
+                            frame: *frame,
+                        }
+                    }
+                    _ => Message::Other(*frame),
+                }
+            }
+        };
+        Ok(message)
+    }
+
+    /// The node the message was sent by or is addressed to, `None` for
+    /// broadcasts and `Other` frames
+    pub fn node_id(&self) -> Option<u8> {
+        match *self {
+            Message::Nmt(ref nmt) => nmt.node_id,
+            Message::Emcy(ref emcy) => Some(emcy.node_id),
+            Message::Heartbeat(ref heartbeat) => Some(heartbeat.node_id),
+            Message::Tpdo { node_id, .. } |
+            Message::Rpdo { node_id, .. } |
+            Message::SdoResponse { node_id, .. } |
+            Message::SdoRequest { node_id, .. } => Some(node_id),
+            Message::Sync(_) | Message::Time(_) | Message::Other(_) => None,
+        }
+    }
+}
+
+/// Decode a received frame for `MessageStream`, `None` if it is no
+/// CANopen frame at all.
+#[cfg(feature = "tokio")]
+fn decode(frame: CanFrame) -> Option<io::Result<Message>> {
+    match Message::from_frame(&frame) {
+        Ok(message) => Some(Ok(message)),
+        Err(FrameError::UnexpectedFrameType) => None,
+        Err(e) => Some(Err(io::Error::new(io::ErrorKind::InvalidData, e))),
+    }
+}
+
+/// Stream of the CANopen messages read from an asynchronous transport
+///
+/// `poll_next` has the signature of `futures::Stream::poll_next`, so the
+/// stream plugs into the combinators of `futures` through a one-line
+/// `Stream` implementation. Extended, RTR and error frames are skipped,
+/// frames that do not decode as their service are yielded as
+/// `InvalidData` errors, as are read errors. The stream does not end on
+/// errors; it never ends.
+///
+/// ```no_run
+/// extern crate socketcan;
+/// extern crate tokio;
+///
+/// use socketcan::async_transport::AsyncCanSocket;
+/// use socketcan::canopen::{Message, MessageStream};
+///
+/// fn main() {
+///     let rt = tokio::runtime::Builder::new_current_thread().enable_io().build().unwrap();
+///     let _runtime = rt.enter();
+///
+///     let mut messages = MessageStream::new(AsyncCanSocket::open("can0").unwrap());
+///     while let Some(message) = rt.block_on(messages.next()) {
+///         match message {
+///             Ok(Message::Emcy(emcy)) => println!("EMCY {:04X}", emcy.error_code),
+///             Ok(message) if message.node_id() == Some(5) => println!("{:?}", message),
+///             Ok(_) => {}
+///             Err(e) => println!("{}", e),
+///         }
+///     }
+/// }
+/// ```
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct MessageStream<T> {
+    transport: T,
+}
+
+#[cfg(feature = "tokio")]
+impl<T: AsyncCanTransport> MessageStream<T> {
+    /// Decode the frames read from `transport`.
+    pub fn new(transport: T) -> MessageStream<T> {
+        MessageStream { transport: transport }
+    }
+
+    /// The underlying transport, e.g. to send
+    pub fn get_ref(&self) -> &T {
+        &self.transport
+    }
+
+    /// Stop decoding, returning the transport.
+    pub fn into_inner(self) -> T {
+        self.transport
+    }
+
+    /// Take the next message if one was received.
+    ///
+    /// Otherwise returns `Poll::Pending` and wakes the task of `cx` once a
+    /// frame arrives.
+    pub fn poll_next(&mut self, cx: &mut Context) -> Poll<Option<io::Result<Message>>> {
+        loop {
+            let frame = match self.transport.poll_read_frame(cx) {
+                Poll::Ready(Ok(frame)) => frame,
+                Poll::Ready(Err(e)) => return Poll::Ready(Some(Err(e))),
+                Poll::Pending => return Poll::Pending,
+            };
+            if let Some(message) = decode(frame) {
+                return Poll::Ready(Some(message));
+            }
+        }
+    }
+
+    /// Wait for the next message.
+    pub fn next(&mut self) -> Next<T> {
+        Next { stream: self }
+    }
+}
+
+/// Future returned by `MessageStream::next`
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct Next<'a, T: 'a> {
+    stream: &'a mut MessageStream<T>,
+}
+
+#[cfg(feature = "tokio")]
+impl<'a, T: AsyncCanTransport> Future for Next<'a, T> {
+    type Output = Option<io::Result<Message>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<io::Result<Message>>> {
+        self.get_mut().stream.poll_next(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use CanFrame;
+    use canopen::{FrameError, NmtCommand};
+    use super::Message;
+
+    fn frame(id: u32, data: &[u8]) -> CanFrame {
+        CanFrame::new(id, data, false, false).unwrap()
+    }
+
+    #[test]
+    fn test_decode() {
+        match Message::from_frame(&frame(0x000, &[0x01, 0x05])).unwrap() {
+            Message::Nmt(nmt) => {
+                assert_eq!((nmt.command, nmt.node_id), (NmtCommand::Start, Some(5)))
+            }
+            m => panic!("unexpected {:?}", m),
+        }
+        match Message::from_frame(&frame(0x080, &[3])).unwrap() {
+            Message::Sync(Some(3)) => {}
+            m => panic!("unexpected {:?}", m),
+        }
+        match Message::from_frame(&frame(0x3a5, &[1, 2])).unwrap() {
+            m @ Message::Tpdo { pdo: 3, .. } => assert_eq!(m.node_id(), Some(0x25)),
+            m => panic!("unexpected {:?}", m),
+        }
+        match Message::from_frame(&frame(0x605, &[0x40, 0x00, 0x10, 0, 0, 0, 0, 0])).unwrap() {
+            Message::SdoRequest { node_id: 5, .. } => {}
+            m => panic!("unexpected {:?}", m),
+        }
+        match Message::from_frame(&frame(0x7e5, &[0x04, 0x00])).unwrap() {
+            m @ Message::Other(_) => assert_eq!(m.node_id(), None),
+            m => panic!("unexpected {:?}", m),
+        }
+
+        match Message::from_frame(&frame(0x705, &[])) {
+            Err(FrameError::NotEnoughData(0)) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+        match Message::from_frame(&frame(0x12345, &[])) {
+            Err(FrameError::UnexpectedFrameType) => {}
+            r => panic!("unexpected {:?}", r),
+        }
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_stream() {
+        use tokio::runtime::Builder;
+        use testing::MockBus;
+        use super::MessageStream;
+
+        let rt = Builder::new_current_thread().build().unwrap();
+        let bus = MockBus::new();
+        let peer = bus.endpoint();
+        let mut messages = MessageStream::new(bus.endpoint());
+
+        peer.write_frame(&frame(0x12345, &[])).unwrap();
+        peer.write_frame(&frame(0x705, &[0x05])).unwrap();
+        peer.write_frame(&frame(0x085, &[0])).unwrap();
+        peer.write_frame(&frame(0x185, &[1, 2])).unwrap();
+
+        match rt.block_on(messages.next()).unwrap().unwrap() {
+            Message::Heartbeat(heartbeat) => assert_eq!(heartbeat.node_id, 5),
+            m => panic!("unexpected {:?}", m),
+        }
+        let err = rt.block_on(messages.next()).unwrap().unwrap_err();
+        assert_eq!(err.kind(), ::std::io::ErrorKind::InvalidData);
+        match rt.block_on(messages.next()).unwrap().unwrap() {
+            Message::Tpdo { node_id: 5, pdo: 1, frame } => assert_eq!(frame.data(), &[1, 2]),
+            m => panic!("unexpected {:?}", m),
+        }
+    }
+}
@@ -0,0 +1,376 @@
+//! CANopen support
+//!
+//! Frame-level building blocks for the CANopen application layer (CiA 301).
+//! Every CANopen object is transported in a standard frame whose 11-bit
+//! COB-ID is composed of a 4-bit function code and a 7-bit node ID:
+//!
+//! ```text
+//!  10  9  8  7  6  5  4  3  2  1  0
+//! | function  |       node ID       |
+//! ```
+//!
+//! The types in this module convert between `CanFrame`s and typed protocol
+//! objects; sending and receiving is left to a `CanSocket` or any other
+//! `CanTransport`.
+//!
+//! Kernel filters for a service of all nodes are built by
+//! `function_filter` and `all_nodes_filters`:
+//!
+//! ```no_run
+//! use socketcan::CanSocket;
+//! use socketcan::canopen::{all_nodes_filters, COB_EMCY, COB_HEARTBEAT};
+//!
+//! let socket = CanSocket::open("can0").unwrap();
+//! let mut filters = all_nodes_filters(COB_HEARTBEAT);
+//! filters.extend(all_nodes_filters(COB_EMCY));
+//! socket.set_filters(&filters).unwrap();
+//! ```
+//!
+//! `NodeFilters` builds the filters for the services of selected nodes
+//! instead, so the traffic of other nodes never reaches userspace:
+//!
+//! ```no_run
+//! use socketcan::CanSocket;
+//! use socketcan::canopen::NodeFilters;
+//!
+//! let socket = CanSocket::open("can0").unwrap();
+//! let filters = NodeFilters::new().nodes(&[5, 6]).tpdos().sdo().emcy().build().unwrap();
+//! socket.set_filters(&filters).unwrap();
+//! ```
+
+use std::{error, fmt};
+use std::collections::BTreeSet;
+use {CanFilter, CanFrame, CanIdFlags, ConstructionError, SFF_MASK};
+
+pub mod boot;
+pub mod emcy;
+pub mod guarding;
+pub mod heartbeat;
+pub mod lss;
+pub mod mapping;
+pub mod message;
+pub mod nmt;
+pub mod node;
+pub mod od;
+pub mod pdo;
+pub mod program;
+pub mod redundancy;
+pub mod scan;
+pub mod sdo;
+pub mod sim;
+pub mod store;
+pub mod time;
+
+pub use self::emcy::{send_emcy, Emcy};
+pub use self::guarding::{GuardError, NodeGuard};
+pub use self::heartbeat::{Heartbeat, HeartbeatConsumer, HeartbeatEvent, HeartbeatProducer,
+                          NmtState};
+pub use self::lss::{BitRate, LssAddress, LssError, LssMaster, LssMode};
+pub use self::message::Message;
+#[cfg(feature = "tokio")]
+pub use self::message::MessageStream;
+pub use self::nmt::{NmtCommand, NmtMaster, NmtMessage, NodeStatus};
+pub use self::node::NodeHandle;
+pub use self::od::{AccessType, DataType, Entry, ObjectDictionary, OdError, Value};
+pub use self::pdo::{PdoExchange, PdoInput, ProcessImage, SyncControl, TpdoProducer};
+pub use self::program::{flash_node, ProgramControl};
+pub use self::redundancy::{FlyingMaster, MasterEvent, MasterState};
+pub use self::scan::{scan_network, Identity, ScannedNode, Scanner};
+pub use self::sdo::{SdoClient, SdoControlByte, SdoError, SdoServer};
+#[cfg(feature = "tokio")]
+pub use self::sdo::AsyncSdoClient;
+pub use self::store::{restore_defaults, store_parameters, ParameterGroup, StoreError};
+pub use self::time::{send_time, TimeOfDay};
+
+/// Highest valid node ID
+pub const MAX_NODE_ID: u8 = 127;
+
+/// Mask selecting the node ID part of a COB-ID
+pub const NODE_ID_MASK: u32 = 0x07f;
+
+/// Mask selecting the function code part of a COB-ID
+pub const FUNCTION_MASK: u32 = 0x780;
+
+/// COB-ID of NMT commands
+pub const COB_NMT: u32 = 0x000;
+
+/// COB-ID of the TIME stamp object
+pub const COB_TIME: u32 = 0x100;
+
+/// Function code of emergency objects (EMCY, `0x080 + node`)
+pub const COB_EMCY: u32 = 0x080;
+
+/// COB-ID of the SYNC object
+pub const COB_SYNC: u32 = 0x080;
+
+/// Function codes of the four default transmit PDOs (node to master,
+/// `0x180 + node` for TPDO1)
+pub const COB_TPDO: [u32; 4] = [0x180, 0x280, 0x380, 0x480];
+
+/// Function codes of the four default receive PDOs (master to node,
+/// `0x200 + node` for RPDO1)
+pub const COB_RPDO: [u32; 4] = [0x200, 0x300, 0x400, 0x500];
+
+/// Function code of SDO responses (server to client, `0x580 + node`)
+pub const COB_SDO_TX: u32 = 0x580;
+
+/// Function code of SDO requests (client to server, `0x600 + node`)
+pub const COB_SDO_RX: u32 = 0x600;
+
+/// Function code of heartbeat and boot-up messages (`0x700 + node`)
+pub const COB_HEARTBEAT: u32 = 0x700;
+
+/// Kernel filter accepting the COB-ID `cob_id`, e.g. `COB_SYNC`
+///
+/// Only standard data frames pass.
+pub fn cob_id_filter(cob_id: u32) -> CanFilter {
+    CanFilter::with_flags(cob_id, SFF_MASK, CanIdFlags::empty()).expect("valid filter")
+}
+
+/// Kernel filter accepting the function code `function` with any node ID,
+/// e.g. `function_filter(COB_TPDO[0])` for the TPDO1s of all nodes
+///
+/// The filter also passes node ID 0, which is not a node but a COB-ID of
+/// its own for some function codes: `COB_EMCY` shares its function code
+/// with `COB_SYNC`. Use `all_nodes_filters` to exclude it. Only standard
+/// data frames pass.
+pub fn function_filter(function: u32) -> CanFilter {
+    CanFilter::with_flags(function & FUNCTION_MASK, FUNCTION_MASK, CanIdFlags::empty())
+        .expect("valid filter")
+}
+
+/// Kernel filters accepting the function code `function` with node IDs
+/// 1..127
+///
+/// A single filter cannot exclude node ID 0, so this returns seven, one
+/// per bit of the node ID: the first passes node 1, the second nodes 2 and
+/// 3, the third nodes 4 to 7 and so on. Install them together, without
+/// join.
+pub fn all_nodes_filters(function: u32) -> Vec<CanFilter> {
+    (0..7)
+        .map(|bit| {
+            let mask = FUNCTION_MASK | NODE_ID_MASK & !((1 << bit) - 1);
+            CanFilter::with_flags(function & FUNCTION_MASK | 1 << bit,
+                                  mask,
+                                  CanIdFlags::empty())
+                .expect("valid filter")
+        })
+        .collect()
+}
+
+/// Builder of kernel filters passing selected services of selected nodes
+///
+/// Every service of every node takes one filter, so keep the product of
+/// the two below the kernel limit of 512 filters per socket.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct NodeFilters {
+    nodes: BTreeSet<u8>,
+    functions: BTreeSet<u32>,
+}
+
+impl NodeFilters {
+    /// Filters passing nothing yet
+    pub fn new() -> NodeFilters {
+        NodeFilters::default()
+    }
+
+    /// Pass the services of `node_id`.
+    pub fn node(mut self, node_id: u8) -> NodeFilters {
+        self.nodes.insert(node_id);
+        self
+    }
+
+    /// Pass the services of all of `node_ids`.
+    pub fn nodes(mut self, node_ids: &[u8]) -> NodeFilters {
+        self.nodes.extend(node_ids);
+        self
+    }
+
+    /// Pass the service with function code `function`, e.g. `COB_TPDO[0]`.
+    pub fn function(mut self, function: u32) -> NodeFilters {
+        self.functions.insert(function & FUNCTION_MASK);
+        self
+    }
+
+    /// Pass the four default TPDOs, sent by the nodes.
+    pub fn tpdos(self) -> NodeFilters {
+        COB_TPDO.iter().fold(self, |filters, &function| filters.function(function))
+    }
+
+    /// Pass the four default RPDOs, received by the nodes.
+    pub fn rpdos(self) -> NodeFilters {
+        COB_RPDO.iter().fold(self, |filters, &function| filters.function(function))
+    }
+
+    /// Pass SDO requests and responses.
+    pub fn sdo(self) -> NodeFilters {
+        self.function(COB_SDO_RX).function(COB_SDO_TX)
+    }
+
+    /// Pass heartbeat, boot-up and node guarding messages.
+    pub fn heartbeat(self) -> NodeFilters {
+        self.function(COB_HEARTBEAT)
+    }
+
+    /// Pass emergency messages.
+    pub fn emcy(self) -> NodeFilters {
+        self.function(COB_EMCY)
+    }
+
+    /// Pass all the services above.
+    pub fn all(self) -> NodeFilters {
+        self.tpdos().rpdos().sdo().heartbeat().emcy()
+    }
+
+    /// Build one filter per service and node.
+    ///
+    /// Fails if a node ID is outside of 1..127.
+    pub fn build(&self) -> Result<Vec<CanFilter>, FrameError> {
+        let mut filters = Vec::with_capacity(self.nodes.len() * self.functions.len());
+        for &node_id in &self.nodes {
+            check_node_id(node_id)?;
+            for &function in &self.functions {
+                filters.push(cob_id_filter(function + node_id as u32));
+            }
+        }
+        Ok(filters)
+    }
+}
+
+/// Check that `node_id` is within the valid range of 1..127.
+#[inline]
+pub fn check_node_id(node_id: u8) -> Result<u8, FrameError> {
+    if node_id == 0 || node_id > MAX_NODE_ID {
+        return Err(FrameError::InvalidNodeId(node_id));
+    }
+    Ok(node_id)
+}
+
+/// Split a COB-ID into function code and node ID.
+#[inline]
+pub fn split_cob_id(cob_id: u32) -> (u32, u8) {
+    (cob_id & FUNCTION_MASK, (cob_id & NODE_ID_MASK) as u8)
+}
+
+/// Ensure `frame` is a standard data frame, as used by all CANopen objects.
+#[inline]
+fn check_data_frame(frame: &CanFrame) -> Result<(), FrameError> {
+    if frame.is_extended() || frame.is_rtr() || frame.is_error() {
+        return Err(FrameError::UnexpectedFrameType);
+    }
+    Ok(())
+}
+
+/// Error converting between `CanFrame`s and CANopen objects
+#[derive(Copy, Clone, Debug)]
+pub enum FrameError {
+    /// Node ID was not in the range of 1..127
+    InvalidNodeId(u8),
+
+    /// The COB-ID of the frame does not belong to the requested object
+    UnexpectedCobId(u32),
+
+    /// The frame carried fewer bytes than the object requires
+    NotEnoughData(usize),
+
+    /// The command specifier of the frame is not known
+    InvalidCommand(u8),
+
+    /// An extended, RTR or error frame was passed where a standard data
+    /// frame was expected
+    UnexpectedFrameType,
+
+    /// The resulting frame could not be constructed
+    Construction(ConstructionError),
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FrameError::InvalidNodeId(id) => write!(f, "invalid node ID {}", id),
+            FrameError::UnexpectedCobId(id) => write!(f, "unexpected COB-ID {:03X}", id),
+            FrameError::NotEnoughData(n) => write!(f, "not enough data ({} bytes)", n),
+            FrameError::InvalidCommand(c) => write!(f, "invalid command specifier {:02X}", c),
+            FrameError::Construction(e) => write!(f, "{}", e),
+            _ => write!(f, "{}", error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for FrameError {
+    fn description(&self) -> &str {
+        match *self {
+            FrameError::InvalidNodeId(_) => "invalid node id",
+            FrameError::UnexpectedCobId(_) => "unexpected cob-id",
+            FrameError::NotEnoughData(_) => "not enough data",
+            FrameError::InvalidCommand(_) => "invalid command specifier",
+            FrameError::UnexpectedFrameType => "unexpected frame type",
+            FrameError::Construction(ref e) => e.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            FrameError::Construction(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<ConstructionError> for FrameError {
+    fn from(e: ConstructionError) -> FrameError {
+        FrameError::Construction(e)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use CanFrame;
+    use filter::filter_matches;
+    use super::{all_nodes_filters, cob_id_filter, function_filter, NodeFilters, COB_EMCY,
+                COB_SYNC, COB_TPDO};
+
+    #[test]
+    fn test_filters() {
+        let frame = |id| CanFrame::new(id, &[], false, false).unwrap();
+        let passes = |filters: &[_], id| filters.iter().any(|f| filter_matches(f, &frame(id)));
+
+        let tpdo1 = [function_filter(COB_TPDO[0])];
+        assert!(passes(&tpdo1, 0x185) && passes(&tpdo1, 0x1ff));
+        assert!(!passes(&tpdo1, 0x285) && !passes(&tpdo1, 0x205));
+
+        let rtr = CanFrame::new(0x185, &[], true, false).unwrap();
+        assert!(!filter_matches(&tpdo1[0], &rtr));
+        let ext = CanFrame::new(0x1000185, &[], false, false).unwrap();
+        assert!(!filter_matches(&tpdo1[0], &ext));
+
+        let emcy = all_nodes_filters(COB_EMCY);
+        assert!(!passes(&emcy, COB_SYNC));
+        assert!((0x081..0x100).all(|id| passes(&emcy, id)));
+        assert!(!passes(&emcy, 0x100) && !passes(&emcy, 0x181));
+
+        assert!(passes(&[cob_id_filter(COB_SYNC)], COB_SYNC));
+        assert!(!passes(&[cob_id_filter(COB_SYNC)], 0x081));
+    }
+
+    #[test]
+    fn test_node_filters() {
+        let frame = |id| CanFrame::new(id, &[], false, false).unwrap();
+        let passes = |filters: &[_], id| filters.iter().any(|f| filter_matches(f, &frame(id)));
+
+        let filters = NodeFilters::new().nodes(&[5, 6]).tpdos().sdo().emcy().build().unwrap();
+        assert_eq!(filters.len(), 2 * 7);
+        for &id in &[0x185, 0x486, 0x585, 0x606, 0x085, 0x086] {
+            assert!(passes(&filters, id), "{:03X} blocked", id);
+        }
+        for &id in &[0x187, 0x205, 0x705, COB_SYNC, 0x000] {
+            assert!(!passes(&filters, id), "{:03X} passed", id);
+        }
+
+        let all = NodeFilters::new().node(127).all().build().unwrap();
+        assert_eq!(all.len(), 12);
+        assert!(passes(&all, 0x77f) && passes(&all, 0x27f) && !passes(&all, 0x77e));
+        assert!(NodeFilters::new().node(128).heartbeat().build().is_err());
+        assert!(NodeFilters::new().node(5).build().unwrap().is_empty());
+    }
+}
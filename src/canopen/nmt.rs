@@ -0,0 +1,227 @@
+//! Network management (NMT)
+//!
+//! NMT commands are sent by the NMT master on COB-ID `0x000`. The two byte
+//! payload contains the command specifier and the addressed node, where
+//! node `0` addresses all nodes at once.
+//!
+//! Nodes report their state through heartbeat (or node guarding) messages;
+//! `NmtMaster` collects these into a map of node states.
+
+use std::collections::hash_map::{self, HashMap};
+use std::io;
+use std::time::Instant;
+use {CanFrame, CanTransport};
+use super::{check_data_frame, check_node_id, FrameError, Heartbeat, NmtState, COB_NMT};
+
+/// NMT command specifier
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum NmtCommand {
+    /// Enter operational state
+    Start,
+
+    /// Enter stopped state
+    Stop,
+
+    /// Enter pre-operational state
+    EnterPreOperational,
+
+    /// Reset the application
+    ResetNode,
+
+    /// Reset communication parameters
+    ResetCommunication,
+}
+
+impl NmtCommand {
+    /// Decode a command specifier.
+    pub fn from_byte(val: u8) -> Option<NmtCommand> {
+        match val {
+            0x01 => Some(NmtCommand::Start),
+            0x02 => Some(NmtCommand::Stop),
+            0x80 => Some(NmtCommand::EnterPreOperational),
+            0x81 => Some(NmtCommand::ResetNode),
+            0x82 => Some(NmtCommand::ResetCommunication),
+            _ => None,
+        }
+    }
+
+    /// The command specifier byte
+    pub fn as_byte(&self) -> u8 {
+        match *self {
+            NmtCommand::Start => 0x01,
+            NmtCommand::Stop => 0x02,
+            NmtCommand::EnterPreOperational => 0x80,
+            NmtCommand::ResetNode => 0x81,
+            NmtCommand::ResetCommunication => 0x82,
+        }
+    }
+}
+
+/// An NMT command addressed to one or all nodes.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NmtMessage {
+    /// Command to execute
+    pub command: NmtCommand,
+
+    /// Addressed node, `None` for a broadcast to all nodes
+    pub node_id: Option<u8>,
+}
+
+impl NmtMessage {
+    /// Create a new NMT message, validating the node ID.
+    pub fn new(command: NmtCommand, node_id: Option<u8>) -> Result<NmtMessage, FrameError> {
+        if let Some(id) = node_id {
+            check_node_id(id)?;
+        }
+
+        Ok(NmtMessage {
+            command: command,
+            node_id: node_id,
+        })
+    }
+
+    /// Decode an NMT message.
+    pub fn from_frame(frame: &CanFrame) -> Result<NmtMessage, FrameError> {
+        check_data_frame(frame)?;
+
+        if frame.id() != COB_NMT {
+            return Err(FrameError::UnexpectedCobId(frame.id()));
+        }
+
+        let data = frame.data();
+        if data.len() < 2 {
+            return Err(FrameError::NotEnoughData(data.len()));
+        }
+
+        let command = NmtCommand::from_byte(data[0])
+            .ok_or(FrameError::InvalidCommand(data[0]))?;
+        let node_id = if data[1] == 0 { None } else { Some(data[1]) };
+
+        NmtMessage::new(command, node_id)
+    }
+
+    /// Encode the NMT message into a frame.
+    pub fn to_frame(&self) -> Result<CanFrame, FrameError> {
+        let node = match self.node_id {
+            Some(id) => check_node_id(id)?,
+            None => 0,
+        };
+
+        Ok(CanFrame::new(COB_NMT, &[self.command.as_byte(), node], false, false)?)
+    }
+}
+
+/// Last state reported by a node
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct NodeStatus {
+    /// Reported NMT state
+    pub state: NmtState,
+
+    /// Time the state was last reported
+    pub last_seen: Instant,
+}
+
+/// NMT master
+///
+/// Sends NMT commands and keeps track of the states reported by the nodes
+/// on the network. All received frames should be passed to
+/// `process_frame`; boot-up and heartbeat messages update the state map,
+/// everything else is ignored.
+#[derive(Debug, Default)]
+pub struct NmtMaster {
+    nodes: HashMap<u8, NodeStatus>,
+}
+
+impl NmtMaster {
+    /// Create a master that has not seen any nodes yet.
+    pub fn new() -> NmtMaster {
+        NmtMaster { nodes: HashMap::new() }
+    }
+
+    /// Send `command` to `node_id`, or all nodes if `None`.
+    pub fn send<T: CanTransport>(&self,
+                                 socket: &T,
+                command: NmtCommand,
+                node_id: Option<u8>)
+                -> io::Result<()> {
+        let frame = NmtMessage::new(command, node_id)
+            .and_then(|msg| msg.to_frame())
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+        socket.write_frame_insist(&frame)
+    }
+
+    /// Update the state map from a received frame.
+    ///
+    /// Returns the node ID and new state if the node was seen for the first
+    /// time or changed its state.
+    pub fn process_frame(&mut self, frame: &CanFrame, now: Instant) -> Option<(u8, NmtState)> {
+        let hb = match Heartbeat::from_frame(frame) {
+            Ok(hb) => hb,
+            Err(_) => return None,
+        };
+
+        let status = NodeStatus {
+            state: hb.state,
+            last_seen: now,
+        };
+
+        match self.nodes.insert(hb.node_id, status) {
+            Some(ref prev) if prev.state == hb.state => None,
+            _ => Some((hb.node_id, hb.state)),
+        }
+    }
+
+    /// Last reported state of `node_id`
+    pub fn state(&self, node_id: u8) -> Option<NmtState> {
+        self.nodes.get(&node_id).map(|s| s.state)
+    }
+
+    /// Last reported status of `node_id`, including the time it was seen
+    pub fn status(&self, node_id: u8) -> Option<&NodeStatus> {
+        self.nodes.get(&node_id)
+    }
+
+    /// Iterate over all known nodes and their status
+    pub fn nodes(&self) -> hash_map::Iter<u8, NodeStatus> {
+        self.nodes.iter()
+    }
+
+    /// Remove `node_id` from the state map.
+    pub fn forget(&mut self, node_id: u8) {
+        self.nodes.remove(&node_id);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Instant;
+    use CanFrame;
+    use canopen::NmtState;
+    use super::{NmtCommand, NmtMaster, NmtMessage};
+
+    #[test]
+    fn test_nmt_message() {
+        let msg = NmtMessage::new(NmtCommand::ResetNode, Some(0x12)).unwrap();
+        let frame = msg.to_frame().unwrap();
+        assert_eq!(frame.id(), 0);
+        assert_eq!(frame.data(), &[0x81, 0x12]);
+        assert_eq!(NmtMessage::from_frame(&frame).unwrap(), msg);
+
+        assert!(NmtMessage::new(NmtCommand::Start, Some(128)).is_err());
+    }
+
+    #[test]
+    fn test_state_tracking() {
+        let mut master = NmtMaster::new();
+        let now = Instant::now();
+
+        let boot = CanFrame::new(0x702, &[0x00], false, false).unwrap();
+        let op = CanFrame::new(0x702, &[0x05], false, false).unwrap();
+
+        assert_eq!(master.process_frame(&boot, now), Some((2, NmtState::BootUp)));
+        assert_eq!(master.process_frame(&op, now), Some((2, NmtState::Operational)));
+        assert_eq!(master.process_frame(&op, now), None);
+        assert_eq!(master.state(2), Some(NmtState::Operational));
+        assert_eq!(master.state(3), None);
+    }
+}
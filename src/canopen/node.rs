@@ -0,0 +1,191 @@
+//! Per-node traffic
+//!
+//! A `NodeHandle` receives the traffic of a single node from a `Router`:
+//! its PDOs, SDOs, heartbeat and emergency messages, and the NMT commands
+//! addressed to it. An application talking to several nodes takes one
+//! handle per node instead of sorting the frames of the bus itself, and
+//! sends on the transport of the router:
+//!
+//! ```no_run
+//! use socketcan::CanSocket;
+//! use socketcan::canopen::NodeHandle;
+//! use socketcan::router::{Overflow, Router};
+//!
+//! let router = Router::new(CanSocket::open("can0").unwrap()).unwrap();
+//! let node = NodeHandle::subscribe(&router, 5, 64, Overflow::DropOldest).unwrap();
+//!
+//! while let Some(frame) = node.recv() {
+//!     println!("node {}: {:X}", node.node_id(), frame);
+//! }
+//! ```
+
+use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
+use {CanFrame, CanTransport};
+#[cfg(feature = "tokio")]
+use async_transport::AsyncCanTransport;
+#[cfg(feature = "tokio")]
+use router::AsyncRouter;
+use router::{Overflow, Router, Subscriber};
+use super::{check_node_id, cob_id_filter, FrameError, NodeFilters, COB_NMT};
+
+/// Receiving end of the traffic of one node
+#[derive(Debug)]
+pub struct NodeHandle {
+    node_id: u8,
+    subscriber: Subscriber,
+}
+
+impl NodeHandle {
+    /// Receive the traffic of `node_id` routed by `router`.
+    ///
+    /// Up to `capacity` frames are queued, see `Router::subscribe`.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is zero.
+    pub fn subscribe<T>(router: &Router<T>,
+                        node_id: u8,
+                        capacity: usize,
+                        overflow: Overflow)
+                        -> Result<NodeHandle, FrameError>
+        where T: CanTransport + Send + Sync + 'static
+    {
+        let filters = node_filters(node_id)?;
+        Ok(NodeHandle {
+            node_id: node_id,
+            subscriber: router.subscribe_filtered(&filters, capacity, overflow),
+        })
+    }
+
+    /// Receive the traffic of `node_id` routed by an `AsyncRouter`.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is zero.
+    #[cfg(feature = "tokio")]
+    pub fn subscribe_async<T>(router: &AsyncRouter<T>,
+                              node_id: u8,
+                              capacity: usize,
+                              overflow: Overflow)
+                              -> Result<NodeHandle, FrameError>
+        where T: AsyncCanTransport + Send + Sync + 'static
+    {
+        let filters = node_filters(node_id)?;
+        Ok(NodeHandle {
+            node_id: node_id,
+            subscriber: router.subscribe_filtered(&filters, capacity, overflow),
+        })
+    }
+
+    /// Node ID
+    #[inline]
+    pub fn node_id(&self) -> u8 {
+        self.node_id
+    }
+
+    /// The underlying subscription, e.g. to count dropped frames
+    pub fn subscriber(&self) -> &Subscriber {
+        &self.subscriber
+    }
+
+    /// Wait for the next frame, see `Subscriber::recv`.
+    pub fn recv(&self) -> Option<CanFrame> {
+        loop {
+            match self.subscriber.recv() {
+                Some(ref frame) if !self.is_own(frame) => {}
+                frame => return frame,
+            }
+        }
+    }
+
+    /// Wait up to `timeout` for the next frame.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<CanFrame> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            match self.subscriber.recv_timeout(deadline - now) {
+                Some(ref frame) if !self.is_own(frame) => {}
+                frame => return frame,
+            }
+        }
+    }
+
+    /// Take the next frame if one is queued.
+    pub fn try_recv(&self) -> Option<CanFrame> {
+        loop {
+            match self.subscriber.try_recv() {
+                Some(ref frame) if !self.is_own(frame) => {}
+                frame => return frame,
+            }
+        }
+    }
+
+    /// Take the next frame if one is queued, see `Subscriber::poll_recv`.
+    pub fn poll_recv(&self, cx: &mut Context) -> Poll<Option<CanFrame>> {
+        loop {
+            match self.subscriber.poll_recv(cx) {
+                Poll::Ready(Some(ref frame)) if !self.is_own(frame) => {}
+                poll => return poll,
+            }
+        }
+    }
+
+    /// Whether `frame` concerns the node, as far as the filters can not
+    /// tell: NMT commands address nodes in their data.
+    fn is_own(&self, frame: &CanFrame) -> bool {
+        let data = frame.data();
+        frame.id() != COB_NMT || data.len() >= 2 && (data[1] == 0 || data[1] == self.node_id)
+    }
+}
+
+/// Filters passing the services of `node_id` and all NMT commands
+fn node_filters(node_id: u8) -> Result<Vec<::CanFilter>, FrameError> {
+    let mut filters = NodeFilters::new().node(check_node_id(node_id)?).all().build()?;
+    filters.push(cob_id_filter(COB_NMT));
+    Ok(filters)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use CanFrame;
+    use router::{Overflow, Router};
+    use testing::MockBus;
+    use super::NodeHandle;
+
+    #[test]
+    fn test_node_handles() {
+        let bus = MockBus::new();
+        let peer = bus.endpoint();
+        let router = Router::new(bus.endpoint()).unwrap();
+        let five = NodeHandle::subscribe(&router, 5, 16, Overflow::Block).unwrap();
+        let six = NodeHandle::subscribe(&router, 6, 16, Overflow::Block).unwrap();
+        assert!(NodeHandle::subscribe(&router, 0, 16, Overflow::Block).is_err());
+
+        // TPDO1, SDO response and EMCY of node 5, heartbeat and RPDO of
+        // node 6, NMT start of node 6 and of all nodes, SYNC
+        for &(id, ref data) in &[(0x185, vec![1]),
+                                 (0x585, vec![0x60, 0, 0x20, 0, 0, 0, 0, 0]),
+                                 (0x706, vec![0x05]),
+                                 (0x000, vec![0x01, 0x06]),
+                                 (0x085, vec![0; 8]),
+                                 (0x306, vec![2]),
+                                 (0x080, vec![]),
+                                 (0x000, vec![0x01, 0x00])] {
+            peer.write_frame(&CanFrame::new(id, data, false, false).unwrap()).unwrap();
+        }
+
+        let timeout = Duration::from_secs(5);
+        let ids = |node: &NodeHandle| {
+            (0..4).map(|_| node.recv_timeout(timeout).unwrap().id()).collect::<Vec<_>>()
+        };
+        assert_eq!(ids(&five), vec![0x185, 0x585, 0x085, 0x000]);
+        assert_eq!(ids(&six), vec![0x706, 0x000, 0x306, 0x000]);
+        assert!(six.try_recv().is_none());
+        assert!(five.recv_timeout(Duration::from_millis(1)).is_none());
+    }
+}
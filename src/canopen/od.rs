@@ -0,0 +1,447 @@
+//! Object dictionary
+//!
+//! The object dictionary is the central data structure of every CANopen
+//! device: a table of entries addressed by a 16-bit index and an 8-bit
+//! subindex. Each entry holds a typed value together with its access
+//! rights and optional limits.
+//!
+//! Writes coming from the network (`write`) and local changes (`set`) are
+//! validated against the entry's type, access rights and limits. Callbacks
+//! registered through `on_change` are invoked for every successful change.
+
+use std::collections::BTreeMap;
+use std::collections::btree_map;
+use std::{error, fmt};
+
+/// Data type of an object dictionary entry (CiA 301, table 44)
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum DataType {
+    Boolean,
+    Integer8,
+    Integer16,
+    Integer32,
+    Integer64,
+    Unsigned8,
+    Unsigned16,
+    Unsigned32,
+    Unsigned64,
+    Real32,
+    VisibleString,
+    OctetString,
+    Domain,
+}
+
+/// Value of an object dictionary entry
+#[derive(Debug, Clone, PartialEq, PartialOrd)]
+pub enum Value {
+    Boolean(bool),
+    Integer8(i8),
+    Integer16(i16),
+    Integer32(i32),
+    Integer64(i64),
+    Unsigned8(u8),
+    Unsigned16(u16),
+    Unsigned32(u32),
+    Unsigned64(u64),
+    Real32(f32),
+    VisibleString(String),
+    OctetString(Vec<u8>),
+    Domain(Vec<u8>),
+}
+
+// little endian helpers, all CANopen values are transmitted little endian
+fn le_bytes(val: u64, len: usize) -> Vec<u8> {
+    (0..len).map(|i| (val >> (8 * i)) as u8).collect()
+}
+
+fn from_le(bytes: &[u8]) -> u64 {
+    bytes.iter().rev().fold(0, |acc, &b| acc << 8 | b as u64)
+}
+
+impl DataType {
+    /// Size in bytes, `None` for variable length types
+    pub fn size(&self) -> Option<usize> {
+        match *self {
+            DataType::Boolean | DataType::Integer8 | DataType::Unsigned8 => Some(1),
+            DataType::Integer16 | DataType::Unsigned16 => Some(2),
+            DataType::Integer32 | DataType::Unsigned32 | DataType::Real32 => Some(4),
+            DataType::Integer64 | DataType::Unsigned64 => Some(8),
+            DataType::VisibleString | DataType::OctetString | DataType::Domain => None,
+        }
+    }
+}
+
+impl Value {
+    /// Data type of the value
+    pub fn data_type(&self) -> DataType {
+        match *self {
+            Value::Boolean(_) => DataType::Boolean,
+            Value::Integer8(_) => DataType::Integer8,
+            Value::Integer16(_) => DataType::Integer16,
+            Value::Integer32(_) => DataType::Integer32,
+            Value::Integer64(_) => DataType::Integer64,
+            Value::Unsigned8(_) => DataType::Unsigned8,
+            Value::Unsigned16(_) => DataType::Unsigned16,
+            Value::Unsigned32(_) => DataType::Unsigned32,
+            Value::Unsigned64(_) => DataType::Unsigned64,
+            Value::Real32(_) => DataType::Real32,
+            Value::VisibleString(_) => DataType::VisibleString,
+            Value::OctetString(_) => DataType::OctetString,
+            Value::Domain(_) => DataType::Domain,
+        }
+    }
+
+    /// Encode the value as transmitted on the bus (little endian).
+    pub fn to_bytes(&self) -> Vec<u8> {
+        match *self {
+            Value::Boolean(v) => vec![v as u8],
+            Value::Integer8(v) => le_bytes(v as u64, 1),
+            Value::Integer16(v) => le_bytes(v as u64, 2),
+            Value::Integer32(v) => le_bytes(v as u64, 4),
+            Value::Integer64(v) => le_bytes(v as u64, 8),
+            Value::Unsigned8(v) => le_bytes(v as u64, 1),
+            Value::Unsigned16(v) => le_bytes(v as u64, 2),
+            Value::Unsigned32(v) => le_bytes(v as u64, 4),
+            Value::Unsigned64(v) => le_bytes(v, 8),
+            Value::Real32(v) => le_bytes(v.to_bits() as u64, 4),
+            Value::VisibleString(ref s) => s.as_bytes().to_vec(),
+            Value::OctetString(ref d) | Value::Domain(ref d) => d.clone(),
+        }
+    }
+
+    /// Decode a value of type `data_type` from its bus representation.
+    pub fn from_bytes(data_type: DataType, bytes: &[u8]) -> Result<Value, OdError> {
+        if let Some(size) = data_type.size() {
+            if bytes.len() != size {
+                return Err(OdError::LengthMismatch);
+            }
+        }
+
+        let raw = from_le(bytes);
+        Ok(match data_type {
+            DataType::Boolean => Value::Boolean(raw != 0),
+            DataType::Integer8 => Value::Integer8(raw as i8),
+            DataType::Integer16 => Value::Integer16(raw as i16),
+            DataType::Integer32 => Value::Integer32(raw as i32),
+            DataType::Integer64 => Value::Integer64(raw as i64),
+            DataType::Unsigned8 => Value::Unsigned8(raw as u8),
+            DataType::Unsigned16 => Value::Unsigned16(raw as u16),
+            DataType::Unsigned32 => Value::Unsigned32(raw as u32),
+            DataType::Unsigned64 => Value::Unsigned64(raw),
+            DataType::Real32 => Value::Real32(f32::from_bits(raw as u32)),
+            DataType::VisibleString => {
+                Value::VisibleString(String::from_utf8(bytes.to_vec())
+                    .map_err(|_| OdError::TypeMismatch)?)
+            }
+            DataType::OctetString => Value::OctetString(bytes.to_vec()),
+            DataType::Domain => Value::Domain(bytes.to_vec()),
+        })
+    }
+}
+
+/// Access rights of an entry, as seen from the network
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AccessType {
+    /// Read only, may still be changed locally
+    ReadOnly,
+
+    /// Write only
+    WriteOnly,
+
+    /// Read and write
+    ReadWrite,
+
+    /// Constant, cannot be changed at all
+    Const,
+}
+
+impl AccessType {
+    /// Check if the entry may be read over the network
+    pub fn is_readable(&self) -> bool {
+        *self != AccessType::WriteOnly
+    }
+
+    /// Check if the entry may be written over the network
+    pub fn is_writable(&self) -> bool {
+        *self == AccessType::WriteOnly || *self == AccessType::ReadWrite
+    }
+}
+
+/// Error accessing the object dictionary
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum OdError {
+    /// No object at the given index
+    NoSuchObject,
+
+    /// The object exists, but not the subindex
+    NoSuchSubindex,
+
+    /// Attempt to write a read only or constant entry
+    ReadOnly,
+
+    /// Attempt to read a write only entry
+    WriteOnly,
+
+    /// The value does not match the entry's data type
+    TypeMismatch,
+
+    /// The length of the written data does not match the data type
+    LengthMismatch,
+
+    /// Value is above the entry's upper limit
+    ValueTooHigh,
+
+    /// Value is below the entry's lower limit
+    ValueTooLow,
+}
+
+impl OdError {
+    /// The corresponding SDO abort code (CiA 301, table 22)
+    pub fn abort_code(&self) -> u32 {
+        match *self {
+            OdError::NoSuchObject => 0x0602_0000,
+            OdError::NoSuchSubindex => 0x0609_0011,
+            OdError::ReadOnly => 0x0601_0002,
+            OdError::WriteOnly => 0x0601_0001,
+            OdError::TypeMismatch | OdError::LengthMismatch => 0x0607_0010,
+            OdError::ValueTooHigh => 0x0609_0031,
+            OdError::ValueTooLow => 0x0609_0032,
+        }
+    }
+}
+
+impl fmt::Display for OdError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", error::Error::description(self))
+    }
+}
+
+impl error::Error for OdError {
+    fn description(&self) -> &str {
+        match *self {
+            OdError::NoSuchObject => "object does not exist",
+            OdError::NoSuchSubindex => "subindex does not exist",
+            OdError::ReadOnly => "attempt to write a read only object",
+            OdError::WriteOnly => "attempt to read a write only object",
+            OdError::TypeMismatch => "data type does not match",
+            OdError::LengthMismatch => "length of data type does not match",
+            OdError::ValueTooHigh => "value too high",
+            OdError::ValueTooLow => "value too low",
+        }
+    }
+}
+
+/// An object dictionary entry
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    value: Value,
+    access: AccessType,
+    min: Option<Value>,
+    max: Option<Value>,
+}
+
+impl Entry {
+    /// Create an entry without limits.
+    pub fn new(value: Value, access: AccessType) -> Entry {
+        Entry {
+            value: value,
+            access: access,
+            min: None,
+            max: None,
+        }
+    }
+
+    /// Restrict values to the inclusive range `min..max`.
+    ///
+    /// Both limits must have the same data type as the entry's value.
+    pub fn with_limits(mut self, min: Value, max: Value) -> Entry {
+        self.min = Some(min);
+        self.max = Some(max);
+        self
+    }
+
+    /// Current value
+    #[inline]
+    pub fn value(&self) -> &Value {
+        &self.value
+    }
+
+    /// Access rights
+    #[inline]
+    pub fn access(&self) -> AccessType {
+        self.access
+    }
+
+    fn check(&self, value: &Value) -> Result<(), OdError> {
+        if value.data_type() != self.value.data_type() {
+            return Err(OdError::TypeMismatch);
+        }
+
+        if let Some(ref min) = self.min {
+            if value < min {
+                return Err(OdError::ValueTooLow);
+            }
+        }
+
+        if let Some(ref max) = self.max {
+            if value > max {
+                return Err(OdError::ValueTooHigh);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// An object dictionary
+#[derive(Default)]
+pub struct ObjectDictionary {
+    entries: BTreeMap<(u16, u8), Entry>,
+    hooks: Vec<Box<FnMut(u16, u8, &Value)>>,
+}
+
+impl fmt::Debug for ObjectDictionary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ObjectDictionary")
+            .field("entries", &self.entries)
+            .field("hooks", &self.hooks.len())
+            .finish()
+    }
+}
+
+impl ObjectDictionary {
+    /// Create an empty object dictionary.
+    pub fn new() -> ObjectDictionary {
+        ObjectDictionary {
+            entries: BTreeMap::new(),
+            hooks: Vec::new(),
+        }
+    }
+
+    /// Add or replace an entry.
+    pub fn insert(&mut self, index: u16, subindex: u8, entry: Entry) {
+        self.entries.insert((index, subindex), entry);
+    }
+
+    /// Look up an entry.
+    pub fn entry(&self, index: u16, subindex: u8) -> Result<&Entry, OdError> {
+        match self.entries.get(&(index, subindex)) {
+            Some(entry) => Ok(entry),
+            None => Err(self.missing(index)),
+        }
+    }
+
+    /// Current value of an entry, regardless of its access rights.
+    pub fn get(&self, index: u16, subindex: u8) -> Result<&Value, OdError> {
+        self.entry(index, subindex).map(|e| &e.value)
+    }
+
+    /// Change an entry locally.
+    ///
+    /// Access rights only restrict the network; only constant entries are
+    /// rejected. Type and limits are checked.
+    pub fn set(&mut self, index: u16, subindex: u8, value: Value) -> Result<(), OdError> {
+        {
+            let entry = self.entry(index, subindex)?;
+            if entry.access == AccessType::Const {
+                return Err(OdError::ReadOnly);
+            }
+            entry.check(&value)?;
+        }
+
+        self.store(index, subindex, value);
+        Ok(())
+    }
+
+    /// Read an entry on behalf of the network (e.g. an SDO upload).
+    pub fn read(&self, index: u16, subindex: u8) -> Result<Vec<u8>, OdError> {
+        let entry = self.entry(index, subindex)?;
+        if !entry.access.is_readable() {
+            return Err(OdError::WriteOnly);
+        }
+        Ok(entry.value.to_bytes())
+    }
+
+    /// Write an entry on behalf of the network (e.g. an SDO download).
+    pub fn write(&mut self, index: u16, subindex: u8, data: &[u8]) -> Result<(), OdError> {
+        let value = {
+            let entry = self.entry(index, subindex)?;
+            if !entry.access.is_writable() {
+                return Err(OdError::ReadOnly);
+            }
+
+            let value = Value::from_bytes(entry.value.data_type(), data)?;
+            entry.check(&value)?;
+            value
+        };
+
+        self.store(index, subindex, value);
+        Ok(())
+    }
+
+    /// Register a callback invoked after every successful change.
+    ///
+    /// The callback receives index, subindex and the new value.
+    pub fn on_change<F>(&mut self, hook: F)
+        where F: FnMut(u16, u8, &Value) + 'static
+    {
+        self.hooks.push(Box::new(hook));
+    }
+
+    /// Iterate over all entries in index order
+    pub fn iter(&self) -> btree_map::Iter<(u16, u8), Entry> {
+        self.entries.iter()
+    }
+
+    fn store(&mut self, index: u16, subindex: u8, value: Value) {
+        if let Some(entry) = self.entries.get_mut(&(index, subindex)) {
+            entry.value = value;
+
+            for hook in &mut self.hooks {
+                hook(index, subindex, &entry.value);
+            }
+        }
+    }
+
+    fn missing(&self, index: u16) -> OdError {
+        if self.entries.range((index, 0)..=(index, 255)).next().is_some() {
+            OdError::NoSuchSubindex
+        } else {
+            OdError::NoSuchObject
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::cell::Cell;
+    use std::rc::Rc;
+    use super::{AccessType, Entry, ObjectDictionary, OdError, Value};
+
+    #[test]
+    fn test_access_and_limits() {
+        let mut od = ObjectDictionary::new();
+        od.insert(0x1000, 0, Entry::new(Value::Unsigned32(0x191), AccessType::Const));
+        od.insert(0x2000,
+                  1,
+                  Entry::new(Value::Unsigned16(10), AccessType::ReadWrite)
+                      .with_limits(Value::Unsigned16(5), Value::Unsigned16(100)));
+
+        let changes = Rc::new(Cell::new(0));
+        let c = changes.clone();
+        od.on_change(move |_, _, _| c.set(c.get() + 1));
+
+        assert_eq!(od.read(0x1000, 0).unwrap(), vec![0x91, 0x01, 0, 0]);
+        assert_eq!(od.write(0x1000, 0, &[0, 0, 0, 0]), Err(OdError::ReadOnly));
+        assert_eq!(od.read(0x2000, 2), Err(OdError::NoSuchSubindex));
+        assert_eq!(od.read(0x3000, 0), Err(OdError::NoSuchObject));
+
+        assert_eq!(od.write(0x2000, 1, &[50]), Err(OdError::LengthMismatch));
+        assert_eq!(od.write(0x2000, 1, &[200, 0]), Err(OdError::ValueTooHigh));
+        assert_eq!(od.set(0x2000, 1, Value::Unsigned16(1)), Err(OdError::ValueTooLow));
+        od.write(0x2000, 1, &[50, 0]).unwrap();
+
+        assert_eq!(od.get(0x2000, 1).unwrap(), &Value::Unsigned16(50));
+        assert_eq!(changes.get(), 1);
+    }
+}
@@ -0,0 +1,564 @@
+//! Synchronous PDO exchange
+//!
+//! In synchronous operation the master transmits the SYNC object at a
+//! fixed period. Nodes answer every SYNC with their synchronous TPDOs and
+//! apply the RPDOs received in between when the next SYNC arrives.
+//!
+//! `PdoExchange` runs the master side of this cycle on a `ProcessImage`:
+//! it sends SYNC followed by the RPDO data of the image, then collects the
+//! TPDOs of the nodes into the image. The image holds the raw PDO data;
+//! mapping it to application values is left to the application, which
+//! shares the image with the exchange loop.
+//!
+//! ```no_run
+//! use std::sync::atomic::AtomicBool;
+//! use std::time::Duration;
+//! use socketcan::CanSocket;
+//! use socketcan::canopen::{PdoExchange, ProcessImage, COB_RPDO, COB_TPDO};
+//!
+//! let mut image = ProcessImage::new();
+//! image.add_rpdo(COB_RPDO[0] + 5, &[0, 0]).unwrap();
+//! image.add_tpdo(COB_TPDO[0] + 5);
+//!
+//! let socket = CanSocket::open("can0").unwrap();
+//! let mut exchange = PdoExchange::new(socket, image, Duration::from_millis(2));
+//! let image = exchange.image();
+//! let running = AtomicBool::new(true);
+//! exchange.run(Duration::from_millis(10), &running).unwrap();
+//! ```
+//!
+//! SYNC is sent on `COB_SYNC` without data by default. Through the
+//! `SyncControl` of the exchange, another thread can change the COB-ID,
+//! add the counter of CiA 301 or change the period, and stop and restart
+//! the cycle, while `run` is active:
+//!
+//! ```no_run
+//! # use std::sync::atomic::AtomicBool;
+//! # use std::thread;
+//! # use std::time::Duration;
+//! # use socketcan::CanSocket;
+//! # use socketcan::canopen::{PdoExchange, ProcessImage};
+//! let socket = CanSocket::open("can0").unwrap();
+//! let mut exchange = PdoExchange::new(socket, ProcessImage::new(), Duration::from_millis(2));
+//! let sync = exchange.sync();
+//! sync.set_counter(16);
+//!
+//! thread::spawn(move || exchange.run(Duration::from_millis(10), &AtomicBool::new(true)));
+//! sync.set_period(Duration::from_millis(5));
+//! sync.stop();
+//! ```
+//!
+//! Nodes implemented in software send their event-driven TPDOs with a
+//! `TpdoProducer`, which keeps the inhibit time and event timer of the
+//! communication parameters:
+//!
+//! ```no_run
+//! use socketcan::CanBus;
+//! use socketcan::canopen::{TpdoProducer, COB_TPDO};
+//! use socketcan::canopen::mapping::TpdoTiming;
+//!
+//! // at most every 10 ms, at least every 100 ms
+//! let timing = TpdoTiming {
+//!     inhibit_time: 100,
+//!     event_timer: 100,
+//! };
+//! let bus = CanBus::open("can0").unwrap();
+//! let tpdo = TpdoProducer::new(&bus, COB_TPDO[0] + 5, &[0, 0], timing).unwrap();
+//!
+//! // a mapped value changed
+//! tpdo.send(&[0x12, 0x34]).unwrap();
+//! ```
+
+use std::{io, thread};
+use std::collections::BTreeMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use {CanFrame, CanSocket, CanTransport};
+use bus::{CanBus, EventDriven};
+use clock::Clock;
+use util::read_until;
+use super::{FrameError, COB_SYNC};
+use super::mapping::TpdoTiming;
+
+/// The last TPDO received on a COB-ID
+#[derive(Clone, Copy, Debug)]
+pub struct PdoInput {
+    frame: CanFrame,
+    cycle: u64,
+}
+
+impl PdoInput {
+    /// The PDO data
+    pub fn data(&self) -> &[u8] {
+        self.frame.data()
+    }
+
+    /// Number of the cycle the PDO was received in
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+}
+
+/// Raw data of the PDOs exchanged every cycle
+#[derive(Clone, Debug, Default)]
+pub struct ProcessImage {
+    outputs: BTreeMap<u32, CanFrame>,
+    inputs: BTreeMap<u32, Option<PdoInput>>,
+    cycle: u64,
+}
+
+impl ProcessImage {
+    pub fn new() -> ProcessImage {
+        ProcessImage::default()
+    }
+
+    /// Transmit an RPDO on `cob_id` every cycle, initially carrying `data`.
+    pub fn add_rpdo(&mut self, cob_id: u32, data: &[u8]) -> Result<(), FrameError> {
+        self.outputs.insert(cob_id, CanFrame::new(cob_id, data, false, false)?);
+        Ok(())
+    }
+
+    /// Collect the TPDO on `cob_id` every cycle.
+    pub fn add_tpdo(&mut self, cob_id: u32) {
+        self.inputs.insert(cob_id, None);
+    }
+
+    /// Transmit `data` on the RPDO `cob_id` from the next cycle on.
+    pub fn set_output(&mut self, cob_id: u32, data: &[u8]) -> Result<(), FrameError> {
+        if !self.outputs.contains_key(&cob_id) {
+            return Err(FrameError::UnexpectedCobId(cob_id));
+        }
+        self.add_rpdo(cob_id, data)
+    }
+
+    /// Data currently transmitted on the RPDO `cob_id`
+    pub fn output(&self, cob_id: u32) -> Option<&[u8]> {
+        self.outputs.get(&cob_id).map(|frame| frame.data())
+    }
+
+    /// The last TPDO received on `cob_id`, `None` if none was received yet
+    /// or the COB-ID is not collected
+    pub fn input(&self, cob_id: u32) -> Option<&PdoInput> {
+        self.inputs.get(&cob_id).and_then(|input| input.as_ref())
+    }
+
+    /// Number of the current cycle, counted from one for the first SYNC
+    pub fn cycle(&self) -> u64 {
+        self.cycle
+    }
+}
+
+/// Highest overflow value of the SYNC counter
+pub const MAX_SYNC_COUNTER: u8 = 240;
+
+#[derive(Debug)]
+struct SyncSettings {
+    cob_id: u32,
+    overflow: u8,
+    counter: u8,
+    period: Option<Duration>,
+    stopped: bool,
+}
+
+impl SyncSettings {
+    /// The next SYNC frame, advancing the counter
+    fn next_frame(&mut self) -> CanFrame {
+        let data = if self.overflow == 0 {
+            vec![]
+        } else {
+            let counter = self.counter;
+            self.counter = if counter >= self.overflow { 1 } else { counter + 1 };
+            vec![counter]
+        };
+        CanFrame::new(self.cob_id, &data, false, false).expect("SYNC COB-ID validated when set")
+    }
+}
+
+/// SYNC settings of a `PdoExchange`, shared with the thread running it
+///
+/// Changes take effect with the next cycle.
+#[derive(Clone, Debug)]
+pub struct SyncControl {
+    settings: Arc<Mutex<SyncSettings>>,
+}
+
+impl SyncControl {
+    fn new() -> SyncControl {
+        SyncControl {
+            settings: Arc::new(Mutex::new(SyncSettings {
+                                              cob_id: COB_SYNC,
+                                              overflow: 0,
+                                              counter: 1,
+                                              period: None,
+                                              stopped: false,
+                                          })),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<SyncSettings> {
+        self.settings.lock().expect("SYNC settings lock poisoned")
+    }
+
+    /// Send SYNC on `cob_id` instead of `COB_SYNC`, as configured in
+    /// object `0x1005`.
+    pub fn set_cob_id(&self, cob_id: u32) -> Result<(), FrameError> {
+        CanFrame::new(cob_id, &[], false, false)?;
+        self.lock().cob_id = cob_id;
+        Ok(())
+    }
+
+    /// COB-ID SYNC is sent on
+    pub fn cob_id(&self) -> u32 {
+        self.lock().cob_id
+    }
+
+    /// Add a counter to SYNC, counting from 1 to `overflow` and starting
+    /// over, as configured in object `0x1019`. Zero sends SYNC without
+    /// data again.
+    ///
+    /// The counter restarts at 1.
+    ///
+    /// # Panics
+    ///
+    /// If `overflow` is 1 or above `MAX_SYNC_COUNTER`.
+    pub fn set_counter(&self, overflow: u8) {
+        assert!(overflow != 1 && overflow <= MAX_SYNC_COUNTER,
+                "invalid SYNC counter overflow value");
+        let mut settings = self.lock();
+        settings.overflow = overflow;
+        settings.counter = 1;
+    }
+
+    /// Run the cycles of `PdoExchange::run` every `period` from the next
+    /// one on.
+    pub fn set_period(&self, period: Duration) {
+        self.lock().period = Some(period);
+    }
+
+    /// Period of the cycles, `None` until set or running
+    pub fn period(&self) -> Option<Duration> {
+        self.lock().period
+    }
+
+    /// Suspend the cycles of `PdoExchange::run`, sending neither SYNC nor
+    /// RPDOs.
+    pub fn stop(&self) {
+        self.lock().stopped = true;
+    }
+
+    /// Resume the cycles after `stop`, restarting the counter at 1.
+    pub fn start(&self) {
+        let mut settings = self.lock();
+        settings.stopped = false;
+        settings.counter = 1;
+    }
+
+    /// Check if the cycles are suspended
+    pub fn is_stopped(&self) -> bool {
+        self.lock().stopped
+    }
+}
+
+/// Master side of the synchronous PDO exchange
+#[derive(Debug)]
+pub struct PdoExchange<T = CanSocket> {
+    socket: T,
+    image: Arc<Mutex<ProcessImage>>,
+    window: Duration,
+    sync: SyncControl,
+}
+
+impl<T: CanTransport> PdoExchange<T> {
+    /// Exchange the PDOs of `image` on `socket`, waiting up to `window`
+    /// after every SYNC for the TPDOs to arrive.
+    pub fn new(socket: T, image: ProcessImage, window: Duration) -> PdoExchange<T> {
+        PdoExchange {
+            socket: socket,
+            image: Arc::new(Mutex::new(image)),
+            window: window,
+            sync: SyncControl::new(),
+        }
+    }
+
+    /// The SYNC settings, to be changed from any thread
+    pub fn sync(&self) -> SyncControl {
+        self.sync.clone()
+    }
+
+    /// The process image, to be shared with the application
+    pub fn image(&self) -> Arc<Mutex<ProcessImage>> {
+        self.image.clone()
+    }
+
+    /// Run a single cycle, also while the cycles of `run` are stopped.
+    ///
+    /// Returns the COB-IDs of the TPDOs that did not arrive within the
+    /// window. Other frames received meanwhile are discarded.
+    pub fn cycle(&mut self) -> io::Result<Vec<u32>> {
+        let (cycle, outputs, mut missing) = {
+            let mut image = self.image.lock().expect("process image lock poisoned");
+            image.cycle += 1;
+            (image.cycle,
+             image.outputs.values().cloned().collect::<Vec<_>>(),
+             image.inputs.keys().cloned().collect::<Vec<_>>())
+        };
+
+        let sync = self.sync.lock().next_frame();
+        self.socket.write_frame_insist(&sync)?;
+        for frame in &outputs {
+            self.socket.write_frame_insist(frame)?;
+        }
+
+        if missing.is_empty() {
+            return Ok(missing);
+        }
+
+        let image = &self.image;
+        read_until(&self.socket, self.window, |frame| {
+            if frame.is_extended() || frame.is_rtr() || frame.is_error() {
+                return false;
+            }
+
+            if let Some(i) = missing.iter().position(|&cob_id| cob_id == frame.id()) {
+                missing.remove(i);
+                let mut image = image.lock().expect("process image lock poisoned");
+                image.inputs.insert(frame.id(),
+                                    Some(PdoInput {
+                                        frame: *frame,
+                                        cycle: cycle,
+                                    }));
+            }
+            missing.is_empty()
+        })?;
+
+        Ok(missing)
+    }
+
+    /// Run a cycle every `period` until `running` is cleared.
+    ///
+    /// The period can be changed and the cycles stopped through `sync`
+    /// meanwhile; a period set before takes precedence over `period`.
+    /// While stopped, the exchange checks every period whether it was
+    /// started again.
+    ///
+    /// Missing TPDOs are not treated as an error, the application can tell
+    /// from the cycle numbers of the inputs.
+    pub fn run(&mut self, period: Duration, running: &AtomicBool) -> io::Result<()> {
+        let mut next = Instant::now();
+        while running.load(Ordering::SeqCst) {
+            let (period, stopped) = {
+                let mut settings = self.sync.lock();
+                (*settings.period.get_or_insert(period), settings.stopped)
+            };
+            if !stopped {
+                self.cycle()?;
+            }
+
+            next += period;
+            let now = Instant::now();
+            if next > now {
+                thread::sleep(next - now);
+            } else {
+                // fell behind, skip the missed cycles
+                next = now;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Transmitter of an event-driven TPDO
+///
+/// Dropping the producer stops the transmission.
+#[derive(Debug)]
+pub struct TpdoProducer {
+    cob_id: u32,
+    sender: EventDriven,
+}
+
+impl TpdoProducer {
+    /// Send `data` on `cob_id` right away, and from then on as `timing`
+    /// allows or demands.
+    pub fn new<T, C>(bus: &CanBus<T, C>,
+                     cob_id: u32,
+                     data: &[u8],
+                     timing: TpdoTiming)
+                     -> Result<TpdoProducer, FrameError>
+        where T: CanTransport + Send + Sync + 'static,
+              C: Clock + Clone + Send + 'static
+    {
+        let frame = CanFrame::new(cob_id, data, false, false)?;
+        Ok(TpdoProducer {
+            cob_id: cob_id,
+            sender: bus.send_on_event(frame, timing.inhibit_duration(), timing.event_duration()),
+        })
+    }
+
+    /// COB-ID the PDO is sent on
+    pub fn cob_id(&self) -> u32 {
+        self.cob_id
+    }
+
+    /// Send `data` as soon as the inhibit time allows, e.g. after a mapped
+    /// value changed.
+    pub fn send(&self, data: &[u8]) -> Result<(), FrameError> {
+        self.sender.send(CanFrame::new(self.cob_id, data, false, false)?);
+        Ok(())
+    }
+
+    /// Send `data` from the next expiry of the event timer on, without an
+    /// event.
+    pub fn set_data(&self, data: &[u8]) -> Result<(), FrameError> {
+        self.sender.set_frame(CanFrame::new(self.cob_id, data, false, false)?);
+        Ok(())
+    }
+
+    /// Apply changed communication parameters.
+    pub fn set_timing(&self, timing: TpdoTiming) {
+        self.sender.set_inhibit_time(timing.inhibit_duration());
+        self.sender.set_event_timer(timing.event_duration());
+    }
+
+    /// Stop sending.
+    ///
+    /// Returns the error that stopped the transmission early, if any.
+    pub fn stop(self) -> io::Result<()> {
+        self.sender.stop()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::{Duration, UNIX_EPOCH};
+    use {CanBus, CanFrame};
+    use canopen::{COB_RPDO, COB_SYNC, COB_TPDO};
+    use canopen::mapping::TpdoTiming;
+    use testing::MockBus;
+    use super::{PdoExchange, ProcessImage, TpdoProducer};
+
+    #[test]
+    fn test_cycle() {
+        let bus = MockBus::new();
+        let (master, node) = (bus.endpoint(), bus.endpoint());
+
+        let responder = thread::spawn(move || {
+            assert_eq!(node.read_frame().unwrap().id(), COB_SYNC);
+            let tpdo = CanFrame::new(COB_TPDO[0] + 5, &[0x12, 0x34], false, false).unwrap();
+            node.write_frame(&tpdo).unwrap();
+            node.read_frame().unwrap()
+        });
+
+        let mut image = ProcessImage::new();
+        image.add_rpdo(COB_RPDO[0] + 5, &[0; 2]).unwrap();
+        image.add_tpdo(COB_TPDO[0] + 5);
+        image.add_tpdo(COB_TPDO[1] + 6);
+        let mut exchange = PdoExchange::new(master, image, Duration::from_millis(200));
+        let image = exchange.image();
+        image.lock().unwrap().set_output(COB_RPDO[0] + 5, &[1, 2]).unwrap();
+        assert!(image.lock().unwrap().set_output(COB_RPDO[1] + 5, &[1, 2]).is_err());
+
+        assert_eq!(exchange.cycle().unwrap(), vec![COB_TPDO[1] + 6]);
+        let rpdo = responder.join().unwrap();
+        assert_eq!((rpdo.id(), rpdo.data()), (COB_RPDO[0] + 5, &[1, 2][..]));
+
+        let image = image.lock().unwrap();
+        assert_eq!(image.cycle(), 1);
+        let input = image.input(COB_TPDO[0] + 5).unwrap();
+        assert_eq!((input.data(), input.cycle()), (&[0x12, 0x34][..], 1));
+        assert!(image.input(COB_TPDO[1] + 6).is_none());
+    }
+
+    #[test]
+    fn test_sync_control() {
+        let bus = MockBus::new();
+        let (master, peer) = (bus.endpoint(), bus.endpoint());
+        let mut exchange = PdoExchange::new(master, ProcessImage::new(), Duration::from_secs(0));
+        let sync = exchange.sync();
+
+        let cycle = |exchange: &mut PdoExchange<_>| {
+            exchange.cycle().unwrap();
+            let frame = peer.read_frame().unwrap();
+            (frame.id(), frame.data().to_vec())
+        };
+        assert_eq!(cycle(&mut exchange), (COB_SYNC, vec![]));
+
+        sync.set_cob_id(0x081).unwrap();
+        assert!(sync.set_cob_id(0x2000_0000).is_err());
+        sync.set_counter(3);
+        let counters: Vec<_> = (0..4).map(|_| cycle(&mut exchange).1).collect();
+        assert_eq!(counters, vec![vec![1], vec![2], vec![3], vec![1]]);
+        sync.set_counter(0);
+        assert_eq!(cycle(&mut exchange), (0x081, vec![]));
+
+        // stopped and restarted while running
+        sync.set_counter(2);
+        sync.set_period(Duration::from_millis(2));
+        let running = Arc::new(AtomicBool::new(true));
+        let runner = {
+            let running = running.clone();
+            thread::spawn(move || exchange.run(Duration::from_secs(60), &running).unwrap())
+        };
+        let counters: Vec<_> = (0..3).map(|_| peer.read_frame().unwrap().data()[0]).collect();
+        assert_eq!(counters, vec![1, 2, 1]);
+        assert_eq!(sync.period(), Some(Duration::from_millis(2)));
+
+        sync.stop();
+        thread::sleep(Duration::from_millis(20));
+        peer.set_nonblocking(true).unwrap();
+        while peer.read_frame().is_ok() {}
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(peer.pending(), 0);
+
+        peer.set_nonblocking(false).unwrap();
+        sync.start();
+        assert_eq!(peer.read_frame().unwrap().data(), &[1]);
+        running.store(false, Ordering::SeqCst);
+        runner.join().unwrap();
+    }
+
+    #[test]
+    fn test_tpdo_producer() {
+        let mock = MockBus::new();
+        let mut peer = mock.endpoint();
+        let bus = CanBus::with_clock(mock.endpoint(), mock.clone()).unwrap();
+
+        let timing = TpdoTiming {
+            inhibit_time: 0,
+            event_timer: 50,
+        };
+        let tpdo = TpdoProducer::new(&bus, COB_TPDO[0] + 5, &[1], timing).unwrap();
+        assert!(TpdoProducer::new(&bus, COB_TPDO[0] + 5, &[0; 9], timing).is_err());
+        for n in 0..3 {
+            let (frame, t) = peer.read_frame_with_timestamp().unwrap();
+            assert_eq!((frame.id(), frame.data()), (COB_TPDO[0] + 5, &[1][..]));
+            assert_eq!(t.duration_since(UNIX_EPOCH).unwrap(), Duration::from_millis(50 * n));
+        }
+
+        tpdo.set_data(&[2]).unwrap();
+        while peer.read_frame().unwrap().data() != &[2] {}
+        tpdo.set_timing(TpdoTiming {
+                            inhibit_time: 10000,
+                            event_timer: 0,
+                        });
+        tpdo.send(&[3]).unwrap();
+        tpdo.send(&[4]).unwrap();
+        let (frame, t) = loop {
+            let (frame, t) = peer.read_frame_with_timestamp().unwrap();
+            if frame.data() != &[2] {
+                break (frame, t);
+            }
+        };
+        if frame.data() != &[4] {
+            // the inhibit time delays the latest data
+            assert_eq!(frame.data(), &[3]);
+            let (frame, next) = peer.read_frame_with_timestamp().unwrap();
+            assert_eq!(frame.data(), &[4]);
+            assert!(next.duration_since(t).unwrap() >= Duration::from_secs(1));
+        }
+        tpdo.stop().unwrap();
+    }
+}
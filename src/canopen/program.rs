@@ -0,0 +1,150 @@
+//! Program download
+//!
+//! CiA 302-3 updates the firmware of a node through two objects: the
+//! program data (`0x1F50`) takes the image, the program control (`0x1F51`)
+//! stops, clears and starts the program. Subindex 1 of both refers to the
+//! application program, further subindexes to other programs of the node.
+//!
+//! `flash_node` runs the whole update with an `SdoClient`:
+//!
+//! ```no_run
+//! use std::fs;
+//! use std::time::Duration;
+//! use socketcan::CanSocket;
+//! use socketcan::canopen::SdoClient;
+//! use socketcan::canopen::program::flash_node;
+//!
+//! let image = fs::read("firmware.bin").unwrap();
+//! let socket = CanSocket::open("can0").unwrap();
+//! let client = SdoClient::new(&socket, Duration::from_secs(1));
+//! flash_node(&client, 5, &image, |sent| println!("{}/{} bytes", sent, image.len())).unwrap();
+//! ```
+//!
+//! The image is written with a segmented transfer, seven bytes per
+//! confirmed segment. Block transfers, which CiA 302-3 suggests for speed,
+//! are not implemented by `SdoClient`, and `SdoServer` aborts them.
+
+use CanTransport;
+use super::sdo::{SdoClient, SdoError};
+
+/// Index of the program data object
+pub const OBJ_PROGRAM_DATA: u16 = 0x1F50;
+
+/// Index of the program control object
+pub const OBJ_PROGRAM_CONTROL: u16 = 0x1F51;
+
+/// Subindex of the application program
+pub const APPLICATION_PROGRAM: u8 = 1;
+
+/// Command written to the program control object
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ProgramControl {
+    /// Stop the program
+    Stop,
+
+    /// Start the program
+    Start,
+
+    /// Reset the program
+    Reset,
+
+    /// Clear the program from memory, before downloading a new one
+    Clear,
+}
+
+impl ProgramControl {
+    /// Value written to the program control object
+    pub fn as_byte(&self) -> u8 {
+        match *self {
+            ProgramControl::Stop => 0,
+            ProgramControl::Start => 1,
+            ProgramControl::Reset => 2,
+            ProgramControl::Clear => 3,
+        }
+    }
+}
+
+/// Send `command` to program `program` of node `node_id`.
+pub fn control_program<T>(client: &SdoClient<T>,
+                          node_id: u8,
+                          program: u8,
+                          command: ProgramControl)
+                          -> Result<(), SdoError>
+    where T: CanTransport
+{
+    client.write_u8(node_id, OBJ_PROGRAM_CONTROL, program, command.as_byte())
+}
+
+/// Replace the application program of node `node_id` with `image`.
+///
+/// Stops and clears the program, downloads the image and starts it again,
+/// passing the number of bytes written so far to `progress`. Clearing and
+/// writing flash may take a while, the timeout of `client` should allow
+/// for that. A failed step ends the update, leaving the node without a
+/// running program.
+pub fn flash_node<T, F>(client: &SdoClient<T>,
+                        node_id: u8,
+                        image: &[u8],
+                        progress: F)
+                        -> Result<(), SdoError>
+    where T: CanTransport,
+          F: FnMut(usize)
+{
+    control_program(client, node_id, APPLICATION_PROGRAM, ProgramControl::Stop)?;
+    control_program(client, node_id, APPLICATION_PROGRAM, ProgramControl::Clear)?;
+    client.download_with_progress(node_id, OBJ_PROGRAM_DATA, APPLICATION_PROGRAM, image, progress)?;
+    control_program(client, node_id, APPLICATION_PROGRAM, ProgramControl::Start)
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::sync::{mpsc, Arc};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+    use canopen::{AccessType, Entry, ObjectDictionary, SdoClient, SdoError, SdoServer, Value};
+    use testing::MockBus;
+    use super::{flash_node, OBJ_PROGRAM_CONTROL, OBJ_PROGRAM_DATA};
+
+    #[test]
+    fn test_flash_node() {
+        let bus = MockBus::new();
+        let (socket, server) = (bus.endpoint(), bus.endpoint());
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let (commands_tx, commands) = mpsc::channel();
+        let flag = shutdown.clone();
+        let responder = thread::spawn(move || {
+            let mut od = ObjectDictionary::new();
+            od.insert(OBJ_PROGRAM_DATA,
+                      1,
+                      Entry::new(Value::Domain(vec![]), AccessType::WriteOnly));
+            od.insert(OBJ_PROGRAM_CONTROL,
+                      1,
+                      Entry::new(Value::Unsigned8(1), AccessType::ReadWrite));
+            od.on_change(move |index, _, value| if index == OBJ_PROGRAM_CONTROL {
+                             commands_tx.send(value.clone()).unwrap();
+                         });
+            SdoServer::new(5).unwrap().serve(&server, &mut od, &flag).unwrap();
+            od.get(OBJ_PROGRAM_DATA, 1).unwrap().clone()
+        });
+        let client = SdoClient::new(&socket, Duration::from_secs(5));
+
+        let image: Vec<u8> = (0..100).collect();
+        let mut progress = Vec::new();
+        flash_node(&client, 5, &image, |sent| progress.push(sent)).unwrap();
+        assert_eq!(progress.len(), 15);
+        assert_eq!(progress[0], 7);
+        assert_eq!(progress.last(), Some(&100));
+        let impatient = SdoClient::new(&socket, Duration::from_millis(10));
+        match flash_node(&impatient, 6, &image, |_| {}) {
+            Err(SdoError::Timeout) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        shutdown.store(true, Ordering::SeqCst);
+        assert_eq!(responder.join().unwrap(), Value::Domain(image));
+        let commands: Vec<_> = commands.try_iter().collect();
+        assert_eq!(commands,
+                   vec![Value::Unsigned8(0), Value::Unsigned8(3), Value::Unsigned8(1)]);
+    }
+}
@@ -0,0 +1,214 @@
+//! NMT master redundancy
+//!
+//! Systems with a backup controller run several NMT master capable
+//! devices, of which only one may act as NMT master at a time. Following
+//! the flying master scheme of CiA 302-2, a device starting up asks for an
+//! active master on COB-ID `0x071`; the active master answers on `0x072`
+//! with its priority and node ID. Without an answer, the device becomes
+//! the active master itself.
+//!
+//! Masters in standby watch the heartbeat of the active master and start a
+//! new detection when it is lost. If two masters are active at the same
+//! time, the one with the lower priority (higher value, then higher node
+//! ID) yields.
+//!
+//! `FlyingMaster` implements this as a state machine: feed it all received
+//! frames and call `check_timeouts` periodically, then act on the returned
+//! events, e.g. by starting or stopping the application's NMT master.
+
+use std::time::{Duration, Instant};
+use CanFrame;
+use super::{check_data_frame, check_node_id, FrameError, Heartbeat};
+
+/// COB-ID of the active master detection request
+pub const COB_MASTER_DETECT: u32 = 0x071;
+
+/// COB-ID of the active master response and announcement
+pub const COB_MASTER_RESPONSE: u32 = 0x072;
+
+/// Role of this device
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MasterState {
+    /// Waiting for an active master to answer the detection request
+    Detecting,
+
+    /// Acting as NMT master
+    Active,
+
+    /// Another device with the given node ID is the active master
+    Standby(u8),
+}
+
+/// Event reported by `FlyingMaster`
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MasterEvent {
+    /// Send `FlyingMaster::announcement`, in response to a detection
+    /// request or to another active master with lower priority
+    Announce,
+
+    /// No other master answered, this device is now the active master
+    Activated,
+
+    /// The master with the given node ID is active, this device must not
+    /// act as NMT master
+    Yielded(u8),
+
+    /// The heartbeat of the active master with the given node ID timed out.
+    /// Send `FlyingMaster::detection_request` to start a new detection.
+    MasterLost(u8),
+}
+
+/// Flying master state machine of a master capable device
+#[derive(Debug)]
+pub struct FlyingMaster {
+    node_id: u8,
+    priority: u8,
+    timeout: Duration,
+    state: MasterState,
+    // start of the detection, or the last sign of life of the active master
+    since: Instant,
+}
+
+impl FlyingMaster {
+    /// Create a device with `priority`, 0 being the highest.
+    ///
+    /// `timeout` is both the time to wait for an answer to the detection
+    /// request and the heartbeat consumer time for the active master. The
+    /// device starts detecting at `now`, send `detection_request` right
+    /// away.
+    pub fn new(node_id: u8,
+               priority: u8,
+               timeout: Duration,
+               now: Instant)
+               -> Result<FlyingMaster, FrameError> {
+        Ok(FlyingMaster {
+            node_id: check_node_id(node_id)?,
+            priority: priority,
+            timeout: timeout,
+            state: MasterState::Detecting,
+            since: now,
+        })
+    }
+
+    pub fn state(&self) -> MasterState {
+        self.state
+    }
+
+    /// Whether this device currently acts as NMT master
+    pub fn is_active(&self) -> bool {
+        self.state == MasterState::Active
+    }
+
+    /// Request asking the active master to identify itself
+    pub fn detection_request(&self) -> CanFrame {
+        CanFrame::new(COB_MASTER_DETECT, &[], false, false)
+            .expect("detection request is always valid")
+    }
+
+    /// Response identifying this device as the active master
+    pub fn announcement(&self) -> CanFrame {
+        CanFrame::new(COB_MASTER_RESPONSE, &[self.priority, self.node_id], false, false)
+            .expect("announcement is always valid")
+    }
+
+    // lower priority values and node IDs win
+    fn wins_over(&self, priority: u8, node_id: u8) -> bool {
+        (self.priority, self.node_id) < (priority, node_id)
+    }
+
+    /// Process a received frame.
+    pub fn process_frame(&mut self, frame: &CanFrame, now: Instant) -> Option<MasterEvent> {
+        if let Ok(hb) = Heartbeat::from_frame(frame) {
+            if self.state == MasterState::Standby(hb.node_id) {
+                self.since = now;
+            }
+            return None;
+        }
+
+        if check_data_frame(frame).is_err() {
+            return None;
+        }
+
+        match frame.id() {
+            COB_MASTER_DETECT if self.is_active() => Some(MasterEvent::Announce),
+            COB_MASTER_RESPONSE if frame.data().len() >= 2 => {
+                let (priority, node_id) = (frame.data()[0], frame.data()[1]);
+                if node_id == self.node_id {
+                    return None;
+                }
+
+                match self.state {
+                    MasterState::Active if self.wins_over(priority, node_id) => {
+                        Some(MasterEvent::Announce)
+                    }
+                    MasterState::Standby(master) if master == node_id => {
+                        self.since = now;
+                        None
+                    }
+                    _ => {
+                        self.state = MasterState::Standby(node_id);
+                        self.since = now;
+                        Some(MasterEvent::Yielded(node_id))
+                    }
+                }
+            }
+            _ => None,
+        }
+    }
+
+    /// Check for an unanswered detection or a lost active master.
+    pub fn check_timeouts(&mut self, now: Instant) -> Option<MasterEvent> {
+        if now.duration_since(self.since) <= self.timeout {
+            return None;
+        }
+
+        match self.state {
+            MasterState::Detecting => {
+                self.state = MasterState::Active;
+                Some(MasterEvent::Activated)
+            }
+            MasterState::Standby(master) => {
+                self.state = MasterState::Detecting;
+                self.since = now;
+                Some(MasterEvent::MasterLost(master))
+            }
+            MasterState::Active => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+    use CanFrame;
+    use super::{FlyingMaster, MasterEvent, MasterState};
+
+    #[test]
+    fn test_flying_master() {
+        let t0 = Instant::now();
+        let ms = |ms| t0 + Duration::from_millis(ms);
+        let mut a = FlyingMaster::new(1, 0, Duration::from_millis(100), t0).unwrap();
+        let mut b = FlyingMaster::new(2, 1, Duration::from_millis(100), t0).unwrap();
+
+        // nobody answers a, it becomes active
+        assert_eq!(a.check_timeouts(ms(50)), None);
+        assert_eq!(a.check_timeouts(ms(150)), Some(MasterEvent::Activated));
+
+        // b detects a and yields
+        assert_eq!(a.process_frame(&b.detection_request(), ms(160)), Some(MasterEvent::Announce));
+        assert_eq!(b.process_frame(&a.announcement(), ms(160)), Some(MasterEvent::Yielded(1)));
+        assert_eq!(b.state(), MasterState::Standby(1));
+
+        // heartbeats keep b in standby until a disappears
+        let hb = CanFrame::new(0x701, &[0x05], false, false).unwrap();
+        assert_eq!(b.process_frame(&hb, ms(250)), None);
+        assert_eq!(b.check_timeouts(ms(300)), None);
+        assert_eq!(b.check_timeouts(ms(400)), Some(MasterEvent::MasterLost(1)));
+        assert_eq!(b.check_timeouts(ms(510)), Some(MasterEvent::Activated));
+
+        // a returns with higher priority, b yields to it
+        assert_eq!(a.process_frame(&b.announcement(), ms(520)), Some(MasterEvent::Announce));
+        assert_eq!(b.process_frame(&a.announcement(), ms(520)), Some(MasterEvent::Yielded(1)));
+        assert!(a.is_active() && !b.is_active());
+    }
+}
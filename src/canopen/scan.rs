@@ -0,0 +1,251 @@
+//! Network scan
+//!
+//! Finds the nodes present on a network by reading the vendor ID of the
+//! identity object (`0x1018`) from every node ID, the first step of any
+//! commissioning tool. Nodes that answer are then asked for the rest of
+//! their identity.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use socketcan::CanSocket;
+//! use socketcan::canopen::scan_network;
+//!
+//! let socket = CanSocket::open("can0").unwrap();
+//! for node in scan_network(&socket, Duration::from_millis(100)).unwrap() {
+//!     println!("node {}: vendor {:08X}", node.node_id, node.identity.vendor_id);
+//! }
+//! ```
+
+use std::io;
+use std::time::Duration;
+use {CanSocket, CanTransport};
+use util::read_until;
+use super::MAX_NODE_ID;
+use super::sdo::{is_response, parse_upload_response, upload_expedited, upload_request,
+                 SdoError};
+
+/// Index of the identity object
+pub const OBJ_IDENTITY: u16 = 0x1018;
+
+/// Contents of the identity object
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Identity {
+    pub vendor_id: u32,
+    /// Product code, if implemented by the node
+    pub product_code: Option<u32>,
+    /// Revision number, if implemented by the node
+    pub revision: Option<u32>,
+    /// Serial number, if implemented by the node
+    pub serial: Option<u32>,
+}
+
+/// A node found by a scan
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScannedNode {
+    pub node_id: u8,
+    pub identity: Identity,
+}
+
+fn to_u32(data: &[u8]) -> Option<u32> {
+    if data.len() != 4 {
+        return None;
+    }
+    Some(data[0] as u32 | (data[1] as u32) << 8 | (data[2] as u32) << 16 | (data[3] as u32) << 24)
+}
+
+/// Scanner of a CANopen network
+///
+/// Waits up to `timeout` for every SDO response. Responses are read from
+/// the same socket, so other traffic received in the meantime is
+/// discarded.
+#[derive(Debug)]
+pub struct Scanner<'a, T: 'a = CanSocket> {
+    socket: &'a T,
+    timeout: Duration,
+    parallel: bool,
+}
+
+impl<'a, T: CanTransport> Scanner<'a, T> {
+    /// Create a scanner probing all nodes at once.
+    pub fn new(socket: &'a T, timeout: Duration) -> Scanner<'a, T> {
+        Scanner {
+            socket: socket,
+            timeout: timeout,
+            parallel: true,
+        }
+    }
+
+    /// Probe one node after the other instead of all at once.
+    ///
+    /// This takes up to 127 timeouts, but avoids a burst of requests on
+    /// busy networks.
+    pub fn sequential(mut self) -> Scanner<'a, T> {
+        self.parallel = false;
+        self
+    }
+
+    /// Scan node IDs 1 to 127.
+    pub fn scan(&self) -> io::Result<Vec<ScannedNode>> {
+        let nodes: Vec<u8> = (1..MAX_NODE_ID + 1).collect();
+        self.scan_nodes(&nodes)
+    }
+
+    /// Scan the given node IDs, returning the nodes found in ascending
+    /// order. Invalid node IDs are skipped.
+    pub fn scan_nodes(&self, nodes: &[u8]) -> io::Result<Vec<ScannedNode>> {
+        let mut found = if self.parallel {
+            self.probe_parallel(nodes)?
+        } else {
+            let mut found = Vec::new();
+            for &node_id in nodes {
+                if let Some(vendor_id) = self.read_u32(node_id, 1)? {
+                    found.push((node_id, vendor_id));
+                }
+            }
+            found
+        };
+        found.sort();
+        found.dedup_by_key(|&mut (node_id, _)| node_id);
+
+        let mut scanned = Vec::with_capacity(found.len());
+        for (node_id, vendor_id) in found {
+            scanned.push(ScannedNode {
+                node_id: node_id,
+                identity: Identity {
+                    vendor_id: vendor_id,
+                    product_code: self.read_u32(node_id, 2)?,
+                    revision: self.read_u32(node_id, 3)?,
+                    serial: self.read_u32(node_id, 4)?,
+                },
+            });
+        }
+        Ok(scanned)
+    }
+
+    /// Read a subindex of the identity object, `None` if the node does not
+    /// provide it.
+    fn read_u32(&self, node_id: u8, subindex: u8) -> io::Result<Option<u32>> {
+        match upload_expedited(self.socket, node_id, OBJ_IDENTITY, subindex, self.timeout) {
+            Ok(data) => Ok(to_u32(&data)),
+            Err(SdoError::Io(e)) => Err(e),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Send the vendor ID requests to all nodes, then collect the answers.
+    fn probe_parallel(&self, nodes: &[u8]) -> io::Result<Vec<(u8, u32)>> {
+        let nodes: Vec<u8> = nodes.iter()
+            .cloned()
+            .filter(|&n| n > 0 && n <= MAX_NODE_ID)
+            .collect();
+        for &node_id in &nodes {
+            let request = upload_request(node_id, OBJ_IDENTITY, 1)
+                .expect("node ID checked before");
+            self.socket.write_frame_insist(&request)?;
+        }
+
+        let mut found = Vec::new();
+        if nodes.is_empty() {
+            return Ok(found);
+        }
+
+        read_until(self.socket, self.timeout, |frame| {
+            let node_id = (frame.id() & 0x7f) as u8;
+            if nodes.contains(&node_id) && is_response(frame, node_id, OBJ_IDENTITY, 1) {
+                let vendor_id = parse_upload_response(frame).ok().and_then(|d| to_u32(&d));
+                if let Some(vendor_id) = vendor_id {
+                    found.push((node_id, vendor_id));
+                }
+            }
+            found.len() == nodes.len()
+        })?;
+        Ok(found)
+    }
+}
+
+/// Scan node IDs 1 to 127 in parallel, see `Scanner`.
+pub fn scan_network<T: CanTransport>(socket: &T,
+                                     timeout: Duration)
+                                     -> io::Result<Vec<ScannedNode>> {
+    Scanner::new(socket, timeout).scan()
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::{Duration, Instant};
+    use canopen::{AccessType, Entry, ObjectDictionary, Value};
+    use canopen::sim::SimNode;
+    use testing::MockBus;
+    use super::{Identity, ScannedNode, Scanner, OBJ_IDENTITY};
+
+    fn node(node_id: u8, serial: bool) -> SimNode {
+        let entry = |value| Entry::new(Value::Unsigned32(value), AccessType::Const);
+        let mut od = ObjectDictionary::new();
+        od.insert(OBJ_IDENTITY, 1, entry(0x100 + node_id as u32));
+        od.insert(OBJ_IDENTITY, 2, entry(0x42));
+        od.insert(OBJ_IDENTITY, 3, entry(0x10001));
+        if serial {
+            od.insert(OBJ_IDENTITY, 4, entry(1234));
+        }
+        SimNode::new(node_id, od).unwrap()
+    }
+
+    #[test]
+    fn test_scan() {
+        let bus = MockBus::new();
+        let (client, server) = (bus.endpoint(), bus.endpoint());
+        server.set_read_timeout(Duration::from_millis(1)).unwrap();
+
+        let stop = Arc::new(AtomicBool::new(false));
+        let responder = {
+            let stop = stop.clone();
+            thread::spawn(move || {
+                let mut nodes = vec![node(3, true), node(42, false)];
+                while !stop.load(Ordering::SeqCst) {
+                    let request = match server.read_frame() {
+                        Ok(request) => request,
+                        Err(_) => {
+                            thread::sleep(Duration::from_millis(1));
+                            continue;
+                        }
+                    };
+                    for node in &mut nodes {
+                        if let Some(response) = node.handle_frame(&request, Instant::now()) {
+                            server.write_frame(&response).unwrap();
+                        }
+                    }
+                }
+            })
+        };
+
+        let expected = vec![ScannedNode {
+                                node_id: 3,
+                                identity: Identity {
+                                    vendor_id: 0x103,
+                                    product_code: Some(0x42),
+                                    revision: Some(0x10001),
+                                    serial: Some(1234),
+                                },
+                            },
+                            ScannedNode {
+                                node_id: 42,
+                                identity: Identity {
+                                    vendor_id: 0x12a,
+                                    product_code: Some(0x42),
+                                    revision: Some(0x10001),
+                                    serial: None,
+                                },
+                            }];
+
+        let scanner = Scanner::new(&client, Duration::from_millis(200));
+        assert_eq!(scanner.scan().unwrap(), expected);
+        let scanner = Scanner::new(&client, Duration::from_millis(20)).sequential();
+        assert_eq!(scanner.scan_nodes(&[1, 3, 42]).unwrap(), expected);
+
+        stop.store(true, Ordering::SeqCst);
+        responder.join().unwrap();
+    }
+}
@@ -0,0 +1,1554 @@
+//! SDO protocol helpers
+//!
+//! Every SDO message starts with a control byte. Its upper three bits hold
+//! the command specifier; for initiate messages the lower bits describe
+//! how the data is transferred:
+//!
+//! ```text
+//!   7   6   5   4   3   2   1   0
+//! |    cs     | x |   n   | e | s |
+//! ```
+//!
+//! `e` marks an expedited transfer (data contained in the message itself),
+//! `s` indicates that the size is given, and `n` is the number of bytes
+//! in the message that do *not* contain data (only valid if `e` and `s` are
+//! set).
+//!
+//! Segment messages carry up to seven bytes of data after the control
+//! byte, which holds a toggle bit alternating from segment to segment:
+//!
+//! ```text
+//!   7   6   5   4   3   2   1   0
+//! |    cs     | t |     n     | c |
+//! ```
+//!
+//! `n` is the number of bytes that do not contain data and `c` marks the
+//! last segment.
+//!
+//! `upload_expedited` and `download_expedited` implement the client side
+//! of expedited transfers, which cover all objects of up to four bytes.
+//! `SdoClient` transfers objects of any size and converts common types:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use socketcan::CanSocket;
+//! use socketcan::canopen::SdoClient;
+//!
+//! let socket = CanSocket::open("can0").unwrap();
+//! let client = SdoClient::new(&socket, Duration::from_millis(100));
+//! println!("device type {:08X}", client.read_u32(5, 0x1000, 0).unwrap());
+//! println!("device name {}", client.read_string(5, 0x1008, 0).unwrap());
+//! client.write_u16(5, 0x1017, 0, 1000).unwrap();
+//! ```
+//!
+//! With the `tokio` feature, `AsyncSdoClient` runs transfers as futures on
+//! the frames routed by an `AsyncRouter`, so requests to several nodes can
+//! be outstanding at once.
+//!
+//! `SdoServer` implements the server side of expedited and segmented
+//! transfers on an `ObjectDictionary`, for implementing slave devices:
+//!
+//! ```no_run
+//! use std::sync::atomic::AtomicBool;
+//! use socketcan::CanSocket;
+//! use socketcan::canopen::{AccessType, Entry, ObjectDictionary, SdoServer, Value};
+//!
+//! let mut od = ObjectDictionary::new();
+//! od.insert(0x1000, 0, Entry::new(Value::Unsigned32(0x00020192), AccessType::Const));
+//!
+//! let socket = CanSocket::open("can0").unwrap();
+//! let shutdown = AtomicBool::new(false);
+//! SdoServer::new(5).unwrap().serve(&socket, &mut od, &shutdown).unwrap();
+//! ```
+
+use std::{cmp, error, fmt, io, mem};
+#[cfg(feature = "tokio")]
+use std::collections::HashMap;
+#[cfg(feature = "tokio")]
+use std::collections::hash_map::Entry as MapEntry;
+#[cfg(feature = "tokio")]
+use std::future::Future;
+#[cfg(feature = "tokio")]
+use std::pin::Pin;
+#[cfg(feature = "tokio")]
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::AtomicBool;
+#[cfg(feature = "tokio")]
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+#[cfg(feature = "tokio")]
+use tokio::time::{sleep, Sleep};
+use try_from::TryFrom;
+use {CanFrame, CanSocket, CanTransport};
+#[cfg(feature = "tokio")]
+use async_transport::AsyncCanTransport;
+#[cfg(feature = "tokio")]
+use router::{AsyncRouter, Overflow, Subscriber};
+use transport::run_read_loop;
+use util::read_until;
+use super::{check_data_frame, check_node_id, FrameError, ObjectDictionary, OdError, COB_SDO_RX,
+            COB_SDO_TX};
+
+/// Client command specifier: download segment
+pub const CCS_DOWNLOAD_SEGMENT: u8 = 0;
+
+/// Client command specifier: initiate download
+pub const CCS_INITIATE_DOWNLOAD: u8 = 1;
+
+/// Client command specifier: initiate upload
+pub const CCS_INITIATE_UPLOAD: u8 = 2;
+
+/// Client command specifier: upload segment
+pub const CCS_UPLOAD_SEGMENT: u8 = 3;
+
+/// Server command specifier: upload segment response
+pub const SCS_UPLOAD_SEGMENT: u8 = 0;
+
+/// Server command specifier: download segment response
+pub const SCS_DOWNLOAD_SEGMENT: u8 = 1;
+
+/// Server command specifier: initiate upload response
+pub const SCS_INITIATE_UPLOAD: u8 = 2;
+
+/// Server command specifier: initiate download response
+pub const SCS_INITIATE_DOWNLOAD: u8 = 3;
+
+/// Command specifier of abort messages (both directions)
+pub const CS_ABORT: u8 = 4;
+
+/// Abort code: toggle bit not alternated
+pub const ABORT_TOGGLE: u32 = 0x0503_0000;
+
+/// Abort code: command specifier not valid or unknown
+pub const ABORT_INVALID_CS: u32 = 0x0504_0001;
+
+/// Toggle bit of segment messages
+const TOGGLE_BIT: u8 = 0x10;
+
+/// Data bytes of a segment
+const SEGMENT_LEN: usize = 7;
+
+/// Control byte of an SDO initiate message
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct SdoControlByte {
+    /// Command specifier (0..7)
+    pub cs: u8,
+
+    /// Number of bytes not containing data
+    pub n: u8,
+
+    /// Expedited transfer
+    pub expedited: bool,
+
+    /// Size is indicated
+    pub size_indicated: bool,
+}
+
+impl SdoControlByte {
+    /// Control byte of an expedited transfer of `len` (1..4) bytes.
+    pub fn expedited(cs: u8, len: usize) -> SdoControlByte {
+        assert!(len >= 1 && len <= 4, "expedited transfers carry 1 to 4 bytes");
+
+        SdoControlByte {
+            cs: cs,
+            n: 4 - len as u8,
+            expedited: true,
+            size_indicated: true,
+        }
+    }
+
+    /// Control byte of an initiate message of a segmented transfer, with
+    /// the size given in the data bytes.
+    pub fn segmented(cs: u8) -> SdoControlByte {
+        SdoControlByte {
+            cs: cs,
+            n: 0,
+            expedited: false,
+            size_indicated: true,
+        }
+    }
+
+    /// Control byte carrying only a command specifier.
+    pub fn command(cs: u8) -> SdoControlByte {
+        SdoControlByte {
+            cs: cs,
+            n: 0,
+            expedited: false,
+            size_indicated: false,
+        }
+    }
+
+    /// Number of data bytes of an expedited transfer.
+    ///
+    /// If the size is not indicated, all four bytes are considered data.
+    pub fn data_len(&self) -> usize {
+        if self.size_indicated {
+            4 - self.n as usize
+        } else {
+            4
+        }
+    }
+}
+
+impl TryFrom<u8> for SdoControlByte {
+    type Err = FrameError;
+
+    /// Decode a control byte.
+    ///
+    /// Fails for the unused command specifiers 7 and for control bytes
+    /// that indicate unused bytes (`n`) without an expedited, size
+    /// indicated transfer.
+    fn try_from(val: u8) -> Result<SdoControlByte, FrameError> {
+        let cb = SdoControlByte {
+            cs: val >> 5,
+            n: (val >> 2) & 0x03,
+            expedited: val & 0x02 != 0,
+            size_indicated: val & 0x01 != 0,
+        };
+
+        if cb.cs == 7 || (cb.n != 0 && !(cb.expedited && cb.size_indicated)) {
+            return Err(FrameError::InvalidCommand(val));
+        }
+
+        Ok(cb)
+    }
+}
+
+impl From<SdoControlByte> for u8 {
+    fn from(cb: SdoControlByte) -> u8 {
+        (cb.cs & 0x07) << 5 | (cb.n & 0x03) << 2 | (cb.expedited as u8) << 1 |
+        cb.size_indicated as u8
+    }
+}
+
+/// Error of an SDO client transfer
+#[derive(Debug)]
+pub enum SdoError {
+    /// The server did not respond within the timeout
+    Timeout,
+
+    /// The server aborted the transfer with the given abort code
+    Aborted(u32),
+
+    /// The response was not a valid answer to the request, e.g. a
+    /// segmented upload answering `upload_expedited` or a toggle bit that
+    /// did not alternate
+    UnexpectedResponse,
+
+    /// Node ID outside of 1..127
+    InvalidNodeId(u8),
+
+    /// Socket error while sending or receiving
+    Io(io::Error),
+}
+
+impl fmt::Display for SdoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SdoError::Aborted(code) => write!(f, "transfer aborted with code {:08X}", code),
+            SdoError::InvalidNodeId(id) => write!(f, "invalid node ID {}", id),
+            SdoError::Io(ref e) => write!(f, "IO: {}", e),
+            _ => write!(f, "{}", error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for SdoError {
+    fn description(&self) -> &str {
+        match *self {
+            SdoError::Timeout => "sdo timeout",
+            SdoError::Aborted(_) => "sdo transfer aborted",
+            SdoError::UnexpectedResponse => "unexpected sdo response",
+            SdoError::InvalidNodeId(_) => "invalid node id",
+            SdoError::Io(ref e) => e.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            SdoError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for SdoError {
+    fn from(e: io::Error) -> SdoError {
+        SdoError::Io(e)
+    }
+}
+
+/// Build an upload (read) request for the object `index`:`subindex` of
+/// node `node_id`.
+pub fn upload_request(node_id: u8, index: u16, subindex: u8) -> Result<CanFrame, SdoError> {
+    let node_id = check_node_id(node_id).map_err(|_| SdoError::InvalidNodeId(node_id))?;
+    let data = [SdoControlByte::command(CCS_INITIATE_UPLOAD).into(),
+                index as u8,
+                (index >> 8) as u8,
+                subindex,
+                0,
+                0,
+                0,
+                0];
+
+    Ok(CanFrame::new(COB_SDO_RX + node_id as u32, &data, false, false)
+        .expect("SDO request is always valid"))
+}
+
+/// Initiate download request writing `data` to `index`:`subindex` of the
+/// checked `node_id`, expedited for 1 to 4 bytes and segmented otherwise.
+fn download_request(node_id: u8, index: u16, subindex: u8, data: &[u8]) -> CanFrame {
+    let mut request = [0; 8];
+    request[1] = index as u8;
+    request[2] = (index >> 8) as u8;
+    request[3] = subindex;
+    if !data.is_empty() && data.len() <= 4 {
+        request[0] = SdoControlByte::expedited(CCS_INITIATE_DOWNLOAD, data.len()).into();
+        request[4..4 + data.len()].copy_from_slice(data);
+    } else {
+        request[0] = SdoControlByte::segmented(CCS_INITIATE_DOWNLOAD).into();
+        request[4..8].copy_from_slice(&u32_bytes(data.len() as u32));
+    }
+
+    CanFrame::new(COB_SDO_RX + node_id as u32, &request, false, false)
+        .expect("SDO request is always valid")
+}
+
+/// Segment request of `node_id` with control byte `cb`, carrying `data`.
+fn segment_request(node_id: u8, cb: u8, data: &[u8]) -> CanFrame {
+    let mut request = [0; 8];
+    request[0] = cb;
+    request[1..1 + data.len()].copy_from_slice(data);
+
+    CanFrame::new(COB_SDO_RX + node_id as u32, &request, false, false)
+        .expect("SDO request is always valid")
+}
+
+/// Whether `frame` is an SDO response of `node_id` for `index`:`subindex`
+pub fn is_response(frame: &CanFrame, node_id: u8, index: u16, subindex: u8) -> bool {
+    let data = frame.data();
+    frame.id() == COB_SDO_TX + node_id as u32 && !frame.is_extended() && !frame.is_rtr() &&
+    !frame.is_error() && data.len() == 8 &&
+    data[1] as u16 | (data[2] as u16) << 8 == index && data[3] == subindex
+}
+
+fn le_u32(data: &[u8]) -> u32 {
+    data[0] as u32 | (data[1] as u32) << 8 | (data[2] as u32) << 16 | (data[3] as u32) << 24
+}
+
+fn abort_code(data: &[u8]) -> u32 {
+    le_u32(&data[4..8])
+}
+
+/// Control byte of a segment message without data.
+fn toggle_byte(cs: u8, toggle: bool) -> u8 {
+    cs << 5 | if toggle { TOGGLE_BIT } else { 0 }
+}
+
+/// Control byte of a segment message carrying `len` bytes.
+fn segment_byte(cs: u8, toggle: bool, len: usize, last: bool) -> u8 {
+    toggle_byte(cs, toggle) | ((SEGMENT_LEN - len) as u8) << 1 | last as u8
+}
+
+/// Build an SDO abort message of node `node_id` for the multiplexer (index
+/// and subindex) `mux`.
+pub fn abort_frame(node_id: u8, mux: &[u8], code: u32) -> CanFrame {
+    let mut data = [0; 8];
+    data[0] = SdoControlByte::command(CS_ABORT).into();
+    data[1..4].copy_from_slice(mux);
+    data[4] = code as u8;
+    data[5] = (code >> 8) as u8;
+    data[6] = (code >> 16) as u8;
+    data[7] = (code >> 24) as u8;
+
+    CanFrame::new(COB_SDO_TX + node_id as u32, &data, false, false)
+        .expect("SDO abort is always valid")
+}
+
+/// Decode the data of an expedited upload response, as matched by
+/// `is_response`.
+pub fn parse_upload_response(frame: &CanFrame) -> Result<Vec<u8>, SdoError> {
+    let data = frame.data();
+    let cb = SdoControlByte::try_from(data[0]).map_err(|_| SdoError::UnexpectedResponse)?;
+    match cb.cs {
+        CS_ABORT => Err(SdoError::Aborted(abort_code(data))),
+        SCS_INITIATE_UPLOAD if cb.expedited => Ok(data[4..4 + cb.data_len()].to_vec()),
+        _ => Err(SdoError::UnexpectedResponse),
+    }
+}
+
+fn wait_response<T>(socket: &T,
+                    node_id: u8,
+                    index: u16,
+                    subindex: u8,
+                    timeout: Duration)
+                    -> Result<CanFrame, SdoError>
+    where T: CanTransport + ?Sized
+{
+    match read_until(socket, timeout, |frame| is_response(frame, node_id, index, subindex))? {
+        Some(frame) => Ok(frame),
+        None => Err(SdoError::Timeout),
+    }
+}
+
+/// Read the object `index`:`subindex` of node `node_id` with an
+/// expedited upload.
+///
+/// Fails with `UnexpectedResponse` if the server starts a segmented
+/// transfer, i.e. for objects longer than four bytes. Frames received
+/// while waiting for the response are discarded.
+pub fn upload_expedited<T>(socket: &T,
+                           node_id: u8,
+                           index: u16,
+                           subindex: u8,
+                           timeout: Duration)
+                           -> Result<Vec<u8>, SdoError>
+    where T: CanTransport + ?Sized
+{
+    socket.write_frame_insist(&upload_request(node_id, index, subindex)?)?;
+    parse_upload_response(&wait_response(socket, node_id, index, subindex, timeout)?)
+}
+
+/// Write 1 to 4 bytes of `data` to the object `index`:`subindex` of node
+/// `node_id` with an expedited download.
+pub fn download_expedited<T>(socket: &T,
+                             node_id: u8,
+                             index: u16,
+                             subindex: u8,
+                             data: &[u8],
+                             timeout: Duration)
+                             -> Result<(), SdoError>
+    where T: CanTransport + ?Sized
+{
+    assert!(!data.is_empty() && data.len() <= 4,
+            "expedited transfers carry 1 to 4 bytes");
+    let node_id = check_node_id(node_id).map_err(|_| SdoError::InvalidNodeId(node_id))?;
+    socket.write_frame_insist(&download_request(node_id, index, subindex, data))?;
+
+    let response = wait_response(socket, node_id, index, subindex, timeout)?;
+    let data = response.data();
+    match SdoControlByte::try_from(data[0]).map(|cb| cb.cs) {
+        Ok(SCS_INITIATE_DOWNLOAD) => Ok(()),
+        Ok(CS_ABORT) => Err(SdoError::Aborted(abort_code(data))),
+        _ => Err(SdoError::UnexpectedResponse),
+    }
+}
+
+/// Wait for the response of node `node_id` to a segment request.
+///
+/// Segment responses carry no multiplexer, so any SDO response of the
+/// node matches.
+fn wait_segment<T>(socket: &T, node_id: u8, timeout: Duration) -> Result<[u8; 8], SdoError>
+    where T: CanTransport + ?Sized
+{
+    let response = read_until(socket, timeout, |frame| {
+        frame.id() == COB_SDO_TX + node_id as u32 && check_data_frame(frame).is_ok() &&
+        frame.data().len() == 8
+    })?;
+
+    let data = match response {
+        Some(frame) => {
+            let mut data = [0; 8];
+            data.copy_from_slice(frame.data());
+            data
+        }
+        None => return Err(SdoError::Timeout),
+    };
+    if data[0] >> 5 == CS_ABORT {
+        return Err(SdoError::Aborted(abort_code(&data)));
+    }
+    Ok(data)
+}
+
+/// SDO client
+///
+/// Reads and writes objects of remote nodes, with expedited transfers for
+/// up to four bytes and segmented transfers otherwise. Waits up to
+/// `timeout` for every response. Responses are read from the same socket,
+/// so other traffic received in the meantime is discarded.
+#[derive(Debug)]
+pub struct SdoClient<'a, T: 'a = CanSocket> {
+    socket: &'a T,
+    timeout: Duration,
+}
+
+impl<'a, T: CanTransport> SdoClient<'a, T> {
+    /// Create a new SDO client.
+    pub fn new(socket: &'a T, timeout: Duration) -> SdoClient<'a, T> {
+        SdoClient {
+            socket: socket,
+            timeout: timeout,
+        }
+    }
+
+    /// The socket requests are sent on
+    #[inline]
+    pub fn socket(&self) -> &'a T {
+        self.socket
+    }
+
+    /// Read the object `index`:`subindex` of node `node_id`.
+    pub fn upload(&self, node_id: u8, index: u16, subindex: u8) -> Result<Vec<u8>, SdoError> {
+        self.socket.write_frame_insist(&upload_request(node_id, index, subindex)?)?;
+        let response = wait_response(self.socket, node_id, index, subindex, self.timeout)?;
+
+        let data = response.data();
+        let cb = SdoControlByte::try_from(data[0]).map_err(|_| SdoError::UnexpectedResponse)?;
+        match cb.cs {
+            CS_ABORT => return Err(SdoError::Aborted(abort_code(data))),
+            SCS_INITIATE_UPLOAD if cb.expedited => return Ok(data[4..4 + cb.data_len()].to_vec()),
+            SCS_INITIATE_UPLOAD => {}
+            _ => return Err(SdoError::UnexpectedResponse),
+        }
+
+        let size = if cb.size_indicated { Some(le_u32(&data[4..8]) as usize) } else { None };
+        let mut value = Vec::with_capacity(size.unwrap_or(0));
+        let mut toggle = false;
+        loop {
+            self.send_segment(node_id, toggle_byte(CCS_UPLOAD_SEGMENT, toggle), &[])?;
+            let data = wait_segment(self.socket, node_id, self.timeout)?;
+            if data[0] >> 5 != SCS_UPLOAD_SEGMENT || (data[0] & TOGGLE_BIT != 0) != toggle {
+                return Err(SdoError::UnexpectedResponse);
+            }
+
+            let unused = (data[0] >> 1 & 0x07) as usize;
+            value.extend_from_slice(&data[1..1 + SEGMENT_LEN - unused]);
+            if data[0] & 0x01 != 0 {
+                break;
+            }
+            toggle = !toggle;
+        }
+
+        if size.map_or(false, |size| size != value.len()) {
+            return Err(SdoError::UnexpectedResponse);
+        }
+        Ok(value)
+    }
+
+    /// Write `data` to the object `index`:`subindex` of node `node_id`.
+    pub fn download(&self,
+                    node_id: u8,
+                    index: u16,
+                    subindex: u8,
+                    data: &[u8])
+                    -> Result<(), SdoError> {
+        self.download_with_progress(node_id, index, subindex, data, |_| {})
+    }
+
+    /// Write `data` like `download`, passing the number of bytes the node
+    /// confirmed so far to `progress` after every segment.
+    pub fn download_with_progress<F>(&self,
+                                     node_id: u8,
+                                     index: u16,
+                                     subindex: u8,
+                                     data: &[u8],
+                                     mut progress: F)
+                                     -> Result<(), SdoError>
+        where F: FnMut(usize)
+    {
+        if !data.is_empty() && data.len() <= 4 {
+            download_expedited(self.socket, node_id, index, subindex, data, self.timeout)?;
+            progress(data.len());
+            return Ok(());
+        }
+        let node_id = check_node_id(node_id).map_err(|_| SdoError::InvalidNodeId(node_id))?;
+        self.socket.write_frame_insist(&download_request(node_id, index, subindex, data))?;
+
+        let response = wait_response(self.socket, node_id, index, subindex, self.timeout)?;
+        let response = response.data();
+        match SdoControlByte::try_from(response[0]).map(|cb| cb.cs) {
+            Ok(SCS_INITIATE_DOWNLOAD) => {}
+            Ok(CS_ABORT) => return Err(SdoError::Aborted(abort_code(response))),
+            _ => return Err(SdoError::UnexpectedResponse),
+        }
+
+        let mut toggle = false;
+        let mut sent = 0;
+        loop {
+            let len = cmp::min(data.len() - sent, SEGMENT_LEN);
+            let last = sent + len == data.len();
+            let cb = segment_byte(CCS_DOWNLOAD_SEGMENT, toggle, len, last);
+            self.send_segment(node_id, cb, &data[sent..sent + len])?;
+
+            let response = wait_segment(self.socket, node_id, self.timeout)?;
+            if response[0] >> 5 != SCS_DOWNLOAD_SEGMENT ||
+               (response[0] & TOGGLE_BIT != 0) != toggle {
+                return Err(SdoError::UnexpectedResponse);
+            }
+
+            sent += len;
+            progress(sent);
+            if last {
+                return Ok(());
+            }
+            toggle = !toggle;
+        }
+    }
+
+    /// Read an `UNSIGNED8` object.
+    pub fn read_u8(&self, node_id: u8, index: u16, subindex: u8) -> Result<u8, SdoError> {
+        self.read_fixed(node_id, index, subindex, 1).map(|data| data[0])
+    }
+
+    /// Read an `UNSIGNED16` object.
+    pub fn read_u16(&self, node_id: u8, index: u16, subindex: u8) -> Result<u16, SdoError> {
+        self.read_fixed(node_id, index, subindex, 2).map(|data| le_u32(&data) as u16)
+    }
+
+    /// Read an `UNSIGNED32` object.
+    pub fn read_u32(&self, node_id: u8, index: u16, subindex: u8) -> Result<u32, SdoError> {
+        self.read_fixed(node_id, index, subindex, 4).map(|data| le_u32(&data))
+    }
+
+    /// Read a `VISIBLE_STRING` object.
+    ///
+    /// Bytes that are not valid UTF-8 are replaced.
+    pub fn read_string(&self, node_id: u8, index: u16, subindex: u8) -> Result<String, SdoError> {
+        let data = self.upload(node_id, index, subindex)?;
+        Ok(String::from_utf8_lossy(&data).into_owned())
+    }
+
+    /// Write an `UNSIGNED8` object.
+    pub fn write_u8(&self, node_id: u8, index: u16, subindex: u8, val: u8) -> Result<(), SdoError> {
+        self.download(node_id, index, subindex, &[val])
+    }
+
+    /// Write an `UNSIGNED16` object.
+    pub fn write_u16(&self,
+                     node_id: u8,
+                     index: u16,
+                     subindex: u8,
+                     val: u16)
+                     -> Result<(), SdoError> {
+        self.download(node_id, index, subindex, &u32_bytes(val as u32)[..2])
+    }
+
+    /// Write an `UNSIGNED32` object.
+    pub fn write_u32(&self,
+                     node_id: u8,
+                     index: u16,
+                     subindex: u8,
+                     val: u32)
+                     -> Result<(), SdoError> {
+        self.download(node_id, index, subindex, &u32_bytes(val))
+    }
+
+    /// Write a `VISIBLE_STRING` object.
+    pub fn write_string(&self,
+                        node_id: u8,
+                        index: u16,
+                        subindex: u8,
+                        val: &str)
+                        -> Result<(), SdoError> {
+        self.download(node_id, index, subindex, val.as_bytes())
+    }
+
+    /// Read an object of `len` bytes, zero-extended to four bytes.
+    ///
+    /// Longer responses are accepted, as servers may answer expedited
+    /// uploads with four bytes without indicating the size.
+    fn read_fixed(&self,
+                  node_id: u8,
+                  index: u16,
+                  subindex: u8,
+                  len: usize)
+                  -> Result<[u8; 4], SdoError> {
+        let data = self.upload(node_id, index, subindex)?;
+        if data.len() < len {
+            return Err(SdoError::UnexpectedResponse);
+        }
+
+        let mut val = [0; 4];
+        val[..len].copy_from_slice(&data[..len]);
+        Ok(val)
+    }
+
+    fn send_segment(&self, node_id: u8, cb: u8, data: &[u8]) -> io::Result<()> {
+        self.socket.write_frame_insist(&segment_request(node_id, cb, data))
+    }
+}
+
+/// Queue depth of the response subscription of an asynchronous transfer
+#[cfg(feature = "tokio")]
+const RESPONSE_CAPACITY: usize = 4;
+
+/// Asynchronous SDO client
+///
+/// Transfers like `SdoClient`, but as futures driven by the responses an
+/// `AsyncRouter` routes to them, so transfers to different nodes proceed
+/// concurrently. Initiate responses are matched to their request by node
+/// ID, index and subindex. A server handles one transfer at a time, so a
+/// transfer to a node that is busy waits until the previous one finished.
+///
+/// Waits up to `timeout` for every response, which requires the runtime's
+/// timer to be enabled. The client is cheap to clone; the futures it
+/// returns own a clone and can be spawned.
+///
+/// ```no_run
+/// extern crate socketcan;
+/// extern crate tokio;
+///
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use socketcan::async_transport::AsyncCanSocket;
+/// use socketcan::canopen::AsyncSdoClient;
+/// use socketcan::router::AsyncRouter;
+///
+/// fn main() {
+///     let rt = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+///     let _runtime = rt.enter();
+///
+///     let router = AsyncRouter::spawn(AsyncCanSocket::open("can0").unwrap());
+///     let client = AsyncSdoClient::new(Arc::new(router), Duration::from_millis(100));
+///     let names: Vec<_> = (1..4).map(|node| rt.spawn(client.upload(node, 0x1008, 0))).collect();
+///     for name in names {
+///         println!("{:?}", rt.block_on(name).unwrap());
+///     }
+/// }
+/// ```
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct AsyncSdoClient<T> {
+    router: Arc<AsyncRouter<T>>,
+    timeout: Duration,
+    // nodes with a transfer in progress, and the transfers waiting for them
+    busy: Arc<Mutex<HashMap<u8, Vec<Waker>>>>,
+}
+
+#[cfg(feature = "tokio")]
+impl<T> Clone for AsyncSdoClient<T> {
+    fn clone(&self) -> AsyncSdoClient<T> {
+        AsyncSdoClient {
+            router: self.router.clone(),
+            timeout: self.timeout,
+            busy: self.busy.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T: AsyncCanTransport + Send + Sync + 'static> AsyncSdoClient<T> {
+    /// Create a new SDO client, sending requests on the transport of
+    /// `router`.
+    pub fn new(router: Arc<AsyncRouter<T>>, timeout: Duration) -> AsyncSdoClient<T> {
+        AsyncSdoClient {
+            router: router,
+            timeout: timeout,
+            busy: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// The router responses are received from
+    pub fn router(&self) -> &Arc<AsyncRouter<T>> {
+        &self.router
+    }
+
+    /// Read the object `index`:`subindex` of node `node_id`.
+    pub fn upload(&self, node_id: u8, index: u16, subindex: u8) -> Upload<T> {
+        let exchange = upload_request(node_id, index, subindex)
+            .map(|request| Exchange::new(self.clone(), node_id, request));
+        Upload {
+            exchange: exchange.map_err(Some),
+            value: Vec::new(),
+            size: None,
+            toggle: None,
+        }
+    }
+
+    /// Write `data` to the object `index`:`subindex` of node `node_id`.
+    pub fn download(&self, node_id: u8, index: u16, subindex: u8, data: &[u8]) -> Download<T> {
+        let exchange = check_node_id(node_id)
+            .map(|node_id| {
+                let request = download_request(node_id, index, subindex, data);
+                Exchange::new(self.clone(), node_id, request)
+            })
+            .map_err(|_| Some(SdoError::InvalidNodeId(node_id)));
+        Download {
+            exchange: exchange,
+            data: data.to_vec(),
+            sent: 0,
+            toggle: None,
+        }
+    }
+}
+
+/// Request/response exchange of an asynchronous transfer with one node
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+struct Exchange<T> {
+    client: AsyncSdoClient<T>,
+    node_id: u8,
+    // multiplexer an initiate response has to carry, `None` for segments
+    mux: Option<[u8; 3]>,
+    request: Option<CanFrame>,
+    // subscribed once the node is no longer busy
+    responses: Option<Subscriber>,
+    deadline: Option<Pin<Box<Sleep>>>,
+}
+
+#[cfg(feature = "tokio")]
+impl<T: AsyncCanTransport + Send + Sync + 'static> Exchange<T> {
+    fn new(client: AsyncSdoClient<T>, node_id: u8, request: CanFrame) -> Exchange<T> {
+        let mut mux = [0; 3];
+        mux.copy_from_slice(&request.data()[1..4]);
+        Exchange {
+            client: client,
+            node_id: node_id,
+            mux: Some(mux),
+            request: Some(request),
+            responses: None,
+            deadline: None,
+        }
+    }
+
+    /// Send the segment request with control byte `cb` and `data` next.
+    fn send_segment(&mut self, cb: u8, data: &[u8]) {
+        self.mux = None;
+        self.request = Some(segment_request(self.node_id, cb, data));
+    }
+
+    /// Take over the node, or register to be woken once it is free.
+    fn poll_acquire(&mut self, cx: &mut Context) -> Poll<()> {
+        if self.responses.is_some() {
+            return Poll::Ready(());
+        }
+
+        let mut busy = self.client.busy.lock().expect("busy nodes lock poisoned");
+        match busy.entry(self.node_id) {
+            MapEntry::Occupied(mut waiting) => {
+                if !waiting.get().iter().any(|waker| waker.will_wake(cx.waker())) {
+                    waiting.get_mut().push(cx.waker().clone());
+                }
+                return Poll::Pending;
+            }
+            MapEntry::Vacant(entry) => {
+                entry.insert(Vec::new());
+            }
+        }
+
+        // subscribe before sending, so a fast response is not missed
+        let cob_id = COB_SDO_TX + self.node_id as u32;
+        let responses = self.client
+            .router
+            .subscribe(cob_id, cob_id, RESPONSE_CAPACITY, Overflow::DropOldest);
+        self.responses = Some(responses);
+        Poll::Ready(())
+    }
+
+    /// Send the pending request and wait for its response.
+    fn poll_response(&mut self, cx: &mut Context) -> Poll<Result<[u8; 8], SdoError>> {
+        if self.poll_acquire(cx).is_pending() {
+            return Poll::Pending;
+        }
+
+        if let Some(request) = self.request {
+            match self.client.router.transport().poll_write_frame(cx, &request) {
+                Poll::Ready(Ok(())) => {}
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e.into())),
+                Poll::Pending => return Poll::Pending,
+            }
+            self.request = None;
+            self.deadline = Some(Box::pin(sleep(self.client.timeout)));
+        }
+
+        let responses = self.responses.as_ref().expect("node acquired");
+        loop {
+            let frame = match responses.poll_recv(cx) {
+                Poll::Ready(Some(frame)) => frame,
+                Poll::Ready(None) => {
+                    let stopped = io::Error::new(io::ErrorKind::BrokenPipe, "router stopped");
+                    return Poll::Ready(Err(stopped.into()));
+                }
+                Poll::Pending => break,
+            };
+
+            let matches = match self.mux {
+                Some(mux) => {
+                    let index = mux[0] as u16 | (mux[1] as u16) << 8;
+                    is_response(&frame, self.node_id, index, mux[2])
+                }
+                None => check_data_frame(&frame).is_ok() && frame.data().len() == 8,
+            };
+            if matches {
+                let mut data = [0; 8];
+                data.copy_from_slice(frame.data());
+                return Poll::Ready(Ok(data));
+            }
+        }
+
+        match self.deadline.as_mut().expect("request sent").as_mut().poll(cx) {
+            Poll::Ready(()) => Poll::Ready(Err(SdoError::Timeout)),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T> Drop for Exchange<T> {
+    fn drop(&mut self) {
+        if self.responses.is_none() {
+            return;
+        }
+
+        // hand the node over to the transfers waiting for it
+        let waiting = self.client
+            .busy
+            .lock()
+            .expect("busy nodes lock poisoned")
+            .remove(&self.node_id);
+        for waker in waiting.unwrap_or_default() {
+            waker.wake();
+        }
+    }
+}
+
+/// Future returned by `AsyncSdoClient::upload`
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct Upload<T> {
+    // the error of a request that could not be built, until returned
+    exchange: Result<Exchange<T>, Option<SdoError>>,
+    value: Vec<u8>,
+    size: Option<usize>,
+    // toggle bit of the next segment, once the transfer is segmented
+    toggle: Option<bool>,
+}
+
+#[cfg(feature = "tokio")]
+impl<T: AsyncCanTransport + Send + Sync + 'static> Upload<T> {
+    /// Process a response, returning the value once complete.
+    fn step(&mut self, data: [u8; 8]) -> Result<Option<Vec<u8>>, SdoError> {
+        if data[0] >> 5 == CS_ABORT {
+            return Err(SdoError::Aborted(abort_code(&data)));
+        }
+
+        let toggle = match self.toggle {
+            None => {
+                let cb = SdoControlByte::try_from(data[0])
+                    .map_err(|_| SdoError::UnexpectedResponse)?;
+                match cb.cs {
+                    SCS_INITIATE_UPLOAD if cb.expedited => {
+                        return Ok(Some(data[4..4 + cb.data_len()].to_vec()))
+                    }
+                    SCS_INITIATE_UPLOAD => {}
+                    _ => return Err(SdoError::UnexpectedResponse),
+                }
+                if cb.size_indicated {
+                    self.size = Some(le_u32(&data[4..8]) as usize);
+                }
+                false
+            }
+            Some(toggle) => {
+                if data[0] >> 5 != SCS_UPLOAD_SEGMENT || (data[0] & TOGGLE_BIT != 0) != toggle {
+                    return Err(SdoError::UnexpectedResponse);
+                }
+
+                let unused = (data[0] >> 1 & 0x07) as usize;
+                self.value.extend_from_slice(&data[1..1 + SEGMENT_LEN - unused]);
+                if data[0] & 0x01 != 0 {
+                    if self.size.map_or(false, |size| size != self.value.len()) {
+                        return Err(SdoError::UnexpectedResponse);
+                    }
+                    return Ok(Some(mem::replace(&mut self.value, Vec::new())));
+                }
+                !toggle
+            }
+        };
+
+        self.toggle = Some(toggle);
+        if let Ok(ref mut exchange) = self.exchange {
+            exchange.send_segment(toggle_byte(CCS_UPLOAD_SEGMENT, toggle), &[]);
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T: AsyncCanTransport + Send + Sync + 'static> Future for Upload<T> {
+    type Output = Result<Vec<u8>, SdoError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<Vec<u8>, SdoError>> {
+        let this = self.get_mut();
+        loop {
+            let response = match this.exchange {
+                Ok(ref mut exchange) => exchange.poll_response(cx),
+                Err(ref mut e) => {
+                    return Poll::Ready(Err(e.take().expect("upload polled after completion")))
+                }
+            };
+            match response {
+                Poll::Ready(Ok(data)) => {
+                    match this.step(data) {
+                        Ok(Some(value)) => return Poll::Ready(Ok(value)),
+                        Ok(None) => {}
+                        Err(e) => return Poll::Ready(Err(e)),
+                    }
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Future returned by `AsyncSdoClient::download`
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct Download<T> {
+    exchange: Result<Exchange<T>, Option<SdoError>>,
+    data: Vec<u8>,
+    sent: usize,
+    // toggle bit of the segment sent last, once the transfer is segmented
+    toggle: Option<bool>,
+}
+
+#[cfg(feature = "tokio")]
+impl<T: AsyncCanTransport + Send + Sync + 'static> Download<T> {
+    /// Process a response, returning `true` once complete.
+    fn step(&mut self, response: [u8; 8]) -> Result<bool, SdoError> {
+        let cs = response[0] >> 5;
+        if cs == CS_ABORT {
+            return Err(SdoError::Aborted(abort_code(&response)));
+        }
+
+        let toggle = match self.toggle {
+            None if cs != SCS_INITIATE_DOWNLOAD => return Err(SdoError::UnexpectedResponse),
+            None if !self.data.is_empty() && self.data.len() <= 4 => return Ok(true),
+            None => false,
+            Some(toggle) => {
+                if cs != SCS_DOWNLOAD_SEGMENT || (response[0] & TOGGLE_BIT != 0) != toggle {
+                    return Err(SdoError::UnexpectedResponse);
+                }
+                if self.sent == self.data.len() {
+                    return Ok(true);
+                }
+                !toggle
+            }
+        };
+
+        let len = cmp::min(self.data.len() - self.sent, SEGMENT_LEN);
+        let last = self.sent + len == self.data.len();
+        let cb = segment_byte(CCS_DOWNLOAD_SEGMENT, toggle, len, last);
+        if let Ok(ref mut exchange) = self.exchange {
+            exchange.send_segment(cb, &self.data[self.sent..self.sent + len]);
+        }
+        self.sent += len;
+        self.toggle = Some(toggle);
+        Ok(false)
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T: AsyncCanTransport + Send + Sync + 'static> Future for Download<T> {
+    type Output = Result<(), SdoError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), SdoError>> {
+        let this = self.get_mut();
+        loop {
+            let response = match this.exchange {
+                Ok(ref mut exchange) => exchange.poll_response(cx),
+                Err(ref mut e) => {
+                    return Poll::Ready(Err(e.take().expect("download polled after completion")))
+                }
+            };
+            match response {
+                Poll::Ready(Ok(data)) => {
+                    match this.step(data) {
+                        Ok(true) => return Poll::Ready(Ok(())),
+                        Ok(false) => {}
+                        Err(e) => return Poll::Ready(Err(e)),
+                    }
+                }
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Segmented transfer in progress on a server
+#[derive(Debug)]
+enum Transfer {
+    Idle,
+
+    /// Upload of `data`, of which `sent` bytes were sent
+    Upload {
+        data: Vec<u8>,
+        sent: usize,
+        toggle: bool,
+    },
+
+    /// Download of the size announced by the client, if any
+    Download {
+        data: Vec<u8>,
+        size: Option<usize>,
+        toggle: bool,
+    },
+}
+
+/// Server side of SDO transfers
+///
+/// Answers the requests of a client on an object dictionary that is passed
+/// to every call, so the application can access it in between. Expedited
+/// and segmented transfers are supported, block transfers are aborted.
+/// Only one transfer is in progress at a time; a new initiate request
+/// replaces the current one.
+#[derive(Debug)]
+pub struct SdoServer {
+    node_id: u8,
+    mux: [u8; 3],
+    transfer: Transfer,
+}
+
+impl SdoServer {
+    /// Create a server answering requests to `node_id`.
+    pub fn new(node_id: u8) -> Result<SdoServer, FrameError> {
+        Ok(SdoServer {
+            node_id: check_node_id(node_id)?,
+            mux: [0; 3],
+            transfer: Transfer::Idle,
+        })
+    }
+
+    /// Node ID
+    #[inline]
+    pub fn node_id(&self) -> u8 {
+        self.node_id
+    }
+
+    /// Process a received frame, returning the response if any.
+    ///
+    /// Frames other than SDO requests to the node are ignored.
+    pub fn handle_frame(&mut self,
+                        od: &mut ObjectDictionary,
+                        frame: &CanFrame)
+                        -> Option<CanFrame> {
+        if frame.id() != COB_SDO_RX + self.node_id as u32 || check_data_frame(frame).is_err() ||
+           frame.data().len() != 8 {
+            return None;
+        }
+
+        let data = frame.data();
+        let cs = data[0] >> 5;
+
+        // segments carry data where initiate messages carry the multiplexer
+        if cs != CCS_DOWNLOAD_SEGMENT && cs != CCS_UPLOAD_SEGMENT {
+            self.mux.copy_from_slice(&data[1..4]);
+        }
+
+        let transfer = mem::replace(&mut self.transfer, Transfer::Idle);
+        let result = match (cs, transfer) {
+            (CCS_INITIATE_UPLOAD, _) => self.initiate_upload(od),
+            (CCS_INITIATE_DOWNLOAD, _) => self.initiate_download(od, data),
+            (CCS_UPLOAD_SEGMENT, Transfer::Upload { data: value, sent, toggle }) => {
+                self.upload_segment(data[0], value, sent, toggle)
+            }
+            (CCS_DOWNLOAD_SEGMENT, Transfer::Download { data: value, size, toggle }) => {
+                self.download_segment(od, data, value, size, toggle)
+            }
+            // abort transfer, nothing to answer
+            (CS_ABORT, _) => return None,
+            _ => Err(ABORT_INVALID_CS),
+        };
+
+        Some(match result {
+            Ok(response) => {
+                CanFrame::new(COB_SDO_TX + self.node_id as u32, &response, false, false)
+                    .expect("SDO response is always valid")
+            }
+            Err(code) => {
+                self.transfer = Transfer::Idle;
+                abort_frame(self.node_id, &self.mux, code)
+            }
+        })
+    }
+
+    /// Answer the SDO requests read from `socket` until `shutdown` is set.
+    ///
+    /// Other frames are discarded. The shutdown flag is checked at least
+    /// every `SHUTDOWN_POLL_INTERVAL_MS`. Returns the number of frames read.
+    pub fn serve<T>(&mut self,
+                    socket: &T,
+                    od: &mut ObjectDictionary,
+                    shutdown: &AtomicBool)
+                    -> io::Result<u64>
+        where T: CanTransport + ?Sized
+    {
+        run_read_loop(socket,
+                      |frame| match self.handle_frame(od, &frame) {
+                          Some(response) => socket.write_frame_insist(&response),
+                          None => Ok(()),
+                      },
+                      shutdown)
+    }
+
+    fn index(&self) -> (u16, u8) {
+        (self.mux[0] as u16 | (self.mux[1] as u16) << 8, self.mux[2])
+    }
+
+    fn response(&self, cb: u8) -> [u8; 8] {
+        [cb, self.mux[0], self.mux[1], self.mux[2], 0, 0, 0, 0]
+    }
+
+    fn initiate_upload(&mut self, od: &ObjectDictionary) -> Result<[u8; 8], u32> {
+        let (index, subindex) = self.index();
+        let value = od.read(index, subindex).map_err(|e| e.abort_code())?;
+
+        if !value.is_empty() && value.len() <= 4 {
+            let mut response =
+                self.response(SdoControlByte::expedited(SCS_INITIATE_UPLOAD, value.len()).into());
+            response[4..4 + value.len()].copy_from_slice(&value);
+            return Ok(response);
+        }
+
+        let mut response = self.response(SdoControlByte::segmented(SCS_INITIATE_UPLOAD).into());
+        response[4..8].copy_from_slice(&u32_bytes(value.len() as u32));
+        self.transfer = Transfer::Upload {
+            data: value,
+            sent: 0,
+            toggle: false,
+        };
+        Ok(response)
+    }
+
+    fn upload_segment(&mut self,
+                      cb: u8,
+                      value: Vec<u8>,
+                      sent: usize,
+                      toggle: bool)
+                      -> Result<[u8; 8], u32> {
+        if (cb & TOGGLE_BIT != 0) != toggle {
+            return Err(ABORT_TOGGLE);
+        }
+
+        let len = cmp::min(value.len() - sent, SEGMENT_LEN);
+        let last = sent + len == value.len();
+        let mut response = [0; 8];
+        response[0] = segment_byte(SCS_UPLOAD_SEGMENT, toggle, len, last);
+        response[1..1 + len].copy_from_slice(&value[sent..sent + len]);
+
+        if !last {
+            self.transfer = Transfer::Upload {
+                data: value,
+                sent: sent + len,
+                toggle: !toggle,
+            };
+        }
+        Ok(response)
+    }
+
+    fn initiate_download(&mut self,
+                         od: &mut ObjectDictionary,
+                         data: &[u8])
+                         -> Result<[u8; 8], u32> {
+        let (index, subindex) = self.index();
+        let cb = SdoControlByte::try_from(data[0]).map_err(|_| ABORT_INVALID_CS)?;
+
+        if cb.expedited {
+            od.write(index, subindex, &data[4..4 + cb.data_len()]).map_err(|e| e.abort_code())?;
+        } else {
+            let entry = od.entry(index, subindex).map_err(|e| e.abort_code())?;
+            if !entry.access().is_writable() {
+                return Err(OdError::ReadOnly.abort_code());
+            }
+
+            let size = if cb.size_indicated {
+                Some(le_u32(&data[4..8]) as usize)
+            } else {
+                None
+            };
+            self.transfer = Transfer::Download {
+                data: Vec::new(),
+                size: size,
+                toggle: false,
+            };
+        }
+
+        Ok(self.response(SdoControlByte::command(SCS_INITIATE_DOWNLOAD).into()))
+    }
+
+    fn download_segment(&mut self,
+                        od: &mut ObjectDictionary,
+                        data: &[u8],
+                        mut value: Vec<u8>,
+                        size: Option<usize>,
+                        toggle: bool)
+                        -> Result<[u8; 8], u32> {
+        if (data[0] & TOGGLE_BIT != 0) != toggle {
+            return Err(ABORT_TOGGLE);
+        }
+
+        let unused = (data[0] >> 1 & 0x07) as usize;
+        value.extend_from_slice(&data[1..1 + SEGMENT_LEN - unused]);
+        if size.map_or(false, |size| value.len() > size) {
+            return Err(OdError::LengthMismatch.abort_code());
+        }
+
+        let mut response = [0; 8];
+        response[0] = toggle_byte(SCS_DOWNLOAD_SEGMENT, toggle);
+
+        if data[0] & 0x01 == 0 {
+            self.transfer = Transfer::Download {
+                data: value,
+                size: size,
+                toggle: !toggle,
+            };
+            return Ok(response);
+        }
+
+        if size.map_or(false, |size| value.len() != size) {
+            return Err(OdError::LengthMismatch.abort_code());
+        }
+        let (index, subindex) = self.index();
+        od.write(index, subindex, &value).map_err(|e| e.abort_code())?;
+        Ok(response)
+    }
+}
+
+fn u32_bytes(val: u32) -> [u8; 4] {
+    [val as u8, (val >> 8) as u8, (val >> 16) as u8, (val >> 24) as u8]
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::{Duration, Instant};
+    use try_from::TryFrom;
+    use CanFrame;
+    use canopen::{AccessType, Entry, ObjectDictionary, Value};
+    use canopen::sim::SimNode;
+    use testing::MockBus;
+    use super::{download_expedited, upload_expedited, SdoClient, SdoControlByte, SdoError,
+                SdoServer, CCS_INITIATE_DOWNLOAD};
+
+    #[test]
+    fn test_control_byte() {
+        let cb = SdoControlByte::try_from(0x2f).unwrap();
+        assert_eq!(cb, SdoControlByte::expedited(CCS_INITIATE_DOWNLOAD, 1));
+        assert_eq!(cb.data_len(), 1);
+        assert_eq!(u8::from(cb), 0x2f);
+
+        assert_eq!(u8::from(SdoControlByte::command(3)), 0x60);
+        assert!(SdoControlByte::try_from(0xe0).is_err());
+        assert!(SdoControlByte::try_from(0x2c).is_err());
+    }
+
+    #[test]
+    fn test_expedited_transfers() {
+        let bus = MockBus::new();
+        let (client, server) = (bus.endpoint(), bus.endpoint());
+        let responder = thread::spawn(move || {
+            let mut od = ObjectDictionary::new();
+            od.insert(0x1000, 0, Entry::new(Value::Unsigned32(0x00020192), AccessType::Const));
+            od.insert(0x2000, 0, Entry::new(Value::Unsigned16(0), AccessType::ReadWrite));
+            let mut node = SimNode::new(5, od).unwrap();
+
+            for _ in 0..4 {
+                let request = server.read_frame().unwrap();
+                let response = node.handle_frame(&request, Instant::now()).unwrap();
+                server.write_frame(&response).unwrap();
+            }
+        });
+
+        let timeout = Duration::from_secs(5);
+        assert_eq!(upload_expedited(&client, 5, 0x1000, 0, timeout).unwrap(),
+                   vec![0x92, 0x01, 0x02, 0x00]);
+        download_expedited(&client, 5, 0x2000, 0, &[0x34, 0x12], timeout).unwrap();
+        assert_eq!(upload_expedited(&client, 5, 0x2000, 0, timeout).unwrap(), vec![0x34, 0x12]);
+        match download_expedited(&client, 5, 0x1000, 0, &[0; 4], timeout) {
+            Err(SdoError::Aborted(_)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        responder.join().unwrap();
+
+        match upload_expedited(&client, 5, 0x1000, 0, Duration::from_millis(5)) {
+            Err(SdoError::Timeout) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        assert!(upload_expedited(&client, 0, 0x1000, 0, timeout).is_err());
+    }
+
+    #[test]
+    fn test_server_segmented() {
+        let mut od = ObjectDictionary::new();
+        od.insert(0x1000, 0, Entry::new(Value::Unsigned32(0x00020192), AccessType::Const));
+        od.insert(0x1008,
+                  0,
+                  Entry::new(Value::VisibleString("socketcan-rs".into()), AccessType::Const));
+        od.insert(0x2000,
+                  0,
+                  Entry::new(Value::VisibleString(String::new()), AccessType::ReadWrite));
+        let mut server = SdoServer::new(5).unwrap();
+        let mut request = |data: [u8; 8]| {
+            let frame = CanFrame::new(0x605, &data, false, false).unwrap();
+            let response = server.handle_frame(&mut od, &frame).unwrap();
+            assert_eq!(response.id(), 0x585);
+            response.data().to_vec()
+        };
+
+        assert_eq!(request([0x40, 0x08, 0x10, 0, 0, 0, 0, 0]),
+                   [0x41, 0x08, 0x10, 0, 12, 0, 0, 0]);
+        assert_eq!(request([0x60, 0, 0, 0, 0, 0, 0, 0]), b"\x00socketc");
+        assert_eq!(request([0x70, 0, 0, 0, 0, 0, 0, 0]), b"\x15an-rs\0\0");
+        assert_eq!(request([0x60, 0, 0, 0, 0, 0, 0, 0]),
+                   [0x80, 0x08, 0x10, 0, 0x01, 0x00, 0x04, 0x05]);
+
+        // toggle bit not alternated
+        request([0x40, 0x08, 0x10, 0, 0, 0, 0, 0]);
+        assert_eq!(request([0x70, 0, 0, 0, 0, 0, 0, 0]),
+                   [0x80, 0x08, 0x10, 0, 0x00, 0x00, 0x03, 0x05]);
+
+        assert_eq!(request([0x21, 0x00, 0x20, 0, 9, 0, 0, 0]), [0x60, 0x00, 0x20, 0, 0, 0, 0, 0]);
+        assert_eq!(request([0x00, b'a', b'b', b'c', b'd', b'e', b'f', b'g']),
+                   [0x20, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(request([0x1b, b'h', b'i', 0, 0, 0, 0, 0]), [0x30, 0, 0, 0, 0, 0, 0, 0]);
+        assert_eq!(od.get(0x2000, 0).unwrap(), &Value::VisibleString("abcdefghi".into()));
+
+        let mut request = |data: [u8; 8]| {
+            let frame = CanFrame::new(0x605, &data, false, false).unwrap();
+            server.handle_frame(&mut od, &frame).unwrap().data().to_vec()
+        };
+        assert_eq!(request([0x21, 0x00, 0x10, 0, 9, 0, 0, 0]),
+                   [0x80, 0x00, 0x10, 0, 0x02, 0x00, 0x01, 0x06]);
+        let other = CanFrame::new(0x606, &[0x40, 0x00, 0x10, 0, 0, 0, 0, 0], false, false)
+            .unwrap();
+        assert!(server.handle_frame(&mut od, &other).is_none());
+    }
+
+    #[test]
+    fn test_serve() {
+        let bus = MockBus::new();
+        let (client, server) = (bus.endpoint(), bus.endpoint());
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let flag = shutdown.clone();
+        let responder = thread::spawn(move || {
+            let mut od = ObjectDictionary::new();
+            od.insert(0x2000, 0, Entry::new(Value::Unsigned16(0), AccessType::ReadWrite));
+            SdoServer::new(5).unwrap().serve(&server, &mut od, &flag).unwrap();
+            od.get(0x2000, 0).unwrap().clone()
+        });
+
+        let timeout = Duration::from_secs(5);
+        download_expedited(&client, 5, 0x2000, 0, &[0x34, 0x12], timeout).unwrap();
+        assert_eq!(upload_expedited(&client, 5, 0x2000, 0, timeout).unwrap(), vec![0x34, 0x12]);
+
+        shutdown.store(true, Ordering::SeqCst);
+        assert_eq!(responder.join().unwrap(), Value::Unsigned16(0x1234));
+    }
+
+    #[test]
+    fn test_client() {
+        let bus = MockBus::new();
+        let (socket, server) = (bus.endpoint(), bus.endpoint());
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let flag = shutdown.clone();
+        let responder = thread::spawn(move || {
+            let mut od = ObjectDictionary::new();
+            od.insert(0x1001, 0, Entry::new(Value::Unsigned8(0x11), AccessType::ReadOnly));
+            od.insert(0x1008,
+                      0,
+                      Entry::new(Value::VisibleString("socketcan-rs".into()), AccessType::Const));
+            od.insert(0x1017, 0, Entry::new(Value::Unsigned16(0), AccessType::ReadWrite));
+            od.insert(0x2000, 0, Entry::new(Value::Unsigned32(0), AccessType::ReadWrite));
+            od.insert(0x2001,
+                      0,
+                      Entry::new(Value::VisibleString(String::new()), AccessType::ReadWrite));
+            SdoServer::new(5).unwrap().serve(&server, &mut od, &flag).unwrap();
+        });
+
+        let client = SdoClient::new(&socket, Duration::from_secs(5));
+        assert_eq!(client.read_u8(5, 0x1001, 0).unwrap(), 0x11);
+        assert_eq!(client.read_string(5, 0x1008, 0).unwrap(), "socketcan-rs");
+
+        client.write_u16(5, 0x1017, 0, 1000).unwrap();
+        assert_eq!(client.read_u16(5, 0x1017, 0).unwrap(), 1000);
+        client.write_u32(5, 0x2000, 0, 0xdeadbeef).unwrap();
+        assert_eq!(client.read_u32(5, 0x2000, 0).unwrap(), 0xdeadbeef);
+
+        for text in &["a somewhat longer text", "exactly14bytes", ""] {
+            client.write_string(5, 0x2001, 0, text).unwrap();
+            assert_eq!(&client.read_string(5, 0x2001, 0).unwrap(), text);
+        }
+
+        match client.write_string(5, 0x1008, 0, "read only") {
+            Err(SdoError::Aborted(0x0601_0002)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match client.read_u32(5, 0x1001, 0) {
+            Err(SdoError::UnexpectedResponse) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        shutdown.store(true, Ordering::SeqCst);
+        responder.join().unwrap();
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_async_client() {
+        use tokio::runtime::Builder;
+        use router::AsyncRouter;
+        use super::AsyncSdoClient;
+
+        let bus = MockBus::new();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let servers: Vec<_> = (5..7)
+            .map(|node_id| {
+                let (socket, flag) = (bus.endpoint(), shutdown.clone());
+                thread::spawn(move || {
+                    let mut od = ObjectDictionary::new();
+                    let name = format!("node {} of socketcan-rs", node_id);
+                    let text = |s| Value::VisibleString(s);
+                    od.insert(0x1008, 0, Entry::new(text(name), AccessType::Const));
+                    od.insert(0x2000, 0, Entry::new(Value::Unsigned32(0), AccessType::ReadWrite));
+                    od.insert(0x2001, 0, Entry::new(text(String::new()), AccessType::ReadWrite));
+                    SdoServer::new(node_id).unwrap().serve(&socket, &mut od, &flag).unwrap();
+                })
+            })
+            .collect();
+
+        let rt = Builder::new_current_thread().enable_time().build().unwrap();
+        let _runtime = rt.enter();
+        let router = Arc::new(AsyncRouter::spawn(bus.endpoint()));
+        let client = AsyncSdoClient::new(router.clone(), Duration::from_secs(5));
+
+        // segmented transfers to both nodes, and two to the same node, at once
+        let names: Vec<_> = (5..7)
+            .map(|node_id| rt.spawn(client.upload(node_id, 0x1008, 0)))
+            .collect();
+        let writes = vec![rt.spawn(client.download(5, 0x2000, 0, &[1, 2, 3, 4])),
+                          rt.spawn(client.download(5, 0x2001, 0, b"a somewhat longer text"))];
+        for (node_id, name) in (5..7).zip(names) {
+            assert_eq!(rt.block_on(name).unwrap().unwrap(),
+                       format!("node {} of socketcan-rs", node_id).into_bytes());
+        }
+        for write in writes {
+            rt.block_on(write).unwrap().unwrap();
+        }
+        assert_eq!(rt.block_on(client.upload(5, 0x2000, 0)).unwrap(), vec![1, 2, 3, 4]);
+        assert_eq!(rt.block_on(client.upload(5, 0x2001, 0)).unwrap(),
+                   b"a somewhat longer text".to_vec());
+        assert_eq!(rt.block_on(client.upload(6, 0x2000, 0)).unwrap(), vec![0; 4]);
+
+        match rt.block_on(client.download(6, 0x1008, 0, b"read only")) {
+            Err(SdoError::Aborted(0x0601_0002)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        let impatient = AsyncSdoClient::new(router, Duration::from_millis(5));
+        match rt.block_on(impatient.upload(7, 0x1008, 0)) {
+            Err(SdoError::Timeout) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        match rt.block_on(client.upload(0, 0x1008, 0)) {
+            Err(SdoError::InvalidNodeId(0)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        shutdown.store(true, Ordering::SeqCst);
+        for server in servers {
+            server.join().unwrap();
+        }
+    }
+}
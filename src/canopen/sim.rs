@@ -0,0 +1,511 @@
+//! Device emulation
+//!
+//! `SimNode` emulates the communication behavior of a CANopen slave:
+//! boot-up, NMT state machine, heartbeat production, node guarding
+//! responses and SDO access to an object dictionary through an
+//! `SdoServer`. A `Simulator` runs any number of emulated nodes on a
+//! socket, which allows testing master-side code against a vcan interface
+//! without hardware.
+//!
+//! In operational state, nodes also exchange the PDOs configured in their
+//! object dictionary (see `mapping`). Synchronous TPDOs are sent on SYNC,
+//! synchronous RPDOs are applied on the SYNC following their receipt, and
+//! event-driven RPDOs right away. Event-driven TPDOs are not sent, and
+//! only mappings of whole bytes are supported.
+//!
+//! Block SDO transfers are not emulated and answered with an abort.
+
+use std::{cmp, mem};
+use std::io;
+use std::time::{Duration, Instant};
+use {CanFrame, CanSocket, CanTransport, ShouldRetry, SFF_MASK};
+use super::{check_node_id, FrameError, Heartbeat, NmtCommand, NmtMessage, NmtState,
+            ObjectDictionary, SdoServer, Value, COB_HEARTBEAT, COB_SDO_RX, COB_SYNC};
+use super::mapping::{MappedObject, OBJ_RPDO_COMMUNICATION, OBJ_RPDO_MAPPING,
+                     OBJ_TPDO_COMMUNICATION, OBJ_TPDO_MAPPING, PDO_DISABLED};
+
+/// Highest transmission type of synchronous PDOs
+const MAX_SYNC_TRANSMISSION: u8 = 240;
+
+fn u8_entry(od: &ObjectDictionary, index: u16, subindex: u8) -> Option<u8> {
+    match od.get(index, subindex) {
+        Ok(&Value::Unsigned8(val)) => Some(val),
+        _ => None,
+    }
+}
+
+fn u32_entry(od: &ObjectDictionary, index: u16, subindex: u8) -> Option<u32> {
+    match od.get(index, subindex) {
+        Ok(&Value::Unsigned32(val)) => Some(val),
+        _ => None,
+    }
+}
+
+/// Enabled PDOs with communication parameters in `base..base + 0x200`, as
+/// the offset from `base`, the COB-ID and the transmission type
+fn enabled_pdos(od: &ObjectDictionary, base: u16) -> Vec<(u16, u32, u8)> {
+    od.iter()
+        .filter(|&(&(index, subindex), _)| index >= base && index - base < 0x200 && subindex == 1)
+        .filter_map(|(&(index, _), entry)| match *entry.value() {
+            Value::Unsigned32(cob_id) if cob_id & PDO_DISABLED == 0 => {
+                let transmission_type = u8_entry(od, index, 2).unwrap_or(0);
+                Some((index - base, cob_id & SFF_MASK, transmission_type))
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Objects mapped by the mapping parameters at `index`
+fn mapped_objects(od: &ObjectDictionary, index: u16) -> Vec<MappedObject> {
+    let count = u8_entry(od, index, 0).unwrap_or(0);
+    (1..count as u16 + 1)
+        .filter_map(|subindex| u32_entry(od, index, subindex as u8))
+        .map(MappedObject::from_u32)
+        .collect()
+}
+
+/// An emulated CANopen slave
+#[derive(Debug)]
+pub struct SimNode {
+    node_id: u8,
+    state: NmtState,
+    od: ObjectDictionary,
+    sdo: SdoServer,
+    heartbeat_period: Option<Duration>,
+    next_heartbeat: Option<Instant>,
+    toggle: bool,
+    syncs: u32,
+    pending_rpdos: Vec<(u16, CanFrame)>,
+}
+
+impl SimNode {
+    /// Create a node that has not booted yet.
+    pub fn new(node_id: u8, od: ObjectDictionary) -> Result<SimNode, FrameError> {
+        Ok(SimNode {
+            node_id: check_node_id(node_id)?,
+            state: NmtState::BootUp,
+            od: od,
+            sdo: SdoServer::new(node_id)?,
+            heartbeat_period: None,
+            next_heartbeat: None,
+            toggle: false,
+            syncs: 0,
+            pending_rpdos: Vec::new(),
+        })
+    }
+
+    /// Node ID
+    #[inline]
+    pub fn node_id(&self) -> u8 {
+        self.node_id
+    }
+
+    /// Current NMT state
+    #[inline]
+    pub fn state(&self) -> NmtState {
+        self.state
+    }
+
+    /// The node's object dictionary
+    #[inline]
+    pub fn od(&self) -> &ObjectDictionary {
+        &self.od
+    }
+
+    /// Mutable access to the node's object dictionary
+    #[inline]
+    pub fn od_mut(&mut self) -> &mut ObjectDictionary {
+        &mut self.od
+    }
+
+    /// Set the heartbeat producer time, `None` disables heartbeats.
+    pub fn set_heartbeat_period(&mut self, period: Option<Duration>, now: Instant) {
+        self.heartbeat_period = period;
+        self.next_heartbeat = period.map(|p| now + p);
+    }
+
+    /// Boot the node, returning the boot-up message.
+    ///
+    /// The node enters pre-operational state afterwards.
+    pub fn boot(&mut self, now: Instant) -> CanFrame {
+        self.state = NmtState::PreOperational;
+        self.toggle = false;
+        self.syncs = 0;
+        self.pending_rpdos.clear();
+        self.next_heartbeat = self.heartbeat_period.map(|p| now + p);
+
+        self.heartbeat_frame(NmtState::BootUp)
+    }
+
+    /// Time at which the node wants to send its next heartbeat
+    #[inline]
+    pub fn next_deadline(&self) -> Option<Instant> {
+        self.next_heartbeat
+    }
+
+    /// Return the heartbeat if it is due at `now`.
+    pub fn poll(&mut self, now: Instant) -> Option<CanFrame> {
+        match (self.next_heartbeat, self.heartbeat_period) {
+            (Some(due), Some(period)) if due <= now => {
+                self.next_heartbeat = Some(due + period);
+                Some(self.heartbeat_frame(self.state))
+            }
+            _ => None,
+        }
+    }
+
+    /// Process a received frame, returning the response if any.
+    pub fn handle_frame(&mut self, frame: &CanFrame, now: Instant) -> Option<CanFrame> {
+        if frame.is_extended() || frame.is_error() {
+            return None;
+        }
+
+        let guarding_id = COB_HEARTBEAT + self.node_id as u32;
+        if frame.is_rtr() {
+            if frame.id() != guarding_id {
+                return None;
+            }
+
+            let byte = self.state.as_byte() | if self.toggle { 0x80 } else { 0 };
+            self.toggle = !self.toggle;
+            return Some(CanFrame::new(guarding_id, &[byte], false, false)
+                .expect("guarding response is always valid"));
+        }
+
+        if let Ok(msg) = NmtMessage::from_frame(frame) {
+            return self.handle_nmt(&msg, now);
+        }
+
+        if frame.id() == COB_SDO_RX + self.node_id as u32 && self.state != NmtState::Stopped {
+            return self.sdo.handle_frame(&mut self.od, frame);
+        }
+
+        if self.state == NmtState::Operational {
+            let rpdos = enabled_pdos(&self.od, OBJ_RPDO_COMMUNICATION);
+            if let Some(&(pdo, _, transmission_type)) =
+                rpdos.iter().find(|&&(_, cob_id, _)| cob_id == frame.id()) {
+                if transmission_type <= MAX_SYNC_TRANSMISSION {
+                    self.pending_rpdos.push((pdo, *frame));
+                } else {
+                    self.apply_rpdo(pdo, frame.data());
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Process a SYNC, returning the TPDOs due.
+    ///
+    /// RPDOs received since the last SYNC are applied first. TPDOs with
+    /// transmission type `n` are sent on every `n`-th SYNC, type 0 on every
+    /// SYNC. Only nodes in operational state take part.
+    pub fn sync(&mut self) -> Vec<CanFrame> {
+        if self.state != NmtState::Operational {
+            return Vec::new();
+        }
+
+        for (pdo, frame) in mem::replace(&mut self.pending_rpdos, Vec::new()) {
+            self.apply_rpdo(pdo, frame.data());
+        }
+
+        self.syncs = self.syncs.wrapping_add(1);
+        let mut tpdos = Vec::new();
+        for (pdo, cob_id, transmission_type) in enabled_pdos(&self.od, OBJ_TPDO_COMMUNICATION) {
+            if transmission_type > MAX_SYNC_TRANSMISSION ||
+               transmission_type > 1 && self.syncs % transmission_type as u32 != 0 {
+                continue;
+            }
+            if let Some(frame) = self.tpdo_frame(pdo, cob_id) {
+                tpdos.push(frame);
+            }
+        }
+        tpdos
+    }
+
+    fn handle_nmt(&mut self, msg: &NmtMessage, now: Instant) -> Option<CanFrame> {
+        if msg.node_id.map_or(false, |id| id != self.node_id) {
+            return None;
+        }
+        self.pending_rpdos.clear();
+
+        match msg.command {
+            NmtCommand::Start => self.state = NmtState::Operational,
+            NmtCommand::Stop => self.state = NmtState::Stopped,
+            NmtCommand::EnterPreOperational => self.state = NmtState::PreOperational,
+            NmtCommand::ResetNode |
+            NmtCommand::ResetCommunication => return Some(self.boot(now)),
+        }
+
+        None
+    }
+
+    /// Write the values carried by `data` to the objects mapped by RPDO
+    /// `pdo`, counted from 0. Values the dictionary rejects are skipped.
+    fn apply_rpdo(&mut self, pdo: u16, data: &[u8]) {
+        let mut offset = 0;
+        for object in mapped_objects(&self.od, OBJ_RPDO_MAPPING + pdo) {
+            let len = object.bits as usize / 8;
+            if object.bits % 8 != 0 || offset + len > data.len() {
+                return;
+            }
+
+            let _ = self.od.write(object.index, object.subindex, &data[offset..offset + len]);
+            offset += len;
+        }
+    }
+
+    /// Build TPDO `pdo`, counted from 0, from the current values of the
+    /// mapped objects.
+    fn tpdo_frame(&self, pdo: u16, cob_id: u32) -> Option<CanFrame> {
+        let mut data = Vec::with_capacity(8);
+        for object in mapped_objects(&self.od, OBJ_TPDO_MAPPING + pdo) {
+            let value = match self.od.get(object.index, object.subindex) {
+                Ok(value) => value.to_bytes(),
+                Err(_) => return None,
+            };
+            let len = object.bits as usize / 8;
+            if object.bits % 8 != 0 || value.len() < len || data.len() + len > 8 {
+                return None;
+            }
+            data.extend_from_slice(&value[..len]);
+        }
+
+        CanFrame::new(cob_id, &data, false, false).ok()
+    }
+
+    fn heartbeat_frame(&self, state: NmtState) -> CanFrame {
+        Heartbeat {
+                node_id: self.node_id,
+                state: state,
+            }
+            .to_frame()
+            .expect("node id validated on construction")
+    }
+}
+
+/// Runs emulated nodes on a socket
+#[derive(Debug)]
+pub struct Simulator<T = CanSocket> {
+    socket: T,
+    nodes: Vec<SimNode>,
+}
+
+impl<T: CanTransport> Simulator<T> {
+    /// Create a simulator on `socket`.
+    pub fn new(socket: T) -> Simulator<T> {
+        Simulator {
+            socket: socket,
+            nodes: Vec::new(),
+        }
+    }
+
+    /// Add a node. It boots when `start` is called.
+    pub fn add_node(&mut self, node: SimNode) {
+        self.nodes.push(node);
+    }
+
+    /// Emulated nodes
+    pub fn nodes(&self) -> &[SimNode] {
+        &self.nodes
+    }
+
+    /// Mutable access to the emulated node `node_id`
+    pub fn node_mut(&mut self, node_id: u8) -> Option<&mut SimNode> {
+        self.nodes.iter_mut().find(|n| n.node_id() == node_id)
+    }
+
+    /// Boot all nodes, sending their boot-up messages.
+    pub fn start(&mut self) -> io::Result<()> {
+        let now = Instant::now();
+        for node in &mut self.nodes {
+            self.socket.write_frame_insist(&node.boot(now))?;
+        }
+        Ok(())
+    }
+
+    /// Process received frames and produce heartbeats and PDOs for
+    /// `duration`.
+    pub fn run_for(&mut self, duration: Duration) -> io::Result<()> {
+        let end = Instant::now() + duration;
+
+        loop {
+            let now = Instant::now();
+            if now >= end {
+                return Ok(());
+            }
+
+            for node in &mut self.nodes {
+                if let Some(frame) = node.poll(now) {
+                    self.socket.write_frame_insist(&frame)?;
+                }
+            }
+
+            // wake up for the next heartbeat or the end of the run
+            let wakeup = self.nodes
+                .iter()
+                .filter_map(|n| n.next_deadline())
+                .fold(end, cmp::min);
+            let timeout = if wakeup > now { wakeup - now } else { Duration::from_millis(0) };
+
+            // a zero timeout would block forever
+            self.socket.set_read_timeout(cmp::max(timeout, Duration::from_millis(1)))?;
+
+            let frame = match self.socket.read_frame() {
+                Ok(frame) => frame,
+                Err(ref e) if e.should_retry() => continue,
+                Err(e) => return Err(e),
+            };
+
+            let now = Instant::now();
+            let sync = frame.id() == COB_SYNC && !frame.is_extended() && !frame.is_rtr() &&
+                       !frame.is_error();
+            for node in &mut self.nodes {
+                if let Some(response) = node.handle_frame(&frame, now) {
+                    self.socket.write_frame_insist(&response)?;
+                }
+                if sync {
+                    for tpdo in node.sync() {
+                        self.socket.write_frame_insist(&tpdo)?;
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::{Duration, Instant};
+    use CanFrame;
+    use canopen::{AccessType, Entry, NmtState, ObjectDictionary, PdoExchange, ProcessImage,
+                  Value};
+    use canopen::mapping::{insert_pdo_objects, MappedObject, Pdo};
+    use testing::MockBus;
+    use super::{SimNode, Simulator};
+
+    /// Node 5 sending 0x2000 and 0x2001 in TPDO1 on every `tpdo_type`-th
+    /// SYNC and receiving 0x2002 in RPDO1 of `rpdo_type`
+    fn pdo_node(tpdo_type: u8, rpdo_type: u8) -> SimNode {
+        let mut od = ObjectDictionary::new();
+        od.insert(0x2000, 0, Entry::new(Value::Unsigned16(0x1234), AccessType::ReadWrite));
+        od.insert(0x2001, 0, Entry::new(Value::Unsigned8(0x56), AccessType::ReadWrite));
+        od.insert(0x2002, 0, Entry::new(Value::Unsigned16(0), AccessType::ReadWrite));
+        insert_pdo_objects(&mut od, Pdo::Tpdo(1));
+        insert_pdo_objects(&mut od, Pdo::Rpdo(1));
+
+        let entry = |index, subindex, bits| {
+            let object = MappedObject {
+                index: index,
+                subindex: subindex,
+                bits: bits,
+            };
+            Value::Unsigned32(object.to_u32())
+        };
+        od.set(0x1800, 1, Value::Unsigned32(0x185)).unwrap();
+        od.set(0x1800, 2, Value::Unsigned8(tpdo_type)).unwrap();
+        od.set(0x1a00, 1, entry(0x2000, 0, 16)).unwrap();
+        od.set(0x1a00, 2, entry(0x2001, 0, 8)).unwrap();
+        od.set(0x1a00, 0, Value::Unsigned8(2)).unwrap();
+        od.set(0x1400, 1, Value::Unsigned32(0x205)).unwrap();
+        od.set(0x1400, 2, Value::Unsigned8(rpdo_type)).unwrap();
+        od.set(0x1600, 1, entry(0x2002, 0, 16)).unwrap();
+        od.set(0x1600, 0, Value::Unsigned8(1)).unwrap();
+        SimNode::new(5, od).unwrap()
+    }
+
+    #[test]
+    fn test_sim_node() {
+        let mut od = ObjectDictionary::new();
+        od.insert(0x1000, 0, Entry::new(Value::Unsigned32(0x00020192), AccessType::Const));
+        od.insert(0x2000, 0, Entry::new(Value::Unsigned8(0), AccessType::ReadWrite));
+
+        let now = Instant::now();
+        let mut node = SimNode::new(0x10, od).unwrap();
+        node.set_heartbeat_period(Some(Duration::from_millis(100)), now);
+
+        assert_eq!(node.boot(now).data(), &[0x00]);
+        assert!(node.poll(now).is_none());
+        assert_eq!(node.poll(now + Duration::from_millis(100)).unwrap().data(), &[0x7f]);
+
+        let start = CanFrame::new(0x000, &[0x01, 0x10], false, false).unwrap();
+        assert!(node.handle_frame(&start, now).is_none());
+        assert_eq!(node.state(), NmtState::Operational);
+
+        let upload = CanFrame::new(0x610, &[0x40, 0x00, 0x10, 0, 0, 0, 0, 0], false, false)
+            .unwrap();
+        let resp = node.handle_frame(&upload, now).unwrap();
+        assert_eq!(resp.id(), 0x590);
+        assert_eq!(resp.data(), &[0x43, 0x00, 0x10, 0x00, 0x92, 0x01, 0x02, 0x00]);
+
+        let download = CanFrame::new(0x610, &[0x2f, 0x00, 0x20, 0, 42, 0, 0, 0], false, false)
+            .unwrap();
+        assert_eq!(node.handle_frame(&download, now).unwrap().data()[0], 0x60);
+        assert_eq!(node.od().get(0x2000, 0).unwrap(), &Value::Unsigned8(42));
+    }
+
+    #[test]
+    fn test_sim_pdos() {
+        let now = Instant::now();
+        let mut node = pdo_node(2, 1);
+        node.boot(now);
+        assert!(node.sync().is_empty());
+
+        let start = CanFrame::new(0x000, &[0x01, 0x05], false, false).unwrap();
+        node.handle_frame(&start, now);
+        assert!(node.sync().is_empty());
+        let tpdos = node.sync();
+        assert_eq!(tpdos.len(), 1);
+        assert_eq!(tpdos[0].id(), 0x185);
+        assert_eq!(tpdos[0].data(), &[0x34, 0x12, 0x56]);
+
+        // synchronous RPDOs take effect on the next SYNC
+        let rpdo = CanFrame::new(0x205, &[0xcd, 0xab], false, false).unwrap();
+        assert!(node.handle_frame(&rpdo, now).is_none());
+        assert_eq!(node.od().get(0x2002, 0).unwrap(), &Value::Unsigned16(0));
+        node.sync();
+        assert_eq!(node.od().get(0x2002, 0).unwrap(), &Value::Unsigned16(0xabcd));
+
+        let mut node = pdo_node(0xff, 0xff);
+        node.boot(now);
+        node.handle_frame(&start, now);
+        assert!(node.sync().is_empty());
+        node.handle_frame(&rpdo, now);
+        assert_eq!(node.od().get(0x2002, 0).unwrap(), &Value::Unsigned16(0xabcd));
+    }
+
+    #[test]
+    fn test_simulator_pdo_exchange() {
+        let bus = MockBus::new();
+        let (master, devices) = (bus.endpoint(), bus.endpoint());
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let flag = shutdown.clone();
+        let simulator = thread::spawn(move || {
+            let mut sim = Simulator::new(devices);
+            sim.add_node(pdo_node(1, 1));
+            sim.start().unwrap();
+            while !flag.load(Ordering::SeqCst) {
+                sim.run_for(Duration::from_millis(10)).unwrap();
+            }
+            sim.nodes()[0].od().get(0x2002, 0).unwrap().clone()
+        });
+
+        let mut image = ProcessImage::new();
+        image.add_rpdo(0x205, &[0x22, 0x11]).unwrap();
+        image.add_tpdo(0x185);
+        let start = CanFrame::new(0x000, &[0x01, 0x05], false, false).unwrap();
+        master.write_frame(&start).unwrap();
+        let mut exchange = PdoExchange::new(master, image, Duration::from_secs(5));
+        assert!(exchange.cycle().unwrap().is_empty());
+        assert!(exchange.cycle().unwrap().is_empty());
+        let image = exchange.image();
+        assert_eq!(image.lock().unwrap().input(0x185).unwrap().data(), &[0x34, 0x12, 0x56]);
+
+        shutdown.store(true, Ordering::SeqCst);
+        assert_eq!(simulator.join().unwrap(), Value::Unsigned16(0x1122));
+    }
+}
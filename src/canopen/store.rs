@@ -0,0 +1,179 @@
+//! Storing and restoring parameters
+//!
+//! Writing the ASCII signature "save" to a subindex of object `0x1010`
+//! makes a node store the corresponding parameters in non-volatile memory,
+//! writing "load" to object `0x1011` restores their default values, which
+//! take effect after the next reset. Reading these subindexes returns the
+//! node's capabilities, bit 0 tells whether it supports the command.
+
+use std::{error, fmt};
+use std::time::Duration;
+use CanTransport;
+use super::sdo::{download_expedited, upload_expedited, SdoError};
+
+/// Index of the store parameters object
+pub const OBJ_STORE_PARAMETERS: u16 = 0x1010;
+
+/// Index of the restore default parameters object
+pub const OBJ_RESTORE_DEFAULTS: u16 = 0x1011;
+
+/// Signature triggering a store, "save" in little endian
+pub const SIGNATURE_SAVE: [u8; 4] = [b's', b'a', b'v', b'e'];
+
+/// Signature triggering a restore, "load" in little endian
+pub const SIGNATURE_LOAD: [u8; 4] = [b'l', b'o', b'a', b'd'];
+
+/// Parameters to store or restore
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParameterGroup {
+    /// All parameters
+    All,
+    /// Communication parameters, objects `0x1000` to `0x1fff`
+    Communication,
+    /// Application parameters, objects `0x6000` to `0x9fff`
+    Application,
+    /// Manufacturer-defined group, subindex 4 to 127
+    Manufacturer(u8),
+}
+
+impl ParameterGroup {
+    /// Subindex of the group in objects `0x1010` and `0x1011`
+    pub fn subindex(&self) -> u8 {
+        match *self {
+            ParameterGroup::All => 1,
+            ParameterGroup::Communication => 2,
+            ParameterGroup::Application => 3,
+            ParameterGroup::Manufacturer(subindex) => subindex,
+        }
+    }
+}
+
+/// Error storing or restoring parameters
+#[derive(Debug)]
+pub enum StoreError {
+    /// The node does not support the command for the group
+    NotSupported,
+
+    /// The SDO transfer failed, an abort code of `0x08000020` means the
+    /// node could not execute the command
+    Sdo(SdoError),
+}
+
+impl fmt::Display for StoreError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StoreError::Sdo(ref e) => write!(f, "SDO: {}", e),
+            _ => write!(f, "{}", error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for StoreError {
+    fn description(&self) -> &str {
+        match *self {
+            StoreError::NotSupported => "command not supported by node",
+            StoreError::Sdo(ref e) => e.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            StoreError::Sdo(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<SdoError> for StoreError {
+    fn from(e: SdoError) -> StoreError {
+        StoreError::Sdo(e)
+    }
+}
+
+fn execute<T>(socket: &T,
+              node_id: u8,
+              index: u16,
+              group: ParameterGroup,
+              signature: &[u8; 4],
+              timeout: Duration)
+              -> Result<(), StoreError>
+    where T: CanTransport + ?Sized
+{
+    let subindex = group.subindex();
+
+    // check the capabilities first, nodes may accept but ignore the write
+    let capabilities = upload_expedited(socket, node_id, index, subindex, timeout)?;
+    if capabilities.first().map_or(true, |c| c & 0x01 == 0) {
+        return Err(StoreError::NotSupported);
+    }
+
+    download_expedited(socket, node_id, index, subindex, signature, timeout)?;
+    Ok(())
+}
+
+/// Make node `node_id` store the parameters of `group`.
+///
+/// Succeeds once the node confirmed that the parameters were stored.
+/// Storing may take a while, `timeout` should allow for that.
+pub fn store_parameters<T>(socket: &T,
+                           node_id: u8,
+                           group: ParameterGroup,
+                           timeout: Duration)
+                           -> Result<(), StoreError>
+    where T: CanTransport + ?Sized
+{
+    execute(socket, node_id, OBJ_STORE_PARAMETERS, group, &SIGNATURE_SAVE, timeout)
+}
+
+/// Make node `node_id` restore the default values of `group`.
+///
+/// The defaults take effect after the next reset of the node, e.g. through
+/// `NmtCommand::ResetNode`.
+pub fn restore_defaults<T>(socket: &T,
+                           node_id: u8,
+                           group: ParameterGroup,
+                           timeout: Duration)
+                           -> Result<(), StoreError>
+    where T: CanTransport + ?Sized
+{
+    execute(socket, node_id, OBJ_RESTORE_DEFAULTS, group, &SIGNATURE_LOAD, timeout)
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::time::{Duration, Instant};
+    use canopen::{AccessType, Entry, ObjectDictionary, Value};
+    use canopen::sim::SimNode;
+    use testing::MockBus;
+    use super::{restore_defaults, store_parameters, ParameterGroup, StoreError,
+                OBJ_RESTORE_DEFAULTS, OBJ_STORE_PARAMETERS};
+
+    #[test]
+    fn test_store_restore() {
+        let bus = MockBus::new();
+        let (client, server) = (bus.endpoint(), bus.endpoint());
+        let responder = thread::spawn(move || {
+            let entry = |value| Entry::new(Value::Unsigned32(value), AccessType::ReadWrite);
+            let mut od = ObjectDictionary::new();
+            od.insert(OBJ_STORE_PARAMETERS, 1, entry(1));
+            od.insert(OBJ_RESTORE_DEFAULTS, 1, entry(0));
+            let mut node = SimNode::new(7, od).unwrap();
+
+            for _ in 0..3 {
+                let request = server.read_frame().unwrap();
+                server.write_frame(&node.handle_frame(&request, Instant::now()).unwrap()).unwrap();
+            }
+            node.od().read(OBJ_STORE_PARAMETERS, 1).unwrap()
+        });
+
+        let timeout = Duration::from_secs(5);
+        store_parameters(&client, 7, ParameterGroup::All, timeout).unwrap();
+        match restore_defaults(&client, 7, ParameterGroup::All, timeout) {
+            Err(StoreError::NotSupported) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        assert_eq!(responder.join().unwrap(), b"save");
+    }
+}
@@ -0,0 +1,119 @@
+//! TIME object
+//!
+//! The TIME stamp object is broadcast on COB-ID `0x100` by the time
+//! producer. Its 6 byte payload is a `TIME_OF_DAY` value: the number of
+//! milliseconds after midnight (28 bits) followed by the number of days
+//! since January 1, 1984 (16 bits).
+
+use std::io;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use {CanFrame, CanTransport};
+use super::{check_data_frame, FrameError, COB_TIME};
+
+/// Days between the UNIX epoch and January 1, 1984
+const EPOCH_OFFSET_DAYS: u64 = 5113;
+
+const MS_PER_DAY: u64 = 24 * 60 * 60 * 1000;
+
+/// A CANopen `TIME_OF_DAY` value
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct TimeOfDay {
+    /// Milliseconds after midnight
+    pub ms: u32,
+
+    /// Days since January 1, 1984
+    pub days: u16,
+}
+
+impl TimeOfDay {
+    /// Convert a `SystemTime`, truncating to millisecond resolution.
+    ///
+    /// Returns `None` for times before 1984 or after the range of the
+    /// 16-bit day counter (mid 2163).
+    pub fn from_system_time(t: SystemTime) -> Option<TimeOfDay> {
+        let since_epoch = match t.duration_since(UNIX_EPOCH) {
+            Ok(d) => d,
+            Err(_) => return None,
+        };
+
+        let total_ms = since_epoch.as_secs() * 1000 +
+                       (since_epoch.subsec_nanos() / 1_000_000) as u64;
+        let days = total_ms / MS_PER_DAY;
+
+        if days < EPOCH_OFFSET_DAYS || days - EPOCH_OFFSET_DAYS > u16::max_value() as u64 {
+            return None;
+        }
+
+        Some(TimeOfDay {
+            ms: (total_ms % MS_PER_DAY) as u32,
+            days: (days - EPOCH_OFFSET_DAYS) as u16,
+        })
+    }
+
+    /// Convert into a `SystemTime`.
+    pub fn to_system_time(&self) -> SystemTime {
+        let days = self.days as u64 + EPOCH_OFFSET_DAYS;
+        UNIX_EPOCH + Duration::from_millis(days * MS_PER_DAY + self.ms as u64)
+    }
+
+    /// Decode a TIME stamp object.
+    pub fn from_frame(frame: &CanFrame) -> Result<TimeOfDay, FrameError> {
+        check_data_frame(frame)?;
+
+        if frame.id() != COB_TIME {
+            return Err(FrameError::UnexpectedCobId(frame.id()));
+        }
+
+        let data = frame.data();
+        if data.len() < 6 {
+            return Err(FrameError::NotEnoughData(data.len()));
+        }
+
+        let ms = data[0] as u32 | (data[1] as u32) << 8 | (data[2] as u32) << 16 |
+                 ((data[3] & 0x0f) as u32) << 24;
+
+        Ok(TimeOfDay {
+            ms: ms,
+            days: data[4] as u16 | (data[5] as u16) << 8,
+        })
+    }
+
+    /// Encode into a TIME stamp object.
+    pub fn to_frame(&self) -> CanFrame {
+        let data = [self.ms as u8,
+                    (self.ms >> 8) as u8,
+                    (self.ms >> 16) as u8,
+                    (self.ms >> 24) as u8 & 0x0f,
+                    self.days as u8,
+                    (self.days >> 8) as u8];
+
+        CanFrame::new(COB_TIME, &data, false, false).expect("TIME frame is always valid")
+    }
+}
+
+/// Broadcast the current system time as TIME stamp object.
+pub fn send_time<T: CanTransport>(socket: &T) -> io::Result<()> {
+    let tod = TimeOfDay::from_system_time(SystemTime::now())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "time out of range"))?;
+    socket.write_frame(&tod.to_frame())
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+    use super::TimeOfDay;
+
+    #[test]
+    fn test_time_of_day() {
+        // 2017-06-01 12:00:00.250 UTC
+        let t = UNIX_EPOCH + Duration::from_millis(1496318400250);
+        let tod = TimeOfDay::from_system_time(t).unwrap();
+
+        assert_eq!(tod.days, 12205);
+        assert_eq!(tod.ms, 12 * 3600 * 1000 + 250);
+        assert_eq!(TimeOfDay::from_frame(&tod.to_frame()).unwrap(), tod);
+        assert_eq!(tod.to_system_time(), t);
+
+        assert!(TimeOfDay::from_system_time(UNIX_EPOCH).is_none());
+    }
+}
@@ -0,0 +1,33 @@
+//! Time sources
+//!
+//! Components that wait, such as the periodic sender of `CanBus` and the
+//! log `Replayer`, take their time from a `Clock`. In production this is
+//! the `SystemClock`; tests can use a `testing::MockBus` instead, whose
+//! simulated time advances instantly when slept on, so timing logic is
+//! tested without actually sleeping.
+
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// A monotonic time source
+pub trait Clock {
+    /// The current time
+    fn now(&self) -> Instant;
+
+    /// Block for `duration`.
+    fn sleep(&self, duration: Duration);
+}
+
+/// The operating system's monotonic clock
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn sleep(&self, duration: Duration) {
+        thread::sleep(duration)
+    }
+}
@@ -0,0 +1,220 @@
+//! SocketCAN constants from the kernel headers
+//!
+//! The frame flags and masks are also available at the crate root.
+//! Protocol implementations on top of this crate should use these instead
+//! of declaring their own copies, and `CanIdFlags` instead of masking raw
+//! IDs by hand.
+
+use libc::c_int;
+
+// linux/can.h
+
+/// if set, indicate 29 bit extended format
+pub const EFF_FLAG: u32 = 0x80000000;
+
+/// remote transmission request flag
+pub const RTR_FLAG: u32 = 0x40000000;
+
+/// error flag
+pub const ERR_FLAG: u32 = 0x20000000;
+
+/// valid bits in standard frame id
+pub const SFF_MASK: u32 = 0x000007ff;
+
+/// valid bits in extended frame id
+pub const EFF_MASK: u32 = 0x1fffffff;
+
+/// valid bits in error frame
+pub const ERR_MASK: u32 = 0x1fffffff;
+
+/// an error mask that will cause SocketCAN to report all errors
+pub const ERR_MASK_ALL: u32 = ERR_MASK;
+
+/// an error mask that will cause SocketCAN to silently drop all errors
+pub const ERR_MASK_NONE: u32 = 0;
+
+/// filter flag inverting the match, see `filter::INV_FILTER`
+pub const INV_FILTER: u32 = ::filter::INV_FILTER;
+
+// linux/can/error.h, error classes in the ID of error frames
+
+/// TX timeout (by netdevice driver)
+pub const CAN_ERR_TX_TIMEOUT: u32 = 0x00000001;
+
+/// lost arbitration
+pub const CAN_ERR_LOSTARB: u32 = 0x00000002;
+
+/// controller problems
+pub const CAN_ERR_CRTL: u32 = 0x00000004;
+
+/// protocol violations
+pub const CAN_ERR_PROT: u32 = 0x00000008;
+
+/// transceiver status
+pub const CAN_ERR_TRX: u32 = 0x00000010;
+
+/// received no ACK on transmission
+pub const CAN_ERR_ACK: u32 = 0x00000020;
+
+/// bus off
+pub const CAN_ERR_BUSOFF: u32 = 0x00000040;
+
+/// bus error (may flood!)
+pub const CAN_ERR_BUSERROR: u32 = 0x00000080;
+
+/// controller restarted
+pub const CAN_ERR_RESTARTED: u32 = 0x00000100;
+
+bitflags! {
+    /// Flags in the upper bits of a raw CAN ID
+    pub struct CanIdFlags: u32 {
+        /// 29 bit extended format
+        const EFF = EFF_FLAG;
+        /// remote transmission request
+        const RTR = RTR_FLAG;
+        /// error frame
+        const ERR = ERR_FLAG;
+    }
+}
+
+impl CanIdFlags {
+    /// Flags set in the raw ID `raw_id`
+    pub fn from_id(raw_id: u32) -> CanIdFlags {
+        CanIdFlags::from_bits_truncate(raw_id)
+    }
+
+    /// Combine the flags with `id` into a raw ID.
+    ///
+    /// Flag bits already set in `id` are kept.
+    pub fn with_id(self, id: u32) -> u32 {
+        id | self.bits()
+    }
+}
+
+/// maximum payload of a classic CAN frame
+pub const CAN_MAX_DLEN: usize = 8;
+
+/// maximum payload of a CAN FD frame
+pub const CANFD_MAX_DLEN: usize = 64;
+
+/// payload lengths of CAN FD frames by DLC
+const FD_LENGTHS: [usize; 16] = [0, 1, 2, 3, 4, 5, 6, 7, 8, 12, 16, 20, 24, 32, 48, 64];
+
+/// Payload length of a CAN FD frame with the data length code `dlc`, or
+/// `None` if the DLC is not in the range of 0..15
+///
+/// DLCs of 0 to 8 are the length itself, 9 to 15 stand for 12 to 64 bytes.
+/// Classic frames carry 8 bytes for all DLCs above 8.
+pub fn dlc_to_len(dlc: u8) -> Option<usize> {
+    FD_LENGTHS.get(dlc as usize).cloned()
+}
+
+/// Smallest data length code of a CAN FD frame carrying `len` bytes, or
+/// `None` if `len` exceeds `CANFD_MAX_DLEN`
+///
+/// Lengths between the steps of the DLC table need padding, e.g. 10 bytes
+/// are sent with DLC 9 as 12 bytes.
+pub fn len_to_dlc(len: usize) -> Option<u8> {
+    FD_LENGTHS.iter().position(|&l| l >= len).map(|dlc| dlc as u8)
+}
+
+/// protocol family, see `socket(2)`
+pub const PF_CAN: c_int = 29;
+
+/// address family
+pub const AF_CAN: c_int = PF_CAN;
+
+/// raw socket protocol
+pub const CAN_RAW: c_int = 1;
+
+// asm-generic/socket.h
+
+/// receive software timestamps as a `timespec` control message
+pub const SO_TIMESTAMPNS: c_int = 35;
+
+/// control message type of `SO_TIMESTAMPNS`
+pub const SCM_TIMESTAMPNS: c_int = SO_TIMESTAMPNS;
+
+/// receive software and hardware timestamps, see `SOF_TIMESTAMPING_*`
+pub const SO_TIMESTAMPING: c_int = 37;
+
+/// control message type of `SO_TIMESTAMPING`, three `timespec`s
+pub const SCM_TIMESTAMPING: c_int = SO_TIMESTAMPING;
+
+/// busy poll the receive queue on blocking reads, in microseconds
+pub const SO_BUSY_POLL: c_int = 46;
+
+// linux/net_tstamp.h
+
+/// generate hardware timestamps on receive
+pub const SOF_TIMESTAMPING_RX_HARDWARE: c_int = 1 << 2;
+
+/// generate software timestamps on receive
+pub const SOF_TIMESTAMPING_RX_SOFTWARE: c_int = 1 << 3;
+
+/// report software timestamps
+pub const SOF_TIMESTAMPING_SOFTWARE: c_int = 1 << 4;
+
+/// report hardware timestamps
+pub const SOF_TIMESTAMPING_RAW_HARDWARE: c_int = 1 << 6;
+
+// linux/can/raw.h
+
+/// base of the socket option levels
+pub const SOL_CAN_BASE: c_int = 100;
+
+/// socket option level of raw sockets
+pub const SOL_CAN_RAW: c_int = SOL_CAN_BASE + CAN_RAW;
+
+/// set filters, an array of `can_filter`
+pub const CAN_RAW_FILTER: c_int = 1;
+
+/// set the error mask
+pub const CAN_RAW_ERR_FILTER: c_int = 2;
+
+/// enable local loopback (default on)
+pub const CAN_RAW_LOOPBACK: c_int = 3;
+
+/// receive own frames (default off)
+pub const CAN_RAW_RECV_OWN_MSGS: c_int = 4;
+
+/// allow CAN FD frames (default off)
+pub const CAN_RAW_FD_FRAMES: c_int = 5;
+
+/// require all filters to match (default off)
+pub const CAN_RAW_JOIN_FILTERS: c_int = 6;
+
+/// allow CAN XL frames (default off)
+pub const CAN_RAW_XL_FRAMES: c_int = 7;
+
+/// CAN XL virtual CAN network ID handling (default: no VCIDs)
+pub const CAN_RAW_XL_VCID_OPTS: c_int = 8;
+
+#[cfg(test)]
+mod test {
+    use super::{dlc_to_len, len_to_dlc, CanIdFlags, EFF_FLAG};
+
+    #[test]
+    fn test_id_flags() {
+        let flags = CanIdFlags::from_id(0x12345 | EFF_FLAG);
+        assert_eq!(flags, CanIdFlags::EFF);
+        assert_eq!(flags.with_id(0x12345), 0x80012345);
+        assert_eq!((CanIdFlags::EFF | CanIdFlags::RTR).with_id(1), 0xc0000001);
+        assert!(CanIdFlags::from_id(0x7ff).is_empty());
+    }
+
+    #[test]
+    fn test_dlc_conversion() {
+        assert_eq!(dlc_to_len(8), Some(8));
+        assert_eq!(dlc_to_len(9), Some(12));
+        assert_eq!(dlc_to_len(15), Some(64));
+        assert_eq!(dlc_to_len(16), None);
+
+        assert_eq!(len_to_dlc(5), Some(5));
+        assert_eq!(len_to_dlc(10), Some(9));
+        assert_eq!(len_to_dlc(33), Some(14));
+        assert_eq!(len_to_dlc(64), Some(15));
+        assert_eq!(len_to_dlc(65), None);
+        assert!((0..16).all(|dlc| len_to_dlc(dlc_to_len(dlc).unwrap()) == Some(dlc)));
+    }
+}
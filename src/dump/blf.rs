@@ -0,0 +1,424 @@
+//! Vector binary logging format (BLF)
+//!
+//! BLF files consist of a file header followed by a sequence of objects.
+//! Modern files wrap the actual log objects into (usually zlib-compressed)
+//! log containers; a single object may span two containers.
+//!
+//! All integers are little endian. Every object starts with a common
+//! header:
+//!
+//! ```text
+//! signature "LOBJ" | header size u16 | header version u16 |
+//! object size u32  | object type u32 | flags u32 | ... | timestamp u64
+//! ```
+//!
+//! Classic CAN messages (including classic frames stored in CAN FD
+//! objects) are converted to `TimestampedFrame`s. CAN FD frames are
+//! reported as `ParseError::UnsupportedFdFrame`, all other objects are
+//! skipped. The channel number is reported as interface name, i.e. channel
+//! 1 becomes `can0`.
+
+use std::{io, path};
+use std::io::Read;
+use flate2::read::ZlibDecoder;
+use super::{build_frame, open_file, LogFile, ParseError, TimestampedFrame};
+
+const FILE_SIGNATURE: &'static [u8] = b"LOGG";
+const OBJ_SIGNATURE: &'static [u8] = b"LOBJ";
+
+/// Size of the header part common to all objects
+const OBJ_HEADER_BASE_SIZE: usize = 16;
+
+/// Largest file header or top-level object read; log containers written by
+/// CANoe and python-can hold at most 128 KiB
+const MAX_OBJECT_SIZE: usize = 128 * 1024;
+
+// object types
+const CAN_MESSAGE: u32 = 1;
+const LOG_CONTAINER: u32 = 10;
+const CAN_MESSAGE2: u32 = 86;
+const CAN_FD_MESSAGE: u32 = 100;
+const CAN_FD_MESSAGE_64: u32 = 101;
+
+// log container compression methods
+const NO_COMPRESSION: u16 = 0;
+const ZLIB_DEFLATE: u16 = 2;
+
+// object header flags, giving the timestamp resolution
+const TIME_TEN_MICS: u32 = 1;
+const TIME_ONE_NANS: u32 = 2;
+
+// message flags
+const CAN_MSG_RTR: u8 = 0x80;
+const CAN_MSG_EXT: u32 = 0x8000_0000;
+const CAN_FD_MSG_EDL: u8 = 0x01;
+const CAN_FD64_MSG_RTR: u32 = 0x0010;
+const CAN_FD64_MSG_EDL: u32 = 0x1000;
+
+fn u16_le(b: &[u8], off: usize) -> u16 {
+    b[off] as u16 | (b[off + 1] as u16) << 8
+}
+
+fn u32_le(b: &[u8], off: usize) -> u32 {
+    u16_le(b, off) as u32 | (u16_le(b, off + 2) as u32) << 16
+}
+
+fn u64_le(b: &[u8], off: usize) -> u64 {
+    u32_le(b, off) as u64 | (u32_le(b, off + 4) as u64) << 32
+}
+
+/// Converts a civil date into days since the UNIX epoch.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    // see http://howardhinnant.github.io/date_algorithms.html
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = if y >= 0 { y } else { y - 399 } / 400;
+    let yoe = y - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Converts a Windows `SYSTEMTIME` into microseconds since the UNIX epoch.
+fn system_time_us(b: &[u8]) -> u64 {
+    let field = |i: usize| u16_le(b, 2 * i) as i64;
+
+    // fields: year, month, day of week, day, hour, minute, second, ms
+    let days = days_from_civil(field(0), field(1), field(3));
+    let secs = days * 86400 + field(4) * 3600 + field(5) * 60 + field(6);
+    let us = secs * 1_000_000 + field(7) * 1000;
+
+    if us > 0 { us as u64 } else { 0 }
+}
+
+/// A BLF file reader.
+#[derive(Debug)]
+pub struct Reader<R> {
+    rdr: R,
+    start_us: u64,
+    buf: Vec<u8>,
+    pos: usize,
+    header_read: bool,
+}
+
+impl<R: io::Read> Reader<R> {
+    pub fn from_reader(rdr: R) -> Reader<io::BufReader<R>> {
+        Reader {
+            rdr: io::BufReader::new(rdr),
+            start_us: 0,
+            buf: Vec::new(),
+            pos: 0,
+            header_read: false,
+        }
+    }
+}
+
+impl Reader<LogFile> {
+    /// Open a log file, decompressing it on the fly if it is gzipped.
+    pub fn from_file<P: AsRef<path::Path>>(path: P) -> io::Result<Reader<io::BufReader<LogFile>>> {
+        Ok(Reader::from_reader(open_file(path)?))
+    }
+}
+
+impl<R: io::Read> Reader<R> {
+    /// Measurement start time in microseconds since the UNIX epoch
+    ///
+    /// Only valid after the first frame has been read.
+    pub fn start_time_us(&self) -> u64 {
+        self.start_us
+    }
+
+    fn read_file_header(&mut self) -> Result<(), ParseError> {
+        let mut head = [0; 8];
+        self.rdr.read_exact(&mut head)?;
+
+        if &head[..4] != FILE_SIGNATURE {
+            return Err(ParseError::InvalidHeader);
+        }
+
+        let size = u32_le(&head, 4) as usize;
+        if size < 56 || size > MAX_OBJECT_SIZE {
+            return Err(ParseError::InvalidHeader);
+        }
+
+        let mut header = vec![0; size - 8];
+        self.rdr.read_exact(&mut header)?;
+
+        // measurement start time is located at offset 40 of the header
+        self.start_us = system_time_us(&header[32..48]);
+        self.header_read = true;
+        Ok(())
+    }
+
+    /// Reads the next top-level object, appending its contents to the
+    /// object buffer. Returns `false` at the end of the file.
+    fn fill_buf(&mut self) -> Result<bool, ParseError> {
+        let mut base = [0; OBJ_HEADER_BASE_SIZE];
+
+        // distinguish a clean end of file from a truncated object
+        match self.rdr.read(&mut base[..1])? {
+            0 => return Ok(false),
+            _ => self.rdr.read_exact(&mut base[1..])?,
+        }
+
+        if &base[..4] != OBJ_SIGNATURE {
+            return Err(ParseError::InvalidHeader);
+        }
+
+        let size = u32_le(&base, 8) as usize;
+        let obj_type = u32_le(&base, 12);
+        if size < OBJ_HEADER_BASE_SIZE || size > MAX_OBJECT_SIZE {
+            return Err(ParseError::InvalidHeader);
+        }
+
+        let mut body = vec![0; size - OBJ_HEADER_BASE_SIZE];
+        self.rdr.read_exact(&mut body)?;
+
+        // top-level objects are padded to a multiple of four bytes
+        let mut padding = [0; 4];
+        self.rdr.read_exact(&mut padding[..size % 4])?;
+
+        // drop consumed data before appending
+        self.buf.drain(..self.pos);
+        self.pos = 0;
+
+        if obj_type != LOG_CONTAINER {
+            // uncompressed object outside of a container
+            self.buf.extend_from_slice(&base);
+            self.buf.extend_from_slice(&body);
+            return Ok(true);
+        }
+
+        if body.len() < 16 {
+            return Err(ParseError::InvalidHeader);
+        }
+
+        let method = u16_le(&body, 0);
+        let data = &body[16..];
+        match method {
+            NO_COMPRESSION => self.buf.extend_from_slice(data),
+            ZLIB_DEFLATE => {
+                ZlibDecoder::new(data).read_to_end(&mut self.buf)?;
+            }
+            _ => return Err(ParseError::InvalidHeader),
+        }
+
+        Ok(true)
+    }
+
+    /// Returns the position, size and padded size of the next complete
+    /// object in the buffer.
+    fn next_object(&self) -> Result<Option<(usize, usize, usize)>, ParseError> {
+        let avail = &self.buf[self.pos..];
+        if avail.len() < OBJ_HEADER_BASE_SIZE {
+            return Ok(None);
+        }
+
+        if &avail[..4] != OBJ_SIGNATURE {
+            return Err(ParseError::InvalidHeader);
+        }
+
+        let size = u32_le(avail, 8) as usize;
+        if size < OBJ_HEADER_BASE_SIZE {
+            return Err(ParseError::InvalidHeader);
+        }
+
+        let padded = if u32_le(avail, 12) == CAN_FD_MESSAGE_64 {
+            size
+        } else {
+            size + size % 4
+        };
+
+        if avail.len() < padded {
+            Ok(None)
+        } else {
+            Ok(Some((self.pos, size, padded)))
+        }
+    }
+
+    // converts a single object, returning `None` for unsupported types
+    fn parse_object(&self, obj: &[u8]) -> Result<Option<TimestampedFrame>, ParseError> {
+        let header_size = u16_le(obj, 4) as usize;
+        let obj_type = u32_le(obj, 12);
+
+        if header_size < OBJ_HEADER_BASE_SIZE + 16 || obj.len() < header_size {
+            return Err(ParseError::InvalidHeader);
+        }
+
+        let flags = u32_le(obj, 16);
+        let timestamp = u64_le(obj, 24);
+        let t_us = self.start_us +
+                   match flags {
+            TIME_TEN_MICS => timestamp * 10,
+            TIME_ONE_NANS => timestamp / 1000,
+            _ => timestamp,
+        };
+
+        let data = &obj[header_size..];
+
+        let (channel, id, rtr, payload) = match obj_type {
+            CAN_MESSAGE | CAN_MESSAGE2 => {
+                // channel u16, flags u8, dlc u8, id u32, data [u8; 8]
+                if data.len() < 16 {
+                    return Err(ParseError::UnexpectedEndOfLine);
+                }
+                let dlc = (data[3] as usize).min(8);
+                (u16_le(data, 0), u32_le(data, 4), data[2] & CAN_MSG_RTR != 0, &data[8..8 + dlc])
+            }
+            CAN_FD_MESSAGE => {
+                // channel u16, flags u8, dlc u8, id u32, frame length u32,
+                // bit count u8, fd flags u8, valid bytes u8, 5 reserved,
+                // data [u8; 64]
+                if data.len() < 84 {
+                    return Err(ParseError::UnexpectedEndOfLine);
+                }
+                if data[13] & CAN_FD_MSG_EDL != 0 {
+                    return Err(ParseError::UnsupportedFdFrame);
+                }
+                let dlc = (data[3] as usize).min(8);
+                (u16_le(data, 0), u32_le(data, 4), data[2] & CAN_MSG_RTR != 0, &data[20..20 + dlc])
+            }
+            CAN_FD_MESSAGE_64 => {
+                // channel u8, dlc u8, valid bytes u8, tx count u8, id u32,
+                // frame length u32, flags u32, ..., data starting at 40
+                if data.len() < 40 {
+                    return Err(ParseError::UnexpectedEndOfLine);
+                }
+                let fd_flags = u32_le(data, 12);
+                if fd_flags & CAN_FD64_MSG_EDL != 0 {
+                    return Err(ParseError::UnsupportedFdFrame);
+                }
+                let len = (data[2] as usize).min(8).min(data.len() - 40);
+                (data[0] as u16,
+                 u32_le(data, 4),
+                 fd_flags & CAN_FD64_MSG_RTR != 0,
+                 &data[40..40 + len])
+            }
+            _ => return Ok(None),
+        };
+
+        let data = if rtr { vec![0; payload.len()] } else { payload.to_vec() };
+        let frame = build_frame(id & !CAN_MSG_EXT, id & CAN_MSG_EXT != 0, &data, rtr)?;
+
+        Ok(Some(TimestampedFrame {
+            t_us: t_us,
+            device: format!("can{}", channel.saturating_sub(1)),
+            frame: frame,
+        }))
+    }
+
+    /// Advance state, returning the next frame.
+    pub fn next_frame(&mut self) -> Result<Option<TimestampedFrame>, ParseError> {
+        if !self.header_read {
+            self.read_file_header()?;
+        }
+
+        loop {
+            let (pos, size, padded) = match self.next_object()? {
+                Some(obj) => obj,
+                None => {
+                    if !self.fill_buf()? {
+                        return Ok(None);
+                    }
+                    continue;
+                }
+            };
+
+            self.pos = pos + padded;
+            if let Some(rec) = self.parse_object(&self.buf[pos..pos + size])? {
+                return Ok(Some(rec));
+            }
+        }
+    }
+}
+
+impl<R: io::Read> Iterator for Reader<R> {
+    type Item = Result<TimestampedFrame, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_frame() {
+            Ok(Some(rec)) => Some(Ok(rec)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io::Write;
+    use flate2::Compression;
+    use flate2::write::ZlibEncoder;
+    use dump::ParseError;
+    use super::Reader;
+
+    fn object(obj_type: u32, header_ext: &[u8], body: &[u8]) -> Vec<u8> {
+        let header_size = 16 + header_ext.len();
+        let size = header_size + body.len();
+
+        let mut obj = b"LOBJ".to_vec();
+        obj.extend_from_slice(&[header_size as u8, 0, 1, 0]);
+        obj.extend_from_slice(&[size as u8, (size >> 8) as u8, 0, 0]);
+        obj.extend_from_slice(&[obj_type as u8, 0, 0, 0]);
+        obj.extend_from_slice(header_ext);
+        obj.extend_from_slice(body);
+        obj
+    }
+
+    /// File header with a start time of 2017-07-01 12:00:00.500
+    fn file_header() -> Vec<u8> {
+        let mut file = b"LOGG".to_vec();
+        file.extend_from_slice(&[144, 0, 0, 0]);
+        file.extend_from_slice(&[0; 32]);
+        for field in &[2017u16, 7, 6, 1, 12, 0, 0, 500] {
+            file.extend_from_slice(&[*field as u8, (*field >> 8) as u8]);
+        }
+        file.extend_from_slice(&[0; 144 - 56]);
+        file
+    }
+
+    #[test]
+    fn test_compressed_container() {
+        let mut file = file_header();
+
+        // CAN message on channel 2 at 25ms (ten microsecond resolution)
+        let header_ext = [1, 0, 0, 0, 0, 0, 0, 0, 0xc4, 0x09, 0, 0, 0, 0, 0, 0];
+        let body = [2, 0, 0, 3, 0x23, 0x01, 0, 0x80, 0xaa, 0xbb, 0xcc, 0, 0, 0, 0, 0];
+        let inner = object(1, &header_ext, &body);
+
+        let mut enc = ZlibEncoder::new(Vec::new(), Compression::default());
+        enc.write_all(&inner).unwrap();
+        let compressed = enc.finish().unwrap();
+
+        // zlib compression method, uncompressed size
+        let mut container_body = vec![2, 0, 0, 0, 0, 0, 0, 0];
+        container_body.extend_from_slice(&[inner.len() as u8, 0, 0, 0, 0, 0, 0, 0]);
+        container_body.extend_from_slice(&compressed);
+        let container = object(10, &[], &container_body);
+        let padding = container.len() % 4;
+        file.extend_from_slice(&container);
+        file.extend_from_slice(&vec![0; padding]);
+
+        let frames: Vec<_> = Reader::from_reader(&file[..]).map(|r| r.unwrap()).collect();
+        assert_eq!(frames.len(), 1);
+
+        assert_eq!(frames[0].t_us, 1498910400500000 + 25000);
+        assert_eq!(frames[0].device, "can1");
+        assert_eq!(frames[0].frame.id(), 0x123);
+        assert!(frames[0].frame.is_extended());
+        assert_eq!(frames[0].frame.data(), &[0xaa, 0xbb, 0xcc]);
+    }
+
+    #[test]
+    fn test_oversized_object() {
+        // a corrupt size must not be allocated
+        let mut file = file_header();
+        file.extend_from_slice(b"LOBJ");
+        file.extend_from_slice(&[16, 0, 1, 0, 0xff, 0xff, 0xff, 0xff, 10, 0, 0, 0]);
+
+        match Reader::from_reader(&file[..]).next() {
+            Some(Err(ParseError::InvalidHeader)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+    }
+}
@@ -0,0 +1,343 @@
+//! Export of decoded traffic
+//!
+//! Writes frames as wide-format CSV or as JSON lines, one row per frame,
+//! for import into tools like pandas or Grafana. Given a `SignalDecoder`,
+//! every signal gets its own column, filled in for the frames carrying it:
+//!
+//! ```text
+//! timestamp,device,id,extended,data,engine_speed,coolant_temp
+//! 1469439874.299654,can0,100,false,1027,2500,
+//! 1469439874.309654,can0,200,false,5A,,50
+//! ```
+//!
+//! `SignalTable` decodes signals defined like in a DBC file.
+
+use std::{fmt, io};
+use CanFrame;
+use super::{ParseError, TimestampedFrame};
+
+/// Decoder of signal values from frames, e.g. defined by a DBC file
+pub trait SignalDecoder {
+    /// Names of all signals, the columns of the export
+    fn signal_names(&self) -> Vec<String>;
+
+    /// Decode the signals carried by `frame`, as pairs of an index into
+    /// `signal_names` and the physical value
+    fn decode(&self, frame: &CanFrame) -> Vec<(usize, f64)>;
+}
+
+/// Bit order of a signal
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ByteOrder {
+    /// Intel format, the start bit is the least significant bit
+    LittleEndian,
+    /// Motorola format, the start bit is the most significant bit
+    BigEndian,
+}
+
+/// A signal definition, following the DBC conventions
+#[derive(Clone, Debug)]
+pub struct Signal {
+    /// ID of the frames carrying the signal
+    pub id: u32,
+    pub name: String,
+    /// Start bit, numbered from bit 0 of byte 0 to bit 7 of byte 7
+    pub start_bit: u8,
+    /// Length in bits, up to 64
+    pub length: u8,
+    pub byte_order: ByteOrder,
+    /// Whether the raw value is two's complement
+    pub signed: bool,
+    pub factor: f64,
+    pub offset: f64,
+}
+
+impl Signal {
+    /// Unsigned little endian signal with a factor of one and no offset
+    pub fn new(id: u32, name: &str, start_bit: u8, length: u8) -> Signal {
+        Signal {
+            id: id,
+            name: name.to_owned(),
+            start_bit: start_bit,
+            length: length,
+            byte_order: ByteOrder::LittleEndian,
+            signed: false,
+            factor: 1.0,
+            offset: 0.0,
+        }
+    }
+
+    /// Extract the raw value from `data`, or `None` if the data is too
+    /// short.
+    pub fn raw_value(&self, data: &[u8]) -> Option<u64> {
+        let bit = |n: usize| data.get(n / 8).map(|byte| ((byte >> (n % 8)) & 1) as u64);
+
+        let mut value = 0;
+        let mut n = self.start_bit as usize;
+        for i in 0..self.length as usize {
+            match self.byte_order {
+                ByteOrder::LittleEndian => value |= bit(n + i)? << i,
+                ByteOrder::BigEndian => {
+                    value = value << 1 | bit(n)?;
+                    // continue with the next byte's most significant bit
+                    n = if n % 8 == 0 { n + 15 } else { n - 1 };
+                }
+            }
+        }
+        Some(value)
+    }
+
+    /// Decode the physical value from `data`.
+    pub fn decode(&self, data: &[u8]) -> Option<f64> {
+        let raw = self.raw_value(data)?;
+        let value = if self.signed && self.length > 0 && self.length < 64 &&
+                       raw >> (self.length - 1) & 1 == 1 {
+            (raw | !0 << self.length) as i64 as f64
+        } else if self.signed {
+            raw as i64 as f64
+        } else {
+            raw as f64
+        };
+        Some(value * self.factor + self.offset)
+    }
+}
+
+/// A list of signal definitions
+#[derive(Clone, Debug, Default)]
+pub struct SignalTable {
+    signals: Vec<Signal>,
+}
+
+impl SignalTable {
+    pub fn new() -> SignalTable {
+        SignalTable::default()
+    }
+
+    /// Add a signal, which becomes the next column.
+    pub fn add(mut self, signal: Signal) -> SignalTable {
+        self.signals.push(signal);
+        self
+    }
+
+    pub fn signals(&self) -> &[Signal] {
+        &self.signals
+    }
+}
+
+impl SignalDecoder for SignalTable {
+    fn signal_names(&self) -> Vec<String> {
+        self.signals.iter().map(|s| s.name.clone()).collect()
+    }
+
+    fn decode(&self, frame: &CanFrame) -> Vec<(usize, f64)> {
+        if frame.is_error() || frame.is_rtr() {
+            return Vec::new();
+        }
+
+        self.signals
+            .iter()
+            .enumerate()
+            .filter(|&(_, s)| s.id == frame.id())
+            .filter_map(|(i, s)| s.decode(frame.data()).map(|v| (i, v)))
+            .collect()
+    }
+}
+
+struct Timestamp(u64);
+
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{:06}", self.0 / 1_000_000, self.0 % 1_000_000)
+    }
+}
+
+fn hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02X}", b)).collect()
+}
+
+fn csv_field(s: &str) -> String {
+    if s.contains(|c| c == ',' || c == '"' || c == '\n') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
+
+fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Values of all signals for one frame, in column order
+fn decode_row(decoder: Option<&SignalDecoder>,
+              columns: usize,
+              frame: &CanFrame)
+              -> Vec<Option<f64>> {
+    let mut row = vec![None; columns];
+    if let Some(decoder) = decoder {
+        for (i, value) in decoder.decode(frame) {
+            if let Some(cell) = row.get_mut(i) {
+                *cell = Some(value);
+            }
+        }
+    }
+    row
+}
+
+/// Write `frames` as CSV, with one column per signal of `decoder`.
+///
+/// Returns the number of frames written. Stops at the first error.
+pub fn write_csv<I, W>(frames: I,
+                       decoder: Option<&SignalDecoder>,
+                       mut wtr: W)
+                       -> Result<u64, ParseError>
+    where I: IntoIterator<Item = Result<TimestampedFrame, ParseError>>,
+          W: io::Write
+{
+    let names = decoder.map_or_else(Vec::new, |d| d.signal_names());
+
+    write!(wtr, "timestamp,device,id,extended,data")?;
+    for name in &names {
+        write!(wtr, ",{}", csv_field(name))?;
+    }
+    writeln!(wtr)?;
+
+    let mut count = 0;
+    for rec in frames {
+        let rec = rec?;
+        write!(wtr,
+               "{},{},{:X},{},{}",
+               Timestamp(rec.t_us),
+               csv_field(&rec.device),
+               rec.frame.id(),
+               rec.frame.is_extended(),
+               hex(rec.frame.data()))?;
+        for value in decode_row(decoder, names.len(), &rec.frame) {
+            match value {
+                Some(value) => write!(wtr, ",{}", value)?,
+                None => write!(wtr, ",")?,
+            }
+        }
+        writeln!(wtr)?;
+        count += 1;
+    }
+
+    wtr.flush()?;
+    Ok(count)
+}
+
+/// Write `frames` as JSON lines, one object per frame.
+///
+/// Decoded signals are added as fields named after the signal, signals a
+/// frame does not carry are left out. Returns the number of frames written.
+pub fn write_json_lines<I, W>(frames: I,
+                              decoder: Option<&SignalDecoder>,
+                              mut wtr: W)
+                              -> Result<u64, ParseError>
+    where I: IntoIterator<Item = Result<TimestampedFrame, ParseError>>,
+          W: io::Write
+{
+    let names: Vec<String> = decoder.map_or_else(Vec::new, |d| d.signal_names())
+        .iter()
+        .map(|name| json_string(name))
+        .collect();
+
+    let mut count = 0;
+    for rec in frames {
+        let rec = rec?;
+        write!(wtr,
+               "{{\"timestamp\":{},\"device\":{},\"id\":{},\"extended\":{},\"data\":\"{}\"",
+               Timestamp(rec.t_us),
+               json_string(&rec.device),
+               rec.frame.id(),
+               rec.frame.is_extended(),
+               hex(rec.frame.data()))?;
+        for (name, value) in names.iter().zip(decode_row(decoder, names.len(), &rec.frame)) {
+            // NaN and infinity are not valid JSON
+            match value {
+                Some(value) if value.is_finite() => write!(wtr, ",{}:{}", name, value)?,
+                _ => {}
+            }
+        }
+        writeln!(wtr, "}}")?;
+        count += 1;
+    }
+
+    wtr.flush()?;
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    use CanFrame;
+    use dump::TimestampedFrame;
+    use super::{write_csv, write_json_lines, ByteOrder, Signal, SignalTable};
+
+    fn frames() -> Vec<TimestampedFrame> {
+        vec![TimestampedFrame {
+                 t_us: 1469439874299654,
+                 device: "can0".to_owned(),
+                 frame: CanFrame::new(0x100, &[0x10, 0x27, 0xff], false, false).unwrap(),
+             },
+             TimestampedFrame {
+                 t_us: 1469439874309654,
+                 device: "can0".to_owned(),
+                 frame: CanFrame::new(0x200, &[0x5a], false, false).unwrap(),
+             }]
+    }
+
+    fn table() -> SignalTable {
+        SignalTable::new()
+            .add(Signal { factor: 0.25, ..Signal::new(0x100, "engine_speed", 0, 16) })
+            .add(Signal { signed: true, ..Signal::new(0x100, "torque", 16, 8) })
+            .add(Signal { offset: -40.0, ..Signal::new(0x200, "coolant_temp", 0, 8) })
+    }
+
+    #[test]
+    fn test_signal_decoding() {
+        let data = [0x12, 0x34, 0x56];
+        let mut signal = Signal::new(0, "", 4, 8);
+        assert_eq!(signal.raw_value(&data), Some(0x41));
+
+        // Motorola: MSB at bit 7 of byte 0, continuing into byte 1
+        signal.byte_order = ByteOrder::BigEndian;
+        signal.start_bit = 7;
+        signal.length = 12;
+        assert_eq!(signal.raw_value(&data), Some(0x123));
+
+        signal.length = 32;
+        assert_eq!(signal.raw_value(&data), None);
+    }
+
+    #[test]
+    fn test_csv() {
+        let mut out = Vec::new();
+        let table = table();
+        assert_eq!(write_csv(frames().into_iter().map(Ok), Some(&table), &mut out).unwrap(), 2);
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "timestamp,device,id,extended,data,engine_speed,torque,coolant_temp\n\
+                    1469439874.299654,can0,100,false,1027FF,2500,-1,\n\
+                    1469439874.309654,can0,200,false,5A,,,50\n");
+    }
+
+    #[test]
+    fn test_json_lines() {
+        let mut out = Vec::new();
+        write_json_lines(frames().into_iter().map(Ok), Some(&table()), &mut out).unwrap();
+        assert_eq!(String::from_utf8(out).unwrap(),
+                   "{\"timestamp\":1469439874.299654,\"device\":\"can0\",\"id\":256,\
+                    \"extended\":false,\"data\":\"1027FF\",\"engine_speed\":2500,\"torque\":-1}\n\
+                    {\"timestamp\":1469439874.309654,\"device\":\"can0\",\"id\":512,\
+                    \"extended\":false,\"data\":\"5A\",\"coolant_temp\":50}\n");
+    }
+}
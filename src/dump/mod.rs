@@ -0,0 +1,770 @@
+//! candump format parsing
+//!
+//! Parses the text format emitted by the `candump` utility, which is part of
+//! [can-utils](https://github.com/linux-can/can-utils).
+//!
+//! Example:
+//!
+//! ```text
+//! (1469439874.299654) can1 701#7F
+//! ```
+//!
+//! Can be parsed by a `Reader` object. The API is inspired by the
+//! [csv](https://crates.io/crates/csv) crate.
+//!
+//! Other log formats live in submodules and yield the same
+//! `TimestampedFrame` type:
+//!
+//! * `blf`: Vector binary logging format (reading only)
+//! * `pcap`: pcap and pcapng captures, as used by Wireshark
+//! * `trc`: PEAK PCAN-View trace files
+//!
+//! `open` detects the format of a log file and returns an iterator over its
+//! frames, `transcode` copies frames from one format into another. Logs
+//! can be sent onto a bus with their original timing by `replay::Replayer`
+//! and recorded from a live bus by `record::Recorder`. `merge` combines
+//! logs recorded separately into a single timeline. `export` writes
+//! frames and their decoded signals as CSV or JSON lines.
+
+use std::{fs, io, path};
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::io::{Read, Seek};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use flate2::read::MultiGzDecoder;
+use hex::FromHex;
+
+pub mod blf;
+pub mod export;
+pub mod pcap;
+pub mod record;
+pub mod replay;
+pub mod trc;
+
+// cannot be generic, because from_str_radix is not part of any Trait
+fn parse_raw(bytes: &[u8], radix: u32) -> Option<u64> {
+    ::std::str::from_utf8(bytes)
+        .ok()
+        .and_then(|s| u64::from_str_radix(s, radix).ok())
+}
+
+// strips a trailing linefeed (and carriage return)
+fn trim_eol(mut bytes: &[u8]) -> &[u8] {
+    while let Some(&c) = bytes.last() {
+        if c != b'\n' && c != b'\r' {
+            break;
+        }
+        bytes = &bytes[..bytes.len() - 1];
+    }
+    bytes
+}
+
+/// Parses a frame in `cansend` syntax, e.g. `123#DEADBEEF`.
+///
+/// Standard frames use three, extended and error frames eight hex digits
+/// for the ID. Error frames are recognized by the error flag inside the
+/// ID, the way `candump` writes them. Remote frames are written as `R`,
+/// optionally followed by the data length code.
+fn parse_frame(can_raw: &[u8]) -> Result<super::CanFrame, ParseError> {
+    let sep_idx = can_raw.iter()
+        .position(|&c| c == b'#')
+        .ok_or(ParseError::InvalidCanFrame)?;
+    let (can_id, can_data) = can_raw.split_at(sep_idx);
+
+    // skip seperator
+    let can_data = &can_data[1..];
+
+    // CAN FD frames are written as `123##<flags><data>`
+    if can_data.first() == Some(&b'#') {
+        return Err(ParseError::UnsupportedFdFrame);
+    }
+
+    let id = parse_raw(can_id, 16).ok_or(ParseError::InvalidCanFrame)? as u32;
+    let (rtr, data) = match can_data.split_first() {
+        Some((&b'R', dlc)) => {
+            let dlc = if dlc.is_empty() {
+                0
+            } else {
+                parse_raw(dlc, 10).ok_or(ParseError::InvalidCanFrame)? as usize
+            };
+            (true, vec![0; dlc])
+        }
+        _ => (false, Vec::from_hex(can_data).map_err(|_| ParseError::InvalidCanFrame)?),
+    };
+
+    if can_id.len() == 8 && id & super::ERR_FLAG != 0 {
+        return Ok(super::CanFrame::new(id & super::ERR_MASK, &data, false, true)?);
+    }
+
+    // small IDs are still extended if written with eight digits
+    Ok(build_frame(id & super::EFF_MASK, can_id.len() == 8, &data, rtr)?)
+}
+
+/// Constructs a data or remote frame, forcing the extended frame format if
+/// `extended` is set, even for IDs that would fit into a standard frame.
+fn build_frame(id: u32,
+               extended: bool,
+               data: &[u8],
+               rtr: bool)
+               -> Result<super::CanFrame, super::ConstructionError> {
+    let mut frame = super::CanFrame::new(id, data, rtr, false)?;
+    if extended {
+        frame._id |= super::EFF_FLAG;
+    }
+    Ok(frame)
+}
+
+impl FromStr for super::CanFrame {
+    type Err = ParseError;
+
+    /// Parse a frame in `cansend` syntax, e.g. `123#DEADBEEF`, `123#R` or
+    /// `00000123#11` (extended).
+    fn from_str(s: &str) -> Result<super::CanFrame, ParseError> {
+        parse_frame(s.trim().as_bytes())
+    }
+}
+
+/// A log file opened for reading
+///
+/// Files starting with the gzip magic number are decompressed on the fly,
+/// so that e.g. `candump.log.gz` can be read without unpacking it first.
+#[derive(Debug)]
+pub enum LogFile {
+    Plain(fs::File),
+    Gzip(MultiGzDecoder<io::BufReader<fs::File>>),
+}
+
+impl io::Read for LogFile {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match *self {
+            LogFile::Plain(ref mut f) => f.read(buf),
+            LogFile::Gzip(ref mut f) => f.read(buf),
+        }
+    }
+}
+
+/// Open a log file, detecting gzip compression.
+///
+/// All `from_file` constructors of the log readers use this function.
+pub fn open_file<P: AsRef<path::Path>>(path: P) -> io::Result<LogFile> {
+    let mut file = fs::File::open(path)?;
+
+    let mut magic = Vec::with_capacity(2);
+    (&mut file).take(2).read_to_end(&mut magic)?;
+    file.seek(io::SeekFrom::Start(0))?;
+
+    if magic == [0x1f, 0x8b] {
+        Ok(LogFile::Gzip(MultiGzDecoder::new(io::BufReader::new(file))))
+    } else {
+        Ok(LogFile::Plain(file))
+    }
+}
+
+#[derive(Debug)]
+/// A CAN log reader.
+pub struct Reader<R> {
+    rdr: R,
+    line_buf: Vec<u8>,
+}
+
+impl<R: io::Read> Reader<R> {
+    pub fn from_reader(rdr: R) -> Reader<io::BufReader<R>> {
+        Reader {
+            rdr: io::BufReader::new(rdr),
+            line_buf: Vec::new(),
+        }
+    }
+}
+
+impl Reader<LogFile> {
+    /// Open a log file, decompressing it on the fly if it is gzipped.
+    pub fn from_file<P: AsRef<path::Path>>(path: P) -> io::Result<Reader<io::BufReader<LogFile>>> {
+        Ok(Reader::from_reader(open_file(path)?))
+    }
+}
+
+/// Record iterator
+#[derive(Debug)]
+pub struct CanDumpRecords<'a, R: 'a> {
+    src: &'a mut Reader<R>,
+}
+
+/// Iterator over `TimestampedFrame`s
+#[derive(Debug)]
+pub struct TimestampedFrames<'a, R: 'a> {
+    src: &'a mut Reader<R>,
+}
+
+/// A frame along with its reception time and interface.
+///
+/// Unlike `CanDumpRecord`, which borrows from the reader's line buffer,
+/// this type owns all its data and is shared by all log formats.
+#[derive(Debug, Clone)]
+pub struct TimestampedFrame {
+    /// Timestamp in microseconds since the UNIX epoch
+    pub t_us: u64,
+
+    /// Name of the interface the frame was received on
+    pub device: String,
+
+    /// The frame itself
+    pub frame: super::CanFrame,
+}
+
+impl TimestampedFrame {
+    /// The bus the frame was received on
+    pub fn bus(&self) -> super::BusId {
+        super::BusId::Name(self.device.clone())
+    }
+}
+
+impl<'a> From<CanDumpRecord<'a>> for TimestampedFrame {
+    fn from(rec: CanDumpRecord<'a>) -> TimestampedFrame {
+        TimestampedFrame {
+            t_us: rec.t_us,
+            device: rec.device.to_owned(),
+            frame: rec.frame,
+        }
+    }
+}
+
+/// Recorded CAN frame.
+#[derive(Debug)]
+pub struct CanDumpRecord<'a> {
+    pub t_us: u64,
+    pub device: &'a str,
+    pub frame: super::CanFrame,
+}
+
+#[derive(Debug)]
+/// Log parse error
+pub enum ParseError {
+    Io(io::Error),
+    /// The file header is missing or describes an unsupported version
+    InvalidHeader,
+    UnexpectedEndOfLine,
+    InvalidTimestamp,
+    InvalidDeviceName,
+    InvalidCanFrame,
+    /// The line contains a CAN FD frame, which cannot be represented
+    UnsupportedFdFrame,
+    ConstructionError(super::ConstructionError),
+}
+
+impl From<io::Error> for ParseError {
+    fn from(e: io::Error) -> ParseError {
+        ParseError::Io(e)
+    }
+}
+
+impl From<super::ConstructionError> for ParseError {
+    fn from(e: super::ConstructionError) -> ParseError {
+        ParseError::ConstructionError(e)
+    }
+}
+
+impl<R: io::BufRead> Reader<R> {
+    /// Returns an iterator over all records
+    pub fn records(&mut self) -> CanDumpRecords<R> {
+        CanDumpRecords { src: self }
+    }
+
+    /// Returns an iterator over all records as `TimestampedFrame`s
+    ///
+    /// A line that fails to parse (e.g. a CAN FD frame) yields an error, but
+    /// iteration can continue with the following line.
+    pub fn frames(&mut self) -> TimestampedFrames<R> {
+        TimestampedFrames { src: self }
+    }
+
+    /// Advance state, returning next record.
+    pub fn next_record(&mut self) -> Result<Option<CanDumpRecord>, ParseError> {
+        loop {
+            self.line_buf.clear();
+            let bytes_read = self.rdr.read_until(b'\n', &mut self.line_buf)?;
+
+            // reached EOF
+            if bytes_read == 0 {
+                return Ok(None);
+            }
+
+            // skip blank lines
+            if !trim_eol(&self.line_buf).is_empty() {
+                break;
+            }
+        }
+
+        let mut field_iter = self.line_buf.split(|&c| c == b' ').filter(|f| !f.is_empty());
+
+        // parse time field
+        let f = field_iter.next().ok_or(ParseError::UnexpectedEndOfLine)?;
+
+        if f.len() < 3 || f[0] != b'(' || f[f.len() - 1] != b')' {
+            return Err(ParseError::InvalidTimestamp);
+        }
+
+        let inner = &f[1..f.len() - 1];
+
+        // split at dot, read both parts
+        let dot = inner.iter()
+            .position(|&c| c == b'.')
+            .ok_or(ParseError::InvalidTimestamp)?;
+
+        let (num, mant) = inner.split_at(dot);
+
+        // parse number and multiply, the fractional part is scaled to
+        // microseconds in case it does not have exactly six digits
+        let mant = &mant[1..];
+        if mant.is_empty() || mant.len() > 9 {
+            return Err(ParseError::InvalidTimestamp);
+        }
+        let n_num: u64 = parse_raw(num, 10).ok_or(ParseError::InvalidTimestamp)?;
+        let n_mant: u64 = parse_raw(mant, 10).ok_or(ParseError::InvalidTimestamp)?;
+        let n_mant = n_mant * 10u64.pow(9 - mant.len() as u32) / 1000;
+        let t_us = n_num.saturating_mul(1_000_000).saturating_add(n_mant);
+
+        let f = field_iter.next().ok_or(ParseError::UnexpectedEndOfLine)?;
+
+        // device name
+        let device = ::std::str::from_utf8(f).map_err(|_| ParseError::InvalidDeviceName)?;
+
+        // parse packet
+        let can_raw = field_iter.next()
+            .ok_or(ParseError::UnexpectedEndOfLine)?;
+        let frame = parse_frame(trim_eol(can_raw))?;
+
+        Ok(Some(CanDumpRecord {
+            t_us: t_us,
+            device: device,
+            frame: frame,
+        }))
+    }
+}
+
+impl<'a, R: io::Read> Iterator for CanDumpRecords<'a, io::BufReader<R>> {
+    type Item = Result<(u64, super::CanFrame), ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // lift Option:
+        match self.src.next_record() {
+            Ok(Some(CanDumpRecord { t_us, frame, .. })) => Some(Ok((t_us, frame))),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+impl<'a, R: io::BufRead> Iterator for TimestampedFrames<'a, R> {
+    type Item = Result<TimestampedFrame, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.src.next_record() {
+            Ok(Some(rec)) => Some(Ok(rec.into())),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+impl<R: io::BufRead> Iterator for Reader<R> {
+    type Item = Result<TimestampedFrame, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.frames().next()
+    }
+}
+
+/// A CAN log writer.
+///
+/// Writes frames in the format of `candump -l`, which can be read back by
+/// `Reader` and replayed by `canplayer`.
+#[derive(Debug)]
+pub struct Writer<W: io::Write> {
+    wtr: W,
+}
+
+impl<W: io::Write> Writer<W> {
+    pub fn from_writer(wtr: W) -> Writer<W> {
+        Writer { wtr: wtr }
+    }
+
+    /// Write a single frame received at `t_us` (microseconds since the
+    /// UNIX epoch) on `device`.
+    pub fn write_frame(&mut self,
+                       t_us: u64,
+                       device: &str,
+                       frame: &super::CanFrame)
+                       -> io::Result<()> {
+        write!(self.wtr,
+               "({}.{:06}) {} ",
+               t_us / 1_000_000,
+               t_us % 1_000_000,
+               device)?;
+
+        if frame.is_error() {
+            write!(self.wtr, "{:08X}#", frame.err() | super::ERR_FLAG)?;
+        } else if frame.is_extended() {
+            write!(self.wtr, "{:08X}#", frame.id())?;
+        } else {
+            write!(self.wtr, "{:03X}#", frame.id())?;
+        }
+
+        if frame.is_rtr() {
+            self.wtr.write_all(b"R")?;
+            if !frame.data().is_empty() {
+                write!(self.wtr, "{}", frame.data().len())?;
+            }
+        } else {
+            for byte in frame.data() {
+                write!(self.wtr, "{:02X}", byte)?;
+            }
+        }
+
+        self.wtr.write_all(b"\n")
+    }
+
+    /// Write a `TimestampedFrame`.
+    pub fn write(&mut self, rec: &TimestampedFrame) -> io::Result<()> {
+        self.write_frame(rec.t_us, &rec.device, &rec.frame)
+    }
+
+    /// Write a frame using the current system time as timestamp.
+    pub fn write_now(&mut self, device: &str, frame: &super::CanFrame) -> io::Result<()> {
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let t_us = now.as_secs() * 1_000_000 + (now.subsec_nanos() / 1000) as u64;
+        self.write_frame(t_us, device, frame)
+    }
+
+    /// Flush the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.wtr.flush()
+    }
+
+    /// Unwrap the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.wtr
+    }
+}
+
+impl Writer<fs::File> {
+    pub fn from_file<P: AsRef<path::Path>>(path: P) -> io::Result<Writer<io::BufWriter<fs::File>>> {
+        Ok(Writer::from_writer(io::BufWriter::new(fs::File::create(path)?)))
+    }
+}
+
+impl<W: io::Write> LogWriter for Writer<W> {
+    fn write(&mut self, rec: &TimestampedFrame) -> io::Result<()> {
+        Writer::write(self, rec)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Writer::flush(self)
+    }
+}
+
+/// Supported log file formats
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// `candump -l` output
+    CanDump,
+    /// Vector binary logging format
+    Blf,
+    /// pcap or pcapng capture
+    Pcap,
+    /// PEAK trace file
+    Trc,
+}
+
+impl Format {
+    /// Detect the format of a log from its first bytes.
+    pub fn detect(header: &[u8]) -> Option<Format> {
+        // pcapng section header and pcap magic numbers in both byte orders
+        const PCAP_MAGICS: [[u8; 4]; 5] = [[0x0a, 0x0d, 0x0d, 0x0a],
+                                           [0xa1, 0xb2, 0xc3, 0xd4],
+                                           [0xd4, 0xc3, 0xb2, 0xa1],
+                                           [0xa1, 0xb2, 0x3c, 0x4d],
+                                           [0x4d, 0x3c, 0xb2, 0xa1]];
+
+        if header.starts_with(b"LOGG") {
+            return Some(Format::Blf);
+        }
+        if PCAP_MAGICS.iter().any(|magic| header.starts_with(magic)) {
+            return Some(Format::Pcap);
+        }
+
+        // text formats, possibly starting with empty lines
+        match header.iter().find(|c| !c.is_ascii_whitespace()) {
+            Some(&b'(') => Some(Format::CanDump),
+            Some(&b';') => Some(Format::Trc),
+            _ => None,
+        }
+    }
+}
+
+/// Iterator over the frames of a log of any format
+pub type Frames = Box<Iterator<Item = Result<TimestampedFrame, ParseError>>>;
+
+/// Open a log file of any supported format.
+///
+/// The format is detected from the file contents, gzipped files are
+/// decompressed on the fly.
+pub fn open<P: AsRef<path::Path>>(path: P) -> Result<Frames, ParseError> {
+    let mut rdr = io::BufReader::new(open_file(path)?);
+    let format = {
+        let header = io::BufRead::fill_buf(&mut rdr)?;
+        Format::detect(header).ok_or(ParseError::InvalidHeader)?
+    };
+
+    Ok(match format {
+        Format::CanDump => Box::new(Reader::from_reader(rdr)),
+        Format::Blf => Box::new(blf::Reader::from_reader(rdr)),
+        Format::Pcap => Box::new(pcap::Reader::from_reader(rdr)),
+        Format::Trc => Box::new(trc::Reader::from_reader(rdr)),
+    })
+}
+
+/// A writer for any log format
+pub trait LogWriter {
+    /// Write a `TimestampedFrame`.
+    fn write(&mut self, rec: &TimestampedFrame) -> io::Result<()>;
+
+    /// Flush the underlying writer.
+    fn flush(&mut self) -> io::Result<()>;
+}
+
+/// Copy all frames from `frames` to `writer`, returning the number of
+/// frames written.
+///
+/// Stops at the first error. To skip frames the target format cannot
+/// represent (e.g. CAN FD frames), filter the iterator beforehand.
+pub fn transcode<I, W>(frames: I, writer: &mut W) -> Result<u64, ParseError>
+    where I: IntoIterator<Item = Result<TimestampedFrame, ParseError>>,
+          W: LogWriter
+{
+    let mut count = 0;
+    for rec in frames {
+        writer.write(&rec?)?;
+        count += 1;
+    }
+    writer.flush()?;
+    Ok(count)
+}
+
+/// Iterator merging several logs into a single timeline
+///
+/// Created by `merge`.
+#[derive(Debug)]
+pub struct Merge<I> {
+    sources: Vec<I>,
+    heads: Vec<Option<TimestampedFrame>>,
+    // (timestamp, source) of all heads, earliest first
+    queue: BinaryHeap<Reverse<(u64, usize)>>,
+    // sources whose next frame has not been read yet
+    refill: Vec<usize>,
+}
+
+/// Merge `sources` by timestamp.
+///
+/// Each source must be ordered by timestamp itself. Frames with equal
+/// timestamps are returned in the order of their sources. Errors are passed
+/// through as soon as they are read, the failing source is read again on
+/// the next call.
+///
+/// To merge logs of different formats, use the boxed iterators returned by
+/// `open`.
+pub fn merge<I>(sources: Vec<I>) -> Merge<I>
+    where I: Iterator<Item = Result<TimestampedFrame, ParseError>>
+{
+    Merge {
+        heads: sources.iter().map(|_| None).collect(),
+        refill: (0..sources.len()).rev().collect(),
+        queue: BinaryHeap::with_capacity(sources.len()),
+        sources: sources,
+    }
+}
+
+impl<I> Iterator for Merge<I>
+    where I: Iterator<Item = Result<TimestampedFrame, ParseError>>
+{
+    type Item = Result<TimestampedFrame, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(idx) = self.refill.pop() {
+            match self.sources[idx].next() {
+                Some(Ok(rec)) => {
+                    self.queue.push(Reverse((rec.t_us, idx)));
+                    self.heads[idx] = Some(rec);
+                }
+                Some(Err(e)) => {
+                    self.refill.push(idx);
+                    return Some(Err(e));
+                }
+                None => (),
+            }
+        }
+
+        let Reverse((_, idx)) = self.queue.pop()?;
+        self.refill.push(idx);
+        self.heads[idx].take().map(Ok)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs};
+    use std::io::Write;
+    use flate2::Compression;
+    use flate2::write::GzEncoder;
+    use CanFrame;
+    use super::{merge, open, pcap, transcode, Format, ParseError, Reader, Writer};
+
+    #[test]
+    fn test_simple_example() {
+        let input: &[u8] = b"(1469439874.299591) can1 080#\n\
+                             (1469439874.299654) can1 701#7F";
+
+        let mut reader = Reader::from_reader(input);
+
+        {
+            let rec1 = reader.next_record().unwrap().unwrap();
+
+            assert_eq!(rec1.t_us, 1469439874299591);
+            assert_eq!(rec1.device, "can1");
+            assert_eq!(rec1.frame.id(), 0x080);
+            assert_eq!(rec1.frame.is_rtr(), false);
+            assert_eq!(rec1.frame.is_error(), false);
+            assert_eq!(rec1.frame.is_extended(), false);
+            assert_eq!(rec1.frame.data(), &[]);
+        }
+
+        {
+            let rec2 = reader.next_record().unwrap().unwrap();
+            assert_eq!(rec2.t_us, 1469439874299654);
+            assert_eq!(rec2.device, "can1");
+            assert_eq!(rec2.frame.id(), 0x701);
+            assert_eq!(rec2.frame.is_rtr(), false);
+            assert_eq!(rec2.frame.is_error(), false);
+            assert_eq!(rec2.frame.is_extended(), false);
+            assert_eq!(rec2.frame.data(), &[0x7F]);
+        }
+
+        assert!(reader.next_record().unwrap().is_none());
+    }
+
+    #[test]
+    fn test_write_roundtrip() {
+        let input: &[u8] = b"(1469439874.000591) can1 080#\n\
+                             (1469439874.299654) can1 701#7F\n\
+                             (1469439874.299654) can1 12345678#DEADBEEF\n\
+                             (1469439874.299654) can1 123#R3\n\
+                             (1469439874.299654) can1 20000080#0000000000000000\n";
+
+        let mut reader = Reader::from_reader(input);
+        let mut writer = Writer::from_writer(Vec::new());
+
+        for rec in reader.frames() {
+            writer.write(&rec.unwrap()).unwrap();
+        }
+
+        assert_eq!(writer.into_inner(), input);
+    }
+
+    #[test]
+    fn test_frame_variants() {
+        let input: &[u8] = b"(1469439874.299591) can0 00000123#1122\n\
+                             \n\
+                             (1469439874.3) can0 20000004#0004000000000000\n\
+                             (1469439874.400000) can0 7FF#R2\n\
+                             (1469439874.500000) can0 123##1DEADBEEF\n";
+
+        let mut reader = Reader::from_reader(input);
+        let frames: Vec<_> = reader.frames().collect();
+        assert_eq!(frames.len(), 4);
+
+        let f = frames[0].as_ref().unwrap();
+        assert_eq!(f.device, "can0");
+        assert_eq!(f.frame.id(), 0x123);
+        assert!(f.frame.is_extended());
+        assert_eq!(f.frame.data(), &[0x11, 0x22]);
+
+        let f = frames[1].as_ref().unwrap();
+        assert_eq!(f.t_us, 1469439874300000);
+        assert!(f.frame.is_error());
+        assert_eq!(f.frame.err(), 0x4);
+
+        let f = frames[2].as_ref().unwrap();
+        assert!(f.frame.is_rtr());
+        assert_eq!(f.frame.data().len(), 2);
+
+        match frames[3] {
+            Err(ParseError::UnsupportedFdFrame) => (),
+            ref r => panic!("unexpected result {:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_frame_from_str() {
+        let frame: CanFrame = "123#DEADBEEF".parse().unwrap();
+        assert_eq!(frame.id(), 0x123);
+        assert_eq!(frame.data(), &[0xde, 0xad, 0xbe, 0xef]);
+
+        let frame: CanFrame = "00000123#R".parse().unwrap();
+        assert!(frame.is_extended() && frame.is_rtr());
+
+        assert!("123DEADBEEF".parse::<CanFrame>().is_err());
+    }
+
+
+    #[test]
+    fn test_gzip_file() {
+        let path = env::temp_dir().join(format!("socketcan-test-{}.log.gz", ::std::process::id()));
+
+        let mut enc = GzEncoder::new(fs::File::create(&path).unwrap(), Compression::default());
+        enc.write_all(b"(1469439874.299654) can1 701#7F\n").unwrap();
+        enc.finish().unwrap();
+
+        let frames: Vec<_> = Reader::from_file(&path).unwrap().frames().collect();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].as_ref().unwrap().frame.data(), &[0x7f]);
+    }
+
+    #[test]
+    fn test_open_transcode() {
+        assert_eq!(Format::detect(b"\n(1469439874.299654) can1"), Some(Format::CanDump));
+        assert_eq!(Format::detect(b";$FILEVERSION=2.0"), Some(Format::Trc));
+        assert_eq!(Format::detect(&[0x0a, 0x0d, 0x0d, 0x0a, 0x1c]), Some(Format::Pcap));
+        assert_eq!(Format::detect(b"date Mon Jul 3"), None);
+
+        let path = env::temp_dir().join(format!("socketcan-test-{}.log", ::std::process::id()));
+        fs::write(&path,
+                  b"(1469439874.299591) can1 080#\n(1469439874.299654) can0 701#7F\n")
+            .unwrap();
+
+        let mut wtr = pcap::Writer::from_writer(Vec::new());
+        let count = transcode(open(&path).unwrap(), &mut wtr).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert_eq!(count, 2);
+
+        let out = wtr.into_inner();
+        let frames: Vec<_> = pcap::Reader::from_reader(&out[..]).map(|r| r.unwrap()).collect();
+        assert_eq!(frames[1].t_us, 1469439874299654);
+        assert_eq!(frames[1].device, "can0");
+        assert_eq!(frames[1].frame.data(), &[0x7f]);
+    }
+
+    #[test]
+    fn test_merge() {
+        let can0: &[u8] = b"(1.000000) can0 100#\n(3.000000) can0 101#\n(3.000000) can0 102#\n";
+        let can1: &[u8] = b"(2.000000) can1 200#\n(3.000000) can1 201#\n(4.000000) can1 202#\n";
+
+        let ids: Vec<_> = merge(vec![Reader::from_reader(can0), Reader::from_reader(can1)])
+            .map(|r| r.unwrap().frame.id())
+            .collect();
+        assert_eq!(ids, vec![0x100, 0x200, 0x101, 0x102, 0x201, 0x202]);
+    }
+}
@@ -0,0 +1,623 @@
+//! pcap and pcapng capture files
+//!
+//! Reads classic pcap and pcapng captures using `LINKTYPE_CAN_SOCKETCAN`
+//! (227), as written by Wireshark, tcpdump and `dumpcap`, and writes
+//! pcapng files that Wireshark opens directly.
+//!
+//! Each packet carries a `struct can_frame`, except that the CAN ID is
+//! stored in network (big endian) byte order. Captures made with older
+//! versions of libpcap on little endian hosts contain the ID in host byte
+//! order instead. The reader detects such IDs by checking which byte order
+//! yields a plausible ID and flag combination; an ambiguous ID is always
+//! interpreted as big endian.
+//!
+//! Packets of interfaces using a different link type are skipped, CAN FD
+//! frames are reported as `ParseError::UnsupportedFdFrame`.
+
+use std::{fs, io, path};
+use {CanFrame, EFF_FLAG, EFF_MASK, ERR_FLAG, ERR_MASK, RTR_FLAG, SFF_MASK};
+use super::{build_frame, open_file, LogFile, LogWriter, ParseError, TimestampedFrame};
+
+/// Link type of SocketCAN captures
+pub const LINKTYPE_CAN_SOCKETCAN: u16 = 227;
+
+const PCAP_MAGIC_US: u32 = 0xa1b2c3d4;
+const PCAP_MAGIC_NS: u32 = 0xa1b23c4d;
+
+// pcapng block types
+const SECTION_HEADER: u32 = 0x0a0d0d0a;
+const INTERFACE_DESCRIPTION: u32 = 0x00000001;
+const ENHANCED_PACKET: u32 = 0x00000006;
+
+const BYTE_ORDER_MAGIC: u32 = 0x1a2b3c4d;
+
+// pcapng option codes
+const OPT_END: u16 = 0;
+const IF_NAME: u16 = 2;
+const IF_TSRESOL: u16 = 9;
+
+/// Frame flag marking a CAN FD frame
+const CANFD_FDF: u8 = 0x04;
+
+/// Size of a classic CAN frame in a capture
+const FRAME_SIZE: usize = 16;
+
+/// Size of a `struct canfd_frame`, the largest packet read
+const MAX_PACKET_SIZE: usize = 72;
+
+/// Largest interface description or packet block read, other blocks are
+/// skipped without reading them into memory
+const MAX_BLOCK_SIZE: usize = 64 * 1024;
+
+fn u16_at(b: &[u8], off: usize, big_endian: bool) -> u16 {
+    let (hi, lo) = if big_endian { (b[off], b[off + 1]) } else { (b[off + 1], b[off]) };
+    (hi as u16) << 8 | lo as u16
+}
+
+fn u32_at(b: &[u8], off: usize, big_endian: bool) -> u32 {
+    let (hi, lo) = if big_endian {
+        (u16_at(b, off, true), u16_at(b, off + 2, true))
+    } else {
+        (u16_at(b, off + 2, false), u16_at(b, off, false))
+    };
+    (hi as u32) << 16 | lo as u32
+}
+
+/// Fills `buf` completely, returning `false` if the reader is at its end.
+fn read_or_eof<R: io::Read>(rdr: &mut R, buf: &mut [u8]) -> io::Result<bool> {
+    match rdr.read(&mut buf[..1])? {
+        0 => Ok(false),
+        _ => rdr.read_exact(&mut buf[1..]).map(|_| true),
+    }
+}
+
+/// Discards the next `len` bytes of `rdr`.
+fn skip<R: io::Read>(rdr: R, len: u64) -> io::Result<()> {
+    if io::copy(&mut rdr.take(len), &mut io::sink())? < len {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "truncated block"));
+    }
+    Ok(())
+}
+
+/// Converts a timestamp in units of `1 / per_sec` seconds to microseconds.
+fn scale_us(ts: u64, per_sec: u64) -> u64 {
+    ts / per_sec * 1_000_000 + (ts % per_sec).saturating_mul(1_000_000) / per_sec
+}
+
+/// Checks if `id` is a consistent combination of CAN ID and flags.
+fn is_plausible_id(id: u32) -> bool {
+    if id & EFF_FLAG != 0 {
+        true
+    } else if id & ERR_FLAG != 0 {
+        // error classes only use the lower bits
+        id & ERR_MASK < 0x1000
+    } else {
+        id & EFF_MASK <= SFF_MASK
+    }
+}
+
+/// Parses a captured `struct can_frame`.
+fn parse_packet(data: &[u8]) -> Result<CanFrame, ParseError> {
+    if data.len() < 8 {
+        return Err(ParseError::InvalidCanFrame);
+    }
+
+    let mut id = u32_at(data, 0, true);
+    if !is_plausible_id(id) && is_plausible_id(id.swap_bytes()) {
+        id = id.swap_bytes();
+    }
+
+    let len = data[4] as usize;
+    if len > 8 || data[5] & CANFD_FDF != 0 {
+        return Err(ParseError::UnsupportedFdFrame);
+    }
+
+    let payload = data.get(8..8 + len).ok_or(ParseError::InvalidCanFrame)?;
+
+    if id & ERR_FLAG != 0 {
+        return Ok(CanFrame::new(id & ERR_MASK, payload, false, true)?);
+    }
+
+    let rtr = id & RTR_FLAG != 0;
+    let payload = if rtr { vec![0; len] } else { payload.to_vec() };
+    Ok(build_frame(id & EFF_MASK, id & EFF_FLAG != 0, &payload, rtr)?)
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Format {
+    Pcap {
+        big_endian: bool,
+        nanos: bool,
+        snaplen: usize,
+    },
+    PcapNg { big_endian: bool },
+}
+
+#[derive(Debug)]
+struct Interface {
+    linktype: u16,
+    name: Option<String>,
+    per_sec: u64,
+}
+
+/// Parses the body of an interface description block.
+fn parse_interface(body: &[u8], big_endian: bool) -> Result<Interface, ParseError> {
+    if body.len() < 8 {
+        return Err(ParseError::InvalidHeader);
+    }
+
+    let mut iface = Interface {
+        linktype: u16_at(body, 0, big_endian),
+        name: None,
+        per_sec: 1_000_000,
+    };
+
+    let mut opts = &body[8..];
+    while opts.len() >= 4 {
+        let code = u16_at(opts, 0, big_endian);
+        let len = u16_at(opts, 2, big_endian) as usize;
+        let value = opts.get(4..4 + len).ok_or(ParseError::InvalidHeader)?;
+
+        match code {
+            OPT_END => break,
+            IF_NAME => {
+                iface.name = Some(String::from_utf8_lossy(value)
+                    .trim_end_matches('\0')
+                    .to_owned());
+            }
+            IF_TSRESOL if len == 1 => {
+                // msb set: negative power of two, otherwise of ten
+                let exp = (value[0] & 0x7f) as u32;
+                let base: u64 = if value[0] & 0x80 != 0 { 2 } else { 10 };
+                iface.per_sec = base.checked_pow(exp).ok_or(ParseError::InvalidHeader)?;
+            }
+            _ => (),
+        }
+
+        // a truncated padding ends the options
+        opts = opts.get((4 + len + 3) / 4 * 4..).unwrap_or(&[]);
+    }
+
+    Ok(iface)
+}
+
+/// A pcap or pcapng file reader.
+///
+/// The format is detected from the file's magic number.
+#[derive(Debug)]
+pub struct Reader<R> {
+    rdr: R,
+    device: String,
+    format: Option<Format>,
+    interfaces: Vec<Interface>,
+}
+
+impl<R: io::Read> Reader<R> {
+    pub fn from_reader(rdr: R) -> Reader<io::BufReader<R>> {
+        Reader {
+            rdr: io::BufReader::new(rdr),
+            device: "can0".to_owned(),
+            format: None,
+            interfaces: Vec::new(),
+        }
+    }
+}
+
+impl Reader<LogFile> {
+    /// Open a log file, decompressing it on the fly if it is gzipped.
+    pub fn from_file<P: AsRef<path::Path>>(path: P) -> io::Result<Reader<io::BufReader<LogFile>>> {
+        Ok(Reader::from_reader(open_file(path)?))
+    }
+}
+
+impl<R> Reader<R> {
+    /// Set the device name reported for frames of interfaces without a
+    /// name, which includes all frames of classic pcap files.
+    pub fn with_device(mut self, device: &str) -> Reader<R> {
+        self.device = device.to_owned();
+        self
+    }
+}
+
+impl<R: io::Read> Reader<R> {
+    fn read_file_header(&mut self) -> Result<Format, ParseError> {
+        let mut magic = [0; 4];
+        self.rdr.read_exact(&mut magic)?;
+
+        let big_endian = match u32_at(&magic, 0, true) {
+            SECTION_HEADER => {
+                self.read_section_header()?;
+                return Ok(self.format.expect("set by section header"));
+            }
+            PCAP_MAGIC_US | PCAP_MAGIC_NS => true,
+            m if m.swap_bytes() == PCAP_MAGIC_US || m.swap_bytes() == PCAP_MAGIC_NS => false,
+            _ => return Err(ParseError::InvalidHeader),
+        };
+
+        let mut header = [0; 20];
+        self.rdr.read_exact(&mut header)?;
+
+        // version u16 u16, time zone i32, sigfigs u32, snaplen u32, link type
+        if u32_at(&header, 16, big_endian) != LINKTYPE_CAN_SOCKETCAN as u32 {
+            return Err(ParseError::InvalidHeader);
+        }
+
+        Ok(Format::Pcap {
+            big_endian: big_endian,
+            nanos: u32_at(&magic, 0, big_endian) == PCAP_MAGIC_NS,
+            snaplen: u32_at(&header, 12, big_endian) as usize,
+        })
+    }
+
+    fn next_pcap_frame(&mut self,
+                       big_endian: bool,
+                       nanos: bool,
+                       snaplen: usize)
+                       -> Result<Option<TimestampedFrame>, ParseError> {
+        let mut header = [0; 16];
+        if !read_or_eof(&mut self.rdr, &mut header)? {
+            return Ok(None);
+        }
+
+        // seconds, sub-second part, captured length, original length
+        let secs = u32_at(&header, 0, big_endian) as u64;
+        let frac = u32_at(&header, 4, big_endian) as u64;
+        let caplen = u32_at(&header, 8, big_endian) as usize;
+        if caplen > snaplen || caplen > MAX_PACKET_SIZE {
+            return Err(ParseError::InvalidCanFrame);
+        }
+        let mut data = vec![0; caplen];
+        self.rdr.read_exact(&mut data)?;
+
+        Ok(Some(TimestampedFrame {
+            t_us: secs * 1_000_000 + if nanos { frac / 1000 } else { frac },
+            device: self.device.clone(),
+            frame: parse_packet(&data)?,
+        }))
+    }
+
+    // called after reading the block type of a section header block
+    fn read_section_header(&mut self) -> Result<(), ParseError> {
+        let mut head = [0; 8];
+        self.rdr.read_exact(&mut head)?;
+
+        let big_endian = match u32_at(&head, 4, true) {
+            BYTE_ORDER_MAGIC => true,
+            m if m.swap_bytes() == BYTE_ORDER_MAGIC => false,
+            _ => return Err(ParseError::InvalidHeader),
+        };
+
+        let len = u32_at(&head, 0, big_endian) as usize;
+        if len < 28 {
+            return Err(ParseError::InvalidHeader);
+        }
+
+        // skip version, section length, options and trailing length
+        skip(&mut self.rdr, len as u64 - 12)?;
+
+        // interface ids are local to a section
+        self.interfaces.clear();
+        self.format = Some(Format::PcapNg { big_endian: big_endian });
+        Ok(())
+    }
+
+    fn next_pcapng_frame(&mut self) -> Result<Option<TimestampedFrame>, ParseError> {
+        loop {
+            let mut block_type = [0; 4];
+            if !read_or_eof(&mut self.rdr, &mut block_type)? {
+                return Ok(None);
+            }
+
+            // the block type of a section header reads the same in both
+            // byte orders, the section may switch the byte order
+            if u32_at(&block_type, 0, true) == SECTION_HEADER {
+                self.read_section_header()?;
+                continue;
+            }
+
+            let big_endian = match self.format {
+                Some(Format::PcapNg { big_endian }) => big_endian,
+                _ => unreachable!("pcapng blocks are only read in pcapng files"),
+            };
+
+            let mut len = [0; 4];
+            self.rdr.read_exact(&mut len)?;
+            let len = u32_at(&len, 0, big_endian) as usize;
+            if len < 12 {
+                return Err(ParseError::InvalidHeader);
+            }
+
+            let block_type = u32_at(&block_type, 0, big_endian);
+            if block_type != INTERFACE_DESCRIPTION && block_type != ENHANCED_PACKET {
+                skip(&mut self.rdr, len as u64 - 8)?;
+                continue;
+            }
+            if len > MAX_BLOCK_SIZE {
+                return Err(ParseError::InvalidHeader);
+            }
+
+            // block body followed by the repeated block length
+            let mut body = vec![0; len - 8];
+            self.rdr.read_exact(&mut body)?;
+            let body = &body[..len - 12];
+
+            match block_type {
+                INTERFACE_DESCRIPTION => {
+                    let iface = parse_interface(body, big_endian)?;
+                    self.interfaces.push(iface);
+                }
+                ENHANCED_PACKET => {
+                    if body.len() < 20 {
+                        return Err(ParseError::InvalidHeader);
+                    }
+
+                    let iface = self.interfaces
+                        .get(u32_at(body, 0, big_endian) as usize)
+                        .ok_or(ParseError::InvalidDeviceName)?;
+                    if iface.linktype != LINKTYPE_CAN_SOCKETCAN {
+                        continue;
+                    }
+
+                    let ts = (u32_at(body, 4, big_endian) as u64) << 32 |
+                             u32_at(body, 8, big_endian) as u64;
+                    let caplen = u32_at(body, 12, big_endian) as usize;
+                    let data = body.get(20..20 + caplen).ok_or(ParseError::InvalidCanFrame)?;
+
+                    return Ok(Some(TimestampedFrame {
+                        t_us: scale_us(ts, iface.per_sec),
+                        device: iface.name.clone().unwrap_or_else(|| self.device.clone()),
+                        frame: parse_packet(data)?,
+                    }));
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Advance state, returning the next frame.
+    pub fn next_frame(&mut self) -> Result<Option<TimestampedFrame>, ParseError> {
+        let format = match self.format {
+            Some(format) => format,
+            None => {
+                let format = self.read_file_header()?;
+                self.format = Some(format);
+                format
+            }
+        };
+
+        match format {
+            Format::Pcap { big_endian, nanos, snaplen } => {
+                self.next_pcap_frame(big_endian, nanos, snaplen)
+            }
+            Format::PcapNg { .. } => self.next_pcapng_frame(),
+        }
+    }
+}
+
+impl<R: io::Read> Iterator for Reader<R> {
+    type Item = Result<TimestampedFrame, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_frame() {
+            Ok(Some(rec)) => Some(Ok(rec)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// A pcapng file writer.
+///
+/// Writes a little endian pcapng file with microsecond timestamps. An
+/// interface description is written for every device name the first time
+/// it is used.
+#[derive(Debug)]
+pub struct Writer<W: io::Write> {
+    wtr: W,
+    header_written: bool,
+    interfaces: Vec<String>,
+}
+
+impl<W: io::Write> Writer<W> {
+    pub fn from_writer(wtr: W) -> Writer<W> {
+        Writer {
+            wtr: wtr,
+            header_written: false,
+            interfaces: Vec::new(),
+        }
+    }
+
+    fn write_block(&mut self, block_type: u32, body: &[u8]) -> io::Result<()> {
+        let padding = (4 - body.len() % 4) % 4;
+        let len = (12 + body.len() + padding) as u32;
+
+        self.wtr.write_all(&u32_le(block_type))?;
+        self.wtr.write_all(&u32_le(len))?;
+        self.wtr.write_all(body)?;
+        self.wtr.write_all(&[0; 3][..padding])?;
+        self.wtr.write_all(&u32_le(len))
+    }
+
+    // returns the interface id of `device`, describing it if necessary
+    fn interface_id(&mut self, device: &str) -> io::Result<u32> {
+        if !self.header_written {
+            let mut body = Vec::new();
+            body.extend_from_slice(&u32_le(BYTE_ORDER_MAGIC));
+            // version 1.0, section length unknown
+            body.extend_from_slice(&[1, 0, 0, 0]);
+            body.extend_from_slice(&[0xff; 8]);
+            self.write_block(SECTION_HEADER, &body)?;
+            self.header_written = true;
+        }
+
+        if let Some(idx) = self.interfaces.iter().position(|name| name == device) {
+            return Ok(idx as u32);
+        }
+
+        let mut body = Vec::new();
+        body.extend_from_slice(&[LINKTYPE_CAN_SOCKETCAN as u8, 0, 0, 0]);
+        // snap length
+        body.extend_from_slice(&u32_le(FRAME_SIZE as u32));
+        body.extend_from_slice(&[IF_NAME as u8, 0, device.len() as u8, 0]);
+        body.extend_from_slice(device.as_bytes());
+        body.extend_from_slice(&[0; 3][..(4 - device.len() % 4) % 4]);
+        body.extend_from_slice(&[0; 4]);
+        self.write_block(INTERFACE_DESCRIPTION, &body)?;
+
+        self.interfaces.push(device.to_owned());
+        Ok(self.interfaces.len() as u32 - 1)
+    }
+
+    /// Write a single frame received at `t_us` (microseconds since the
+    /// UNIX epoch) on `device`.
+    pub fn write_frame(&mut self, t_us: u64, device: &str, frame: &CanFrame) -> io::Result<()> {
+        if device.len() > 255 {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "device name too long"));
+        }
+
+        let iface = self.interface_id(device)?;
+
+        let mut body = Vec::with_capacity(20 + FRAME_SIZE);
+        body.extend_from_slice(&u32_le(iface));
+        body.extend_from_slice(&u32_le((t_us >> 32) as u32));
+        body.extend_from_slice(&u32_le(t_us as u32));
+        body.extend_from_slice(&u32_le(FRAME_SIZE as u32));
+        body.extend_from_slice(&u32_le(FRAME_SIZE as u32));
+
+        // the CAN ID is stored in network byte order
+        body.extend_from_slice(&u32_le(frame._id.swap_bytes()));
+        body.extend_from_slice(&[frame.data().len() as u8, 0, 0, 0]);
+        let mut data = [0; 8];
+        if !frame.is_rtr() {
+            data[..frame.data().len()].copy_from_slice(frame.data());
+        }
+        body.extend_from_slice(&data);
+
+        self.write_block(ENHANCED_PACKET, &body)
+    }
+
+    /// Write a `TimestampedFrame`.
+    pub fn write(&mut self, rec: &TimestampedFrame) -> io::Result<()> {
+        self.write_frame(rec.t_us, &rec.device, &rec.frame)
+    }
+
+    /// Flush the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.wtr.flush()
+    }
+
+    /// Unwrap the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.wtr
+    }
+}
+
+impl<W: io::Write> LogWriter for Writer<W> {
+    fn write(&mut self, rec: &TimestampedFrame) -> io::Result<()> {
+        Writer::write(self, rec)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Writer::flush(self)
+    }
+}
+
+impl Writer<fs::File> {
+    pub fn from_file<P: AsRef<path::Path>>(path: P) -> io::Result<Writer<io::BufWriter<fs::File>>> {
+        Ok(Writer::from_writer(io::BufWriter::new(fs::File::create(path)?)))
+    }
+}
+
+fn u32_le(v: u32) -> [u8; 4] {
+    [v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8]
+}
+
+#[cfg(test)]
+mod test {
+    use CanFrame;
+    use dump::ParseError;
+    use super::{Reader, Writer};
+
+    #[test]
+    fn test_write_roundtrip() {
+        let frames = [(1498910400000000, "can0", CanFrame::new(0x123, &[1, 2], false, false)),
+                      (1498910400001500, "vcan1", CanFrame::new(0x12345, &[], false, false)),
+                      (1498910400002000, "can0", CanFrame::new(0x7ff, &[0; 4], true, false)),
+                      (1498910400003000, "can0", CanFrame::new(0x4, &[0, 8], false, true))];
+
+        let mut wtr = Writer::from_writer(Vec::new());
+        for &(t_us, device, ref frame) in &frames {
+            wtr.write_frame(t_us, device, frame.as_ref().unwrap()).unwrap();
+        }
+        let out = wtr.into_inner();
+
+        let read: Vec<_> = Reader::from_reader(&out[..]).map(|r| r.unwrap()).collect();
+        assert_eq!(read.len(), frames.len());
+        for (rec, &(t_us, device, ref frame)) in read.iter().zip(frames.iter()) {
+            let frame = frame.as_ref().unwrap();
+            assert_eq!(rec.t_us, t_us);
+            assert_eq!(rec.device, device);
+            assert_eq!(rec.frame._id, frame._id);
+            assert_eq!(rec.frame.data(), frame.data());
+        }
+    }
+
+    #[test]
+    fn test_pcap_host_byte_order() {
+        let mut input = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        input.extend_from_slice(&[0; 8]);
+        input.extend_from_slice(&[0xff, 0xff, 0, 0, 227, 0, 0, 0]);
+
+        // id 0x123 in network byte order, then 0x80012345 in host order
+        for id in &[[0x00, 0x00, 0x01, 0x23], [0x45, 0x23, 0x01, 0x80]] {
+            input.extend_from_slice(&[10, 0, 0, 0, 20, 0, 0, 0, 16, 0, 0, 0, 16, 0, 0, 0]);
+            input.extend_from_slice(id);
+            input.extend_from_slice(&[1, 0, 0, 0, 0xaa, 0, 0, 0, 0, 0, 0, 0]);
+        }
+
+        let read: Vec<_> = Reader::from_reader(&input[..])
+            .with_device("vcan0")
+            .map(|r| r.unwrap())
+            .collect();
+
+        assert_eq!(read.len(), 2);
+        assert_eq!(read[0].t_us, 10000020);
+        assert_eq!(read[0].device, "vcan0");
+        assert_eq!(read[0].frame.id(), 0x123);
+        assert!(!read[0].frame.is_extended());
+        assert_eq!(read[1].frame.id(), 0x12345);
+        assert!(read[1].frame.is_extended());
+        assert_eq!(read[1].frame.data(), &[0xaa]);
+    }
+
+    #[test]
+    fn test_malformed_input() {
+        // a caplen beyond the snaplen is rejected before allocating
+        let mut input = vec![0xd4, 0xc3, 0xb2, 0xa1, 2, 0, 4, 0];
+        input.extend_from_slice(&[0; 8]);
+        input.extend_from_slice(&[0xff, 0xff, 0, 0, 227, 0, 0, 0]);
+        input.extend_from_slice(&[10, 0, 0, 0, 20, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 16, 0, 0, 0]);
+        match Reader::from_reader(&input[..]).next() {
+            Some(Err(ParseError::InvalidCanFrame)) => (),
+            r => panic!("unexpected result: {:?}", r),
+        }
+
+        // section header, then an interface whose last option lacks its
+        // padding, then a packet
+        let mut input = vec![0x0a, 0x0d, 0x0d, 0x0a, 28, 0, 0, 0, 0x4d, 0x3c, 0x2b, 0x1a];
+        input.extend_from_slice(&[1, 0, 0, 0]);
+        input.extend_from_slice(&[0xff; 8]);
+        input.extend_from_slice(&[28, 0, 0, 0]);
+        input.extend_from_slice(&[1, 0, 0, 0, 29, 0, 0, 0, 227, 0, 0, 0, 0, 0, 0, 0]);
+        input.extend_from_slice(&[2, 0, 5, 0, b'v', b'c', b'a', b'n', b'0', 29, 0, 0, 0]);
+        input.extend_from_slice(&[6, 0, 0, 0, 48, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        input.extend_from_slice(&[0, 0, 0, 0, 16, 0, 0, 0, 16, 0, 0, 0]);
+        input.extend_from_slice(&[0, 0, 0x01, 0x23, 1, 0, 0, 0, 0xaa, 0, 0, 0, 0, 0, 0, 0]);
+        input.extend_from_slice(&[48, 0, 0, 0]);
+
+        let read: Vec<_> = Reader::from_reader(&input[..]).map(|r| r.unwrap()).collect();
+        assert_eq!(read.len(), 1);
+        assert_eq!(read[0].device, "vcan0");
+        assert_eq!(read[0].frame.id(), 0x123);
+    }
+}
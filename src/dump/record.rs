@@ -0,0 +1,256 @@
+//! Live recording
+//!
+//! A `Recorder` writes frames to a series of log files, starting a new file
+//! once the current one exceeds a size limit or covers a given time span.
+//! Files are named `<prefix>-<n>.<ext>`, with `n` counting up from `0000`.
+//!
+//! For a long-running logger, run `capture` on a thread per interface and
+//! set the stop flag on shutdown; the current file is flushed before
+//! `capture` returns.
+
+use std::{fs, io, path};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, UNIX_EPOCH};
+use {BusId, CanFrame, CanSocket, ShouldRetry};
+use super::{pcap, trc, Format, LogWriter, TimestampedFrame};
+
+/// Interval at which `capture` checks the stop flag
+const STOP_POLL_INTERVAL_MS: u64 = 100;
+
+/// File writer counting the bytes written
+#[derive(Debug)]
+struct CountingFile {
+    file: fs::File,
+    written: Arc<AtomicUsize>,
+}
+
+impl io::Write for CountingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.file.write(buf)?;
+        self.written.fetch_add(n, Ordering::Relaxed);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// The currently open log file
+struct Output {
+    writer: Box<LogWriter + Send>,
+    written: Arc<AtomicUsize>,
+    start_us: u64,
+}
+
+/// Records frames to rotating log files
+pub struct Recorder {
+    prefix: path::PathBuf,
+    format: Format,
+    max_size: Option<usize>,
+    max_duration: Option<Duration>,
+    index: u32,
+    output: Option<Output>,
+}
+
+impl Recorder {
+    /// Create a recorder writing `format` files starting with `prefix`.
+    ///
+    /// Fails if no writer exists for `format`. No file is created until the
+    /// first frame is recorded.
+    pub fn new<P: AsRef<path::Path>>(prefix: P, format: Format) -> io::Result<Recorder> {
+        if format == Format::Blf {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "BLF files cannot be written"));
+        }
+
+        Ok(Recorder {
+            prefix: prefix.as_ref().to_owned(),
+            format: format,
+            max_size: None,
+            max_duration: None,
+            index: 0,
+            output: None,
+        })
+    }
+
+    /// Start a new file once the current one reached `bytes`.
+    pub fn rotate_size(mut self, bytes: usize) -> Recorder {
+        self.max_size = Some(bytes);
+        self
+    }
+
+    /// Start a new file once a frame is recorded `duration` after the first
+    /// frame of the current file.
+    pub fn rotate_interval(mut self, duration: Duration) -> Recorder {
+        self.max_duration = Some(duration);
+        self
+    }
+
+    /// Path of the `index`th file
+    pub fn file_path(&self, index: u32) -> path::PathBuf {
+        let ext = match self.format {
+            Format::CanDump => "log",
+            Format::Blf => "blf",
+            Format::Pcap => "pcapng",
+            Format::Trc => "trc",
+        };
+
+        let mut name = self.prefix.file_name().unwrap_or_default().to_owned();
+        name.push(format!("-{:04}.{}", index, ext));
+        self.prefix.with_file_name(name)
+    }
+
+    fn open_output(&mut self, start_us: u64) -> io::Result<Output> {
+        let written = Arc::new(AtomicUsize::new(0));
+        let file = CountingFile {
+            file: fs::File::create(self.file_path(self.index))?,
+            written: written.clone(),
+        };
+        self.index += 1;
+
+        let wtr = io::BufWriter::new(file);
+        let writer: Box<LogWriter + Send> = match self.format {
+            Format::CanDump => Box::new(super::Writer::from_writer(wtr)),
+            Format::Pcap => Box::new(pcap::Writer::from_writer(wtr)),
+            Format::Trc => Box::new(trc::Writer::from_writer(wtr)),
+            Format::Blf => unreachable!("rejected on construction"),
+        };
+
+        Ok(Output {
+            writer: writer,
+            written: written,
+            start_us: start_us,
+        })
+    }
+
+    fn needs_rotation(&self, output: &Output, t_us: u64) -> bool {
+        let size_exceeded = match self.max_size {
+            Some(max) => output.written.load(Ordering::Relaxed) >= max,
+            None => false,
+        };
+        let duration_exceeded = match self.max_duration {
+            Some(max) => {
+                let max_us = max.as_secs() * 1_000_000 + (max.subsec_nanos() / 1000) as u64;
+                t_us.saturating_sub(output.start_us) >= max_us
+            }
+            None => false,
+        };
+
+        size_exceeded || duration_exceeded
+    }
+
+    /// Record a frame, rotating files if necessary.
+    ///
+    /// The size limit is checked against the bytes already passed to the
+    /// file, so buffered output is only counted once it is flushed.
+    pub fn record(&mut self, rec: &TimestampedFrame) -> io::Result<()> {
+        let rotate = match self.output {
+            Some(ref output) => self.needs_rotation(output, rec.t_us),
+            None => true,
+        };
+
+        if rotate {
+            self.flush()?;
+            let output = self.open_output(rec.t_us)?;
+            self.output = Some(output);
+        }
+
+        self.output.as_mut().expect("opened above").writer.write(rec)
+    }
+
+    /// Flush the current file.
+    pub fn flush(&mut self) -> io::Result<()> {
+        match self.output {
+            Some(ref mut output) => output.writer.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Record frames received on `socket` until `stop` is set.
+    ///
+    /// Frames are recorded with their kernel receive timestamps, tagged with
+    /// the name of `bus`, e.g. `"can0"` or `BusId::of(&socket)`. Changes the
+    /// socket's read timeout. Returns the number of frames recorded.
+    pub fn capture<B: Into<BusId>>(&mut self,
+                                   socket: &mut CanSocket,
+                                   bus: B,
+                                   stop: &AtomicBool)
+                                   -> io::Result<u64> {
+        let bus = bus.into();
+        let device = bus.name().unwrap_or_else(|| bus.to_string());
+        socket.set_read_timeout(Duration::from_millis(STOP_POLL_INTERVAL_MS))?;
+
+        let mut count = 0;
+        while !stop.load(Ordering::Relaxed) {
+            let (frame, ts) = match socket.read_frame_with_timestamp() {
+                Ok(r) => r,
+                Err(ref e) if e.should_retry() => continue,
+                Err(e) => {
+                    self.flush()?;
+                    return Err(e);
+                }
+            };
+
+            let since_epoch = ts.duration_since(UNIX_EPOCH)
+                .map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+            self.record(&timestamped(since_epoch, &device, frame))?;
+            count += 1;
+        }
+
+        self.flush()?;
+        Ok(count)
+    }
+}
+
+fn timestamped(since_epoch: Duration, device: &str, frame: CanFrame) -> TimestampedFrame {
+    TimestampedFrame {
+        t_us: since_epoch.as_secs() * 1_000_000 + (since_epoch.subsec_nanos() / 1000) as u64,
+        device: device.to_owned(),
+        frame: frame,
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        // errors cannot be reported here, call `flush` to handle them
+        let _ = self.flush();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{env, fs};
+    use std::time::Duration;
+    use CanFrame;
+    use dump::{Format, Reader, TimestampedFrame};
+    use super::Recorder;
+
+    #[test]
+    fn test_rotation() {
+        let dir = env::temp_dir().join(format!("socketcan-record-{}", ::std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut recorder = Recorder::new(dir.join("trip"), Format::CanDump)
+            .unwrap()
+            .rotate_interval(Duration::from_secs(1));
+
+        for &t_us in &[0, 500_000, 1_000_000, 1_200_000, 2_500_000] {
+            recorder.record(&TimestampedFrame {
+                    t_us: t_us,
+                    device: "can0".to_owned(),
+                    frame: CanFrame::new(0x123, &[1], false, false).unwrap(),
+                })
+                .unwrap();
+        }
+        recorder.flush().unwrap();
+
+        let counts: Vec<_> = (0..3)
+            .map(|i| Reader::from_file(recorder.file_path(i)).unwrap().count())
+            .collect();
+        assert_eq!(counts, vec![2, 2, 1]);
+        assert!(!recorder.file_path(3).exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,151 @@
+//! Log replay
+//!
+//! Sends recorded frames onto a socket, reproducing the gaps between them:
+//!
+//! ```no_run
+//! use socketcan::CanSocket;
+//! use socketcan::dump::{self, replay::Replayer};
+//!
+//! let log: Vec<_> = dump::open("candump.log").unwrap().filter_map(Result::ok).collect();
+//! let socket = CanSocket::open("vcan0").unwrap();
+//! Replayer::new(log).speed(2.0).play(&socket).unwrap();
+//! ```
+
+use std::io;
+use std::time::Duration;
+use CanTransport;
+use clock::{Clock, SystemClock};
+use super::TimestampedFrame;
+
+/// Replays a log onto a socket honoring the original timing
+///
+/// The device names of the recorded frames are ignored, all frames are
+/// sent on the socket passed to `play`.
+#[derive(Debug)]
+pub struct Replayer<C = SystemClock> {
+    frames: Vec<TimestampedFrame>,
+    speed: f64,
+    looped: bool,
+    clock: C,
+}
+
+/// Time between the start of the replay and a frame recorded `offset_us`
+/// after the first frame, at `speed`.
+fn scaled_offset(offset_us: u64, speed: f64) -> Duration {
+    let us = (offset_us as f64 / speed) as u64;
+    Duration::new(us / 1_000_000, (us % 1_000_000) as u32 * 1000)
+}
+
+impl Replayer {
+    /// Create a replayer for `log`, in real time and without looping.
+    pub fn new<I: IntoIterator<Item = TimestampedFrame>>(log: I) -> Replayer {
+        Replayer {
+            frames: log.into_iter().collect(),
+            speed: 1.0,
+            looped: false,
+            clock: SystemClock,
+        }
+    }
+}
+
+impl<C: Clock> Replayer<C> {
+    /// Time the replay with `clock` instead of the system clock.
+    pub fn clock<D: Clock>(self, clock: D) -> Replayer<D> {
+        Replayer {
+            frames: self.frames,
+            speed: self.speed,
+            looped: self.looped,
+            clock: clock,
+        }
+    }
+
+    /// Set the speed factor.
+    ///
+    /// A factor of `2.0` replays twice as fast as recorded, a factor of
+    /// `f64::INFINITY` sends all frames without delay.
+    ///
+    /// # Panics
+    ///
+    /// If `speed` is not positive.
+    pub fn speed(mut self, speed: f64) -> Replayer<C> {
+        assert!(speed > 0.0, "replay speed must be positive");
+        self.speed = speed;
+        self
+    }
+
+    /// Restart the log after the last frame instead of returning.
+    pub fn looped(mut self, looped: bool) -> Replayer<C> {
+        self.looped = looped;
+        self
+    }
+
+    /// Replay the log on `socket`, returning the number of frames sent.
+    ///
+    /// Blocks until the log was replayed; in loop mode this function only
+    /// returns on an error. Frames whose timestamps go backwards are sent
+    /// immediately.
+    pub fn play<T: CanTransport>(&self, socket: &T) -> io::Result<u64> {
+        let mut count = 0;
+
+        loop {
+            let t0 = match self.frames.first() {
+                Some(rec) => rec.t_us,
+                None => return Ok(count),
+            };
+            let start = self.clock.now();
+
+            for rec in &self.frames {
+                let due = start + scaled_offset(rec.t_us.saturating_sub(t0), self.speed);
+                let now = self.clock.now();
+                if due > now {
+                    self.clock.sleep(due - now);
+                }
+
+                socket.write_frame_insist(&rec.frame)?;
+                count += 1;
+            }
+
+            if !self.looped {
+                return Ok(count);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::f64;
+    use std::time::{Duration, UNIX_EPOCH};
+    use CanFrame;
+    use dump::TimestampedFrame;
+    use testing::MockBus;
+    use super::{scaled_offset, Replayer};
+
+    #[test]
+    fn test_scaled_offset() {
+        assert_eq!(scaled_offset(1_500_000, 1.0), Duration::from_millis(1500));
+        assert_eq!(scaled_offset(1_500_000, 2.0), Duration::from_millis(750));
+        assert_eq!(scaled_offset(1_500_000, 0.5), Duration::from_secs(3));
+        assert_eq!(scaled_offset(1_500_000, f64::INFINITY), Duration::from_secs(0));
+    }
+
+    #[test]
+    fn test_replay_timing() {
+        let bus = MockBus::new();
+        let (socket, mut peer) = (bus.endpoint(), bus.endpoint());
+        let log = [1_000_000, 1_250_000, 2_000_000].iter().map(|&t_us| {
+            TimestampedFrame {
+                t_us: t_us,
+                device: "can0".to_owned(),
+                frame: CanFrame::new(0x123, &[], false, false).unwrap(),
+            }
+        });
+
+        assert_eq!(Replayer::new(log).speed(2.0).clock(bus.clone()).play(&socket).unwrap(), 3);
+
+        for &ms in &[0, 125, 500] {
+            let (_, t) = peer.read_frame_with_timestamp().unwrap();
+            assert_eq!(t, UNIX_EPOCH + Duration::from_millis(ms));
+        }
+    }
+}
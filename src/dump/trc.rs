@@ -0,0 +1,463 @@
+//! PEAK trace (TRC) format
+//!
+//! TRC files are written by PCAN-View and other PEAK tools. Versions 1.x
+//! and 2.x are supported for reading; files are always written as version
+//! 2.0.
+//!
+//! Example (version 2.0):
+//!
+//! ```text
+//! ;$FILEVERSION=2.0
+//! ;$STARTTIME=43474.6036271643
+//! ;
+//! ;---+-- ------+------ +- --+----- +- +- +- -- -- -- -- -- -- --
+//!       1      1059.900 DT     0300 Rx 7  00 00 00 00 04 00 00
+//!       2      1283.200 RR 18FF0001 Tx 8
+//! ```
+//!
+//! Timestamps are given as offsets relative to the start time, which is
+//! stored in the header as days since December 30, 1899. TRC files do not
+//! record the interface name; the reader reports a configurable name
+//! instead (`can0` by default).
+
+use std::{fs, io, path};
+use CanFrame;
+use super::{build_frame, open_file, LogFile, LogWriter, ParseError, TimestampedFrame};
+
+/// Days between the TRC start time epoch (1899-12-30) and the UNIX epoch
+const EPOCH_OFFSET_DAYS: f64 = 25569.0;
+
+const US_PER_DAY: f64 = 86400.0 * 1_000_000.0;
+
+/// TRC file version
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Version {
+    /// Versions 1.0 and 1.1
+    V1,
+
+    /// Versions 2.0 and 2.1
+    V2,
+}
+
+/// Column layout of version 2.0 files without a `$COLUMNS` header
+const DEFAULT_COLUMNS: &'static [u8] = b"NOTIdlD";
+
+/// A TRC file reader.
+///
+/// Iterating yields all data and remote frames; status and error records
+/// are skipped.
+#[derive(Debug)]
+pub struct Reader<R> {
+    rdr: R,
+    line_buf: String,
+    version: Version,
+    columns: Vec<u8>,
+    start_us: u64,
+    device: String,
+}
+
+impl<R: io::Read> Reader<R> {
+    pub fn from_reader(rdr: R) -> Reader<io::BufReader<R>> {
+        Reader {
+            rdr: io::BufReader::new(rdr),
+            line_buf: String::new(),
+            version: Version::V1,
+            columns: DEFAULT_COLUMNS.to_vec(),
+            start_us: 0,
+            device: "can0".to_owned(),
+        }
+    }
+}
+
+impl Reader<LogFile> {
+    /// Open a log file, decompressing it on the fly if it is gzipped.
+    pub fn from_file<P: AsRef<path::Path>>(path: P) -> io::Result<Reader<io::BufReader<LogFile>>> {
+        Ok(Reader::from_reader(open_file(path)?))
+    }
+}
+
+impl<R> Reader<R> {
+    /// Set the interface name reported for all frames.
+    pub fn with_device(mut self, device: &str) -> Reader<R> {
+        self.device = device.to_owned();
+        self
+    }
+
+    /// File version, known after the header has been read
+    pub fn version(&self) -> Version {
+        self.version
+    }
+
+    /// Start time in microseconds since the UNIX epoch
+    pub fn start_time_us(&self) -> u64 {
+        self.start_us
+    }
+}
+
+fn start_us_from_days(days: f64) -> u64 {
+    let us = (days - EPOCH_OFFSET_DAYS) * US_PER_DAY;
+    if us > 0.0 { us.round() as u64 } else { 0 }
+}
+
+fn parse_offset_us(s: &str) -> Result<u64, ParseError> {
+    let ms: f64 = s.parse().map_err(|_| ParseError::InvalidTimestamp)?;
+    if ms < 0.0 {
+        return Err(ParseError::InvalidTimestamp);
+    }
+    Ok((ms * 1000.0).round() as u64)
+}
+
+fn parse_hex(s: &str) -> Result<u32, ParseError> {
+    u32::from_str_radix(s, 16).map_err(|_| ParseError::InvalidCanFrame)
+}
+
+fn parse_data(tokens: &[&str], dlc: usize) -> Result<Vec<u8>, ParseError> {
+    if tokens.len() < dlc {
+        return Err(ParseError::UnexpectedEndOfLine);
+    }
+
+    tokens[..dlc]
+        .iter()
+        .map(|t| u8::from_str_radix(t, 16).map_err(|_| ParseError::InvalidCanFrame))
+        .collect()
+}
+
+impl<R: io::BufRead> Reader<R> {
+    fn parse_header(&mut self) -> Result<(), ParseError> {
+        let line = self.line_buf.trim();
+
+        if line.starts_with(";$FILEVERSION=") {
+            let version = &line[";$FILEVERSION=".len()..];
+            self.version = if version.starts_with("1.") {
+                Version::V1
+            } else if version.starts_with("2.") {
+                Version::V2
+            } else {
+                return Err(ParseError::InvalidHeader);
+            };
+        } else if line.starts_with(";$STARTTIME=") {
+            let days: f64 = line[";$STARTTIME=".len()..]
+                .parse()
+                .map_err(|_| ParseError::InvalidHeader)?;
+            self.start_us = start_us_from_days(days);
+        } else if line.starts_with(";$COLUMNS=") {
+            self.columns = line[";$COLUMNS=".len()..]
+                .split(',')
+                .filter_map(|c| c.trim().bytes().next())
+                .collect();
+        }
+
+        Ok(())
+    }
+
+    // returns `None` for records that do not contain a frame
+    fn parse_v1(&self, tokens: &[&str]) -> Result<Option<TimestampedFrame>, ParseError> {
+        if tokens.len() < 4 {
+            return Err(ParseError::UnexpectedEndOfLine);
+        }
+
+        let offset = parse_offset_us(tokens[1])?;
+
+        // version 1.0 has no type column
+        let idx = match tokens[2] {
+            "Rx" | "Tx" => 3,
+            "Warng" | "Error" => return Ok(None),
+            _ => 2,
+        };
+
+        if tokens.len() < idx + 2 {
+            return Err(ParseError::UnexpectedEndOfLine);
+        }
+
+        let id = parse_hex(tokens[idx])?;
+        let extended = tokens[idx].len() > 4;
+        let dlc: usize = tokens[idx + 1].parse().map_err(|_| ParseError::InvalidCanFrame)?;
+
+        let rest = &tokens[idx + 2..];
+        let (rtr, data) = if rest.first() == Some(&"RTR") {
+            (true, vec![0; dlc])
+        } else {
+            (false, parse_data(rest, dlc)?)
+        };
+
+        self.frame(offset, id, extended, &data, rtr).map(Some)
+    }
+
+    fn parse_v2(&self, tokens: &[&str]) -> Result<Option<TimestampedFrame>, ParseError> {
+        let mut offset = None;
+        let mut rtr = false;
+        let mut id = None;
+        let mut len = None;
+        let mut data_idx = None;
+
+        for (&col, (i, token)) in self.columns.iter().zip(tokens.iter().enumerate()) {
+            match col {
+                b'O' => offset = Some(parse_offset_us(token)?),
+                b'T' => {
+                    match *token {
+                        "DT" => (),
+                        "RR" => rtr = true,
+                        "FD" | "FB" | "FE" | "BI" => return Err(ParseError::UnsupportedFdFrame),
+                        _ => return Ok(None),
+                    }
+                }
+                b'I' => id = Some((parse_hex(token)?, token.len() > 4)),
+                b'l' | b'L' => {
+                    len = Some(token.parse::<usize>().map_err(|_| ParseError::InvalidCanFrame)?)
+                }
+                b'D' => {
+                    data_idx = Some(i);
+                    break;
+                }
+                _ => (),
+            }
+        }
+
+        let offset = offset.ok_or(ParseError::UnexpectedEndOfLine)?;
+        let (id, extended) = id.ok_or(ParseError::UnexpectedEndOfLine)?;
+        let len = len.ok_or(ParseError::UnexpectedEndOfLine)?;
+
+        let data = if rtr {
+            vec![0; len]
+        } else {
+            let idx = data_idx.unwrap_or(tokens.len());
+            parse_data(&tokens[idx..], len)?
+        };
+
+        self.frame(offset, id, extended, &data, rtr).map(Some)
+    }
+
+    fn frame(&self,
+             offset_us: u64,
+             id: u32,
+             extended: bool,
+             data: &[u8],
+             rtr: bool)
+             -> Result<TimestampedFrame, ParseError> {
+        Ok(TimestampedFrame {
+            t_us: self.start_us + offset_us,
+            device: self.device.clone(),
+            frame: build_frame(id, extended, data, rtr)?,
+        })
+    }
+
+    /// Advance state, returning the next frame.
+    pub fn next_frame(&mut self) -> Result<Option<TimestampedFrame>, ParseError> {
+        loop {
+            self.line_buf.clear();
+            if self.rdr.read_line(&mut self.line_buf)? == 0 {
+                return Ok(None);
+            }
+
+            if self.line_buf.starts_with(';') {
+                self.parse_header()?;
+                continue;
+            }
+
+            let rec = {
+                let tokens: Vec<&str> = self.line_buf.split_whitespace().collect();
+                if tokens.is_empty() {
+                    continue;
+                }
+
+                match self.version {
+                    Version::V1 => self.parse_v1(&tokens)?,
+                    Version::V2 => self.parse_v2(&tokens)?,
+                }
+            };
+
+            if rec.is_some() {
+                return Ok(rec);
+            }
+        }
+    }
+}
+
+impl<R: io::BufRead> Iterator for Reader<R> {
+    type Item = Result<TimestampedFrame, ParseError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_frame() {
+            Ok(Some(rec)) => Some(Ok(rec)),
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// A TRC file writer.
+///
+/// Writes version 2.0 files. The start time in the header is derived from
+/// the first frame written. Error frames cannot be represented and are
+/// skipped.
+#[derive(Debug)]
+pub struct Writer<W: io::Write> {
+    wtr: W,
+    start_us: Option<u64>,
+    count: u64,
+}
+
+impl<W: io::Write> Writer<W> {
+    pub fn from_writer(wtr: W) -> Writer<W> {
+        Writer {
+            wtr: wtr,
+            start_us: None,
+            count: 0,
+        }
+    }
+
+    // writes the header for a file whose first frame was received at
+    // `t_us`, returning the start time as a reader will reconstruct it
+    fn write_header(&mut self, t_us: u64) -> io::Result<u64> {
+        // the start time is stored as fractional days, which cannot
+        // represent every microsecond. choose a start time up to a second
+        // before the first frame and use the value it will be parsed as, so
+        // that all offsets are exact.
+        let start = t_us.saturating_sub(1_000_000) / 1_000_000 * 1_000_000;
+        let days = format!("{:.10}", start as f64 / US_PER_DAY + EPOCH_OFFSET_DAYS);
+        let start_us = start_us_from_days(days.parse().expect("formatted float parses"));
+
+        write!(self.wtr,
+               ";$FILEVERSION=2.0\n\
+                ;$STARTTIME={}\n\
+                ;\n\
+                ;   Message   Time    Type ID     Rx/Tx\n\
+                ;   Number    Offset  |    [hex]  |  Data Length\n\
+                ;   |         [ms]    |    |      |  |  Data [hex] ...\n\
+                ;   |         |       |    |      |  |  |\n\
+                ;---+-- ------+------ +- --+----- +- +- +- -- -- -- -- -- -- --\n",
+               days)?;
+
+        Ok(start_us)
+    }
+
+    /// Write a single frame received at `t_us` (microseconds since the
+    /// UNIX epoch).
+    pub fn write_frame(&mut self, t_us: u64, frame: &CanFrame) -> io::Result<()> {
+        if frame.is_error() {
+            return Ok(());
+        }
+
+        let start_us = match self.start_us {
+            Some(start_us) => start_us,
+            None => {
+                let start_us = self.write_header(t_us)?;
+                self.start_us = Some(start_us);
+                start_us
+            }
+        };
+
+        self.count += 1;
+        let offset = t_us.saturating_sub(start_us);
+
+        let id = if frame.is_extended() {
+            format!("{:08X}", frame.id())
+        } else {
+            format!("{:04X}", frame.id())
+        };
+
+        write!(self.wtr,
+               "{:>7} {:>9}.{:03} {} {:>8} Rx {}",
+               self.count,
+               offset / 1000,
+               offset % 1000,
+               if frame.is_rtr() { "RR" } else { "DT" },
+               id,
+               frame.data().len())?;
+
+        if !frame.is_rtr() {
+            self.wtr.write_all(b" ")?;
+            for byte in frame.data() {
+                write!(self.wtr, " {:02X}", byte)?;
+            }
+        }
+
+        self.wtr.write_all(b"\n")
+    }
+
+    /// Write a `TimestampedFrame`.
+    pub fn write(&mut self, rec: &TimestampedFrame) -> io::Result<()> {
+        self.write_frame(rec.t_us, &rec.frame)
+    }
+
+    /// Flush the underlying writer.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.wtr.flush()
+    }
+
+    /// Unwrap the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.wtr
+    }
+}
+
+impl<W: io::Write> LogWriter for Writer<W> {
+    fn write(&mut self, rec: &TimestampedFrame) -> io::Result<()> {
+        Writer::write(self, rec)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Writer::flush(self)
+    }
+}
+
+impl Writer<fs::File> {
+    pub fn from_file<P: AsRef<path::Path>>(path: P) -> io::Result<Writer<io::BufWriter<fs::File>>> {
+        Ok(Writer::from_writer(io::BufWriter::new(fs::File::create(path)?)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Reader, Version, Writer};
+
+    #[test]
+    fn test_read_v1() {
+        let input: &[u8] = b";$FILEVERSION=1.1\n\
+                             ;$STARTTIME=25569.5\n\
+                             ;\n     1)         0.3  Rx         0300  2  11 22\n\
+                             \x20    2)         1.9  Rx     00000001  0  RTR\n\
+                             \x20    3)         2.5  Warng  FFFFFFFF  4  00 00 00 08  BUSHEAVY\n";
+
+        let frames: Vec<_> = Reader::from_reader(input).map(|r| r.unwrap()).collect();
+        assert_eq!(frames.len(), 2);
+
+        assert_eq!(frames[0].t_us, 12 * 3600 * 1_000_000 + 300);
+        assert_eq!(frames[0].frame.id(), 0x300);
+        assert_eq!(frames[0].frame.data(), &[0x11, 0x22]);
+
+        assert_eq!(frames[1].t_us, 12 * 3600 * 1_000_000 + 1900);
+        assert!(frames[1].frame.is_extended());
+        assert!(frames[1].frame.is_rtr());
+    }
+
+    #[test]
+    fn test_write_roundtrip() {
+        let input: &[u8] = b";$FILEVERSION=2.0\n\
+                             ;$STARTTIME=43474.6036271643\n\
+                             \x20     1      1059.900 DT     0300 Rx 7  00 00 00 00 04 00 00\n\
+                             \x20     2      1283.200 RR 18FF0001 Tx 8\n";
+
+        let mut reader = Reader::from_reader(input);
+        let frames: Vec<_> = reader.by_ref().map(|r| r.unwrap()).collect();
+        assert_eq!(reader.version(), Version::V2);
+        assert_eq!(frames.len(), 2);
+
+        let mut writer = Writer::from_writer(Vec::new());
+        for rec in &frames {
+            writer.write(rec).unwrap();
+        }
+
+        let output = writer.into_inner();
+        let again: Vec<_> = Reader::from_reader(&output[..]).map(|r| r.unwrap()).collect();
+
+        assert_eq!(again.len(), 2);
+        for (a, b) in frames.iter().zip(again.iter()) {
+            assert_eq!(a.t_us, b.t_us);
+            assert_eq!(a.frame.id(), b.frame.id());
+            assert_eq!(a.frame.is_extended(), b.frame.is_extended());
+            assert_eq!(a.frame.is_rtr(), b.frame.is_rtr());
+            assert_eq!(a.frame.data(), b.frame.data());
+        }
+    }
+}
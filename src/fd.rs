@@ -0,0 +1,360 @@
+//! CAN FD frames
+//!
+//! CAN FD frames carry up to 64 bytes and may send their data phase at a
+//! higher bit rate. A `CanSocket` only exchanges them once enabled with
+//! `set_fd_frames`; it then returns classic frames as `CanFdFrame`s too,
+//! told apart by `is_fd`.
+//!
+//! ```no_run
+//! use socketcan::{CanFdFrame, CanSocket};
+//!
+//! let socket = CanSocket::open_fd("can0").unwrap();
+//!
+//! // 20 bytes with bit rate switch
+//! let frame = CanFdFrame::new(0x123, &[0xaa; 20], true, false).unwrap();
+//! socket.write_fd_frame(&frame).unwrap();
+//!
+//! let frame = socket.read_fd_frame().unwrap();
+//! println!("{:X} {:?} brs={}", frame.id(), frame.data(), frame.is_brs());
+//! ```
+
+use std::{cmp, fmt, io, ptr, slice};
+use std::mem::size_of;
+use libc::{c_int, c_void, read, write};
+use {CanFrame, CanSocket, CanSocketOpenError, ConstructionError, FrameReadError, EFF_FLAG,
+     EFF_MASK, ERR_FLAG, RTR_FLAG, SFF_MASK};
+use constants::{dlc_to_len, len_to_dlc, CANFD_MAX_DLEN, CAN_MAX_DLEN, CAN_RAW_FD_FRAMES,
+                SOL_CAN_RAW};
+use util::{get_socket_option, set_socket_option};
+
+/// size of a `struct canfd_frame`
+pub const CANFD_MTU: usize = 72;
+
+/// bit rate switch, the data phase is sent at the data bit rate
+pub const CANFD_BRS: u8 = 0x01;
+
+/// error state indicator, the sender is error passive
+pub const CANFD_ESI: u8 = 0x02;
+
+/// marks a CAN FD frame, as opposed to a classic frame in the FD layout
+pub const CANFD_FDF: u8 = 0x04;
+
+/// A CAN FD frame, or a classic frame read from an FD enabled socket
+///
+/// Uses the memory layout of the kernel's `struct canfd_frame`.
+#[derive(Copy, Clone)]
+#[repr(C)]
+pub struct CanFdFrame {
+    /// 32 bit CAN_ID + EFF/RTR/ERR flags
+    _id: u32,
+
+    /// payload length in bytes
+    _len: u8,
+
+    /// CANFD_* flags
+    _flags: u8,
+
+    _res0: u8,
+    _res1: u8,
+
+    _data: [u8; CANFD_MAX_DLEN],
+}
+
+impl CanFdFrame {
+    /// Create an FD frame, with the bit rate switch and error state
+    /// indicator flags set as given.
+    ///
+    /// Lengths between the steps of the DLC table are padded with zeros,
+    /// e.g. 10 bytes are sent as 12.
+    pub fn new(id: u32,
+               data: &[u8],
+               brs: bool,
+               esi: bool)
+               -> Result<CanFdFrame, ConstructionError> {
+        let len = len_to_dlc(data.len())
+            .and_then(dlc_to_len)
+            .ok_or(ConstructionError::TooMuchData)?;
+        if id > EFF_MASK {
+            return Err(ConstructionError::IDTooLarge);
+        }
+
+        let mut frame = CanFdFrame::empty();
+        frame._id = if id > SFF_MASK { id | EFF_FLAG } else { id };
+        frame._len = len as u8;
+        frame._flags = CANFD_FDF | if brs { CANFD_BRS } else { 0 } |
+                       if esi { CANFD_ESI } else { 0 };
+        frame._data[..data.len()].copy_from_slice(data);
+        Ok(frame)
+    }
+
+    /// Read a classic or FD frame from the kernel's `struct can_frame` or
+    /// `struct canfd_frame`, as returned by `as_bytes`.
+    ///
+    /// Whether it is an FD frame is decided by the size of `bytes`. A
+    /// length above 8 in a classic frame, or above 64 in an FD frame, is
+    /// rejected.
+    pub fn from_bytes(bytes: &[u8]) -> Result<CanFdFrame, FrameReadError> {
+        let mut frame = CanFdFrame::empty();
+        if bytes.len() != CANFD_MTU && bytes.len() != size_of::<CanFrame>() {
+            return Err(FrameReadError::ShortRead(bytes.len()));
+        }
+        unsafe {
+            ptr::copy_nonoverlapping(bytes.as_ptr(),
+                                     &mut frame as *mut CanFdFrame as *mut u8,
+                                     bytes.len());
+        }
+
+        let max_len = if bytes.len() == CANFD_MTU {
+            frame._flags |= CANFD_FDF;
+            CANFD_MAX_DLEN
+        } else {
+            frame._flags = 0;
+            CAN_MAX_DLEN
+        };
+        if frame._len as usize > max_len {
+            return Err(FrameReadError::InvalidLength(frame._len));
+        }
+        Ok(frame)
+    }
+
+    /// The frame as the kernel's `struct canfd_frame`, in host byte order
+    ///
+    /// Classic frames are returned as the shorter `struct can_frame`, the
+    /// same bytes `write_fd_frame` hands to the kernel.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        let size = if self.is_fd() { size_of::<CanFdFrame>() } else { size_of::<CanFrame>() };
+        unsafe { slice::from_raw_parts(self as *const CanFdFrame as *const u8, size) }
+    }
+
+    fn empty() -> CanFdFrame {
+        CanFdFrame {
+            _id: 0,
+            _len: 0,
+            _flags: 0,
+            _res0: 0,
+            _res1: 0,
+            _data: [0; CANFD_MAX_DLEN],
+        }
+    }
+
+    /// Return the actual CAN ID (without EFF/RTR/ERR flags)
+    #[inline]
+    pub fn id(&self) -> u32 {
+        if self.is_extended() {
+            self._id & EFF_MASK
+        } else {
+            self._id & SFF_MASK
+        }
+    }
+
+    /// Check if frame uses 29 bit extended frame format
+    #[inline]
+    pub fn is_extended(&self) -> bool {
+        self._id & EFF_FLAG != 0
+    }
+
+    /// Check if this is a CAN FD frame rather than a classic one
+    #[inline]
+    pub fn is_fd(&self) -> bool {
+        self._flags & CANFD_FDF != 0
+    }
+
+    /// Check if the data phase is sent at the data bit rate
+    #[inline]
+    pub fn is_brs(&self) -> bool {
+        self._flags & CANFD_BRS != 0
+    }
+
+    /// Check if the sender was error passive
+    #[inline]
+    pub fn is_esi(&self) -> bool {
+        self._flags & CANFD_ESI != 0
+    }
+
+    /// Set or clear the bit rate switch flag.
+    pub fn set_brs(&mut self, brs: bool) {
+        self.set_flag(CANFD_BRS, brs);
+    }
+
+    /// Set or clear the error state indicator flag.
+    pub fn set_esi(&mut self, esi: bool) {
+        self.set_flag(CANFD_ESI, esi);
+    }
+
+    fn set_flag(&mut self, flag: u8, set: bool) {
+        if set {
+            self._flags |= flag;
+        } else {
+            self._flags &= !flag;
+        }
+    }
+
+    /// Raw CANFD_* flags
+    #[inline]
+    pub fn flags(&self) -> u8 {
+        self._flags
+    }
+
+    /// The payload, up to 64 bytes
+    #[inline]
+    pub fn data(&self) -> &[u8] {
+        &self._data[..cmp::min(self._len as usize, CANFD_MAX_DLEN)]
+    }
+
+    /// Number of data bytes
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.data().len()
+    }
+
+    /// Check if the frame carries no data
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Data length code as sent on the bus
+    #[inline]
+    pub fn dlc(&self) -> u8 {
+        len_to_dlc(self.len()).expect("length checked on construction")
+    }
+
+    /// The classic frame, if this is not an FD frame
+    pub fn to_classic(&self) -> Option<CanFrame> {
+        if self.is_fd() {
+            return None;
+        }
+        let data = &self._data[..cmp::min(self.len(), CAN_MAX_DLEN)];
+        let mut frame = CanFrame::new(0, data, false, false).expect("classic payload");
+        frame._id = self._id;
+        Some(frame)
+    }
+}
+
+impl CanSocket {
+    /// Open a named CAN device with FD frames enabled.
+    ///
+    /// Fails if the interface does not support CAN FD.
+    pub fn open_fd(ifname: &str) -> Result<CanSocket, CanSocketOpenError> {
+        let socket = CanSocket::open(ifname)?;
+        socket.set_fd_frames(true)?;
+        Ok(socket)
+    }
+
+    /// Enable or disable CAN FD frames.
+    ///
+    /// Fails if the interface does not support CAN FD. While enabled,
+    /// `read_frame` fails on FD frames with `FrameReadError::Oversized`,
+    /// use `read_fd_frame` instead.
+    pub fn set_fd_frames(&self, enabled: bool) -> io::Result<()> {
+        let fd_frames: c_int = if enabled { 1 } else { 0 };
+        set_socket_option(self.fd, SOL_CAN_RAW, CAN_RAW_FD_FRAMES, &fd_frames)
+    }
+
+    /// Check if FD frames are enabled.
+    pub fn fd_frames(&self) -> io::Result<bool> {
+        let fd_frames: c_int = get_socket_option(self.fd, SOL_CAN_RAW, CAN_RAW_FD_FRAMES)?;
+        Ok(fd_frames != 0)
+    }
+
+    /// Blocking read a single classic or FD frame.
+    ///
+    /// Requires FD frames to be enabled with `set_fd_frames` to receive FD
+    /// frames.
+    pub fn read_fd_frame(&self) -> io::Result<CanFdFrame> {
+        let mut buf = [0u8; CANFD_MTU];
+        let rv = unsafe { read(self.fd, buf.as_mut_ptr() as *mut c_void, buf.len()) };
+
+        if rv < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        CanFdFrame::from_bytes(&buf[..rv as usize]).map_err(io::Error::from)
+    }
+
+    /// Write a single classic or FD frame.
+    ///
+    /// FD frames require FD frames to be enabled with `set_fd_frames`.
+    pub fn write_fd_frame(&self, frame: &CanFdFrame) -> io::Result<()> {
+        let bytes = frame.as_bytes();
+        let rv = unsafe { write(self.fd, bytes.as_ptr() as *const c_void, bytes.len()) };
+
+        if rv as usize != bytes.len() {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+impl From<CanFrame> for CanFdFrame {
+    /// The classic frame in the FD layout, `is_fd` is not set.
+    fn from(frame: CanFrame) -> CanFdFrame {
+        let mut fd = CanFdFrame::empty();
+        fd._id = frame._id;
+        fd._len = frame.data().len() as u8;
+        fd._data[..frame.data().len()].copy_from_slice(frame.data());
+        fd
+    }
+}
+
+impl fmt::Debug for CanFdFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CanFdFrame")
+            .field("id", &self.id())
+            .field("extended", &self.is_extended())
+            .field("rtr", &(self._id & RTR_FLAG != 0))
+            .field("error", &(self._id & ERR_FLAG != 0))
+            .field("flags", &self._flags)
+            .field("data", &self.data())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {CanFrame, FrameReadError};
+    use super::CanFdFrame;
+
+    #[test]
+    fn test_fd_frame() {
+        let frame = CanFdFrame::new(0x18ff0001, &[1; 10], true, false).unwrap();
+        assert!(frame.is_fd() && frame.is_extended());
+        assert!(frame.is_brs() && !frame.is_esi());
+        assert_eq!((frame.len(), frame.dlc()), (12, 9));
+        assert_eq!(&frame.data()[8..], &[1, 1, 0, 0]);
+        assert!(frame.to_classic().is_none());
+
+        let mut esi = frame;
+        esi.set_esi(true);
+        esi.set_brs(false);
+        assert!(esi.is_esi() && !esi.is_brs() && esi.is_fd());
+
+        assert!(CanFdFrame::new(0x123, &[0; 65], false, false).is_err());
+
+        let classic = CanFdFrame::from(CanFrame::new(0x123, &[1, 2], true, false).unwrap());
+        assert!(!classic.is_fd());
+        let back = classic.to_classic().unwrap();
+        assert!(back.is_rtr());
+        assert_eq!((back.id(), back.data()), (0x123, &[1, 2][..]));
+    }
+
+    #[test]
+    fn test_fd_frame_bytes() {
+        let frame = CanFdFrame::new(0x123, &[7; 20], true, false).unwrap();
+        assert_eq!(frame.as_bytes().len(), 72);
+        assert_eq!(&frame.as_bytes()[..6], &[0x23, 0x01, 0, 0, 20, 0x05]);
+        let copy = CanFdFrame::from_bytes(frame.as_bytes()).unwrap();
+        assert_eq!(copy.as_bytes(), frame.as_bytes());
+
+        let classic = CanFrame::new(0x7ff, &[1, 2], false, false).unwrap();
+        let fd = CanFdFrame::from_bytes(classic.as_bytes()).unwrap();
+        assert!(!fd.is_fd());
+        assert_eq!(fd.as_bytes(), classic.as_bytes());
+        assert!(CanFdFrame::from_bytes(&[0; 20]).is_err());
+
+        let mut corrupt = classic.as_bytes().to_vec();
+        corrupt[4] = 12;
+        assert_eq!(CanFdFrame::from_bytes(&corrupt).unwrap_err(),
+                   FrameReadError::InvalidLength(12));
+    }
+}
@@ -0,0 +1,594 @@
+//! Filter expressions
+//!
+//! Parses filter strings as accepted by `candump`:
+//!
+//! ```text
+//! 123:7FF,400~7F0,#FFFFFFFF
+//! ```
+//!
+//! * `<id>:<mask>` accepts frames with `received_id & mask == id & mask`
+//! * `<id>~<mask>` accepts frames with `received_id & mask != id & mask`
+//! * `#<mask>` sets the error mask
+//! * `j` or `J` requires all filters to match instead of any
+//!
+//! IDs given with eight digits select the extended frame format.
+//!
+//! Alternatively, a filter can be given as an expression over the frame's
+//! `id`, its data length `len` and payload bytes `data[n]`:
+//!
+//! ```text
+//! id == 0x123 && data[0] > 5 || !(len < 2)
+//! ```
+//!
+//! Expressions consisting only of ID comparisons joined by `||` are
+//! compiled into kernel filters. Everything else is evaluated in userspace
+//! by `FilterSpec::matches`, with the kernel passing all frames.
+
+use std::{error, fmt, io};
+use std::str::FromStr;
+use {CanFilter, CanFrame, CanIdFlags, CanSocket, ERR_FLAG, EFF_MASK, ERR_MASK_ALL, ERR_MASK_NONE,
+     SFF_MASK};
+use constants::{CAN_ERR_ACK, CAN_ERR_BUSERROR, CAN_ERR_BUSOFF, CAN_ERR_CRTL, CAN_ERR_LOSTARB,
+                CAN_ERR_PROT, CAN_ERR_RESTARTED, CAN_ERR_TRX, CAN_ERR_TX_TIMEOUT};
+
+/// Filter flag inverting the match, shares its value with `ERR_FLAG`
+pub const INV_FILTER: u32 = 0x20000000;
+
+/// Error parsing a filter string
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FilterParseError {
+    /// A number could not be parsed
+    InvalidNumber(String),
+    /// Unexpected input at the given byte offset
+    UnexpectedToken(usize),
+    /// The expression ended prematurely
+    UnexpectedEnd,
+}
+
+impl fmt::Display for FilterParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FilterParseError::InvalidNumber(ref s) => write!(f, "invalid number {:?}", s),
+            FilterParseError::UnexpectedToken(pos) => write!(f, "unexpected token at {}", pos),
+            FilterParseError::UnexpectedEnd => write!(f, "unexpected end of filter"),
+        }
+    }
+}
+
+impl error::Error for FilterParseError {
+    fn description(&self) -> &str {
+        match *self {
+            FilterParseError::InvalidNumber(_) => "invalid number",
+            FilterParseError::UnexpectedToken(_) => "unexpected token",
+            FilterParseError::UnexpectedEnd => "unexpected end of filter",
+        }
+    }
+}
+
+/// Value a comparison operates on
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Operand {
+    /// The frame's ID
+    Id,
+    /// The frame's data length
+    Len,
+    /// A payload byte, zero if the frame is shorter
+    Data(usize),
+    /// A constant
+    Const(u32),
+}
+
+/// Comparison operator
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CmpOp {
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+}
+
+/// A filter expression
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Expr {
+    Cmp(Operand, CmpOp, Operand),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+impl Operand {
+    fn eval(&self, frame: &CanFrame) -> u32 {
+        match *self {
+            Operand::Id => frame.id(),
+            Operand::Len => frame.data().len() as u32,
+            Operand::Data(n) => frame.data().get(n).map_or(0, |&b| b as u32),
+            Operand::Const(c) => c,
+        }
+    }
+}
+
+impl Expr {
+    /// Evaluate the expression for `frame`.
+    pub fn matches(&self, frame: &CanFrame) -> bool {
+        match *self {
+            Expr::Cmp(ref a, op, ref b) => {
+                let (a, b) = (a.eval(frame), b.eval(frame));
+                match op {
+                    CmpOp::Eq => a == b,
+                    CmpOp::Ne => a != b,
+                    CmpOp::Lt => a < b,
+                    CmpOp::Le => a <= b,
+                    CmpOp::Gt => a > b,
+                    CmpOp::Ge => a >= b,
+                }
+            }
+            Expr::Not(ref e) => !e.matches(frame),
+            Expr::And(ref a, ref b) => a.matches(frame) && b.matches(frame),
+            Expr::Or(ref a, ref b) => a.matches(frame) || b.matches(frame),
+        }
+    }
+
+    /// Collect kernel filters equivalent to the expression into `filters`,
+    /// returning `false` if the expression cannot be expressed as such.
+    fn kernel_filters(&self, filters: &mut Vec<CanFilter>) -> bool {
+        match *self {
+            Expr::Cmp(Operand::Id, CmpOp::Eq, Operand::Const(id)) |
+            Expr::Cmp(Operand::Const(id), CmpOp::Eq, Operand::Id) => {
+                filters.push(exact_id_filter(id));
+                true
+            }
+            Expr::Or(ref a, ref b) => a.kernel_filters(filters) && b.kernel_filters(filters),
+            _ => false,
+        }
+    }
+}
+
+/// Filter accepting exactly the data and remote frames with `id`
+fn exact_id_filter(id: u32) -> CanFilter {
+    if id > SFF_MASK {
+        CanFilter {
+            _id: CanIdFlags::EFF.with_id(id),
+            _mask: CanIdFlags::EFF.with_id(EFF_MASK),
+        }
+    } else {
+        CanFilter {
+            _id: id,
+            _mask: CanIdFlags::EFF.with_id(SFF_MASK),
+        }
+    }
+}
+
+/// Check if `frame` passes `filter`, the way the kernel evaluates it.
+pub fn filter_matches(filter: &CanFilter, frame: &CanFrame) -> bool {
+    let inverted = filter._id & INV_FILTER != 0;
+    let id = filter._id & !INV_FILTER;
+    let mask = filter._mask & !ERR_FLAG;
+
+    (frame._id & mask == id & mask) != inverted
+}
+
+fn parse_hex(s: &str) -> Result<u32, FilterParseError> {
+    u32::from_str_radix(s, 16).map_err(|_| FilterParseError::InvalidNumber(s.to_owned()))
+}
+
+/// A complete filter configuration
+#[derive(Clone, Debug, PartialEq)]
+pub struct FilterSpec {
+    /// Filters to install in the kernel, all frames pass if empty
+    pub filters: Vec<CanFilter>,
+    /// Error mask to install, if given
+    pub error_mask: Option<u32>,
+    /// Whether all filters need to match instead of any
+    pub join: bool,
+    /// Expression to evaluate in userspace, if it could not be compiled
+    /// into kernel filters
+    pub predicate: Option<Expr>,
+}
+
+impl FilterSpec {
+    /// Parse a `candump` filter list.
+    pub fn parse_candump(s: &str) -> Result<FilterSpec, FilterParseError> {
+        let mut spec = FilterSpec {
+            filters: Vec::new(),
+            error_mask: None,
+            join: false,
+            predicate: None,
+        };
+
+        let mut pos = 0;
+        for item in s.split(',') {
+            let trimmed = item.trim();
+            if trimmed == "j" || trimmed == "J" {
+                spec.join = true;
+            } else if trimmed.starts_with('#') {
+                spec.error_mask = Some(parse_hex(&trimmed[1..])?);
+            } else if let Some(sep) = trimmed.find(|c| c == ':' || c == '~') {
+                let (id_str, mask_str) = (&trimmed[..sep], &trimmed[sep + 1..]);
+                let mut id = parse_hex(id_str)?;
+                let mask = parse_hex(mask_str)? & !ERR_FLAG;
+
+                if id_str.len() == 8 {
+                    id = CanIdFlags::EFF.with_id(id);
+                }
+                if trimmed[sep..].starts_with('~') {
+                    id |= INV_FILTER;
+                }
+
+                spec.filters.push(CanFilter {
+                    _id: id,
+                    _mask: mask,
+                });
+            } else {
+                return Err(FilterParseError::UnexpectedToken(pos));
+            }
+
+            pos += item.len() + 1;
+        }
+
+        Ok(spec)
+    }
+
+    /// Parse a filter expression.
+    pub fn parse_expr(s: &str) -> Result<FilterSpec, FilterParseError> {
+        let expr = Parser::new(s).parse()?;
+
+        let mut filters = Vec::new();
+        let compiled = expr.kernel_filters(&mut filters);
+
+        Ok(FilterSpec {
+            filters: if compiled { filters } else { Vec::new() },
+            error_mask: None,
+            join: false,
+            predicate: if compiled { None } else { Some(expr) },
+        })
+    }
+
+    /// Check if `frame` passes the filters and the predicate.
+    ///
+    /// Useful for frames that did not pass through a socket configured by
+    /// `apply`, e.g. those read from a log.
+    pub fn matches(&self, frame: &CanFrame) -> bool {
+        if frame.is_error() {
+            return match self.error_mask {
+                Some(mask) => frame.err() & mask != 0,
+                None => false,
+            };
+        }
+
+        let mut results = self.filters.iter().map(|f| filter_matches(f, frame));
+        let passes = if self.filters.is_empty() {
+            true
+        } else if self.join {
+            results.all(|m| m)
+        } else {
+            results.any(|m| m)
+        };
+
+        passes && self.predicate.as_ref().map_or(true, |p| p.matches(frame))
+    }
+
+    /// Install the kernel side of the filter on `socket`.
+    ///
+    /// Frames read from the socket still need to be checked against the
+    /// predicate, if there is one.
+    pub fn apply(&self, socket: &CanSocket) -> io::Result<()> {
+        if !self.filters.is_empty() {
+            socket.set_filters(&self.filters)?;
+        }
+        if let Some(mask) = self.error_mask {
+            socket.set_error_mask(mask)?;
+        }
+        if self.join {
+            socket.set_join_filters(true)?;
+        }
+        Ok(())
+    }
+
+    /// Switch `socket` to this filter, e.g. when a long-running monitor is
+    /// reconfigured.
+    ///
+    /// Unlike `apply`, a previous configuration is replaced completely:
+    /// without filters, all frames pass again, and join is disabled unless
+    /// set. See `CanSocket::update_filters` for which frames are received
+    /// during the update.
+    pub fn update(&self, socket: &CanSocket) -> io::Result<()> {
+        if self.filters.is_empty() {
+            socket.update_filters(&[CanFilter::new(0, 0).expect("valid filter")], false)?;
+        } else {
+            socket.update_filters(&self.filters, self.join)?;
+        }
+        if let Some(mask) = self.error_mask {
+            socket.set_error_mask(mask)?;
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for FilterSpec {
+    type Err = FilterParseError;
+
+    /// Parse a `candump` filter list, or an expression if the string is not
+    /// one.
+    fn from_str(s: &str) -> Result<FilterSpec, FilterParseError> {
+        FilterSpec::parse_candump(s).or_else(|_| FilterSpec::parse_expr(s))
+    }
+}
+
+/// Builder for the error mask of a socket, see `CanSocket::error_filter`
+///
+/// ```no_run
+/// use socketcan::CanSocket;
+///
+/// let socket = CanSocket::open("vcan0").unwrap();
+/// socket.error_filter().bus_off().no_ack().restarted().apply().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct ErrorFilter<'a> {
+    socket: &'a CanSocket,
+    mask: u32,
+}
+
+impl<'a> ErrorFilter<'a> {
+    /// Start with an empty mask for `socket`.
+    pub fn new(socket: &'a CanSocket) -> ErrorFilter<'a> {
+        ErrorFilter {
+            socket: socket,
+            mask: ERR_MASK_NONE,
+        }
+    }
+
+    fn with(self, class: u32) -> ErrorFilter<'a> {
+        ErrorFilter { mask: self.mask | class, ..self }
+    }
+
+    /// Report TX timeouts of the driver.
+    pub fn tx_timeout(self) -> ErrorFilter<'a> {
+        self.with(CAN_ERR_TX_TIMEOUT)
+    }
+
+    /// Report lost arbitration.
+    pub fn lost_arbitration(self) -> ErrorFilter<'a> {
+        self.with(CAN_ERR_LOSTARB)
+    }
+
+    /// Report controller problems, e.g. error passive or overflows.
+    pub fn controller(self) -> ErrorFilter<'a> {
+        self.with(CAN_ERR_CRTL)
+    }
+
+    /// Report protocol violations.
+    pub fn protocol(self) -> ErrorFilter<'a> {
+        self.with(CAN_ERR_PROT)
+    }
+
+    /// Report transceiver problems.
+    pub fn transceiver(self) -> ErrorFilter<'a> {
+        self.with(CAN_ERR_TRX)
+    }
+
+    /// Report transmissions no node acknowledged.
+    pub fn no_ack(self) -> ErrorFilter<'a> {
+        self.with(CAN_ERR_ACK)
+    }
+
+    /// Report the controller going bus off.
+    pub fn bus_off(self) -> ErrorFilter<'a> {
+        self.with(CAN_ERR_BUSOFF)
+    }
+
+    /// Report bus errors, which can be very frequent on a faulty bus.
+    pub fn bus_error(self) -> ErrorFilter<'a> {
+        self.with(CAN_ERR_BUSERROR)
+    }
+
+    /// Report controller restarts after bus off.
+    pub fn restarted(self) -> ErrorFilter<'a> {
+        self.with(CAN_ERR_RESTARTED)
+    }
+
+    /// Report all errors.
+    pub fn all(self) -> ErrorFilter<'a> {
+        self.with(ERR_MASK_ALL)
+    }
+
+    /// The error mask built so far
+    pub fn mask(&self) -> u32 {
+        self.mask
+    }
+
+    /// Install the error mask on the socket.
+    pub fn apply(self) -> io::Result<()> {
+        self.socket.set_error_mask(self.mask)
+    }
+}
+
+/// Recursive descent parser for filter expressions
+struct Parser<'a> {
+    input: &'a str,
+    pos: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(input: &'a str) -> Parser<'a> {
+        Parser {
+            input: input,
+            pos: 0,
+        }
+    }
+
+    fn rest(&self) -> &'a str {
+        &self.input[self.pos..]
+    }
+
+    fn skip_whitespace(&mut self) {
+        let rest = self.rest();
+        self.pos += rest.len() - rest.trim_left().len();
+    }
+
+    fn eat(&mut self, token: &str) -> bool {
+        self.skip_whitespace();
+        if self.rest().starts_with(token) {
+            self.pos += token.len();
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expect(&mut self, token: &str) -> Result<(), FilterParseError> {
+        if self.eat(token) {
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    fn unexpected(&self) -> FilterParseError {
+        if self.rest().is_empty() {
+            FilterParseError::UnexpectedEnd
+        } else {
+            FilterParseError::UnexpectedToken(self.pos)
+        }
+    }
+
+    fn parse(mut self) -> Result<Expr, FilterParseError> {
+        let expr = self.or()?;
+        self.skip_whitespace();
+        if !self.rest().is_empty() {
+            return Err(self.unexpected());
+        }
+        Ok(expr)
+    }
+
+    fn or(&mut self) -> Result<Expr, FilterParseError> {
+        let mut expr = self.and()?;
+        while self.eat("||") {
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, FilterParseError> {
+        let mut expr = self.unary()?;
+        while self.eat("&&") {
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, FilterParseError> {
+        if self.eat("!") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        if self.eat("(") {
+            let expr = self.or()?;
+            self.expect(")")?;
+            return Ok(expr);
+        }
+
+        let a = self.operand()?;
+        let op = self.cmp_op()?;
+        let b = self.operand()?;
+        Ok(Expr::Cmp(a, op, b))
+    }
+
+    fn cmp_op(&mut self) -> Result<CmpOp, FilterParseError> {
+        // two character operators first
+        let ops = [("==", CmpOp::Eq),
+                   ("!=", CmpOp::Ne),
+                   ("<=", CmpOp::Le),
+                   (">=", CmpOp::Ge),
+                   ("<", CmpOp::Lt),
+                   (">", CmpOp::Gt)];
+
+        for &(token, op) in &ops {
+            if self.eat(token) {
+                return Ok(op);
+            }
+        }
+        Err(self.unexpected())
+    }
+
+    fn number(&mut self) -> Result<u32, FilterParseError> {
+        self.skip_whitespace();
+        let rest = self.rest();
+        let len = rest.find(|c: char| !c.is_ascii_alphanumeric()).unwrap_or(rest.len());
+        if len == 0 {
+            return Err(self.unexpected());
+        }
+
+        let token = &rest[..len];
+        let parsed = if token.starts_with("0x") || token.starts_with("0X") {
+            u32::from_str_radix(&token[2..], 16)
+        } else {
+            token.parse()
+        };
+
+        self.pos += len;
+        parsed.map_err(|_| FilterParseError::InvalidNumber(token.to_owned()))
+    }
+
+    fn operand(&mut self) -> Result<Operand, FilterParseError> {
+        if self.eat("id") {
+            Ok(Operand::Id)
+        } else if self.eat("len") {
+            Ok(Operand::Len)
+        } else if self.eat("data") {
+            self.expect("[")?;
+            let idx = self.number()?;
+            self.expect("]")?;
+            Ok(Operand::Data(idx as usize))
+        } else {
+            self.number().map(Operand::Const)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {CanFilter, CanFrame, CanIdFlags, EFF_FLAG};
+    use super::{filter_matches, FilterSpec};
+
+    #[test]
+    fn test_candump_syntax() {
+        let spec: FilterSpec = "123:7FF,400~7F0,00012345:1FFFFFFF,#FFFFFFFF".parse().unwrap();
+        assert_eq!(spec.filters.len(), 3);
+        assert_eq!(spec.filters[2]._id, 0x12345 | EFF_FLAG);
+        assert_eq!(spec.error_mask, Some(0xffffffff));
+        assert_eq!(spec.predicate, None);
+
+        let frame = |id| CanFrame::new(id, &[], false, false).unwrap();
+        assert!(spec.matches(&frame(0x123)));
+        // everything outside 0x400..0x40f passes the inverted filter
+        assert!(spec.matches(&frame(0x200)));
+        assert!(!FilterSpec::parse_candump("400~7F0").unwrap().matches(&frame(0x405)));
+    }
+
+    #[test]
+    fn test_expressions() {
+        let spec: FilterSpec = "id == 0x123 || id == 0x12345".parse().unwrap();
+        assert_eq!(spec.filters.len(), 2);
+        assert_eq!(spec.predicate, None);
+
+        let spec: FilterSpec = "id == 0x123 && data[0] > 5 || !(len < 2)".parse().unwrap();
+        assert!(spec.filters.is_empty());
+
+        let frame = |id, data: &[u8]| CanFrame::new(id, data, false, false).unwrap();
+        assert!(spec.matches(&frame(0x123, &[6])));
+        assert!(!spec.matches(&frame(0x123, &[5])));
+        assert!(spec.matches(&frame(0x100, &[0, 0])));
+
+        assert!("id == ".parse::<FilterSpec>().is_err());
+        assert!("id = 5".parse::<FilterSpec>().is_err());
+    }
+
+    #[test]
+    fn test_flag_filter() {
+        let filter = CanFilter::with_flags(0, 0, CanIdFlags::EFF).unwrap();
+        assert!(filter_matches(&filter, &CanFrame::new(0x12345, &[], false, false).unwrap()));
+        assert!(!filter_matches(&filter, &CanFrame::new(0x123, &[], false, false).unwrap()));
+        assert!(!filter_matches(&filter, &CanFrame::new(0x12345, &[], true, false).unwrap()));
+    }
+}
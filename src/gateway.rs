@@ -0,0 +1,802 @@
+//! Userspace gateway
+//!
+//! A `Gateway` forwards frames between two transports, e.g. two
+//! `CanSocket`s on different interfaces or a socket and a `RemoteSocket`.
+//! Each direction has its own interceptor chain (see `middleware`) to
+//! filter or modify forwarded frames. Forwarded frames can be tapped for
+//! monitoring, tagged with the `BusId` of the side they came from.
+//!
+//! Buses with conflicting ID schemes can be bridged with a `RemapTable`,
+//! which translates IDs according to declarative rules:
+//!
+//! ```text
+//! # exact remap
+//! 123 -> 456
+//! # range remap, 0x100..0x10f to 0x200..0x20f
+//! 100-10f -> 200
+//! # standard to extended, IDs with 8 digits are extended
+//! 7df -> 18db33f1
+//! ```
+//!
+//! Where several bridges connect the same buses, frames could circle
+//! between them forever; `prevent_loops` drops frames that show up again
+//! on a side they were just received from or forwarded to.
+//!
+//! A `RateLimit` keeps a chatty device on one bus from flooding the other,
+//! with token buckets per ID or per range of IDs:
+//!
+//! ```no_run
+//! use socketcan::CanSocket;
+//! use socketcan::gateway::{Gateway, Id, RateLimit, RemapTable};
+//!
+//! let table: RemapTable = "123 -> 456\n100-10f -> 200".parse().unwrap();
+//!
+//! // at most 100 frames per second for each diagnostic ID
+//! let mut limit = RateLimit::new();
+//! limit.limit_each(Id::Standard(0x700), Id::Standard(0x7ff), 100, 10).unwrap();
+//!
+//! let gateway = Gateway::new(CanSocket::open("can0").unwrap(),
+//!                            CanSocket::open("can1").unwrap())
+//!     .a_to_b(table)
+//!     .a_to_b(limit);
+//! gateway.run().unwrap();
+//! ```
+
+use std::{cmp, error, fmt, io, thread};
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::{mpsc, Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{Duration, Instant};
+use middleware::{Action, Chain, Interceptor};
+use bus::TaggedFrame;
+use {run_read_loop, BusId, CanFrame, CanTransport, EFF_FLAG, EFF_MASK, ERR_FLAG, RTR_FLAG,
+     SFF_MASK};
+
+/// A CAN ID together with its format
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Id {
+    Standard(u32),
+    Extended(u32),
+}
+
+impl Id {
+    /// ID of `frame`
+    pub fn of(frame: &CanFrame) -> Id {
+        if frame.is_extended() {
+            Id::Extended(frame.id())
+        } else {
+            Id::Standard(frame.id())
+        }
+    }
+
+    /// The ID without format
+    pub fn value(&self) -> u32 {
+        match *self {
+            Id::Standard(id) | Id::Extended(id) => id,
+        }
+    }
+
+    fn is_extended(&self) -> bool {
+        match *self {
+            Id::Standard(_) => false,
+            Id::Extended(_) => true,
+        }
+    }
+
+    fn max(&self) -> u32 {
+        if self.is_extended() { EFF_MASK } else { SFF_MASK }
+    }
+
+    fn with_value(&self, value: u32) -> Id {
+        match *self {
+            Id::Standard(_) => Id::Standard(value),
+            Id::Extended(_) => Id::Extended(value),
+        }
+    }
+
+    fn check(&self) -> Result<Id, RemapError> {
+        if self.value() > self.max() {
+            return Err(RemapError::IdTooLarge(self.value()));
+        }
+        Ok(*self)
+    }
+}
+
+/// Error building a `RemapTable` or `RateLimit`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RemapError {
+    /// The ID does not fit its format
+    IdTooLarge(u32),
+
+    /// The bounds of a range differ in format or are in the wrong order
+    InvalidRange,
+
+    /// The translated range exceeds the largest ID of the target format
+    TargetOutOfRange,
+
+    /// A rate limit without burst, which would drop every frame
+    ZeroBurst,
+
+    /// A rule could not be parsed; carries the line number
+    Syntax(usize),
+}
+
+impl fmt::Display for RemapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RemapError::IdTooLarge(id) => write!(f, "ID {:X} too large", id),
+            RemapError::Syntax(line) => write!(f, "invalid rule in line {}", line),
+            _ => write!(f, "{}", error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for RemapError {
+    fn description(&self) -> &str {
+        match *self {
+            RemapError::IdTooLarge(_) => "id too large",
+            RemapError::InvalidRange => "invalid range",
+            RemapError::TargetOutOfRange => "target range out of range",
+            RemapError::ZeroBurst => "zero burst",
+            RemapError::Syntax(_) => "invalid rule",
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+struct Rule {
+    first: Id,
+    last: Id,
+    to: Id,
+}
+
+/// ID translation rules
+///
+/// Rules are checked in the order they were added; the first one matching
+/// a frame's ID (including its format) translates it. RTR and error flags
+/// as well as the payload are kept. Unmatched frames pass unchanged unless
+/// `drop_unmatched` is set.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RemapTable {
+    rules: Vec<Rule>,
+    drop_unmatched: bool,
+}
+
+impl RemapTable {
+    pub fn new() -> RemapTable {
+        RemapTable::default()
+    }
+
+    /// Translate `from` to `to`.
+    pub fn add_exact(&mut self, from: Id, to: Id) -> Result<(), RemapError> {
+        self.add_range(from, from, to)
+    }
+
+    /// Translate `first..=last` to a range of the same size starting at
+    /// `to`.
+    pub fn add_range(&mut self, first: Id, last: Id, to: Id) -> Result<(), RemapError> {
+        first.check()?;
+        last.check()?;
+        to.check()?;
+
+        if first.is_extended() != last.is_extended() || first.value() > last.value() {
+            return Err(RemapError::InvalidRange);
+        }
+        if (last.value() - first.value()) as u64 + to.value() as u64 > to.max() as u64 {
+            return Err(RemapError::TargetOutOfRange);
+        }
+
+        self.rules.push(Rule {
+                            first: first,
+                            last: last,
+                            to: to,
+                        });
+        Ok(())
+    }
+
+    /// Drop frames not matched by any rule instead of passing them.
+    pub fn drop_unmatched(mut self, drop: bool) -> RemapTable {
+        self.drop_unmatched = drop;
+        self
+    }
+
+    /// Translate the ID of `frame`.
+    ///
+    /// Returns `None` if the frame is unmatched and `drop_unmatched` is
+    /// set.
+    pub fn translate(&self, frame: &CanFrame) -> Option<CanFrame> {
+        let id = Id::of(frame);
+        let rule = self.rules.iter().find(|rule| {
+            rule.first.is_extended() == id.is_extended() && rule.first.value() <= id.value() &&
+            id.value() <= rule.last.value()
+        });
+
+        match rule {
+            Some(rule) => {
+                let to = rule.to.with_value(rule.to.value() + id.value() - rule.first.value());
+                let mut translated = *frame;
+                translated._id = frame._id & (RTR_FLAG | ERR_FLAG) | to.value() |
+                                 if to.is_extended() { EFF_FLAG } else { 0 };
+                Some(translated)
+            }
+            None if self.drop_unmatched => None,
+            None => Some(*frame),
+        }
+    }
+}
+
+/// Parse an ID, extended if it has 8 digits like in candump logs.
+fn parse_id(s: &str, line: usize) -> Result<Id, RemapError> {
+    let value = u32::from_str_radix(s, 16).map_err(|_| RemapError::Syntax(line))?;
+    let id = if s.len() == 8 { Id::Extended(value) } else { Id::Standard(value) };
+    id.check()
+}
+
+impl FromStr for RemapTable {
+    type Err = RemapError;
+
+    /// Parse one rule per line, `<id> -> <id>` or `<first>-<last> -> <id>`
+    /// with IDs in hex. Empty lines and lines starting with `#` are
+    /// ignored.
+    fn from_str(s: &str) -> Result<RemapTable, RemapError> {
+        let mut table = RemapTable::new();
+
+        for (n, line) in s.lines().enumerate().map(|(i, l)| (i + 1, l.trim())) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let mut sides = line.splitn(2, "->");
+            let from = sides.next().map(str::trim).unwrap_or("");
+            let to = sides.next().map(str::trim).ok_or(RemapError::Syntax(n))?;
+            let to = parse_id(to, n)?;
+
+            let mut bounds = from.splitn(2, '-');
+            let first = parse_id(bounds.next().unwrap_or("").trim(), n)?;
+            match bounds.next() {
+                Some(last) => table.add_range(first, parse_id(last.trim(), n)?, to)?,
+                None => table.add_exact(first, to)?,
+            }
+        }
+
+        Ok(table)
+    }
+}
+
+impl Interceptor for RemapTable {
+    fn intercept(&mut self, frame: &mut CanFrame) -> Action {
+        match self.translate(frame) {
+            Some(translated) => Action::Replace(translated),
+            None => Action::Drop,
+        }
+    }
+}
+
+/// Token bucket state
+#[derive(Copy, Clone, Debug)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+#[derive(Clone, Debug)]
+struct Limit {
+    first: Id,
+    last: Id,
+    rate: u32,
+    burst: u32,
+    per_id: bool,
+    buckets: HashMap<u32, Bucket>,
+}
+
+impl Limit {
+    /// Take a token for `id` at `now`, returning whether one was left.
+    fn take(&mut self, id: Id, now: Instant) -> bool {
+        let (rate, burst) = (self.rate as f64, self.burst as f64);
+        let key = if self.per_id { id.value() } else { self.first.value() };
+        let bucket = self.buckets.entry(key).or_insert(Bucket {
+                                                            tokens: burst,
+                                                            updated: now,
+                                                        });
+
+        if now > bucket.updated {
+            let elapsed = now.duration_since(bucket.updated);
+            let elapsed = elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 * 1e-9;
+            bucket.tokens = (bucket.tokens + elapsed * rate).min(burst);
+            bucket.updated = now;
+        }
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
+/// Rate limits for forwarded frames
+///
+/// Each limit is a token bucket refilled at `rate` frames per second and
+/// holding up to `burst` frames, which must be at least one. Limits are
+/// checked in the order they were added; the first one matching a frame's
+/// ID (including its format) decides whether it is forwarded. Frames
+/// exceeding their limit are dropped, unmatched frames always pass. To
+/// block IDs altogether, add an interceptor returning `Action::Drop`.
+#[derive(Clone, Debug, Default)]
+pub struct RateLimit {
+    limits: Vec<Limit>,
+}
+
+impl RateLimit {
+    pub fn new() -> RateLimit {
+        RateLimit::default()
+    }
+
+    /// Limit each ID in `first..=last` to `rate` frames per second, with
+    /// bursts of up to `burst` frames.
+    pub fn limit_each(&mut self,
+                      first: Id,
+                      last: Id,
+                      rate: u32,
+                      burst: u32)
+                      -> Result<(), RemapError> {
+        self.add(first, last, rate, burst, true)
+    }
+
+    /// Limit all IDs in `first..=last` together to `rate` frames per
+    /// second, with bursts of up to `burst` frames.
+    pub fn limit_range(&mut self,
+                       first: Id,
+                       last: Id,
+                       rate: u32,
+                       burst: u32)
+                       -> Result<(), RemapError> {
+        self.add(first, last, rate, burst, false)
+    }
+
+    fn add(&mut self,
+           first: Id,
+           last: Id,
+           rate: u32,
+           burst: u32,
+           per_id: bool)
+           -> Result<(), RemapError> {
+        first.check()?;
+        last.check()?;
+        if first.is_extended() != last.is_extended() || first.value() > last.value() {
+            return Err(RemapError::InvalidRange);
+        }
+        if burst == 0 {
+            return Err(RemapError::ZeroBurst);
+        }
+
+        self.limits.push(Limit {
+                             first: first,
+                             last: last,
+                             rate: rate,
+                             burst: burst,
+                             per_id: per_id,
+                             buckets: HashMap::new(),
+                         });
+        Ok(())
+    }
+
+    /// Check `frame` received at `now`, returning whether it may be
+    /// forwarded.
+    pub fn allow(&mut self, frame: &CanFrame, now: Instant) -> bool {
+        let id = Id::of(frame);
+        let limit = self.limits.iter_mut().find(|limit| {
+            limit.first.is_extended() == id.is_extended() && limit.first.value() <= id.value() &&
+            id.value() <= limit.last.value()
+        });
+
+        match limit {
+            Some(limit) => limit.take(id, now),
+            None => true,
+        }
+    }
+}
+
+impl Interceptor for RateLimit {
+    fn intercept(&mut self, frame: &mut CanFrame) -> Action {
+        if self.allow(frame, Instant::now()) { Action::Pass } else { Action::Drop }
+    }
+}
+
+/// Side of a gateway
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+enum Side {
+    A,
+    B,
+}
+
+/// Frames recently seen on either side, received from it or forwarded to
+/// it, to recognize them when they show up on that side again
+#[derive(Debug)]
+struct LoopGuard {
+    window: Duration,
+    seen: HashMap<(Side, (u32, u8, [u8; 8])), Instant>,
+    prune_at: usize,
+}
+
+impl LoopGuard {
+    fn new(window: Duration) -> LoopGuard {
+        LoopGuard {
+            window: window,
+            seen: HashMap::new(),
+            prune_at: 64,
+        }
+    }
+
+    /// Check if `frame`, received from `side` at `now`, was seen on that
+    /// side within the window.
+    fn is_echo(&self, side: Side, frame: &CanFrame, now: Instant) -> bool {
+        match self.seen.get(&(side, frame.content_key())) {
+            Some(&seen) => now.duration_since(seen) < self.window,
+            None => false,
+        }
+    }
+
+    /// Remember that `frame` is on `side` at `now`.
+    fn record(&mut self, side: Side, frame: &CanFrame, now: Instant) {
+        let window = self.window;
+        self.seen.insert((side, frame.content_key()), now);
+
+        // forget frames that can no longer be recognized as echoes
+        if self.seen.len() >= self.prune_at {
+            self.seen.retain(|_, seen| now.duration_since(*seen) < window);
+            self.prune_at = cmp::max(self.seen.len() * 2, 64);
+        }
+    }
+}
+
+/// Forwards frames between two transports in both directions
+#[derive(Debug)]
+pub struct Gateway<A, B> {
+    a: A,
+    b: B,
+    a_to_b: Chain,
+    b_to_a: Chain,
+    ids: (BusId, BusId),
+    tap: Option<mpsc::Sender<TaggedFrame>>,
+    loops: Option<LoopGuard>,
+}
+
+impl<A, B> Gateway<A, B>
+    where A: CanTransport + Send + Sync + 'static,
+          B: CanTransport + Send + Sync + 'static
+{
+    pub fn new(a: A, b: B) -> Gateway<A, B> {
+        Gateway {
+            a: a,
+            b: b,
+            a_to_b: Chain::new(),
+            b_to_a: Chain::new(),
+            ids: (BusId::from("a"), BusId::from("b")),
+            tap: None,
+            loops: None,
+        }
+    }
+
+    /// Identify the two sides in tapped frames, `"a"` and `"b"` by default.
+    pub fn ids<X: Into<BusId>, Y: Into<BusId>>(mut self, a: X, b: Y) -> Gateway<A, B> {
+        self.ids = (a.into(), b.into());
+        self
+    }
+
+    /// Send a copy of every forwarded frame to `tap`, as written to the
+    /// destination and tagged with the side it was received on.
+    pub fn tap(mut self, tap: mpsc::Sender<TaggedFrame>) -> Gateway<A, B> {
+        self.tap = Some(tap);
+        self
+    }
+
+    /// Drop frames received from a side within `window` of receiving the
+    /// same frame (ID and data) from it, or forwarding it to it.
+    ///
+    /// Breaks loops where another bridge between the same buses sends
+    /// forwarded frames back, to either side. Frames legitimately repeated
+    /// within the window are dropped as well, so keep the window shorter
+    /// than the period of cyclic frames.
+    pub fn prevent_loops(mut self, window: Duration) -> Gateway<A, B> {
+        self.loops = Some(LoopGuard::new(window));
+        self
+    }
+
+    /// Append an interceptor to the chain of frames forwarded from `a` to
+    /// `b`.
+    pub fn a_to_b<I: Interceptor + Send + 'static>(mut self, interceptor: I) -> Gateway<A, B> {
+        self.a_to_b.push(interceptor);
+        self
+    }
+
+    /// Append an interceptor to the chain of frames forwarded from `b` to
+    /// `a`.
+    pub fn b_to_a<I: Interceptor + Send + 'static>(mut self, interceptor: I) -> Gateway<A, B> {
+        self.b_to_a.push(interceptor);
+        self
+    }
+
+    /// Forward frames until reading or writing fails on either side.
+    ///
+    /// Each direction is forwarded on its own thread. Once one of them
+    /// fails, the other is stopped as well, and the first error is
+    /// returned. Read timeouts are not errors.
+    pub fn run(self) -> io::Result<()> {
+        self.run_until(Arc::new(AtomicBool::new(false)))
+    }
+
+    /// Forward frames until `shutdown` is set, or reading or writing fails
+    /// on either side.
+    ///
+    /// A failing direction sets `shutdown` to stop the other one. Both
+    /// directions have stopped when this returns, which takes up to
+    /// `SHUTDOWN_POLL_INTERVAL_MS` after `shutdown` is set. The read
+    /// timeouts of both transports are changed.
+    pub fn run_until(self, shutdown: Arc<AtomicBool>) -> io::Result<()> {
+        let a = Arc::new(self.a);
+        let b = Arc::new(self.b);
+        let (tx, rx) = mpsc::channel();
+        let (a_id, b_id) = self.ids;
+        let loops = self.loops.map(|guard| Arc::new(Mutex::new(guard)));
+
+        let b_to_a = Direction {
+            chain: self.b_to_a,
+            src_side: Side::B,
+            src_id: b_id,
+            tap: self.tap.clone(),
+            loops: loops.clone(),
+        };
+        let a_to_b = Direction {
+            chain: self.a_to_b,
+            src_side: Side::A,
+            src_id: a_id,
+            tap: self.tap,
+            loops: loops,
+        };
+
+        let threads = vec![spawn_forward(b.clone(), a.clone(), b_to_a, &shutdown, tx.clone()),
+                           spawn_forward(a, b, a_to_b, &shutdown, tx)];
+
+        let result = rx.recv().expect("forwarding threads report their result");
+        shutdown.store(true, Ordering::SeqCst);
+        for thread in threads {
+            thread.join().expect("forwarding thread panicked");
+        }
+        result
+    }
+}
+
+/// State of one direction of a gateway
+struct Direction {
+    chain: Chain,
+    src_side: Side,
+    src_id: BusId,
+    tap: Option<mpsc::Sender<TaggedFrame>>,
+    loops: Option<Arc<Mutex<LoopGuard>>>,
+}
+
+/// Forward from `src` to `dst` on a new thread, reporting the result to
+/// `done`.
+fn spawn_forward<S, D>(src: Arc<S>,
+                       dst: Arc<D>,
+                       direction: Direction,
+                       shutdown: &Arc<AtomicBool>,
+                       done: mpsc::Sender<io::Result<()>>)
+                       -> thread::JoinHandle<()>
+    where S: CanTransport + Send + Sync + 'static,
+          D: CanTransport + Send + Sync + 'static
+{
+    let shutdown = shutdown.clone();
+    thread::spawn(move || {
+        let _ = done.send(forward(&*src, &*dst, direction, &shutdown));
+    })
+}
+
+fn forward<S, D>(src: &S,
+                 dst: &D,
+                 mut direction: Direction,
+                 shutdown: &AtomicBool)
+                 -> io::Result<()>
+    where S: CanTransport,
+          D: CanTransport
+{
+    let src_side = direction.src_side;
+    let dst_side = if src_side == Side::A { Side::B } else { Side::A };
+
+    let handle = |frame: CanFrame| {
+        // echoes are compared as received, before the chain modifies them
+        if let Some(ref loops) = direction.loops {
+            let mut loops = loops.lock().expect("loop guard lock poisoned");
+            let now = Instant::now();
+            if loops.is_echo(src_side, &frame, now) {
+                return Ok(());
+            }
+            loops.record(src_side, &frame, now);
+        }
+
+        if let Some(frame) = direction.chain.apply(frame) {
+            if let Some(ref loops) = direction.loops {
+                // before writing, so an immediate echo is recognized
+                let mut loops = loops.lock().expect("loop guard lock poisoned");
+                loops.record(dst_side, &frame, Instant::now());
+            }
+
+            dst.write_frame_insist(&frame)?;
+            if let Some(ref tap) = direction.tap {
+                // a dropped receiver only ends the monitoring
+                let _ = tap.send(TaggedFrame {
+                    bus: direction.src_id.clone(),
+                    frame: frame,
+                });
+            }
+        }
+        Ok(())
+    };
+
+    run_read_loop(src, handle, shutdown).map(|_| ())
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io, thread};
+    use std::sync::{mpsc, Arc};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::{Duration, Instant};
+    use libc::ENETDOWN;
+    use {BusId, CanFrame, CanTransport};
+    use testing::MockBus;
+    use super::{Gateway, Id, RateLimit, RemapError, RemapTable};
+
+    fn spawn<A, B>(gateway: Gateway<A, B>,
+                   shutdown: &Arc<AtomicBool>)
+                   -> thread::JoinHandle<io::Result<()>>
+        where A: CanTransport + Send + Sync + 'static,
+              B: CanTransport + Send + Sync + 'static
+    {
+        let shutdown = shutdown.clone();
+        thread::spawn(move || gateway.run_until(shutdown))
+    }
+
+    #[test]
+    fn test_remap_table() {
+        let table: RemapTable = "# comment\n123 -> 456\n100-10f -> 18ff0000\n\n7df -> 7e0"
+            .parse()
+            .unwrap();
+
+        let frame = |id| CanFrame::new(id, &[1, 2], false, false).unwrap();
+        assert_eq!(table.translate(&frame(0x123)).unwrap().id(), 0x456);
+
+        let ext = table.translate(&frame(0x105)).unwrap();
+        assert!(ext.is_extended());
+        assert_eq!(ext.id(), 0x18ff0005);
+        assert_eq!(ext.data(), &[1, 2]);
+
+        let rtr = CanFrame::new(0x7df, &[], true, false).unwrap();
+        let translated = table.translate(&rtr).unwrap();
+        assert!(translated.is_rtr());
+        assert_eq!(translated.id(), 0x7e0);
+
+        assert_eq!(table.translate(&frame(0x200)).unwrap().id(), 0x200);
+        assert!(table.clone().drop_unmatched(true).translate(&frame(0x200)).is_none());
+
+        let mut table = RemapTable::new();
+        assert_eq!(table.add_exact(Id::Standard(0x800), Id::Standard(1)),
+                   Err(RemapError::IdTooLarge(0x800)));
+        assert_eq!(table.add_range(Id::Standard(0x10), Id::Standard(0x1f), Id::Standard(0x7f8)),
+                   Err(RemapError::TargetOutOfRange));
+        assert_eq!("123 => 456".parse::<RemapTable>(), Err(RemapError::Syntax(1)));
+    }
+
+    #[test]
+    fn test_prevent_loops() {
+        let (bus_a, bus_b) = (MockBus::new(), MockBus::new());
+        let (a, b) = (bus_a.endpoint(), bus_b.endpoint());
+        a.set_read_timeout(Duration::from_secs(5)).unwrap();
+        b.set_read_timeout(Duration::from_secs(5)).unwrap();
+
+        let gateway = Gateway::new(bus_a.endpoint(), bus_b.endpoint())
+            .prevent_loops(Duration::from_secs(60));
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let running = spawn(gateway, &shutdown);
+
+        let frame = |id| CanFrame::new(id, &[1], false, false).unwrap();
+        a.write_frame(&frame(0x123)).unwrap();
+        assert_eq!(b.read_frame().unwrap().id(), 0x123);
+
+        // another bridge between the buses forwards the frame once more, in
+        // both directions, followed by a marker; each direction is forwarded
+        // in order, so the first frame to arrive shows if the echo was dropped
+        a.write_frame(&frame(0x123)).unwrap();
+        a.write_frame(&frame(0x1)).unwrap();
+        b.write_frame(&frame(0x123)).unwrap();
+        b.write_frame(&frame(0x2)).unwrap();
+        assert_eq!(b.read_frame().unwrap().id(), 0x1);
+        assert_eq!(a.read_frame().unwrap().id(), 0x2);
+
+        shutdown.store(true, Ordering::SeqCst);
+        running.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_rate_limit() {
+        let mut limit = RateLimit::new();
+        limit.limit_each(Id::Standard(0x100), Id::Standard(0x1ff), 10, 2).unwrap();
+        limit.limit_range(Id::Standard(0x200), Id::Standard(0x2ff), 1, 1).unwrap();
+        assert_eq!(limit.limit_each(Id::Standard(0x10), Id::Extended(0x20), 1, 1),
+                   Err(RemapError::InvalidRange));
+        assert_eq!(limit.limit_range(Id::Standard(0x300), Id::Standard(0x3ff), 10, 0),
+                   Err(RemapError::ZeroBurst));
+
+        let frame = |id| CanFrame::new(id, &[], false, false).unwrap();
+        let t0 = Instant::now();
+        let allowed = |limit: &mut RateLimit, id, t| limit.allow(&frame(id), t);
+
+        assert!(allowed(&mut limit, 0x100, t0) && allowed(&mut limit, 0x100, t0));
+        assert!(!allowed(&mut limit, 0x100, t0));
+        assert!(allowed(&mut limit, 0x101, t0));
+        assert!(allowed(&mut limit, 0x100, t0 + Duration::from_millis(100)));
+        assert!(!allowed(&mut limit, 0x100, t0 + Duration::from_millis(150)));
+
+        assert!(allowed(&mut limit, 0x200, t0));
+        assert!(!allowed(&mut limit, 0x2ff, t0));
+        assert!(allowed(&mut limit, 0x2ff, t0 + Duration::from_secs(1)));
+
+        // unmatched IDs pass, standard and extended
+        assert!((0..10).all(|_| allowed(&mut limit, 0x300, t0)));
+        assert!(allowed(&mut limit, 0x18000100, t0));
+    }
+
+    #[test]
+    fn test_gateway() {
+        let (bus_a, bus_b) = (MockBus::new(), MockBus::new());
+        let (a, b) = (bus_a.endpoint(), bus_b.endpoint());
+
+        let (tap, tapped) = mpsc::channel();
+
+        let gateway = Gateway::new(bus_a.endpoint(), bus_b.endpoint())
+            .a_to_b("123 -> 321".parse::<RemapTable>().unwrap())
+            .ids("can0", "can1")
+            .tap(tap);
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let running = spawn(gateway, &shutdown);
+
+        a.write_frame(&CanFrame::new(0x123, &[1], false, false).unwrap()).unwrap();
+        assert_eq!(b.read_frame().unwrap().id(), 0x321);
+
+        b.write_frame(&CanFrame::new(0x123, &[2], false, false).unwrap()).unwrap();
+        assert_eq!(a.read_frame().unwrap().id(), 0x123);
+
+        // the two directions are tapped from different threads
+        let mut tagged: Vec<_> = (0..2)
+            .map(|_| tapped.recv().unwrap())
+            .map(|t| (t.bus, t.frame.id()))
+            .collect();
+        tagged.sort();
+        assert_eq!(tagged, vec![(BusId::from("can0"), 0x321), (BusId::from("can1"), 0x123)]);
+
+        shutdown.store(true, Ordering::SeqCst);
+        running.join().unwrap().unwrap();
+    }
+
+    #[test]
+    fn test_run_fails() {
+        let (bus_a, bus_b) = (MockBus::new(), MockBus::new());
+        let (a, b) = (bus_a.endpoint(), bus_b.endpoint());
+        let side_a = bus_a.endpoint();
+        side_a.inject_read_error(io::Error::from_raw_os_error(ENETDOWN));
+
+        // the failing direction stops the other one
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let gateway = Gateway::new(side_a, bus_b.endpoint());
+        let err = gateway.run_until(shutdown.clone()).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(ENETDOWN));
+        assert!(shutdown.load(Ordering::SeqCst));
+
+        b.write_frame(&CanFrame::new(0x123, &[], false, false).unwrap()).unwrap();
+        a.set_read_timeout(Duration::from_millis(1)).unwrap();
+        assert!(a.read_frame().is_err());
+    }
+}
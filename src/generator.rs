@@ -0,0 +1,349 @@
+//! Traffic generator
+//!
+//! Produces frames in the manner of `cangen` from can-utils, for load
+//! testing gateways, filters and receivers:
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use socketcan::CanSocket;
+//! use socketcan::generator::{DataMode, Gap, Generator, IdMode};
+//!
+//! let socket = CanSocket::open("vcan0").unwrap();
+//! Generator::new()
+//!     .ids(IdMode::Incrementing { min: 0x100, max: 0x1ff })
+//!     .data(DataMode::Random)
+//!     .gap(Gap::Exponential(Duration::from_millis(5)))
+//!     .burst(10)
+//!     .run(&socket, Some(1000))
+//!     .unwrap();
+//! ```
+//!
+//! Random values come from a small built-in pseudo random generator; pass a
+//! seed to reproduce a sequence exactly.
+
+use std::{io, thread};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use {CanFrame, CanTransport, EFF_FLAG, EFF_MASK, SFF_MASK};
+use util::{duration_from_us, duration_to_us};
+
+/// How frame IDs are chosen
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IdMode {
+    /// Always the same ID
+    Fixed(u32),
+    /// Uniformly distributed in `min..=max`
+    Random { min: u32, max: u32 },
+    /// Counting up from `min`, wrapping after `max`
+    Incrementing { min: u32, max: u32 },
+}
+
+/// How the payload is filled
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DataMode {
+    /// Always the same payload, which also fixes the data length
+    Fixed(Vec<u8>),
+    /// Random bytes
+    Random,
+    /// A little endian counter incremented with every frame
+    Incrementing,
+}
+
+/// How the data length is chosen, unless fixed by `DataMode::Fixed`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LengthMode {
+    /// Always the same length
+    Fixed(usize),
+    /// Uniformly distributed in `0..=8`
+    Random,
+    /// Counting from 0 to 8, then starting over
+    Incrementing,
+}
+
+/// Distribution of the gaps between frames (or bursts)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Gap {
+    /// A constant gap
+    Fixed(Duration),
+    /// Uniformly distributed in `min..=max`
+    Uniform { min: Duration, max: Duration },
+    /// Exponentially distributed with the given mean, i.e. frames arriving
+    /// as a Poisson process
+    Exponential(Duration),
+}
+
+/// xorshift64* pseudo random number generator
+#[derive(Clone, Debug)]
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Rng {
+        // the state must never be zero
+        Rng(if seed == 0 { 0x9e37_79b9_7f4a_7c15 } else { seed })
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.0 ^= self.0 >> 12;
+        self.0 ^= self.0 << 25;
+        self.0 ^= self.0 >> 27;
+        self.0.wrapping_mul(0x2545_f491_4f6c_dd1d)
+    }
+
+    /// Uniformly distributed value in `min..=max`
+    fn range(&mut self, min: u64, max: u64) -> u64 {
+        match (max - min).checked_add(1) {
+            Some(n) => min + self.next_u64() % n,
+            None => self.next_u64(),
+        }
+    }
+
+    /// Uniformly distributed value in `(0, 1]`
+    fn unit(&mut self) -> f64 {
+        ((self.next_u64() >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Frame generator
+///
+/// Yields `(gap, frame)` pairs as an iterator, where `gap` is the time to
+/// wait before sending `frame`. The iterator never ends.
+#[derive(Clone, Debug)]
+pub struct Generator {
+    ids: IdMode,
+    data: DataMode,
+    length: LengthMode,
+    gap: Gap,
+    burst: usize,
+    extended: bool,
+    rng: Rng,
+    count: u64,
+}
+
+impl Generator {
+    /// Create a generator with `cangen`'s defaults: random standard IDs,
+    /// random data of random length, 200 ms between frames.
+    pub fn new() -> Generator {
+        let seed = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs() ^ (d.subsec_nanos() as u64) << 32)
+            .unwrap_or(0);
+
+        Generator {
+            ids: IdMode::Random { min: 0, max: SFF_MASK },
+            data: DataMode::Random,
+            length: LengthMode::Random,
+            gap: Gap::Fixed(Duration::from_millis(200)),
+            burst: 1,
+            extended: false,
+            rng: Rng::new(seed),
+            count: 0,
+        }
+    }
+
+    /// Set the ID mode.
+    ///
+    /// # Panics
+    ///
+    /// If an ID is larger than `EFF_MASK` or a range is empty.
+    pub fn ids(mut self, ids: IdMode) -> Generator {
+        let (min, max) = match ids {
+            IdMode::Fixed(id) => (id, id),
+            IdMode::Random { min, max } |
+            IdMode::Incrementing { min, max } => (min, max),
+        };
+        assert!(min <= max && max <= EFF_MASK, "invalid ID range");
+
+        self.ids = ids;
+        self
+    }
+
+    /// Set the payload mode.
+    ///
+    /// # Panics
+    ///
+    /// If a fixed payload is longer than 8 bytes.
+    pub fn data(mut self, data: DataMode) -> Generator {
+        if let DataMode::Fixed(ref bytes) = data {
+            assert!(bytes.len() <= 8, "payload too long");
+        }
+
+        self.data = data;
+        self
+    }
+
+    /// Set the data length mode.
+    ///
+    /// # Panics
+    ///
+    /// If a fixed length is larger than 8.
+    pub fn length(mut self, length: LengthMode) -> Generator {
+        if let LengthMode::Fixed(len) = length {
+            assert!(len <= 8, "data length too large");
+        }
+
+        self.length = length;
+        self
+    }
+
+    /// Set the gap distribution.
+    pub fn gap(mut self, gap: Gap) -> Generator {
+        self.gap = gap;
+        self
+    }
+
+    /// Send frames in bursts of `size` without gaps in between.
+    ///
+    /// The gap distribution then applies to the time between bursts.
+    pub fn burst(mut self, size: usize) -> Generator {
+        self.burst = size.max(1);
+        self
+    }
+
+    /// Always use the extended frame format, even for IDs that fit into a
+    /// standard frame.
+    pub fn extended(mut self, extended: bool) -> Generator {
+        self.extended = extended;
+        self
+    }
+
+    /// Seed the random number generator.
+    pub fn seed(mut self, seed: u64) -> Generator {
+        self.rng = Rng::new(seed);
+        self
+    }
+
+    fn next_id(&mut self) -> u32 {
+        match self.ids {
+            IdMode::Fixed(id) => id,
+            IdMode::Random { min, max } => self.rng.range(min as u64, max as u64) as u32,
+            IdMode::Incrementing { min, max } => {
+                min + (self.count % (max - min + 1) as u64) as u32
+            }
+        }
+    }
+
+    fn next_data(&mut self) -> Vec<u8> {
+        let len = match self.length {
+            LengthMode::Fixed(len) => len,
+            LengthMode::Random => self.rng.range(0, 8) as usize,
+            LengthMode::Incrementing => (self.count % 9) as usize,
+        };
+
+        match self.data {
+            DataMode::Fixed(ref bytes) => bytes.clone(),
+            DataMode::Random => (0..len).map(|_| self.rng.next_u64() as u8).collect(),
+            DataMode::Incrementing => (0..len).map(|i| (self.count >> (8 * i)) as u8).collect(),
+        }
+    }
+
+    fn next_gap(&mut self) -> Duration {
+        // no gap within a burst
+        if self.count % self.burst as u64 != 0 {
+            return Duration::from_millis(0);
+        }
+
+        match self.gap {
+            Gap::Fixed(gap) => gap,
+            Gap::Uniform { min, max } => {
+                let (min, max) = (duration_to_us(min), duration_to_us(max));
+                duration_from_us(if min < max { self.rng.range(min, max) } else { min })
+            }
+            Gap::Exponential(mean) => {
+                duration_from_us((-self.rng.unit().ln() * duration_to_us(mean) as f64) as u64)
+            }
+        }
+    }
+
+    /// Send frames on `socket`, waiting for the gaps in between.
+    ///
+    /// Sends `count` frames, or runs until an error occurs if `count` is
+    /// `None`. Returns the number of frames sent.
+    pub fn run<T: CanTransport>(&mut self, socket: &T, count: Option<u64>) -> io::Result<u64> {
+        let mut sent = 0;
+        while count.map_or(true, |count| sent < count) {
+            let (gap, frame) = self.next().expect("generator never ends");
+            if gap > Duration::from_millis(0) {
+                thread::sleep(gap);
+            }
+
+            socket.write_frame_insist(&frame)?;
+            sent += 1;
+        }
+
+        Ok(sent)
+    }
+}
+
+impl Default for Generator {
+    fn default() -> Generator {
+        Generator::new()
+    }
+}
+
+impl Iterator for Generator {
+    type Item = (Duration, CanFrame);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let gap = self.next_gap();
+        let id = self.next_id();
+        let data = self.next_data();
+        self.count += 1;
+
+        let mut frame = CanFrame::new(id, &data, false, false)
+            .expect("ID and data length validated by the builder");
+        if self.extended {
+            frame._id |= EFF_FLAG;
+        }
+
+        Some((gap, frame))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use super::{DataMode, Gap, Generator, IdMode, LengthMode};
+
+    #[test]
+    fn test_incrementing() {
+        let frames: Vec<_> = Generator::new()
+            .ids(IdMode::Incrementing { min: 0x7fe, max: 0x800 })
+            .data(DataMode::Incrementing)
+            .length(LengthMode::Fixed(2))
+            .gap(Gap::Fixed(Duration::from_millis(10)))
+            .burst(2)
+            .take(4)
+            .collect();
+
+        let ids: Vec<_> = frames.iter().map(|&(_, ref f)| f.id()).collect();
+        assert_eq!(ids, vec![0x7fe, 0x7ff, 0x800, 0x7fe]);
+        assert!(frames[2].1.is_extended());
+
+        assert_eq!(frames[3].1.data(), &[3, 0]);
+
+        let gaps: Vec<_> = frames.iter().map(|&(gap, _)| gap.subsec_nanos() / 1_000_000).collect();
+        assert_eq!(gaps, vec![10, 0, 10, 0]);
+    }
+
+    #[test]
+    fn test_seeded_random() {
+        let gen = Generator::new()
+            .seed(42)
+            .gap(Gap::Uniform {
+                min: Duration::from_millis(1),
+                max: Duration::from_millis(3),
+            });
+
+        let a: Vec<_> = gen.clone().take(100).collect();
+        let b: Vec<_> = gen.take(100).collect();
+        for (&(gap_a, ref frame_a), &(gap_b, ref frame_b)) in a.iter().zip(b.iter()) {
+            assert_eq!(gap_a, gap_b);
+            assert_eq!(frame_a.id(), frame_b.id());
+            assert_eq!(frame_a.data(), frame_b.data());
+        }
+
+        for &(gap, ref frame) in &a {
+            assert!(frame.id() <= 0x7ff && frame.data().len() <= 8);
+            assert!(gap >= Duration::from_millis(1) && gap <= Duration::from_millis(3));
+        }
+    }
+}
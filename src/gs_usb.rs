@@ -0,0 +1,376 @@
+//! gs_usb (candleLight) userspace backend
+//!
+//! Talks to gs_usb compatible adapters such as the candleLight, CANable
+//! (candleLight firmware) or CANtact through libusb, for systems where the
+//! `gs_usb` kernel driver is not available, e.g. inside containers. Requires
+//! the `gs_usb` feature.
+//!
+//! The kernel driver has to be detached from the adapter; `GsUsb::open`
+//! does so automatically where libusb supports it.
+//!
+//! ```no_run
+//! use socketcan::gs_usb::GsUsb;
+//!
+//! let adapter = GsUsb::open_first().unwrap();
+//! adapter.start(500_000).unwrap();
+//! println!("{:?}", adapter.read_frame().unwrap());
+//! ```
+
+use std::{io, time};
+use std::sync::Mutex;
+use rusb::{self, DeviceHandle, GlobalContext};
+use {CanFrame, CanTransport};
+
+/// USB vendor and product IDs of known gs_usb compatible adapters
+pub const KNOWN_DEVICES: &'static [(u16, u16)] = &[(0x1d50, 0x606f), // candleLight, CANable
+                                                   (0x1209, 0x2323), // candleLight (pid.codes)
+                                                   (0x1cd2, 0x606f), // CES CANext FD
+                                                   (0x16d0, 0x10b8)]; // ABE CANdebugger FD
+
+// vendor requests
+const BREQ_HOST_FORMAT: u8 = 0;
+const BREQ_BITTIMING: u8 = 1;
+const BREQ_MODE: u8 = 2;
+const BREQ_BT_CONST: u8 = 4;
+const BREQ_DEVICE_CONFIG: u8 = 5;
+
+const MODE_RESET: u32 = 0;
+const MODE_START: u32 = 1;
+
+const ENDPOINT_IN: u8 = 0x81;
+const ENDPOINT_OUT: u8 = 0x02;
+
+const INTERFACE: u8 = 0;
+const CONTROL_TIMEOUT_MS: u64 = 1000;
+
+/// `echo_id` of frames received from the bus; all other values mark the
+/// echo of a transmitted frame
+const ECHO_ID_RX: u32 = 0xffffffff;
+
+const HOST_FRAME_LEN: usize = 20;
+
+fn usb_error(e: rusb::Error) -> io::Error {
+    match e {
+        rusb::Error::Timeout => io::Error::new(io::ErrorKind::WouldBlock, e),
+        rusb::Error::NoDevice => io::Error::new(io::ErrorKind::NotConnected, e),
+        rusb::Error::Access => io::Error::new(io::ErrorKind::PermissionDenied, e),
+        _ => io::Error::new(io::ErrorKind::Other, e),
+    }
+}
+
+fn le_u32(b: &[u8]) -> u32 {
+    b[0] as u32 | (b[1] as u32) << 8 | (b[2] as u32) << 16 | (b[3] as u32) << 24
+}
+
+fn put_le_u32(buf: &mut Vec<u8>, v: u32) {
+    buf.extend_from_slice(&[v as u8, (v >> 8) as u8, (v >> 16) as u8, (v >> 24) as u8]);
+}
+
+/// Bit timing limits reported by the adapter
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BitTimingConst {
+    /// CAN clock frequency in Hz
+    pub fclk_can: u32,
+    pub tseg1_min: u32,
+    pub tseg1_max: u32,
+    pub tseg2_min: u32,
+    pub tseg2_max: u32,
+    pub sjw_max: u32,
+    pub brp_min: u32,
+    pub brp_max: u32,
+    pub brp_inc: u32,
+}
+
+/// Bit timing in time quanta
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct BitTiming {
+    pub prop_seg: u32,
+    pub phase_seg1: u32,
+    pub phase_seg2: u32,
+    pub sjw: u32,
+    pub brp: u32,
+}
+
+impl BitTimingConst {
+    /// Find the bit timing for `bitrate` with the sample point closest to
+    /// 87.5%, preferring the smallest prescaler.
+    ///
+    /// Returns `None` if `bitrate` cannot be reached exactly.
+    pub fn calculate(&self, bitrate: u32) -> Option<BitTiming> {
+        if bitrate == 0 {
+            return None;
+        }
+
+        let mut best: Option<(u32, BitTiming)> = None;
+        let mut brp = self.brp_min;
+        while brp <= self.brp_max {
+            let per_bit = bitrate as u64 * brp as u64;
+            if self.fclk_can as u64 % per_bit == 0 {
+                let tq = (self.fclk_can as u64 / per_bit) as u32;
+                if tq >= 1 + self.tseg1_min + self.tseg2_min &&
+                   tq <= 1 + self.tseg1_max + self.tseg2_max {
+                    // sample point at 87.5%, rounded to the nearest quantum
+                    let tseg2 = ((tq + 4) / 8)
+                        .max(self.tseg2_min)
+                        .min(self.tseg2_max)
+                        .max((tq - 1).saturating_sub(self.tseg1_max));
+                    let tseg1 = tq - 1 - tseg2;
+                    let deviation = ((tq - tseg2) * 1000 / tq) as i64 - 875;
+
+                    let timing = BitTiming {
+                        prop_seg: 1,
+                        phase_seg1: tseg1 - 1,
+                        phase_seg2: tseg2,
+                        sjw: 1.min(self.sjw_max),
+                        brp: brp,
+                    };
+                    let deviation = deviation.abs() as u32;
+                    if best.map_or(true, |(d, _)| deviation < d) {
+                        best = Some((deviation, timing));
+                    }
+                }
+            }
+            brp += self.brp_inc.max(1);
+        }
+
+        best.map(|(_, timing)| timing)
+    }
+}
+
+/// A channel of a gs_usb adapter
+#[derive(Debug)]
+pub struct GsUsb {
+    handle: DeviceHandle<GlobalContext>,
+    channel: u8,
+    read_timeout: Mutex<time::Duration>,
+}
+
+impl GsUsb {
+    /// Open the first known adapter, using its first channel.
+    pub fn open_first() -> io::Result<GsUsb> {
+        for device in rusb::devices().map_err(usb_error)?.iter() {
+            let desc = device.device_descriptor().map_err(usb_error)?;
+            if KNOWN_DEVICES.contains(&(desc.vendor_id(), desc.product_id())) {
+                return GsUsb::open(device.open().map_err(usb_error)?, 0);
+            }
+        }
+        Err(io::Error::new(io::ErrorKind::NotFound, "no gs_usb adapter found"))
+    }
+
+    /// Use `channel` of an opened adapter.
+    pub fn open(handle: DeviceHandle<GlobalContext>, channel: u8) -> io::Result<GsUsb> {
+        if rusb::supports_detach_kernel_driver() {
+            handle.set_auto_detach_kernel_driver(true).map_err(usb_error)?;
+        }
+        handle.claim_interface(INTERFACE).map_err(usb_error)?;
+
+        let adapter = GsUsb {
+            handle: handle,
+            channel: channel,
+            read_timeout: Mutex::new(time::Duration::from_millis(0)),
+        };
+
+        // announce our byte order; the device always uses little endian
+        let mut host_format = Vec::new();
+        put_le_u32(&mut host_format, 0x0000beef);
+        adapter.control_out(BREQ_HOST_FORMAT, 1, &host_format)?;
+
+        let mut config = [0; 12];
+        adapter.control_in(BREQ_DEVICE_CONFIG, 1, &mut config)?;
+        if channel > config[3] {
+            return Err(io::Error::new(io::ErrorKind::NotFound,
+                                      format!("adapter has no channel {}", channel)));
+        }
+
+        Ok(adapter)
+    }
+
+    fn control_out(&self, request: u8, value: u16, data: &[u8]) -> io::Result<()> {
+        let request_type = rusb::request_type(rusb::Direction::Out,
+                                              rusb::RequestType::Vendor,
+                                              rusb::Recipient::Interface);
+        self.handle
+            .write_control(request_type,
+                           request,
+                           value,
+                           INTERFACE as u16,
+                           data,
+                           time::Duration::from_millis(CONTROL_TIMEOUT_MS))
+            .map(|_| ())
+            .map_err(usb_error)
+    }
+
+    fn control_in(&self, request: u8, value: u16, buf: &mut [u8]) -> io::Result<()> {
+        let request_type = rusb::request_type(rusb::Direction::In,
+                                              rusb::RequestType::Vendor,
+                                              rusb::Recipient::Interface);
+        let n = self.handle
+            .read_control(request_type,
+                          request,
+                          value,
+                          INTERFACE as u16,
+                          buf,
+                          time::Duration::from_millis(CONTROL_TIMEOUT_MS))
+            .map_err(usb_error)?;
+        if n < buf.len() {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "short control response"));
+        }
+        Ok(())
+    }
+
+    /// Read the bit timing limits of the channel.
+    pub fn bit_timing_const(&self) -> io::Result<BitTimingConst> {
+        let mut buf = [0; 40];
+        self.control_in(BREQ_BT_CONST, self.channel as u16, &mut buf)?;
+        let f = |i: usize| le_u32(&buf[4 * i..]);
+        Ok(BitTimingConst {
+            fclk_can: f(1),
+            tseg1_min: f(2),
+            tseg1_max: f(3),
+            tseg2_min: f(4),
+            tseg2_max: f(5),
+            sjw_max: f(6),
+            brp_min: f(7),
+            brp_max: f(8),
+            brp_inc: f(9),
+        })
+    }
+
+    /// Configure the bit timing. The channel must be stopped.
+    pub fn set_bit_timing(&self, timing: &BitTiming) -> io::Result<()> {
+        let mut buf = Vec::new();
+        for &v in &[timing.prop_seg, timing.phase_seg1, timing.phase_seg2, timing.sjw, timing.brp] {
+            put_le_u32(&mut buf, v);
+        }
+        self.control_out(BREQ_BITTIMING, self.channel as u16, &buf)
+    }
+
+    fn set_mode(&self, mode: u32) -> io::Result<()> {
+        let mut buf = Vec::new();
+        put_le_u32(&mut buf, mode);
+        put_le_u32(&mut buf, 0);
+        self.control_out(BREQ_MODE, self.channel as u16, &buf)
+    }
+
+    /// Configure `bitrate` and go on bus.
+    pub fn start(&self, bitrate: u32) -> io::Result<()> {
+        let timing = self.bit_timing_const()?
+            .calculate(bitrate)
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput,
+                               format!("bitrate {} not supported", bitrate))
+            })?;
+
+        self.set_mode(MODE_RESET)?;
+        self.set_bit_timing(&timing)?;
+        self.set_mode(MODE_START)
+    }
+
+    /// Go off bus.
+    pub fn stop(&self) -> io::Result<()> {
+        self.set_mode(MODE_RESET)
+    }
+
+    /// Set the read timeout. A zero duration disables the timeout.
+    pub fn set_read_timeout(&self, duration: time::Duration) -> io::Result<()> {
+        *self.read_timeout.lock().expect("read timeout lock poisoned") = duration;
+        Ok(())
+    }
+
+    /// Blocking read of a single frame.
+    ///
+    /// Echoes of transmitted frames and frames of other channels are
+    /// skipped. A timeout is reported as `WouldBlock`.
+    pub fn read_frame(&self) -> io::Result<CanFrame> {
+        let timeout = *self.read_timeout.lock().expect("read timeout lock poisoned");
+        let mut buf = [0; 64];
+        loop {
+            let n = self.handle.read_bulk(ENDPOINT_IN, &mut buf, timeout).map_err(usb_error)?;
+            if n < HOST_FRAME_LEN {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "short frame from adapter"));
+            }
+
+            if le_u32(&buf) != ECHO_ID_RX || buf[9] != self.channel {
+                continue;
+            }
+
+            let len = buf[8] as usize;
+            if len > 8 {
+                return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                          format!("invalid frame length {}", len)));
+            }
+
+            let mut frame = CanFrame::new(0, &buf[12..12 + len], false, false)
+                .expect("length was checked above");
+            frame._id = le_u32(&buf[4..]);
+            return Ok(frame);
+        }
+    }
+
+    pub fn write_frame(&self, frame: &CanFrame) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(HOST_FRAME_LEN);
+        put_le_u32(&mut buf, 0);
+        put_le_u32(&mut buf, frame._id);
+        buf.extend_from_slice(&[frame._data_len, self.channel, 0, 0]);
+        buf.extend_from_slice(&frame._data);
+
+        self.handle
+            .write_bulk(ENDPOINT_OUT, &buf, time::Duration::from_millis(CONTROL_TIMEOUT_MS))
+            .map(|_| ())
+            .map_err(usb_error)
+    }
+}
+
+impl Drop for GsUsb {
+    fn drop(&mut self) {
+        let _ = self.stop();
+        let _ = self.handle.release_interface(INTERFACE);
+    }
+}
+
+impl CanTransport for GsUsb {
+    fn read_frame(&self) -> io::Result<CanFrame> {
+        GsUsb::read_frame(self)
+    }
+
+    fn write_frame(&self, frame: &CanFrame) -> io::Result<()> {
+        GsUsb::write_frame(self, frame)
+    }
+
+    fn set_read_timeout(&self, duration: time::Duration) -> io::Result<()> {
+        GsUsb::set_read_timeout(self, duration)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BitTiming, BitTimingConst};
+
+    #[test]
+    fn test_calculate_bit_timing() {
+        // candleLight (STM32F072)
+        let bt = BitTimingConst {
+            fclk_can: 48_000_000,
+            tseg1_min: 1,
+            tseg1_max: 16,
+            tseg2_min: 1,
+            tseg2_max: 8,
+            sjw_max: 4,
+            brp_min: 1,
+            brp_max: 1024,
+            brp_inc: 1,
+        };
+
+        assert_eq!(bt.calculate(500_000),
+                   Some(BitTiming {
+                            prop_seg: 1,
+                            phase_seg1: 12,
+                            phase_seg2: 2,
+                            sjw: 1,
+                            brp: 6,
+                        }));
+        assert_eq!(bt.calculate(1_000_000).map(|t| t.brp), Some(3));
+        assert_eq!(bt.calculate(0), None);
+        assert_eq!(bt.calculate(333_333), None);
+    }
+}
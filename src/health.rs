@@ -0,0 +1,206 @@
+//! Network health
+//!
+//! `NetworkHealth` answers "who is alive" for mixed networks. It watches
+//! CANopen nodes through their heartbeats, J1939 nodes through their
+//! address claims and any other traffic they send, and plain cyclic frames
+//! by ID. A member is alive if it was seen within its timeout.
+//!
+//! ```
+//! use std::time::{Duration, Instant};
+//! use socketcan::CanFrame;
+//! use socketcan::health::{Member, NetworkHealth};
+//!
+//! let timeout = Duration::from_millis(500);
+//! let mut health = NetworkHealth::new()
+//!     .watch(Member::CanOpen(5), timeout)
+//!     .watch(Member::Cyclic(0x123), timeout);
+//!
+//! let now = Instant::now();
+//! health.process_frame(&CanFrame::new(0x705, &[0x05], false, false).unwrap(), now);
+//! assert!(health.is_alive(Member::CanOpen(5), now));
+//! assert!(!health.is_alive(Member::Cyclic(0x123), now));
+//! ```
+
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+use {CanFrame, EFF_FLAG};
+use canopen::{Heartbeat, NmtState};
+use j1939::{AddressClaim, J1939Id, J1939Name, ADDRESS_NULL};
+
+/// A watched participant of the network
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Member {
+    /// CANopen node, seen through its heartbeats
+    CanOpen(u8),
+
+    /// J1939 node with the given source address, seen through any frame it
+    /// sends
+    J1939(u8),
+
+    /// Cyclic frame with the given raw ID, including `EFF_FLAG` for
+    /// extended frames
+    Cyclic(u32),
+}
+
+/// State of a member
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct MemberStatus {
+    pub member: Member,
+
+    /// Whether the member was seen within its timeout
+    pub alive: bool,
+
+    /// Time the member was seen last
+    pub last_seen: Option<Instant>,
+
+    /// NMT state from the last heartbeat of a CANopen node
+    pub nmt_state: Option<NmtState>,
+
+    /// NAME from the last address claim of a J1939 node
+    pub name: Option<J1939Name>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    timeout: Duration,
+    last_seen: Option<Instant>,
+    nmt_state: Option<NmtState>,
+    name: Option<J1939Name>,
+}
+
+impl Entry {
+    fn new(timeout: Duration) -> Entry {
+        Entry {
+            timeout: timeout,
+            last_seen: None,
+            nmt_state: None,
+            name: None,
+        }
+    }
+}
+
+/// Table of watched network members
+#[derive(Debug, Default)]
+pub struct NetworkHealth {
+    members: BTreeMap<Member, Entry>,
+    discover: Option<Duration>,
+}
+
+impl NetworkHealth {
+    /// Create a table without any members.
+    pub fn new() -> NetworkHealth {
+        NetworkHealth::default()
+    }
+
+    /// Watch `member`, considering it lost if not seen for `timeout`.
+    pub fn watch(mut self, member: Member, timeout: Duration) -> NetworkHealth {
+        self.members.entry(member).or_insert_with(|| Entry::new(timeout)).timeout = timeout;
+        self
+    }
+
+    /// Also watch CANopen nodes sending heartbeats and J1939 nodes claiming
+    /// an address, with `timeout`.
+    pub fn discover(mut self, timeout: Duration) -> NetworkHealth {
+        self.discover = Some(timeout);
+        self
+    }
+
+    fn seen(&mut self, member: Member, discovered: bool, now: Instant) -> Option<&mut Entry> {
+        if discovered {
+            if let Some(timeout) = self.discover {
+                self.members.entry(member).or_insert_with(|| Entry::new(timeout));
+            }
+        }
+
+        self.members.get_mut(&member).map(|entry| {
+            entry.last_seen = Some(now);
+            entry
+        })
+    }
+
+    /// Account for `frame` received at `now`. Error frames are ignored.
+    pub fn process_frame(&mut self, frame: &CanFrame, now: Instant) {
+        if frame.is_error() {
+            return;
+        }
+
+        let raw_id = if frame.is_extended() { frame.id() | EFF_FLAG } else { frame.id() };
+        self.seen(Member::Cyclic(raw_id), false, now);
+
+        if let Ok(hb) = Heartbeat::from_frame(frame) {
+            if let Some(entry) = self.seen(Member::CanOpen(hb.node_id), true, now) {
+                entry.nmt_state = Some(hb.state);
+            }
+        }
+
+        if let Ok(claim) = AddressClaim::from_frame(frame) {
+            if claim.address != ADDRESS_NULL {
+                if let Some(entry) = self.seen(Member::J1939(claim.address), true, now) {
+                    entry.name = Some(claim.name);
+                }
+            }
+        } else if let Ok(id) = J1939Id::from_frame(frame) {
+            self.seen(Member::J1939(id.source), false, now);
+        }
+    }
+
+    /// Whether `member` was seen within its timeout
+    pub fn is_alive(&self, member: Member, now: Instant) -> bool {
+        self.status(member, now).map_or(false, |s| s.alive)
+    }
+
+    /// State of `member`, `None` if it is not watched
+    pub fn status(&self, member: Member, now: Instant) -> Option<MemberStatus> {
+        self.members.get(&member).map(|entry| {
+            MemberStatus {
+                member: member,
+                alive: entry.last_seen
+                    .map_or(false, |t| now.duration_since(t) <= entry.timeout),
+                last_seen: entry.last_seen,
+                nmt_state: entry.nmt_state,
+                name: entry.name,
+            }
+        })
+    }
+
+    /// State of all members, CANopen nodes first, then J1939 nodes and
+    /// cyclic frames, each in ascending order
+    pub fn table(&self, now: Instant) -> Vec<MemberStatus> {
+        self.members.keys().filter_map(|&member| self.status(member, now)).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+    use {CanFrame, EFF_FLAG};
+    use canopen::NmtState;
+    use super::{Member, NetworkHealth};
+
+    #[test]
+    fn test_health() {
+        let t0 = Instant::now();
+        let ms = |ms| t0 + Duration::from_millis(ms);
+        let mut health = NetworkHealth::new()
+            .watch(Member::Cyclic(0x18fef180 | EFF_FLAG), Duration::from_millis(100))
+            .discover(Duration::from_millis(50));
+
+        health.process_frame(&CanFrame::new(0x703, &[0x7f], false, false).unwrap(), ms(0));
+        let claim = [0x45, 0x23, 0xe1, 0xff, 0x00, 0x81, 0x00, 0x80];
+        health.process_frame(&CanFrame::new(0x18eeff80, &claim, false, false).unwrap(), ms(0));
+        health.process_frame(&CanFrame::new(0x18fef180, &[0; 8], false, false).unwrap(), ms(40));
+
+        let table = health.table(ms(60));
+        assert_eq!(table.iter().map(|s| (s.member, s.alive)).collect::<Vec<_>>(),
+                   vec![(Member::CanOpen(3), false),
+                        (Member::J1939(0x80), true),
+                        (Member::Cyclic(0x18fef180 | EFF_FLAG), true)]);
+        assert_eq!(table[0].nmt_state, Some(NmtState::PreOperational));
+        assert_eq!(table[1].name.unwrap().identity_number(), 0x12345);
+        assert_eq!(table[1].last_seen, Some(ms(40)));
+
+        // a frame from an unknown J1939 source is not discovered
+        health.process_frame(&CanFrame::new(0x18fef117, &[0; 8], false, false).unwrap(), ms(60));
+        assert!(health.status(Member::J1939(0x17), ms(60)).is_none());
+    }
+}
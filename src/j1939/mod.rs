@@ -0,0 +1,156 @@
+//! SAE J1939 support
+//!
+//! J1939 uses extended frames whose 29-bit ID is composed of a priority,
+//! the parameter group number (PGN) and the source address:
+//!
+//! ```text
+//!  28  26 25 24 23     16 15     8 7      0
+//! | prio |R |DP|   PF    |   PS    |   SA   |
+//! ```
+//!
+//! For PDU1 formats (PF below 240) the PS field holds the destination
+//! address and is not part of the PGN, PDU2 formats are always broadcast
+//! and use PS as group extension.
+
+use std::{error, fmt};
+use CanFrame;
+
+pub mod name;
+pub mod request;
+
+pub use self::name::{AddressClaim, J1939Name};
+pub use self::request::{request_pgn, RequestError};
+
+/// PGN of the request message
+pub const PGN_REQUEST: u32 = 0xea00;
+
+/// PGN of the address claimed message
+pub const PGN_ADDRESS_CLAIMED: u32 = 0xee00;
+
+/// PGN of acknowledgment messages, including NACKs
+pub const PGN_ACKNOWLEDGMENT: u32 = 0xe800;
+
+/// Destination address of broadcasts
+pub const ADDRESS_GLOBAL: u8 = 0xff;
+
+/// Source address of nodes without an address
+pub const ADDRESS_NULL: u8 = 0xfe;
+
+/// Default priority of control messages
+pub const DEFAULT_PRIORITY: u8 = 6;
+
+/// Whether `pgn` uses the PDU1 format, which carries a destination address
+#[inline]
+pub fn is_pdu1(pgn: u32) -> bool {
+    (pgn >> 8) & 0xff < 240
+}
+
+/// The fields of a J1939 frame ID
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct J1939Id {
+    /// Priority, 0 being the highest
+    pub priority: u8,
+
+    /// Parameter group number, with the PS field cleared for PDU1 formats
+    pub pgn: u32,
+
+    /// Address of the sender
+    pub source: u8,
+
+    /// Address of the receiver, `ADDRESS_GLOBAL` for PDU2 formats
+    pub destination: u8,
+}
+
+impl J1939Id {
+    /// Split a 29-bit ID.
+    pub fn from_raw(id: u32) -> J1939Id {
+        let pgn = (id >> 8) & 0x3ffff;
+        let (pgn, destination) = if is_pdu1(pgn) {
+            (pgn & !0xff, pgn as u8)
+        } else {
+            (pgn, ADDRESS_GLOBAL)
+        };
+
+        J1939Id {
+            priority: ((id >> 26) & 0x07) as u8,
+            pgn: pgn,
+            source: id as u8,
+            destination: destination,
+        }
+    }
+
+    /// Compose the 29-bit ID. The destination is ignored for PDU2 formats.
+    pub fn to_raw(&self) -> u32 {
+        let mut pgn = self.pgn & 0x3ffff;
+        if is_pdu1(pgn) {
+            pgn = pgn & !0xff | self.destination as u32;
+        }
+        (self.priority as u32 & 0x07) << 26 | pgn << 8 | self.source as u32
+    }
+
+    /// Split the ID of `frame`, failing for standard, RTR and error frames.
+    pub fn from_frame(frame: &CanFrame) -> Result<J1939Id, FrameError> {
+        if !frame.is_extended() || frame.is_rtr() || frame.is_error() {
+            return Err(FrameError::UnexpectedFrameType);
+        }
+        Ok(J1939Id::from_raw(frame.id()))
+    }
+}
+
+/// Error converting between `CanFrame`s and J1939 messages
+#[derive(Copy, Clone, Debug)]
+pub enum FrameError {
+    /// The frame belongs to a different parameter group
+    UnexpectedPgn(u32),
+
+    /// The frame carried fewer bytes than the message requires
+    NotEnoughData(usize),
+
+    /// A standard, RTR or error frame was passed where an extended data
+    /// frame was expected
+    UnexpectedFrameType,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FrameError::UnexpectedPgn(pgn) => write!(f, "unexpected PGN {:05X}", pgn),
+            FrameError::NotEnoughData(n) => write!(f, "not enough data ({} bytes)", n),
+            _ => write!(f, "{}", error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for FrameError {
+    fn description(&self) -> &str {
+        match *self {
+            FrameError::UnexpectedPgn(_) => "unexpected pgn",
+            FrameError::NotEnoughData(_) => "not enough data",
+            FrameError::UnexpectedFrameType => "unexpected frame type",
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{J1939Id, ADDRESS_GLOBAL, PGN_REQUEST};
+
+    #[test]
+    fn test_id() {
+        // request from 0xf9 to 0x00
+        let id = J1939Id::from_raw(0x18ea00f9);
+        assert_eq!(id,
+                   J1939Id {
+                       priority: 6,
+                       pgn: PGN_REQUEST,
+                       source: 0xf9,
+                       destination: 0x00,
+                   });
+        assert_eq!(id.to_raw(), 0x18ea00f9);
+
+        // PDU2: engine speed (EEC1) from 0x00
+        let id = J1939Id::from_raw(0x0cf00400);
+        assert_eq!((id.priority, id.pgn, id.destination), (3, 0xf004, ADDRESS_GLOBAL));
+        assert_eq!(id.to_raw(), 0x0cf00400);
+    }
+}
@@ -0,0 +1,194 @@
+//! J1939 NAME
+//!
+//! Every J1939 node is identified by a 64-bit NAME, sent in the address
+//! claimed message (PGN 60928). When two nodes claim the same address, the
+//! one with the numerically lower NAME keeps it.
+//!
+//! ```text
+//!  63  62  60 59  56 55    49 48 47      40 39  35 34 32 31     21 20       0
+//! |AAC| IG |VSI |   VS    |R |  function  | FI |ECU| manufacturer| identity |
+//! ```
+
+use CanFrame;
+use super::{FrameError, J1939Id, ADDRESS_GLOBAL, DEFAULT_PRIORITY, PGN_ADDRESS_CLAIMED};
+
+/// A J1939 NAME
+///
+/// Ordered by value, which is the order of priority in address claiming.
+/// The `with_*` builders panic if a value does not fit into its field.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct J1939Name(u64);
+
+macro_rules! name_field {
+    ($get:ident, $with:ident, $ty:ty, $shift:expr, $bits:expr, $doc:expr) => {
+        #[doc = $doc]
+        pub fn $get(&self) -> $ty {
+            ((self.0 >> $shift) & ((1 << $bits) - 1)) as $ty
+        }
+
+        #[doc = $doc]
+        pub fn $with(self, value: $ty) -> J1939Name {
+            let mask: u64 = (1 << $bits) - 1;
+            assert!(value as u64 <= mask, concat!(stringify!($get), " out of range"));
+            J1939Name(self.0 & !(mask << $shift) | (value as u64) << $shift)
+        }
+    }
+}
+
+impl J1939Name {
+    /// NAME with all fields set to zero
+    pub fn new() -> J1939Name {
+        J1939Name(0)
+    }
+
+    pub fn from_raw(raw: u64) -> J1939Name {
+        J1939Name(raw)
+    }
+
+    pub fn raw(&self) -> u64 {
+        self.0
+    }
+
+    /// Decode the eight bytes of an address claimed message.
+    pub fn from_bytes(bytes: [u8; 8]) -> J1939Name {
+        J1939Name(bytes.iter().rev().fold(0, |acc, &b| acc << 8 | b as u64))
+    }
+
+    /// Encode as the eight bytes of an address claimed message.
+    pub fn to_bytes(&self) -> [u8; 8] {
+        let mut bytes = [0; 8];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = (self.0 >> (8 * i)) as u8;
+        }
+        bytes
+    }
+
+    name_field!(identity_number, with_identity_number, u32, 0, 21, "Identity number (21 bits)");
+    name_field!(manufacturer_code,
+                with_manufacturer_code,
+                u16,
+                21,
+                11,
+                "Manufacturer code (11 bits)");
+    name_field!(ecu_instance, with_ecu_instance, u8, 32, 3, "ECU instance (3 bits)");
+    name_field!(function_instance,
+                with_function_instance,
+                u8,
+                35,
+                5,
+                "Function instance (5 bits)");
+    name_field!(function, with_function, u8, 40, 8, "Function (8 bits)");
+    name_field!(vehicle_system, with_vehicle_system, u8, 49, 7, "Vehicle system (7 bits)");
+    name_field!(vehicle_system_instance,
+                with_vehicle_system_instance,
+                u8,
+                56,
+                4,
+                "Vehicle system instance (4 bits)");
+    name_field!(industry_group, with_industry_group, u8, 60, 3, "Industry group (3 bits)");
+
+    /// Whether the node can pick another address after losing a claim
+    pub fn arbitrary_address_capable(&self) -> bool {
+        self.0 >> 63 != 0
+    }
+
+    /// Set whether the node can pick another address after losing a claim.
+    pub fn with_arbitrary_address_capable(self, capable: bool) -> J1939Name {
+        J1939Name(self.0 & !(1 << 63) | (capable as u64) << 63)
+    }
+}
+
+/// An address claimed message
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub struct AddressClaim {
+    /// Claimed address, `ADDRESS_NULL` if no address could be claimed
+    pub address: u8,
+
+    pub name: J1939Name,
+}
+
+impl AddressClaim {
+    /// Decode an address claimed message observed on the bus.
+    pub fn from_frame(frame: &CanFrame) -> Result<AddressClaim, FrameError> {
+        let id = J1939Id::from_frame(frame)?;
+        if id.pgn != PGN_ADDRESS_CLAIMED {
+            return Err(FrameError::UnexpectedPgn(id.pgn));
+        }
+
+        let data = frame.data();
+        if data.len() < 8 {
+            return Err(FrameError::NotEnoughData(data.len()));
+        }
+
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&data[..8]);
+        Ok(AddressClaim {
+            address: id.source,
+            name: J1939Name::from_bytes(bytes),
+        })
+    }
+
+    /// Encode as a broadcast address claimed message.
+    pub fn to_frame(&self) -> CanFrame {
+        let id = J1939Id {
+            priority: DEFAULT_PRIORITY,
+            pgn: PGN_ADDRESS_CLAIMED,
+            source: self.address,
+            destination: ADDRESS_GLOBAL,
+        };
+        CanFrame::new(id.to_raw(), &self.name.to_bytes(), false, false)
+            .expect("address claim is always valid")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use CanFrame;
+    use super::{AddressClaim, J1939Name};
+
+    #[test]
+    fn test_name_fields() {
+        let name = J1939Name::new()
+            .with_identity_number(0x12345)
+            .with_manufacturer_code(0x7ff)
+            .with_ecu_instance(1)
+            .with_function_instance(2)
+            .with_function(0x81)
+            .with_vehicle_system(0x7f)
+            .with_vehicle_system_instance(3)
+            .with_industry_group(4)
+            .with_arbitrary_address_capable(true);
+
+        assert_eq!(name.raw(), 0xc3fe_8111_ffe1_2345);
+        assert_eq!(name.identity_number(), 0x12345);
+        assert_eq!(name.manufacturer_code(), 0x7ff);
+        assert_eq!(name.ecu_instance(), 1);
+        assert_eq!(name.function_instance(), 2);
+        assert_eq!(name.function(), 0x81);
+        assert_eq!(name.vehicle_system(), 0x7f);
+        assert_eq!(name.vehicle_system_instance(), 3);
+        assert_eq!(name.industry_group(), 4);
+        assert!(name.arbitrary_address_capable());
+        assert_eq!(J1939Name::from_bytes(name.to_bytes()), name);
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_name_field_range() {
+        J1939Name::new().with_industry_group(8);
+    }
+
+    #[test]
+    fn test_address_claim() {
+        let data = [0x45, 0x23, 0xe1, 0xff, 0x00, 0x81, 0x00, 0x80];
+        let frame = CanFrame::new(0x18eeff80, &data, false, false).unwrap();
+        let claim = AddressClaim::from_frame(&frame).unwrap();
+        assert_eq!(claim.address, 0x80);
+        assert_eq!(claim.name.identity_number(), 0x12345);
+        assert_eq!(claim.name.manufacturer_code(), 0x7ff);
+        assert_eq!(claim.name.function(), 0x81);
+        assert!(claim.name.arbitrary_address_capable());
+        assert_eq!(claim.to_frame().data(), frame.data());
+        assert_eq!(claim.to_frame().id(), frame.id());
+    }
+}
@@ -0,0 +1,197 @@
+//! Requesting parameter groups
+//!
+//! A node asks for a parameter group by sending the request message (PGN
+//! 59904) carrying the requested PGN, either to a single node or to all
+//! nodes. Addressed nodes answer with the requested parameter group, or
+//! with an acknowledgment message (PGN 59392) if they cannot provide it.
+//! Requests to all nodes are never answered negatively.
+
+use std::{error, fmt, io};
+use std::time::Duration;
+use {CanFrame, CanTransport};
+use util::read_until;
+use super::{is_pdu1, J1939Id, ADDRESS_GLOBAL, DEFAULT_PRIORITY, PGN_ACKNOWLEDGMENT,
+            PGN_REQUEST};
+
+/// Negative acknowledgment
+pub const ACK_NEGATIVE: u8 = 1;
+
+/// Access denied
+pub const ACK_ACCESS_DENIED: u8 = 2;
+
+/// Cannot respond, e.g. because the node is busy
+pub const ACK_CANNOT_RESPOND: u8 = 3;
+
+/// Error requesting a parameter group
+#[derive(Debug)]
+pub enum RequestError {
+    /// No response arrived within the timeout
+    Timeout,
+
+    /// The node answered with the given negative acknowledgment control
+    /// byte, e.g. `ACK_NEGATIVE`
+    Nack(u8),
+
+    /// Socket error while sending or receiving
+    Io(io::Error),
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RequestError::Nack(control) => write!(f, "request not acknowledged ({})", control),
+            RequestError::Io(ref e) => write!(f, "IO: {}", e),
+            _ => write!(f, "{}", error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for RequestError {
+    fn description(&self) -> &str {
+        match *self {
+            RequestError::Timeout => "request timeout",
+            RequestError::Nack(_) => "request not acknowledged",
+            RequestError::Io(ref e) => e.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            RequestError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RequestError {
+    fn from(e: io::Error) -> RequestError {
+        RequestError::Io(e)
+    }
+}
+
+/// Build a request for `pgn` from `source` to `destination`.
+pub fn request_frame(source: u8, pgn: u32, destination: u8) -> CanFrame {
+    let id = J1939Id {
+        priority: DEFAULT_PRIORITY,
+        pgn: PGN_REQUEST,
+        source: source,
+        destination: destination,
+    };
+    CanFrame::new(id.to_raw(), &[pgn as u8, (pgn >> 8) as u8, (pgn >> 16) as u8], false, false)
+        .expect("request is always valid")
+}
+
+/// Whether `id` may belong to a response to a request sent by `source` to
+/// `destination`
+fn is_response(id: &J1939Id, source: u8, destination: u8) -> bool {
+    // PDU1 responses are addressed to the requester or to all nodes
+    (destination == ADDRESS_GLOBAL || id.source == destination) &&
+    (!is_pdu1(id.pgn) || id.destination == source || id.destination == ADDRESS_GLOBAL)
+}
+
+/// Request `pgn` from `destination`, sending from address `source`.
+///
+/// For a request to a single node, returns its response as soon as it
+/// arrives and fails with `Nack` if the node acknowledges negatively. For a
+/// request to `ADDRESS_GLOBAL`, collects the responses of all nodes until
+/// `timeout` expires. Fails with `Timeout` if no node responded. Other
+/// frames received meanwhile are discarded.
+pub fn request_pgn<T>(socket: &T,
+                      source: u8,
+                      pgn: u32,
+                      destination: u8,
+                      timeout: Duration)
+                      -> Result<Vec<CanFrame>, RequestError>
+    where T: CanTransport + ?Sized
+{
+    socket.write_frame_insist(&request_frame(source, pgn, destination))?;
+
+    let broadcast = destination == ADDRESS_GLOBAL;
+    let mut responses = Vec::new();
+    let mut nack = None;
+    read_until(socket, timeout, |frame| {
+        let id = match J1939Id::from_frame(frame) {
+            Ok(id) => id,
+            Err(_) => return false,
+        };
+        if !is_response(&id, source, destination) {
+            return false;
+        }
+
+        let data = frame.data();
+        if id.pgn == PGN_ACKNOWLEDGMENT && pgn != PGN_ACKNOWLEDGMENT && data.len() >= 8 &&
+           (data[5] as u32 | (data[6] as u32) << 8 | (data[7] as u32) << 16) == pgn {
+            match data[0] {
+                0 => {}
+                control if !broadcast => {
+                    nack = Some(control);
+                    return true;
+                }
+                _ => return false,
+            }
+        } else if id.pgn != pgn {
+            return false;
+        }
+
+        responses.push(*frame);
+        !broadcast
+    })?;
+
+    if let Some(control) = nack {
+        return Err(RequestError::Nack(control));
+    }
+    if responses.is_empty() {
+        Err(RequestError::Timeout)
+    } else {
+        Ok(responses)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::time::Duration;
+    use CanFrame;
+    use j1939::{J1939Id, ADDRESS_GLOBAL, PGN_ADDRESS_CLAIMED, PGN_REQUEST};
+    use testing::MockBus;
+    use super::{request_pgn, RequestError, ACK_NEGATIVE};
+
+    #[test]
+    fn test_request_pgn() {
+        let bus = MockBus::new();
+        let (requester, ecu) = (bus.endpoint(), bus.endpoint());
+
+        let responder = thread::spawn(move || {
+            for _ in 0..3 {
+                let request = ecu.read_frame().unwrap();
+                let id = J1939Id::from_raw(request.id());
+                assert_eq!((id.pgn, id.source), (PGN_REQUEST, 0xf9));
+                if request.data() == &[0x00, 0xee, 0x00] {
+                    // claims of two nodes, of which only 0x00 is addressed
+                    let claim = CanFrame::new(0x18eeff17, &[1; 8], false, false).unwrap();
+                    ecu.write_frame(&claim).unwrap();
+                    let claim = CanFrame::new(0x18eeff00, &[0; 8], false, false).unwrap();
+                    ecu.write_frame(&claim).unwrap();
+                } else {
+                    let nack = [ACK_NEGATIVE, 0xff, 0xff, 0xff, 0xf9, 0xe5, 0xfe, 0x00];
+                    ecu.write_frame(&CanFrame::new(0x18e8ff00, &nack, false, false).unwrap())
+                        .unwrap();
+                }
+            }
+        });
+
+        let timeout = Duration::from_millis(200);
+        let responses = request_pgn(&requester, 0xf9, PGN_ADDRESS_CLAIMED, 0x00, timeout).unwrap();
+        assert_eq!(responses.iter().map(|f| f.id()).collect::<Vec<_>>(), vec![0x18eeff00]);
+
+        let responses = request_pgn(&requester, 0xf9, PGN_ADDRESS_CLAIMED, ADDRESS_GLOBAL, timeout)
+            .unwrap();
+        assert_eq!(responses.len(), 2);
+
+        match request_pgn(&requester, 0xf9, 0xfee5, 0x00, timeout) {
+            Err(RequestError::Nack(ACK_NEGATIVE)) => {}
+            r => panic!("unexpected result: {:?}", r),
+        }
+        responder.join().unwrap();
+    }
+}
@@ -40,39 +40,156 @@
 //! Raw access to the underlying file descriptor and construction through
 //! is available through the `AsRawFd`, `IntoRawFd` and `FromRawFd`
 //! implementations.
+//!
+//! # High-level access
+//!
+//! `CanBus` bundles periodic sending, subscriptions to ID ranges,
+//! request/response exchanges and error reporting for a single interface.
+//!
+//! # Features
+//!
+//! * `gs_usb`: userspace backend for gs_usb (candleLight) adapters using
+//!   libusb, see the `gs_usb` module.
+//! * `metrics`: count frames, bytes and errors of all sockets through the
+//!   [metrics](https://crates.io/crates/metrics) facade, e.g. to export
+//!   them to Prometheus.
+//! * `serde`: deserialize cyclic frame tables, see the `schedule` module.
+//! * `tokio`: asynchronous transports and a read loop stopped by a
+//!   `CancellationToken`, running on a tokio runtime, see the
+//!   `async_transport` module, `router::AsyncRouter`, which routes
+//!   frames on a tokio task, `canopen::AsyncSdoClient` and
+//!   `canopen::MessageStream`.
+//! * `tools`: build the `candump`, `cansend` and `cansniffer` binaries,
+//!   simple versions of the can-utils tools of the same name.
+//! * `tracing`: emit [tracing](https://crates.io/crates/tracing) events for
+//!   socket operations, with frame IDs as fields.
 
 
 
 // clippy: do not warn about things like "SocketCAN" inside the docs
 #![cfg_attr(feature = "cargo-clippy", allow(doc_markdown))]
 
+#[macro_use]
+extern crate bitflags;
 extern crate byte_conv;
+extern crate flate2;
 extern crate hex;
 extern crate itertools;
 extern crate libc;
+#[cfg(feature = "metrics")]
+extern crate metrics;
 extern crate netlink_rs;
 extern crate nix;
+#[cfg(feature = "gs_usb")]
+extern crate rusb;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "tokio")]
+extern crate tokio;
+#[cfg(feature = "tokio")]
+extern crate tokio_util;
+#[cfg(feature = "tracing")]
+extern crate tracing;
 extern crate try_from;
+#[cfg(all(test, feature = "serde"))]
+extern crate toml;
+
+/// Build a `CanFrame` at compile time.
+///
+/// Takes the ID and the payload as an array of bytes, checking both while
+/// compiling, so the macro can be used in `const` and `static` items:
+///
+/// ```
+/// #[macro_use]
+/// extern crate socketcan;
+/// use socketcan::CanFrame;
+///
+/// static CYCLIC: [CanFrame; 2] = [can_frame!(0x123, [0xde, 0xad]), can_frame!(0x18ff0001, [])];
+///
+/// fn main() {
+///     assert_eq!(CYCLIC[0].data(), &[0xde, 0xad]);
+///     assert!(CYCLIC[1].is_extended());
+/// }
+/// ```
+///
+/// Invalid frames fail to compile:
+///
+/// ```compile_fail
+/// #[macro_use]
+/// extern crate socketcan;
+///
+/// fn main() {
+///     let frame = can_frame!(0x123, [0, 1, 2, 3, 4, 5, 6, 7, 8]);
+/// }
+/// ```
+#[macro_export]
+macro_rules! can_frame {
+    ($id:expr, [$($byte:expr),*]) => {{
+        const FRAME: $crate::CanFrame =
+            $crate::CanFrame::new_const($id, &[$($byte),*], false, false);
+        FRAME
+    }};
+}
 
 mod err;
 pub use err::{CanError, CanErrorDecodingFailure};
+pub mod analysis;
+#[cfg(feature = "tokio")]
+pub mod async_transport;
+pub mod bus;
+pub mod cannelloni;
+pub mod clock;
+pub mod canopen;
+pub mod constants;
 pub mod dump;
+pub mod fd;
+pub mod filter;
+pub mod gateway;
+pub mod generator;
+#[cfg(feature = "gs_usb")]
+pub mod gs_usb;
+pub mod health;
+pub mod j1939;
+pub mod middleware;
 mod nl;
+pub mod pool;
+pub mod reader;
+pub mod remote;
+pub mod router;
+pub mod schedule;
+pub mod spsc;
+#[cfg(feature = "xl")]
+pub mod xl;
+pub mod state;
+mod telemetry;
+pub mod testing;
+pub mod timestamp;
+mod transport;
+pub mod txqueue;
 mod util;
 
 #[cfg(test)]
 mod tests;
 
-use libc::{c_int, c_short, c_void, c_uint, c_ulong, socket, SOCK_RAW, close, bind, sockaddr, read,
-           write, SOL_SOCKET, SO_RCVTIMEO, timespec, timeval, EINPROGRESS, SO_SNDTIMEO, time_t,
-           suseconds_t, fcntl, F_GETFL, F_SETFL, O_NONBLOCK};
+use libc::{c_int, c_short, c_void, c_uint, c_ulong, socket, SOCK_RAW, close, bind, sockaddr,
+           recvmsg, write, SOL_SOCKET, SO_RCVTIMEO, timespec, timeval, EINPROGRESS, SO_SNDTIMEO,
+           time_t, suseconds_t, fcntl, F_GETFL, F_SETFL, O_NONBLOCK, MSG_DONTWAIT, MSG_CONFIRM,
+           MSG_DONTROUTE, MSG_TRUNC, iovec, msghdr, getsockname, socklen_t, poll, pollfd,
+           POLLOUT};
 use itertools::Itertools;
 use nix::net::if_::if_nametoindex;
-pub use nl::CanInterface;
-use std::{error, fmt, io, time};
-use std::mem::{size_of, uninitialized};
+pub use bus::{BusId, CanBus, TaggedFrame};
+pub use constants::{CanIdFlags, EFF_FLAG, RTR_FLAG, ERR_FLAG, SFF_MASK, EFF_MASK, ERR_MASK,
+                    ERR_MASK_ALL, ERR_MASK_NONE, dlc_to_len, len_to_dlc};
+use constants::{AF_CAN, PF_CAN, CAN_RAW, SOL_CAN_RAW, CAN_RAW_FILTER, CAN_RAW_ERR_FILTER,
+                CAN_RAW_LOOPBACK, CAN_RAW_RECV_OWN_MSGS, CAN_RAW_JOIN_FILTERS, SO_BUSY_POLL};
+pub use fd::CanFdFrame;
+pub use nl::{CanDeviceStats, CanInterface, CanStats};
+pub use transport::{run_read_loop, CanTransport, SHUTDOWN_POLL_INTERVAL_MS};
+use std::{cmp, error, fmt, io, ptr, slice, time};
+use std::mem::{self, size_of, uninitialized};
 use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
-use util::{set_socket_option, set_socket_option_mult};
+use util::{get_socket_option, set_socket_option, set_socket_option_mult};
 
 /// Check an error return value for timeouts.
 ///
@@ -117,52 +234,12 @@ impl<E: fmt::Debug> ShouldRetry for io::Result<E> {
     }
 }
 
-// constants stolen from C headers
-const AF_CAN: c_int = 29;
-const PF_CAN: c_int = 29;
-const CAN_RAW: c_int = 1;
-const SOL_CAN_BASE: c_int = 100;
-const SOL_CAN_RAW: c_int = SOL_CAN_BASE + CAN_RAW;
-const CAN_RAW_FILTER: c_int = 1;
-const CAN_RAW_ERR_FILTER: c_int = 2;
-const CAN_RAW_LOOPBACK: c_int = 3;
-const CAN_RAW_RECV_OWN_MSGS: c_int = 4;
-// unused:
-// const CAN_RAW_FD_FRAMES: c_int = 5;
-const CAN_RAW_JOIN_FILTERS: c_int = 6;
-
-
 // get timestamp in a struct timeval (us accuracy)
 // const SIOCGSTAMP: c_int = 0x8906;
 
 // get timestamp in a struct timespec (ns accuracy)
 const SIOCGSTAMPNS: c_int = 0x8907;
 
-/// if set, indicate 29 bit extended format
-pub const EFF_FLAG: u32 = 0x80000000;
-
-/// remote transmission request flag
-pub const RTR_FLAG: u32 = 0x40000000;
-
-/// error flag
-pub const ERR_FLAG: u32 = 0x20000000;
-
-/// valid bits in standard frame id
-pub const SFF_MASK: u32 = 0x000007ff;
-
-/// valid bits in extended frame id
-pub const EFF_MASK: u32 = 0x1fffffff;
-
-/// valid bits in error frame
-pub const ERR_MASK: u32 = 0x1fffffff;
-
-
-/// an error mask that will cause SocketCAN to report all errors
-pub const ERR_MASK_ALL: u32 = ERR_MASK;
-
-/// an error mask that will cause SocketCAN to silently drop all errors
-pub const ERR_MASK_NONE: u32 = 0;
-
 fn c_timeval_new(t: time::Duration) -> timeval {
     timeval {
         tv_sec: t.as_secs() as time_t,
@@ -214,6 +291,26 @@ impl error::Error for CanSocketOpenError {
     }
 }
 
+/// How received frames with a length field above 8 are handled
+///
+/// Set per socket with `CanSocket::set_length_policy`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LengthPolicy {
+    /// Reject every length above 8 with `FrameReadError::InvalidLength`
+    Strict,
+    /// Accept lengths of 9 to 15 as DLCs of frames carrying 8 bytes, see
+    /// `CanFrame::len8_dlc`, and reject larger ones (default)
+    Len8Dlc,
+    /// Like `Len8Dlc`, but keep frames with larger lengths as well, cut to
+    /// 8 bytes and marked by `CanFrame::is_truncated`
+    Truncate,
+}
+
+impl Default for LengthPolicy {
+    fn default() -> LengthPolicy {
+        LengthPolicy::Len8Dlc
+    }
+}
 
 #[derive(Debug, Copy, Clone)]
 /// Error that occurs when creating CAN packets
@@ -222,6 +319,8 @@ pub enum ConstructionError {
     IDTooLarge,
     /// More than 8 Bytes of payload data were passed in
     TooMuchData,
+    /// Less payload data was passed in than the frame type requires
+    NotEnoughData,
 }
 
 impl fmt::Display for ConstructionError {
@@ -231,6 +330,7 @@ impl fmt::Display for ConstructionError {
             ConstructionError::TooMuchData => {
                 write!(f, "Payload is larger than CAN maximum of 8 bytes")
             }
+            ConstructionError::NotEnoughData => write!(f, "Payload is too short"),
         }
     }
 }
@@ -240,10 +340,53 @@ impl error::Error for ConstructionError {
         match *self {
             ConstructionError::IDTooLarge => "can id too large",
             ConstructionError::TooMuchData => "too much data",
+            ConstructionError::NotEnoughData => "not enough data",
+        }
+    }
+}
+
+/// Malformed frame received from the socket
+///
+/// Returned wrapped in an `io::Error` of kind `InvalidData`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum FrameReadError {
+    /// Fewer bytes than a full frame were read
+    ShortRead(usize),
+    /// The length field exceeds the largest possible DLC of 15
+    InvalidLength(u8),
+    /// A frame larger than a classic frame was received, e.g. a CAN FD
+    /// frame, which requires `read_fd_frame`
+    Oversized,
+}
+
+impl fmt::Display for FrameReadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            FrameReadError::ShortRead(n) => {
+                write!(f, "short read of {} bytes, expected {}", n, size_of::<CanFrame>())
+            }
+            FrameReadError::InvalidLength(len) => write!(f, "invalid frame length {}", len),
+            FrameReadError::Oversized => write!(f, "frame larger than a classic frame"),
         }
     }
 }
 
+impl error::Error for FrameReadError {
+    fn description(&self) -> &str {
+        match *self {
+            FrameReadError::ShortRead(_) => "short read",
+            FrameReadError::InvalidLength(_) => "invalid frame length",
+            FrameReadError::Oversized => "oversized frame",
+        }
+    }
+}
+
+impl From<FrameReadError> for io::Error {
+    fn from(e: FrameReadError) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, e)
+    }
+}
+
 impl From<nix::Error> for CanSocketOpenError {
     fn from(e: nix::Error) -> CanSocketOpenError {
         CanSocketOpenError::LookupError(e)
@@ -259,10 +402,12 @@ impl From<io::Error> for CanSocketOpenError {
 /// A socket for a CAN device.
 ///
 /// Will be closed upon deallocation. To close manually, use std::drop::Drop.
-/// Internally this is just a wrapped file-descriptor.
+/// Internally this is just a wrapped file-descriptor, along with the
+/// `LengthPolicy` applied to received frames.
 #[derive(Debug)]
 pub struct CanSocket {
     fd: c_int,
+    length_policy: LengthPolicy,
 }
 
 impl CanSocket {
@@ -275,6 +420,22 @@ impl CanSocket {
         CanSocket::open_if(if_index)
     }
 
+    /// Open the first CAN interface that is up and matches `pattern`.
+    ///
+    /// Useful where the interface name differs between machines, e.g.
+    /// `"*can*"` finds `can0`, `slcan0` or `vcan0`. See
+    /// `CanInterface::list_matching`.
+    pub fn open_first_matching(pattern: &str) -> Result<CanSocket, CanSocketOpenError> {
+        match CanInterface::list_matching(pattern)?.first() {
+            Some(ifname) => CanSocket::open(ifname),
+            None => {
+                Err(io::Error::new(io::ErrorKind::NotFound,
+                                   format!("no CAN interface up matching {:?}", pattern))
+                    .into())
+            }
+        }
+    }
+
     /// Open CAN device by interface number.
     ///
     /// Opens a CAN device by kernel interface number.
@@ -293,7 +454,9 @@ impl CanSocket {
         }
 
         if sock_fd == -1 {
-            return Err(CanSocketOpenError::from(io::Error::last_os_error()));
+            let e = io::Error::last_os_error();
+            telemetry::open_failed(if_index, &e);
+            return Err(CanSocketOpenError::from(e));
         }
 
         // bind it
@@ -311,10 +474,15 @@ impl CanSocket {
             unsafe {
                 close(sock_fd);
             }
+            telemetry::open_failed(if_index, &e);
             return Err(CanSocketOpenError::from(e));
         }
 
-        Ok(CanSocket { fd: sock_fd })
+        telemetry::socket_opened(if_index, sock_fd);
+        Ok(CanSocket {
+               fd: sock_fd,
+               length_policy: LengthPolicy::default(),
+           })
     }
 
     fn close(&mut self) -> io::Result<()> {
@@ -327,6 +495,28 @@ impl CanSocket {
         Ok(())
     }
 
+    /// Index of the interface the socket is bound to
+    pub fn if_index(&self) -> io::Result<c_uint> {
+        let mut addr: CanAddr = unsafe { mem::zeroed() };
+        let mut len = size_of::<CanAddr>() as socklen_t;
+        let rv = unsafe {
+            getsockname(self.fd, &mut addr as *mut CanAddr as *mut sockaddr, &mut len)
+        };
+
+        if rv == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(addr.if_index as c_uint)
+    }
+
+    /// Snapshot of the kernel's counters for the socket's interface
+    ///
+    /// The counters cover all traffic on the interface, not only that of
+    /// this socket. See `CanInterface::stats`.
+    pub fn stats(&self) -> io::Result<CanStats> {
+        CanInterface::open_if(self.if_index()?).stats()
+    }
+
     /// Change socket to non-blocking mode
     pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
         // retrieve current flags
@@ -350,6 +540,34 @@ impl CanSocket {
         Ok(())
     }
 
+    /// Wait up to `timeout` for space in the transmit queue.
+    ///
+    /// Returns `true` if a frame can be written without blocking or
+    /// failing with `ENOBUFS`, `false` if the timeout expired first.
+    /// Timeouts are rounded up to whole milliseconds.
+    pub fn poll_writable(&self, timeout: time::Duration) -> io::Result<bool> {
+        let ms = timeout.as_secs()
+            .saturating_mul(1000)
+            .saturating_add((timeout.subsec_nanos() as u64 + 999_999) / 1_000_000);
+        let mut fds = pollfd {
+            fd: self.fd,
+            events: POLLOUT,
+            revents: 0,
+        };
+
+        let rv = unsafe { poll(&mut fds, 1, cmp::min(ms, c_int::max_value() as u64) as c_int) };
+        if rv == -1 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(fds.revents & POLLOUT != 0)
+    }
+
+    /// Check without waiting whether a frame can be written, see
+    /// `poll_writable`.
+    pub fn is_writable(&self) -> io::Result<bool> {
+        self.poll_writable(time::Duration::from_millis(0))
+    }
+
     /// Sets the read timeout on the socket
     ///
     /// For convenience, the result value can be checked using
@@ -358,6 +576,19 @@ impl CanSocket {
         set_socket_option(self.fd, SOL_SOCKET, SO_RCVTIMEO, &c_timeval_new(duration))
     }
 
+    /// Choose how received frames with a length above 8 are handled.
+    ///
+    /// Strict tools can reject malformed frames, while loggers can keep
+    /// them with `LengthPolicy::Truncate`. Only affects classic frames.
+    pub fn set_length_policy(&mut self, policy: LengthPolicy) {
+        self.length_policy = policy;
+    }
+
+    /// How received frames with a length above 8 are handled
+    pub fn length_policy(&self) -> LengthPolicy {
+        self.length_policy
+    }
+
     /// Sets the write timeout on the socket
     pub fn set_write_timeout(&self, duration: time::Duration) -> io::Result<()> {
         set_socket_option(self.fd, SOL_SOCKET, SO_SNDTIMEO, &c_timeval_new(duration))
@@ -365,6 +596,111 @@ impl CanSocket {
 
     /// Blocking read a single can frame.
     pub fn read_frame(&self) -> io::Result<CanFrame> {
+        self.recv_frame(0)
+    }
+
+    /// Read a single frame, waiting at most `timeout`.
+    ///
+    /// Returns `Ok(None)` if no frame arrived in time, errors are real
+    /// failures. A zero timeout only returns an already queued frame.
+    /// Changes the socket's read timeout.
+    pub fn read_opt(&self, timeout: time::Duration) -> io::Result<Option<CanFrame>> {
+        let result = if timeout == time::Duration::from_millis(0) {
+            self.recv_frame(MSG_DONTWAIT)
+        } else {
+            self.set_read_timeout(timeout)?;
+            self.recv_frame(0)
+        };
+
+        match result {
+            Ok(frame) => Ok(Some(frame)),
+            Err(ref e) if e.should_retry() => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Busy poll the device for up to `duration` on blocking reads.
+    ///
+    /// Lowers receive latency at the cost of CPU time, where the driver
+    /// supports it. Raising the value above the `net.core.busy_read`
+    /// sysctl requires `CAP_NET_ADMIN`; zero disables busy polling.
+    pub fn set_busy_poll(&self, duration: time::Duration) -> io::Result<()> {
+        let usecs = duration.as_secs() * 1_000_000 + duration.subsec_nanos() as u64 / 1000;
+        let usecs = cmp::min(usecs, c_int::max_value() as u64) as c_int;
+        set_socket_option(self.fd, SOL_SOCKET, SO_BUSY_POLL, &usecs)
+    }
+
+    /// Read a single frame, spinning for up to `spin` before blocking.
+    ///
+    /// Checks the socket without sleeping until a frame arrives or `spin`
+    /// has passed, then falls back to a blocking read. Avoids the wakeup
+    /// latency of a blocking read for control loops that expect a frame
+    /// soon, but keeps a core busy while spinning.
+    pub fn read_frame_spin(&self, spin: time::Duration) -> io::Result<CanFrame> {
+        let start = time::Instant::now();
+        loop {
+            match self.recv_frame(MSG_DONTWAIT) {
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {}
+                result => return result,
+            }
+            if start.elapsed() >= spin {
+                return self.recv_frame(0);
+            }
+        }
+    }
+
+    /// Read as many frames as are available into `frames`, up to its
+    /// length.
+    ///
+    /// Blocks until the first frame arrives, but not for the rest. Returns
+    /// the number of frames read.
+    pub fn read_into(&self, frames: &mut [CanFrame]) -> io::Result<usize> {
+        if frames.is_empty() {
+            return Ok(0);
+        }
+
+        frames[0] = self.recv_frame(0)?;
+        for i in 1..frames.len() {
+            match self.recv_frame(MSG_DONTWAIT) {
+                Ok(frame) => frames[i] = frame,
+                Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(i),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(frames.len())
+    }
+
+    /// Blocking read a single can frame, along with where it came from.
+    ///
+    /// See `RecvMeta`.
+    pub fn read_frame_with_meta(&self) -> io::Result<(CanFrame, RecvMeta)> {
+        self.recv_frame_msg(0).map(|(frame, msg_flags)| {
+            (frame,
+             RecvMeta {
+                 own: msg_flags & MSG_CONFIRM != 0,
+                 local: msg_flags & MSG_DONTROUTE != 0,
+             })
+        })
+    }
+
+    fn recv_frame(&self, flags: c_int) -> io::Result<CanFrame> {
+        self.recv_frame_msg(flags).map(|(frame, _)| frame)
+    }
+
+    /// Receive a frame with `recvmsg`, returning the message flags as well
+    fn recv_frame_msg(&self, flags: c_int) -> io::Result<(CanFrame, c_int)> {
+        self.recv_frame_cmsg(flags, &mut []).map(|(frame, msg_flags, _)| (frame, msg_flags))
+    }
+
+    /// Receive a frame with `recvmsg`, along with control messages.
+    ///
+    /// Control messages are stored in `control`, which is made of `u64`s
+    /// for the alignment of `cmsghdr`. Returns the message flags and the
+    /// length of the control messages in bytes.
+    fn recv_frame_cmsg(&self,
+                       flags: c_int,
+                       control: &mut [u64])
+                       -> io::Result<(CanFrame, c_int, usize)> {
         let mut frame = CanFrame {
             _id: 0,
             _data_len: 0,
@@ -374,23 +710,46 @@ impl CanSocket {
             _data: [0; 8],
         };
 
-        let read_rv = unsafe {
-            let frame_ptr = &mut frame as *mut CanFrame;
-            read(self.fd, frame_ptr as *mut c_void, size_of::<CanFrame>())
+        let mut iov = iovec {
+            iov_base: &mut frame as *mut CanFrame as *mut c_void,
+            iov_len: size_of::<CanFrame>(),
         };
+        let mut msg: msghdr = unsafe { mem::zeroed() };
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        if !control.is_empty() {
+            msg.msg_control = control.as_mut_ptr() as *mut c_void;
+            msg.msg_controllen = (control.len() * size_of::<u64>()) as _;
+        }
 
-        if read_rv as usize != size_of::<CanFrame>() {
-            return Err(io::Error::last_os_error());
+        let read_rv = unsafe { recvmsg(self.fd, &mut msg, flags) };
+
+        let result = if read_rv < 0 {
+            Err(io::Error::last_os_error())
+        } else if msg.msg_flags & MSG_TRUNC != 0 {
+            // the rest of an FD frame would be mistaken for a classic one
+            Err(FrameReadError::Oversized.into())
+        } else if read_rv as usize != size_of::<CanFrame>() {
+            Err(FrameReadError::ShortRead(read_rv as usize).into())
+        } else {
+            frame.validate_with(self.length_policy).map_err(io::Error::from)
+        };
+
+        if let Err(err) = result {
+            telemetry::io_error(self.fd, &err);
+            return Err(err);
         }
 
-        Ok(frame)
+        telemetry::frame_received(self.fd, &frame);
+        Ok((frame, msg.msg_flags, msg.msg_controllen as usize))
     }
 
     /// Blocking read a single can frame with timestamp
     ///
     /// Note that reading a frame and retrieving the timestamp requires two
     /// consecutive syscalls. To avoid race conditions, exclusive access
-    /// to the socket is enforce through requiring a `mut &self`.
+    /// to the socket is enforce through requiring a `mut &self`. See
+    /// `read_with_timestamp` for a single syscall and hardware timestamps.
     pub fn read_frame_with_timestamp(&mut self) -> io::Result<(CanFrame, time::SystemTime)> {
         let frame = self.read_frame()?;
 
@@ -424,9 +783,12 @@ impl CanSocket {
         };
 
         if write_rv as usize != size_of::<CanFrame>() {
-            return Err(io::Error::last_os_error());
+            let err = io::Error::last_os_error();
+            telemetry::io_error(self.fd, &err);
+            return Err(err);
         }
 
+        telemetry::frame_sent(self.fd, frame);
         Ok(())
     }
 
@@ -453,7 +815,33 @@ impl CanSocket {
     /// See `CanFilter` for details on how filtering works. By default, all
     /// single filter matching all incoming frames is installed.
     pub fn set_filters(&self, filters: &[CanFilter]) -> io::Result<()> {
-        set_socket_option_mult(self.fd, SOL_CAN_RAW, CAN_RAW_FILTER, filters)
+        set_socket_option_mult(self.fd, SOL_CAN_RAW, CAN_RAW_FILTER, filters)?;
+        telemetry::filters_set(self.fd, filters.len());
+        Ok(())
+    }
+
+    /// Replace the filters and the join setting while frames keep
+    /// arriving.
+    ///
+    /// The kernel installs new filters before removing the old ones, so
+    /// frames passing both the old and the new filters are never dropped.
+    /// The join setting is changed in a separate step, ordered so the
+    /// configuration in between is wider than both: join is disabled
+    /// before the filters are replaced and enabled after. Frames passing
+    /// only the old or only the new configuration may or may not be
+    /// received while the update is in progress.
+    pub fn update_filters(&self, filters: &[CanFilter], join: bool) -> io::Result<()> {
+        let joined: c_int = get_socket_option(self.fd, SOL_CAN_RAW, CAN_RAW_JOIN_FILTERS)?;
+        if joined != 0 && !join {
+            self.set_join_filters(false)?;
+        }
+
+        self.set_filters(filters)?;
+
+        if joined == 0 && join {
+            self.set_join_filters(true)?;
+        }
+        Ok(())
     }
 
     /// Sets the error mask on the socket.
@@ -464,14 +852,25 @@ impl CanSocket {
     /// socket to receive notification about the specified conditions.
     #[inline]
     pub fn set_error_mask(&self, mask: u32) -> io::Result<()> {
-        set_socket_option(self.fd, SOL_CAN_RAW, CAN_RAW_ERR_FILTER, &mask)
+        set_socket_option(self.fd, SOL_CAN_RAW, CAN_RAW_ERR_FILTER, &mask)?;
+        telemetry::error_mask_set(self.fd, mask);
+        Ok(())
+    }
+
+    /// Build an error mask from error classes, to be installed with
+    /// `apply`.
+    pub fn error_filter(&self) -> filter::ErrorFilter {
+        filter::ErrorFilter::new(self)
     }
 
     /// Enable or disable loopback.
     ///
     /// By default, loopback is enabled, causing other applications that open
     /// the same CAN bus to see frames emitted by different applications on
-    /// the same system.
+    /// the same system. Disabling it on a socket keeps its frames from all
+    /// other sockets of the system, e.g. to stop several writers of one
+    /// process from reading each other's traffic. Frames are still sent on
+    /// the bus.
     #[inline]
     pub fn set_loopback(&self, enabled: bool) -> io::Result<()> {
         let loopback: c_int = if enabled { 1 } else { 0 };
@@ -506,7 +905,10 @@ impl AsRawFd for CanSocket {
 
 impl FromRawFd for CanSocket {
     unsafe fn from_raw_fd(fd: RawFd) -> CanSocket {
-        CanSocket { fd: fd }
+        CanSocket {
+            fd: fd,
+            length_policy: LengthPolicy::default(),
+        }
     }
 }
 
@@ -522,6 +924,31 @@ impl Drop for CanSocket {
     }
 }
 
+/// Origin of a received frame
+///
+/// Frames sent on the same host are looped back to other sockets on the
+/// interface, and to the sending socket if `set_recv_own_msgs` is enabled.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct RecvMeta {
+    own: bool,
+    local: bool,
+}
+
+impl RecvMeta {
+    /// Whether the frame was sent by the receiving socket itself
+    #[inline]
+    pub fn is_own_frame(&self) -> bool {
+        self.own
+    }
+
+    /// Whether the frame was sent by any socket on this host, rather than
+    /// received from the bus
+    #[inline]
+    pub fn is_local(&self) -> bool {
+        self.local
+    }
+}
+
 /// CanFrame
 ///
 /// Uses the same memory layout as the underlying kernel struct for performance
@@ -541,7 +968,7 @@ pub struct CanFrame {
     /// reserved
     _res0: u8,
 
-    /// reserved
+    /// DLC of 9..15 for frames carrying 8 bytes, 0 otherwise
     _res1: u8,
 
     /// buffer for data
@@ -550,8 +977,6 @@ pub struct CanFrame {
 
 impl CanFrame {
     pub fn new(id: u32, data: &[u8], rtr: bool, err: bool) -> Result<CanFrame, ConstructionError> {
-        let mut _id = id;
-
         if data.len() > 8 {
             return Err(ConstructionError::TooMuchData);
         }
@@ -560,6 +985,20 @@ impl CanFrame {
             return Err(ConstructionError::IDTooLarge);
         }
 
+        Ok(CanFrame::new_const(id, data, rtr, err))
+    }
+
+    /// Create a frame in a const context, e.g. for static frame tables.
+    ///
+    /// Like `new`, but panics instead of returning an error. In a `const`
+    /// or `static` item, that makes an invalid ID or payload a compile
+    /// error; see also the `can_frame!` macro.
+    pub const fn new_const(id: u32, data: &[u8], rtr: bool, err: bool) -> CanFrame {
+        assert!(data.len() <= 8, "too much data for a CAN frame");
+        assert!(id <= EFF_MASK, "CAN ID too large");
+
+        let mut _id = id;
+
         // set EFF_FLAG on large message
         if id > SFF_MASK {
             _id |= EFF_FLAG;
@@ -576,19 +1015,21 @@ impl CanFrame {
 
         let mut full_data = [0; 8];
 
-        // not cool =/
-        for (n, c) in data.iter().enumerate() {
-            full_data[n] = *c;
+        // no iterators in const fn
+        let mut n = 0;
+        while n < data.len() {
+            full_data[n] = data[n];
+            n += 1;
         }
 
-        Ok(CanFrame {
-               _id: _id,
-               _data_len: data.len() as u8,
-               _pad: 0,
-               _res0: 0,
-               _res1: 0,
-               _data: full_data,
-           })
+        CanFrame {
+            _id: _id,
+            _data_len: data.len() as u8,
+            _pad: 0,
+            _res0: 0,
+            _res1: 0,
+            _data: full_data,
+        }
     }
 
     /// Return the actual CAN ID (without EFF/RTR/ERR flags)
@@ -625,10 +1066,128 @@ impl CanFrame {
         self._id & RTR_FLAG != 0
     }
 
+    /// Flags set in the frame's raw ID
+    #[inline]
+    pub fn flags(&self) -> CanIdFlags {
+        CanIdFlags::from_id(self._id)
+    }
+
     /// A slice into the actual data. Slice will always be <= 8 bytes in length
     #[inline]
     pub fn data(&self) -> &[u8] {
-        &self._data[..(self._data_len as usize)]
+        &self._data[..cmp::min(self._data_len as usize, self._data.len())]
+    }
+
+    /// Number of data bytes, at most 8
+    ///
+    /// For remote frames, the number of bytes requested. Unlike `dlc`, this
+    /// is always the length of `data`.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.data().len()
+    }
+
+    /// Check if the frame carries no data
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Data length code as sent on the bus
+    ///
+    /// Equal to `len`, except for frames carrying 8 bytes sent with a DLC
+    /// of 9 to 15, see `len8_dlc`.
+    #[inline]
+    pub fn dlc(&self) -> u8 {
+        self.len8_dlc().unwrap_or(self.len() as u8)
+    }
+
+    /// Raw DLC of a frame carrying 8 bytes, if it was sent with a DLC of
+    /// 9 to 15
+    #[inline]
+    pub fn len8_dlc(&self) -> Option<u8> {
+        if self._res1 > 8 && self._res1 <= 15 { Some(self._res1) } else { None }
+    }
+
+    /// Check if the frame was received with a length above 15 and cut to
+    /// 8 bytes, see `LengthPolicy::Truncate`
+    #[inline]
+    pub fn is_truncated(&self) -> bool {
+        self._res1 > 15
+    }
+
+    /// The frame as the kernel's `struct can_frame`, in host byte order
+    ///
+    /// Lets frames be moved between processes or over tunnels without
+    /// converting them field by field; `from_bytes` reverses it.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        let ptr = self as *const CanFrame as *const u8;
+        unsafe { slice::from_raw_parts(ptr, size_of::<CanFrame>()) }
+    }
+
+    /// Read a frame from the kernel's `struct can_frame`, as returned by
+    /// `as_bytes`.
+    ///
+    /// Fails unless `bytes` holds exactly one frame with a valid length.
+    pub fn from_bytes(bytes: &[u8]) -> Result<CanFrame, FrameReadError> {
+        if bytes.len() != size_of::<CanFrame>() {
+            return Err(FrameReadError::ShortRead(bytes.len()));
+        }
+
+        let mut frame = CanFrame {
+            _id: 0,
+            _data_len: 0,
+            _pad: 0,
+            _res0: 0,
+            _res1: 0,
+            _data: [0; 8],
+        };
+        unsafe {
+            ptr::copy_nonoverlapping(bytes.as_ptr(),
+                                     &mut frame as *mut CanFrame as *mut u8,
+                                     size_of::<CanFrame>());
+        }
+        frame.validate()?;
+        Ok(frame)
+    }
+
+    /// Check a frame received from the kernel.
+    ///
+    /// Lengths of 9 to 15 are DLCs of frames carrying 8 bytes; they are
+    /// clamped to 8 and kept in `len8_dlc`.
+    fn validate(&mut self) -> Result<(), FrameReadError> {
+        self.validate_with(LengthPolicy::default())
+    }
+
+    /// Check a frame received from the kernel, handling lengths above 8
+    /// according to `policy`.
+    ///
+    /// Clamped lengths are kept in `_res1`, which holds `len8_dlc`.
+    fn validate_with(&mut self, policy: LengthPolicy) -> Result<(), FrameReadError> {
+        let limit = match policy {
+            LengthPolicy::Strict => 8,
+            LengthPolicy::Len8Dlc => 15,
+            LengthPolicy::Truncate => u8::max_value(),
+        };
+        if self._data_len > limit {
+            return Err(FrameReadError::InvalidLength(self._data_len));
+        }
+
+        if self._data_len > 8 {
+            self._res1 = self._data_len;
+            self._data_len = 8;
+        }
+        Ok(())
+    }
+
+    /// ID and payload, with the bytes past the length zeroed, for telling
+    /// frames apart by content.
+    fn content_key(&self) -> (u32, u8, [u8; 8]) {
+        let data = self.data();
+        let mut key = [0; 8];
+        key[..data.len()].copy_from_slice(data);
+        (self._id, data.len() as u8, key)
     }
 
     /// Read error from message and transform it into a `CanError`.
@@ -660,7 +1219,7 @@ impl fmt::UpperHex for CanFrame {
 ///
 /// Contains an internal id and mask. Packets are considered to be matched by
 /// a filter if `received_id & mask == filter_id & mask` holds true.
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 #[repr(C)]
 pub struct CanFilter {
     _id: u32,
@@ -675,4 +1234,17 @@ impl CanFilter {
                _mask: mask,
            })
     }
+
+    /// Construct a filter that also requires the frame's EFF and RTR flags
+    /// to be exactly `flags`.
+    ///
+    /// E.g. `CanFilter::with_flags(0, 0, CanIdFlags::EFF)` accepts all
+    /// extended data frames.
+    pub fn with_flags(id: u32,
+                      mask: u32,
+                      flags: CanIdFlags)
+                      -> Result<CanFilter, ConstructionError> {
+        let compared = CanIdFlags::EFF | CanIdFlags::RTR;
+        CanFilter::new((flags & compared).with_id(id), compared.with_id(mask))
+    }
 }
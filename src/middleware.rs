@@ -0,0 +1,308 @@
+//! Interceptor chains for received and transmitted frames
+//!
+//! `Middleware` wraps any `CanTransport` and passes every frame through a
+//! chain of interceptors before it is returned by `read_frame` or handed
+//! to the wrapped transport by `write_frame`. Interceptors can log, modify
+//! (in place or by replacing) or drop frames:
+//!
+//! ```
+//! use socketcan::CanFrame;
+//! use socketcan::middleware::{Action, Middleware};
+//! use socketcan::testing::MockBus;
+//!
+//! let bus = MockBus::new();
+//! let socket = Middleware::new(bus.endpoint())
+//!     .on_receive(|frame: &mut CanFrame| {
+//!         if frame.id() == 0x7df { Action::Drop } else { Action::Pass }
+//!     })
+//!     .on_transmit(|frame: &mut CanFrame| {
+//!         println!("tx {:03X}", frame.id());
+//!         Action::Pass
+//!     });
+//! ```
+//!
+//! Ready-made interceptors:
+//!
+//! * `Dedup` suppresses duplicates, e.g. on buses where gateways forward
+//!   the same traffic twice.
+
+use std::{cmp, fmt, io};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use {CanFrame, CanTransport};
+
+/// Verdict of an interceptor
+#[derive(Debug, Copy, Clone)]
+pub enum Action {
+    /// Hand the (possibly modified) frame to the next interceptor
+    Pass,
+
+    /// Discard the frame; later interceptors do not see it
+    Drop,
+
+    /// Continue with the given frame instead
+    Replace(CanFrame),
+}
+
+/// A stage of an interceptor chain
+///
+/// Implemented for all closures taking a `&mut CanFrame` and returning an
+/// `Action`.
+pub trait Interceptor {
+    fn intercept(&mut self, frame: &mut CanFrame) -> Action;
+}
+
+impl<F: FnMut(&mut CanFrame) -> Action> Interceptor for F {
+    fn intercept(&mut self, frame: &mut CanFrame) -> Action {
+        self(frame)
+    }
+}
+
+/// An ordered list of interceptors
+#[derive(Default)]
+pub struct Chain {
+    stages: Vec<Box<Interceptor + Send>>,
+}
+
+impl fmt::Debug for Chain {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Chain {{ {} stages }}", self.stages.len())
+    }
+}
+
+impl Chain {
+    pub fn new() -> Chain {
+        Chain::default()
+    }
+
+    /// Append `interceptor` to the end of the chain.
+    pub fn push<I: Interceptor + Send + 'static>(&mut self, interceptor: I) {
+        self.stages.push(Box::new(interceptor));
+    }
+
+    pub fn len(&self) -> usize {
+        self.stages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// Run `frame` through all interceptors.
+    ///
+    /// Returns the resulting frame, or `None` if it was dropped.
+    pub fn apply(&mut self, mut frame: CanFrame) -> Option<CanFrame> {
+        for stage in &mut self.stages {
+            match stage.intercept(&mut frame) {
+                Action::Pass => {}
+                Action::Drop => return None,
+                Action::Replace(replacement) => frame = replacement,
+            }
+        }
+        Some(frame)
+    }
+}
+
+/// A transport with interceptor chains on its receive and transmit paths
+#[derive(Debug)]
+pub struct Middleware<T> {
+    inner: T,
+    rx: Mutex<Chain>,
+    tx: Mutex<Chain>,
+}
+
+impl<T: CanTransport> Middleware<T> {
+    /// Wrap `inner` with empty chains.
+    pub fn new(inner: T) -> Middleware<T> {
+        Middleware {
+            inner: inner,
+            rx: Mutex::new(Chain::new()),
+            tx: Mutex::new(Chain::new()),
+        }
+    }
+
+    /// Append an interceptor to the receive chain.
+    pub fn on_receive<I: Interceptor + Send + 'static>(self, interceptor: I) -> Middleware<T> {
+        self.rx.lock().expect("rx chain lock poisoned").push(interceptor);
+        self
+    }
+
+    /// Append an interceptor to the transmit chain.
+    pub fn on_transmit<I: Interceptor + Send + 'static>(self, interceptor: I) -> Middleware<T> {
+        self.tx.lock().expect("tx chain lock poisoned").push(interceptor);
+        self
+    }
+
+    /// The wrapped transport, bypassing the chains
+    pub fn inner(&self) -> &T {
+        &self.inner
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+
+    /// Read frames until one passes the receive chain.
+    pub fn read_frame(&self) -> io::Result<CanFrame> {
+        loop {
+            let frame = self.inner.read_frame()?;
+            if let Some(frame) = self.rx.lock().expect("rx chain lock poisoned").apply(frame) {
+                return Ok(frame);
+            }
+        }
+    }
+
+    /// Write a frame after passing it through the transmit chain.
+    ///
+    /// Dropped frames are not written, but reported as success.
+    pub fn write_frame(&self, frame: &CanFrame) -> io::Result<()> {
+        match self.tx.lock().expect("tx chain lock poisoned").apply(*frame) {
+            Some(frame) => self.inner.write_frame(&frame),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<T: CanTransport> CanTransport for Middleware<T> {
+    fn read_frame(&self) -> io::Result<CanFrame> {
+        Middleware::read_frame(self)
+    }
+
+    fn write_frame(&self, frame: &CanFrame) -> io::Result<()> {
+        Middleware::write_frame(self, frame)
+    }
+
+    fn set_read_timeout(&self, duration: Duration) -> io::Result<()> {
+        self.inner.set_read_timeout(duration)
+    }
+}
+
+/// Suppresses frames identical in ID and payload to one passed less than
+/// `window` ago
+#[derive(Debug)]
+pub struct Dedup {
+    window: Duration,
+    passed: HashMap<(u32, u8, [u8; 8]), Instant>,
+    prune_at: usize,
+}
+
+impl Dedup {
+    pub fn new(window: Duration) -> Dedup {
+        Dedup {
+            window: window,
+            passed: HashMap::new(),
+            prune_at: 64,
+        }
+    }
+
+    /// Check `frame` received at `now`, returning whether it is a
+    /// duplicate.
+    pub fn is_duplicate(&mut self, frame: &CanFrame, now: Instant) -> bool {
+        let key = frame.content_key();
+        let window = self.window;
+
+        if let Some(&passed) = self.passed.get(&key) {
+            if now.duration_since(passed) < window {
+                return true;
+            }
+        }
+        self.passed.insert(key, now);
+
+        // forget frames that can no longer cause a suppression
+        if self.passed.len() >= self.prune_at {
+            self.passed.retain(|_, passed| now.duration_since(*passed) < window);
+            self.prune_at = cmp::max(self.passed.len() * 2, 64);
+        }
+
+        false
+    }
+}
+
+impl Interceptor for Dedup {
+    fn intercept(&mut self, frame: &mut CanFrame) -> Action {
+        if self.is_duplicate(frame, Instant::now()) { Action::Drop } else { Action::Pass }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use CanFrame;
+    use testing::MockBus;
+    use super::{Action, Dedup, Middleware};
+
+    #[test]
+    fn test_chains() {
+        let bus = MockBus::new();
+        let peer = bus.endpoint();
+        let log = Arc::new(Mutex::new(Vec::new()));
+
+        let rx_log = log.clone();
+        let socket = Middleware::new(bus.endpoint())
+            .on_receive(|frame: &mut CanFrame| if frame.id() == 0x100 {
+                            Action::Drop
+                        } else {
+                            Action::Pass
+                        })
+            .on_receive(move |frame: &mut CanFrame| {
+                            rx_log.lock().unwrap().push(frame.id());
+                            Action::Pass
+                        })
+            .on_transmit(|frame: &mut CanFrame| {
+                             frame._data[0] ^= 0xff;
+                             Action::Pass
+                         })
+            .on_transmit(|frame: &mut CanFrame| if frame.id() == 0x300 {
+                             Action::Replace(CanFrame::new(0x301, &[], false, false).unwrap())
+                         } else {
+                             Action::Pass
+                         });
+
+        peer.write_frame(&CanFrame::new(0x100, &[], false, false).unwrap()).unwrap();
+        peer.write_frame(&CanFrame::new(0x200, &[], false, false).unwrap()).unwrap();
+        assert_eq!(socket.read_frame().unwrap().id(), 0x200);
+        assert_eq!(*log.lock().unwrap(), vec![0x200]);
+
+        socket.write_frame(&CanFrame::new(0x123, &[0x0f], false, false).unwrap()).unwrap();
+        assert_eq!(peer.read_frame().unwrap().data(), &[0xf0]);
+
+        socket.write_frame(&CanFrame::new(0x300, &[1], false, false).unwrap()).unwrap();
+        assert_eq!(peer.read_frame().unwrap().id(), 0x301);
+    }
+
+    #[test]
+    fn test_dedup() {
+        let mut dedup = Dedup::new(Duration::from_millis(10));
+        let a = CanFrame::new(0x100, &[1, 2], false, false).unwrap();
+        let b = CanFrame::new(0x100, &[1, 3], false, false).unwrap();
+        let t0 = Instant::now();
+
+        assert!(!dedup.is_duplicate(&a, t0));
+        assert!(!dedup.is_duplicate(&b, t0));
+        assert!(dedup.is_duplicate(&a, t0 + Duration::from_millis(5)));
+        assert!(dedup.is_duplicate(&a, t0 + Duration::from_millis(9)));
+        assert!(!dedup.is_duplicate(&a, t0 + Duration::from_millis(10)));
+
+        for i in 0..100 {
+            let frame = CanFrame::new(i, &[], false, false).unwrap();
+            assert!(!dedup.is_duplicate(&frame, t0 + Duration::from_millis(100)));
+        }
+        assert!(dedup.passed.len() <= 101);
+        assert!(dedup.is_duplicate(&CanFrame::new(99, &[], false, false).unwrap(),
+                                   t0 + Duration::from_millis(101)));
+    }
+
+    #[test]
+    fn test_dedup_ignores_stale_bytes() {
+        let mut dedup = Dedup::new(Duration::from_millis(10));
+        let a = CanFrame::new(0x100, &[1, 2], false, false).unwrap();
+        let mut b = a;
+        b._data[5] = 0xff;
+        let t0 = Instant::now();
+
+        assert!(!dedup.is_duplicate(&a, t0));
+        assert!(dedup.is_duplicate(&b, t0));
+    }
+}
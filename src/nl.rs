@@ -16,16 +16,41 @@
 //! functionality might be required.
 
 use byte_conv::As as AsBytes;
-use libc::{self, c_char, c_ushort, c_int, c_uint};
+use libc::{self, c_char, c_ushort, c_int, c_uint, c_void};
 use netlink_rs::socket::{Msg as NetlinkMessage, Socket as NetlinkSocket, NetlinkAddr,
                          Payload as NetlinkPayload, NlMsgHeader};
 use netlink_rs::Protocol as NetlinkProtocol;
 use nix;
 use nix::net::if_::if_nametoindex;
-use std::{mem, io};
+use std::{cmp, fs, mem, io, ptr};
+use util::glob_match;
 
 // linux/rtnetlink.h
 const RTM_NEWLINK: u16 = 16;
+const RTM_DELLINK: u16 = 17;
+const RTM_GETLINK: u16 = 18;
+
+// linux/netlink.h
+const NLMSG_HDR_LEN: usize = 16;
+const NLMSG_ERROR: u16 = 2;
+const NLM_F_REQUEST: u16 = 0x001;
+const NLM_F_ACK: u16 = 0x004;
+const NLM_F_EXCL: u16 = 0x200;
+const NLM_F_CREATE: u16 = 0x400;
+
+// linux/if_link.h
+const IFLA_IFNAME: u16 = 3;
+const IFLA_STATS: u16 = 7;
+const IFLA_LINKINFO: u16 = 18;
+const IFLA_STATS64: u16 = 23;
+const IFLA_INFO_KIND: u16 = 1;
+const IFLA_INFO_XSTATS: u16 = 3;
+
+// linux/netlink.h, flags in the attribute type
+const NLA_TYPE_MASK: u16 = 0x3fff;
+
+// linux/if.h
+const IFNAMSIZ: usize = 16;
 
 // linux/socket.h
 const AF_UNSPEC: c_char = 0;
@@ -121,6 +146,159 @@ fn send_and_read_ack(sock: &mut NetlinkSocket,
 
 }
 
+/// Appends a route attribute to `buf`, padded to a multiple of four bytes.
+fn push_attr(buf: &mut Vec<u8>, kind: u16, payload: &[u8]) {
+    let len = 4 + payload.len();
+    buf.extend_from_slice((len as u16).as_bytes());
+    buf.extend_from_slice(kind.as_bytes());
+    buf.extend_from_slice(payload);
+    buf.resize((buf.len() + 3) & !3, 0);
+}
+
+/// Assembles a route netlink message with the given link attributes.
+fn rtnl_message(msg_type: u16, flags: u16, info: IfInfoMsg, attrs: &[u8]) -> Vec<u8> {
+    let len = NLMSG_HDR_LEN + mem::size_of::<IfInfoMsg>() + attrs.len();
+    let mut msg = Vec::with_capacity(len);
+    msg.extend_from_slice((len as u32).as_bytes());
+    msg.extend_from_slice(msg_type.as_bytes());
+    msg.extend_from_slice((flags | NLM_F_REQUEST).as_bytes());
+    msg.extend_from_slice(1u32.as_bytes()); // sequence number
+    msg.extend_from_slice(0u32.as_bytes()); // port ID, assigned by the kernel
+    msg.extend_from_slice(info.as_bytes());
+    msg.extend_from_slice(attrs);
+    msg
+}
+
+/// Runs `f` on a plain route netlink socket.
+fn with_rtnl_socket<T, F: FnOnce(c_int) -> io::Result<T>>(f: F) -> io::Result<T> {
+    let fd = unsafe {
+        libc::socket(libc::AF_NETLINK,
+                     libc::SOCK_RAW | libc::SOCK_CLOEXEC,
+                     libc::NETLINK_ROUTE)
+    };
+    if fd == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let result = f(fd);
+    unsafe { libc::close(fd) };
+    result
+}
+
+/// Sends a route netlink request with the given link attributes and waits
+/// for the ACK.
+///
+/// netlink-rs offers no way to set the `NLM_F_CREATE` and `NLM_F_EXCL`
+/// flags, so the message is assembled and sent through a plain socket.
+fn rtnl_request(msg_type: u16, flags: u16, info: IfInfoMsg, attrs: &[u8]) -> io::Result<()> {
+    let msg = rtnl_message(msg_type, flags | NLM_F_ACK, info, attrs);
+    with_rtnl_socket(|fd| rtnl_transact(fd, &msg))
+}
+
+/// Requests the link attributes of interface `if_index`.
+fn rtnl_get_link(if_index: c_uint) -> io::Result<Vec<u8>> {
+    let msg = rtnl_message(RTM_GETLINK, 0, IfInfoMsg::new(if_index as i32, 0, 0), &[]);
+    with_rtnl_socket(|fd| {
+        rtnl_send(fd, &msg)?;
+
+        let mut buf = vec![0u8; 16384];
+        let n = unsafe { libc::recv(fd, buf.as_mut_ptr() as *mut c_void, buf.len(), 0) };
+        if n == -1 {
+            return Err(io::Error::last_os_error());
+        }
+
+        let n = n as usize;
+        let header = NLMSG_HDR_LEN + mem::size_of::<IfInfoMsg>();
+        let msg_type = unsafe { ptr::read_unaligned(buf[4..].as_ptr() as *const u16) };
+        if n >= NLMSG_HDR_LEN + 4 && msg_type == NLMSG_ERROR {
+            let errno = unsafe { ptr::read_unaligned(buf[NLMSG_HDR_LEN..].as_ptr() as *const i32) };
+            return Err(io::Error::from_raw_os_error(-errno));
+        }
+        if n < header || msg_type != RTM_NEWLINK {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "link information expected"));
+        }
+
+        let len = unsafe { ptr::read_unaligned(buf.as_ptr() as *const u32) } as usize;
+        buf.truncate(cmp::min(len, n));
+        Ok(buf.split_off(header))
+    })
+}
+
+/// Splits route attributes into their types and payloads.
+fn parse_attrs(mut buf: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut attrs = Vec::new();
+    while buf.len() >= 4 {
+        let len = unsafe { ptr::read_unaligned(buf.as_ptr() as *const u16) } as usize;
+        let kind = unsafe { ptr::read_unaligned(buf[2..].as_ptr() as *const u16) };
+        if len < 4 || len > buf.len() {
+            break;
+        }
+        attrs.push((kind & NLA_TYPE_MASK, &buf[4..len]));
+        buf = &buf[cmp::min((len + 3) & !3, buf.len())..];
+    }
+    attrs
+}
+
+fn rtnl_send(fd: c_int, msg: &[u8]) -> io::Result<()> {
+    let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+    addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+
+    let sent = unsafe {
+        libc::sendto(fd,
+                     msg.as_ptr() as *const c_void,
+                     msg.len(),
+                     0,
+                     &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                     mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t)
+    };
+    if sent == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+fn rtnl_transact(fd: c_int, msg: &[u8]) -> io::Result<()> {
+    rtnl_send(fd, msg)?;
+
+    let mut buf = [0u8; 4096];
+    let n = unsafe { libc::recv(fd, buf.as_mut_ptr() as *mut c_void, buf.len(), 0) };
+    if n == -1 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // an ACK is an error message with an error code of 0
+    let msg_type = unsafe { ptr::read_unaligned(buf[4..].as_ptr() as *const u16) };
+    if (n as usize) < NLMSG_HDR_LEN + 4 || msg_type != NLMSG_ERROR {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "ACK expected"));
+    }
+
+    let errno = unsafe { ptr::read_unaligned(buf[NLMSG_HDR_LEN..].as_ptr() as *const i32) };
+    if errno != 0 {
+        return Err(io::Error::from_raw_os_error(-errno));
+    }
+
+    Ok(())
+}
+
+/// Network interfaces in sysfs
+const SYSFS_NET: &'static str = "/sys/class/net";
+
+/// Hardware type of CAN interfaces (linux/if_arp.h)
+const ARPHRD_CAN: &'static str = "280";
+
+/// Read a sysfs attribute of network interface `ifname`
+fn sysfs_attr(ifname: &str, attr: &str) -> io::Result<String> {
+    fs::read_to_string(format!("{}/{}/{}", SYSFS_NET, ifname, attr))
+        .map(|s| s.trim().to_owned())
+}
+
+fn is_up(ifname: &str) -> io::Result<bool> {
+    let flags = sysfs_attr(ifname, "flags")?;
+    let flags = c_uint::from_str_radix(flags.trim_start_matches("0x"), 16)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "invalid interface flags"))?;
+    Ok(flags & IFF_UP != 0)
+}
+
 /// Opens a new netlink socket, bound to this process' PID
 fn open_nl_route_socket() -> io::Result<NetlinkSocket> {
     let sock = NetlinkSocket::new(NetlinkProtocol::Route)?;
@@ -136,10 +314,97 @@ fn open_nl_route_socket() -> io::Result<NetlinkSocket> {
     Ok(sock)
 }
 
+/// Traffic counters of a CAN interface
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CanStats {
+    pub rx_packets: u64,
+    pub tx_packets: u64,
+    pub rx_bytes: u64,
+    pub tx_bytes: u64,
+    pub rx_errors: u64,
+    pub tx_errors: u64,
+    pub rx_dropped: u64,
+    pub tx_dropped: u64,
+
+    /// Counters of the CAN controller, `None` for interfaces without a
+    /// controller such as `vcan`
+    pub device: Option<CanDeviceStats>,
+}
+
+/// Counters of a CAN controller, mirrors `struct can_device_stats`
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CanDeviceStats {
+    /// Bus errors
+    pub bus_error: u32,
+    /// Changes to error warning state
+    pub error_warning: u32,
+    /// Changes to error passive state
+    pub error_passive: u32,
+    /// Changes to bus off state
+    pub bus_off: u32,
+    /// Arbitration lost errors
+    pub arbitration_lost: u32,
+    /// Restarts of the controller
+    pub restarts: u32,
+}
+
+/// Reads the `n`th counter of `size` bytes from `buf`.
+fn counter(buf: &[u8], n: usize, size: usize) -> u64 {
+    match buf.get(n * size..(n + 1) * size) {
+        Some(bytes) => bytes.iter().rev().fold(0, |acc, &b| acc << 8 | b as u64),
+        None => 0,
+    }
+}
+
+/// Extracts the counters from the link attributes of a CAN interface.
+fn parse_stats(attrs: &[u8]) -> CanStats {
+    let mut stats = CanStats::default();
+    let mut stats64 = false;
+
+    for (kind, payload) in parse_attrs(attrs) {
+        // the 64 bit counters are preferred, the 32 bit ones may wrap
+        let size = match kind {
+            IFLA_STATS64 => 8,
+            IFLA_STATS if !stats64 => 4,
+            IFLA_LINKINFO => {
+                for (kind, payload) in parse_attrs(payload) {
+                    if kind == IFLA_INFO_XSTATS {
+                        let c = |n| counter(payload, n, 4) as u32;
+                        stats.device = Some(CanDeviceStats {
+                            bus_error: c(0),
+                            error_warning: c(1),
+                            error_passive: c(2),
+                            bus_off: c(3),
+                            arbitration_lost: c(4),
+                            restarts: c(5),
+                        });
+                    }
+                }
+                continue;
+            }
+            _ => continue,
+        };
+
+        stats64 = size == 8;
+        let c = |n| counter(payload, n, size);
+        stats.rx_packets = c(0);
+        stats.tx_packets = c(1);
+        stats.rx_bytes = c(2);
+        stats.tx_bytes = c(3);
+        stats.rx_errors = c(4);
+        stats.tx_errors = c(5);
+        stats.rx_dropped = c(6);
+        stats.tx_dropped = c(7);
+    }
+
+    stats
+}
+
 /// SocketCAN interface
 ///
 /// Controlled through the kernel's netlink interface, CAN devices can be
 /// brought up or down or configured through this.
+#[derive(Debug)]
 pub struct CanInterface {
     if_index: c_uint,
 }
@@ -161,6 +426,72 @@ impl CanInterface {
         CanInterface { if_index: if_index }
     }
 
+    /// Names of all CAN interfaces, sorted
+    pub fn list() -> io::Result<Vec<String>> {
+        let mut names = Vec::new();
+        for entry in fs::read_dir(SYSFS_NET)? {
+            let name = entry?.file_name().to_string_lossy().into_owned();
+            // interfaces may disappear while listing
+            if sysfs_attr(&name, "type").ok().map_or(false, |t| t == ARPHRD_CAN) {
+                names.push(name);
+            }
+        }
+        names.sort();
+        Ok(names)
+    }
+
+    /// Names of the CAN interfaces that are up and match `pattern`, sorted
+    ///
+    /// The pattern may contain the wildcards `*` and `?`, e.g. `"*can*"`.
+    pub fn list_matching(pattern: &str) -> io::Result<Vec<String>> {
+        Ok(CanInterface::list()?
+            .into_iter()
+            .filter(|name| glob_match(pattern, name) && is_up(name).unwrap_or(false))
+            .collect())
+    }
+
+    /// Current traffic and controller counters of the interface
+    pub fn stats(&self) -> io::Result<CanStats> {
+        Ok(parse_stats(&rtnl_get_link(self.if_index)?))
+    }
+
+    /// Create a virtual CAN interface
+    ///
+    /// Adds a new `vcan` interface named `ifname`, which starts out down.
+    /// Requires `CAP_NET_ADMIN` and the `vcan` kernel module.
+    pub fn create_vcan(ifname: &str) -> io::Result<CanInterface> {
+        if ifname.is_empty() || ifname.len() >= IFNAMSIZ || ifname.contains('\0') {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "invalid interface name"));
+        }
+
+        let mut name = ifname.as_bytes().to_vec();
+        name.push(0);
+
+        let mut link_info = Vec::new();
+        push_attr(&mut link_info, IFLA_INFO_KIND, b"vcan");
+
+        let mut attrs = Vec::new();
+        push_attr(&mut attrs, IFLA_IFNAME, &name);
+        push_attr(&mut attrs, IFLA_LINKINFO, &link_info);
+
+        rtnl_request(RTM_NEWLINK,
+                     NLM_F_CREATE | NLM_F_EXCL,
+                     IfInfoMsg::new(0, 0, 0),
+                     &attrs)?;
+
+        let if_index = if_nametoindex(ifname)
+            .map_err(|e| io::Error::new(io::ErrorKind::NotFound, format!("{:?}", e)))?;
+        Ok(CanInterface::open_if(if_index))
+    }
+
+    /// Delete CAN interface
+    ///
+    /// Removes the interface from the system, which is only possible for
+    /// virtual interfaces.
+    pub fn delete(self) -> io::Result<()> {
+        rtnl_request(RTM_DELLINK, 0, IfInfoMsg::new(self.if_index as i32, 0, 0), &[])
+    }
+
     /// Bring down CAN interface
     ///
     /// Use a netlink control socket to set the interface status to "down".
@@ -195,3 +526,41 @@ impl CanInterface {
         send_and_read_ack(&mut nl, msg, &NetlinkAddr::new(0, 0))
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::{parse_stats, push_attr, CanDeviceStats, IFLA_INFO_KIND, IFLA_INFO_XSTATS,
+                IFLA_LINKINFO, IFLA_STATS, IFLA_STATS64};
+
+    #[test]
+    fn test_parse_stats() {
+        let stats64: Vec<u8> = (1..24u64).flat_map(|n| (0..8).map(move |i| (n >> (8 * i)) as u8))
+            .collect();
+        let stats32: Vec<u8> = (0..23 * 4).map(|_| 0xff).collect();
+        let xstats: Vec<u8> = (1..7u32).flat_map(|n| (0..4).map(move |i| (n >> (8 * i)) as u8))
+            .collect();
+
+        let mut link_info = Vec::new();
+        push_attr(&mut link_info, IFLA_INFO_KIND, b"can\0");
+        push_attr(&mut link_info, IFLA_INFO_XSTATS, &xstats);
+
+        let mut attrs = Vec::new();
+        push_attr(&mut attrs, IFLA_STATS64, &stats64);
+        push_attr(&mut attrs, IFLA_STATS, &stats32);
+        push_attr(&mut attrs, IFLA_LINKINFO, &link_info);
+
+        let stats = parse_stats(&attrs);
+        assert_eq!((stats.rx_packets, stats.tx_packets, stats.tx_dropped), (1, 2, 8));
+        assert_eq!(stats.device,
+                   Some(CanDeviceStats {
+                       bus_error: 1,
+                       error_warning: 2,
+                       error_passive: 3,
+                       bus_off: 4,
+                       arbitration_lost: 5,
+                       restarts: 6,
+                   }));
+
+        assert_eq!(parse_stats(&attrs[..4 + 23 * 8]).device, None);
+    }
+}
@@ -0,0 +1,178 @@
+//! Preallocated frame buffers
+//!
+//! Gateways and loggers running at full bus rate read frames in batches.
+//! A `FramePool` hands out fixed-size batch buffers that return to the
+//! pool when dropped, so the hot path does not allocate:
+//!
+//! ```
+//! use socketcan::CanFrame;
+//! use socketcan::pool::FramePool;
+//! use socketcan::testing::MockBus;
+//!
+//! let bus = MockBus::new();
+//! let peer = bus.endpoint();
+//! let socket = bus.endpoint();
+//! let pool = FramePool::new(4, 32);
+//!
+//! peer.write_frame(&CanFrame::new(0x123, &[], false, false).unwrap()).unwrap();
+//! peer.write_frame(&CanFrame::new(0x124, &[], false, false).unwrap()).unwrap();
+//!
+//! let mut batch = pool.get().unwrap();
+//! assert_eq!(batch.fill(&socket).unwrap(), 2);
+//! for frame in batch.iter() {
+//!     println!("{:X}", frame);
+//! }
+//! ```
+
+use std::{io, mem};
+use std::ops::{Deref, DerefMut};
+use std::sync::{Arc, Mutex};
+use {CanFrame, CanTransport};
+
+/// A fixed number of equally sized frame buffers
+#[derive(Clone, Debug)]
+pub struct FramePool {
+    free: Arc<Mutex<Vec<Box<[CanFrame]>>>>,
+    batch_size: usize,
+}
+
+impl FramePool {
+    /// Allocate `buffers` buffers of `batch_size` frames each.
+    pub fn new(buffers: usize, batch_size: usize) -> FramePool {
+        let empty = CanFrame {
+            _id: 0,
+            _data_len: 0,
+            _pad: 0,
+            _res0: 0,
+            _res1: 0,
+            _data: [0; 8],
+        };
+
+        FramePool {
+            free: Arc::new(Mutex::new((0..buffers)
+                                          .map(|_| vec![empty; batch_size].into_boxed_slice())
+                                          .collect())),
+            batch_size: batch_size,
+        }
+    }
+
+    /// Take an empty buffer, or `None` if all are in use.
+    pub fn get(&self) -> Option<PooledFrames> {
+        self.free.lock().expect("frame pool lock poisoned").pop().map(|buf| {
+            PooledFrames {
+                buf: buf,
+                len: 0,
+                free: self.free.clone(),
+            }
+        })
+    }
+
+    /// Number of buffers not in use
+    pub fn available(&self) -> usize {
+        self.free.lock().expect("frame pool lock poisoned").len()
+    }
+
+    /// Frames per buffer
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+}
+
+/// A buffer borrowed from a `FramePool`
+///
+/// Dereferences to the frames it holds. Returns to the pool when dropped.
+#[derive(Debug)]
+pub struct PooledFrames {
+    buf: Box<[CanFrame]>,
+    len: usize,
+    free: Arc<Mutex<Vec<Box<[CanFrame]>>>>,
+}
+
+impl PooledFrames {
+    /// Replace the contents with the frames available on `transport`,
+    /// blocking until at least one arrives.
+    ///
+    /// Returns the number of frames read.
+    pub fn fill<T: CanTransport + ?Sized>(&mut self, transport: &T) -> io::Result<usize> {
+        self.len = 0;
+        self.len = transport.read_into(&mut self.buf)?;
+        Ok(self.len)
+    }
+
+    /// Append `frame`, returning it back if the buffer is full.
+    pub fn push(&mut self, frame: CanFrame) -> Result<(), CanFrame> {
+        if self.len == self.buf.len() {
+            return Err(frame);
+        }
+        self.buf[self.len] = frame;
+        self.len += 1;
+        Ok(())
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    /// Maximum number of frames
+    pub fn capacity(&self) -> usize {
+        self.buf.len()
+    }
+}
+
+impl Deref for PooledFrames {
+    type Target = [CanFrame];
+
+    fn deref(&self) -> &[CanFrame] {
+        &self.buf[..self.len]
+    }
+}
+
+impl DerefMut for PooledFrames {
+    fn deref_mut(&mut self) -> &mut [CanFrame] {
+        &mut self.buf[..self.len]
+    }
+}
+
+impl Drop for PooledFrames {
+    fn drop(&mut self) {
+        let buf = mem::replace(&mut self.buf, Box::new([]));
+        self.free.lock().expect("frame pool lock poisoned").push(buf);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use CanFrame;
+    use testing::MockBus;
+    use super::FramePool;
+
+    #[test]
+    fn test_pool() {
+        let bus = MockBus::new();
+        let peer = bus.endpoint();
+        let socket = bus.endpoint();
+        let pool = FramePool::new(2, 3);
+
+        for id in 0..5 {
+            peer.write_frame(&CanFrame::new(id, &[], false, false).unwrap()).unwrap();
+        }
+
+        let mut a = pool.get().unwrap();
+        let mut b = pool.get().unwrap();
+        assert!(pool.get().is_none());
+
+        assert_eq!(a.fill(&socket).unwrap(), 3);
+        assert_eq!(b.fill(&socket).unwrap(), 2);
+        assert_eq!(a.iter().map(|f| f.id()).collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(b.iter().map(|f| f.id()).collect::<Vec<_>>(), vec![3, 4]);
+
+        assert!(b.push(CanFrame::new(5, &[], false, false).unwrap()).is_ok());
+        assert!(b.push(CanFrame::new(6, &[], false, false).unwrap()).is_err());
+
+        drop(a);
+        assert_eq!(pool.available(), 1);
+        let c = pool.get().unwrap();
+        assert!(c.is_empty());
+        assert_eq!(c.capacity(), 3);
+    }
+}
@@ -0,0 +1,287 @@
+//! Dedicated reader threads
+//!
+//! For soft real-time consumers, a reader thread can be pinned to a core
+//! and run with a real-time priority, so frames are taken off the socket
+//! promptly regardless of what the consumer is doing. Frames are handed
+//! over through a `FrameSink`, e.g. a bounded `mpsc` channel or the
+//! lock-free queue of `spsc`.
+//!
+//! ```no_run
+//! use std::sync::mpsc;
+//! use socketcan::CanSocket;
+//! use socketcan::reader::ReaderOptions;
+//!
+//! let (tx, rx) = mpsc::sync_channel(1024);
+//! let options = ReaderOptions::new().cpu(3).rt_priority(50);
+//! let reader = CanSocket::open("can0").unwrap().spawn_reader(options, tx).unwrap();
+//!
+//! for frame in rx.iter().take(100) {
+//!     println!("{:X}", frame);
+//! }
+//! reader.stop().unwrap();
+//! ```
+
+use std::{io, mem, thread};
+use std::mem::size_of;
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::{AtomicBool, Ordering};
+use libc::{c_int, cpu_set_t, sched_param, sched_setaffinity, sched_setscheduler, CPU_SET,
+           CPU_SETSIZE, SCHED_FIFO};
+use {run_read_loop, CanFrame, CanSocket, CanTransport};
+
+/// Receives the frames of a reader thread
+pub trait FrameSink: Send {
+    /// Hand over a frame, blocking if the sink is full.
+    ///
+    /// An error ends the reader thread, e.g. once the consumer is gone.
+    fn send_frame(&mut self, frame: CanFrame) -> io::Result<()>;
+
+    /// Hand over a frame without blocking, returning it if the sink is full.
+    ///
+    /// The reader thread retries a returned frame until it is stopped, so a
+    /// stalled consumer can not keep it from shutting down. The default
+    /// implementation blocks in `send_frame`.
+    fn try_send_frame(&mut self, frame: CanFrame) -> io::Result<Option<CanFrame>> {
+        self.send_frame(frame).map(|()| None)
+    }
+}
+
+fn disconnected() -> io::Error {
+    io::Error::new(io::ErrorKind::BrokenPipe, "frame receiver disconnected")
+}
+
+impl FrameSink for mpsc::Sender<CanFrame> {
+    fn send_frame(&mut self, frame: CanFrame) -> io::Result<()> {
+        self.send(frame).map_err(|_| disconnected())
+    }
+}
+
+impl FrameSink for mpsc::SyncSender<CanFrame> {
+    fn send_frame(&mut self, frame: CanFrame) -> io::Result<()> {
+        self.send(frame).map_err(|_| disconnected())
+    }
+
+    fn try_send_frame(&mut self, frame: CanFrame) -> io::Result<Option<CanFrame>> {
+        match self.try_send(frame) {
+            Ok(()) => Ok(None),
+            Err(mpsc::TrySendError::Full(frame)) => Ok(Some(frame)),
+            Err(mpsc::TrySendError::Disconnected(_)) => Err(disconnected()),
+        }
+    }
+}
+
+/// Scheduling of a reader thread
+///
+/// By default, the thread is scheduled like any other.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ReaderOptions {
+    cpu: Option<usize>,
+    rt_priority: Option<i32>,
+    name: Option<String>,
+}
+
+impl ReaderOptions {
+    /// Options scheduling the thread like any other
+    pub fn new() -> ReaderOptions {
+        ReaderOptions::default()
+    }
+
+    /// Pin the thread to the given core.
+    pub fn cpu(mut self, cpu: usize) -> ReaderOptions {
+        self.cpu = Some(cpu);
+        self
+    }
+
+    /// Run the thread with `SCHED_FIFO` at the given priority, 1 to 99.
+    ///
+    /// Usually requires `CAP_SYS_NICE` or an `RLIMIT_RTPRIO` limit.
+    pub fn rt_priority(mut self, priority: i32) -> ReaderOptions {
+        self.rt_priority = Some(priority);
+        self
+    }
+
+    /// Name the thread, e.g. to find it in `top`.
+    pub fn name<S: Into<String>>(mut self, name: S) -> ReaderOptions {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Apply the scheduling options to the calling thread.
+    fn apply(&self) -> io::Result<()> {
+        if let Some(cpu) = self.cpu {
+            if cpu >= CPU_SETSIZE as usize {
+                return Err(io::Error::new(io::ErrorKind::InvalidInput, "no such cpu"));
+            }
+            let rv = unsafe {
+                let mut set: cpu_set_t = mem::zeroed();
+                CPU_SET(cpu, &mut set);
+                sched_setaffinity(0, size_of::<cpu_set_t>(), &set)
+            };
+            if rv != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+
+        if let Some(priority) = self.rt_priority {
+            let param = sched_param { sched_priority: priority as c_int };
+            if unsafe { sched_setscheduler(0, SCHED_FIFO, &param) } != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Handle of a reader thread
+///
+/// Dropping the handle stops the thread.
+#[derive(Debug)]
+pub struct ReaderThread {
+    shutdown: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<io::Result<u64>>>,
+}
+
+impl ReaderThread {
+    /// Stop reading.
+    ///
+    /// Returns the number of frames read, or the error that ended the
+    /// thread early. Takes up to `SHUTDOWN_POLL_INTERVAL_MS`.
+    pub fn stop(mut self) -> io::Result<u64> {
+        self.shutdown.store(true, Ordering::SeqCst);
+        match self.thread.take() {
+            Some(thread) => thread.join().expect("reader thread panicked"),
+            None => Ok(0),
+        }
+    }
+}
+
+impl Drop for ReaderThread {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Read frames from `transport` on a new thread, passing them to `sink`.
+///
+/// Fails if the thread can not be spawned or the scheduling options can
+/// not be applied. The thread reads until stopped, or until a read or the
+/// sink fails.
+pub fn spawn_reader<T, S>(transport: T,
+                          options: ReaderOptions,
+                          mut sink: S)
+                          -> io::Result<ReaderThread>
+    where T: CanTransport + Send + 'static,
+          S: FrameSink + 'static
+{
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let (started_tx, started_rx) = mpsc::channel();
+
+    let mut builder = thread::Builder::new();
+    if let Some(ref name) = options.name {
+        builder = builder.name(name.clone());
+    }
+
+    let thread = {
+        let shutdown = shutdown.clone();
+        builder.spawn(move || {
+                let applied = options.apply();
+                let failed = applied.is_err();
+                let _ = started_tx.send(applied);
+                if failed {
+                    return Ok(0);
+                }
+                let handle = |mut frame| loop {
+                    frame = match sink.try_send_frame(frame)? {
+                        Some(frame) => frame,
+                        None => return Ok(()),
+                    };
+                    // the frame is dropped if stopped while the sink is full
+                    if shutdown.load(Ordering::Relaxed) {
+                        return Ok(());
+                    }
+                    thread::yield_now();
+                };
+                run_read_loop(&transport, handle, &shutdown)
+            })?
+    };
+
+    match started_rx.recv() {
+        Ok(Ok(())) => {
+            Ok(ReaderThread {
+                   shutdown: shutdown,
+                   thread: Some(thread),
+               })
+        }
+        Ok(Err(e)) => {
+            let _ = thread.join();
+            Err(e)
+        }
+        Err(_) => Err(io::Error::new(io::ErrorKind::Other, "reader thread panicked")),
+    }
+}
+
+impl CanSocket {
+    /// Read frames on a dedicated thread, passing them to `sink`.
+    ///
+    /// See `reader::spawn_reader`.
+    pub fn spawn_reader<S: FrameSink + 'static>(self,
+                                                options: ReaderOptions,
+                                                sink: S)
+                                                -> io::Result<ReaderThread> {
+        spawn_reader(self, options, sink)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io, thread};
+    use std::sync::mpsc;
+    use std::time::Duration;
+    use CanFrame;
+    use testing::MockBus;
+    use super::{spawn_reader, ReaderOptions};
+
+    #[test]
+    fn test_reader_thread() {
+        let bus = MockBus::new();
+        let peer = bus.endpoint();
+        let (tx, rx) = mpsc::sync_channel(2);
+
+        let options = ReaderOptions::new().name("can reader");
+        let reader = spawn_reader(bus.endpoint(), options, tx).unwrap();
+        for id in 0..3 {
+            peer.write_frame(&CanFrame::new(id, &[], false, false).unwrap()).unwrap();
+        }
+        for id in 0..3 {
+            assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap().id(), id);
+        }
+        assert_eq!(reader.stop().unwrap(), 3);
+
+        let (tx, _rx) = mpsc::sync_channel(2);
+        let err = spawn_reader(bus.endpoint(), ReaderOptions::new().cpu(1 << 20), tx)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidInput);
+    }
+
+    #[test]
+    fn test_stop_while_full() {
+        let bus = MockBus::new();
+        let peer = bus.endpoint();
+        let (tx, rx) = mpsc::sync_channel(1);
+
+        let reader = spawn_reader(bus.endpoint(), ReaderOptions::new(), tx).unwrap();
+        for id in 0..3 {
+            peer.write_frame(&CanFrame::new(id, &[], false, false).unwrap()).unwrap();
+        }
+        // the channel fills up again, leaving the reader retrying a frame
+        while rx.try_recv().is_err() {
+            thread::yield_now();
+        }
+        let (done_tx, done_rx) = mpsc::channel();
+        thread::spawn(move || done_tx.send(reader.stop()).unwrap());
+        assert!(done_rx.recv_timeout(Duration::from_secs(5)).unwrap().is_ok());
+    }
+}
@@ -0,0 +1,328 @@
+//! Remote CAN access over TCP
+//!
+//! A `RemoteServer` exposes a local CAN interface (or any other
+//! `CanTransport`) to TCP clients, e.g. to let a development machine use
+//! the bus of a remote vehicle. `RemoteSocket` connects to such a server
+//! and implements `CanTransport` itself.
+//!
+//! Frames travel as length-prefixed messages in both directions. The
+//! length is a big-endian `u16`, followed by the kernel `struct can_frame`
+//! layout with the CAN ID in network byte order:
+//!
+//! ```text
+//! length (u16) | can_id (u32) | can_dlc (u8) | pad | res0 | res1 | data ([u8; 8])
+//! ```
+//!
+//! Every client receives the frames read from the bus as well as those
+//! sent by the other clients.
+
+use std::{io, thread};
+use std::io::{Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use {CanFrame, CanTransport, ShouldRetry};
+
+/// Length of an encoded frame, without the length prefix
+pub const FRAME_LEN: usize = 16;
+
+const PREFIX_LEN: usize = 2;
+
+/// Encode `frame` into a length-prefixed message.
+pub fn encode(frame: &CanFrame) -> [u8; PREFIX_LEN + FRAME_LEN] {
+    let mut msg = [0; PREFIX_LEN + FRAME_LEN];
+    let id = frame._id;
+    msg[..8].copy_from_slice(&[0,
+                               FRAME_LEN as u8,
+                               (id >> 24) as u8,
+                               (id >> 16) as u8,
+                               (id >> 8) as u8,
+                               id as u8,
+                               frame._data_len,
+                               0]);
+    msg[10..].copy_from_slice(&frame._data);
+    msg
+}
+
+/// Decode the body of a message, i.e. without the length prefix.
+pub fn decode(body: &[u8]) -> io::Result<CanFrame> {
+    if body.len() != FRAME_LEN {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("unsupported message length {}", body.len())));
+    }
+
+    let len = body[4] as usize;
+    if len > 8 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                  format!("invalid frame length {}", len)));
+    }
+
+    let mut frame = CanFrame::new(0, &body[8..8 + len], false, false)
+        .expect("length was checked above");
+    frame._id = (body[0] as u32) << 24 | (body[1] as u32) << 16 | (body[2] as u32) << 8 |
+                body[3] as u32;
+    Ok(frame)
+}
+
+/// Reassembles messages from a stream
+///
+/// Partially received messages are kept when a read times out, so the
+/// stream stays in sync.
+#[derive(Debug, Default)]
+struct MessageReader {
+    buf: Vec<u8>,
+}
+
+impl MessageReader {
+    fn read_frame<R: Read>(&mut self, mut stream: R) -> io::Result<CanFrame> {
+        loop {
+            if self.buf.len() >= PREFIX_LEN {
+                let len = (self.buf[0] as usize) << 8 | self.buf[1] as usize;
+                if self.buf.len() >= PREFIX_LEN + len {
+                    let frame = decode(&self.buf[PREFIX_LEN..PREFIX_LEN + len]);
+                    self.buf.drain(..PREFIX_LEN + len);
+                    return frame;
+                }
+            }
+
+            let mut chunk = [0; 512];
+            let n = stream.read(&mut chunk)?;
+            if n == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "connection closed"));
+            }
+            self.buf.extend_from_slice(&chunk[..n]);
+        }
+    }
+}
+
+/// Connection to a `RemoteServer`
+#[derive(Debug)]
+pub struct RemoteSocket {
+    stream: TcpStream,
+    reader: Mutex<MessageReader>,
+    writer: Mutex<()>,
+}
+
+impl RemoteSocket {
+    /// Connect to the server at `addr`.
+    pub fn connect<A: ToSocketAddrs>(addr: A) -> io::Result<RemoteSocket> {
+        let stream = TcpStream::connect(addr)?;
+        stream.set_nodelay(true)?;
+        Ok(RemoteSocket {
+            stream: stream,
+            reader: Mutex::new(MessageReader::default()),
+            writer: Mutex::new(()),
+        })
+    }
+
+    pub fn peer_addr(&self) -> io::Result<SocketAddr> {
+        self.stream.peer_addr()
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.stream.set_nonblocking(nonblocking)
+    }
+
+    /// Set the read timeout. A zero duration disables the timeout.
+    pub fn set_read_timeout(&self, duration: Duration) -> io::Result<()> {
+        self.stream.set_read_timeout(non_zero(duration))
+    }
+
+    /// Set the write timeout. A zero duration disables the timeout.
+    pub fn set_write_timeout(&self, duration: Duration) -> io::Result<()> {
+        self.stream.set_write_timeout(non_zero(duration))
+    }
+
+    /// Blocking read of a single frame.
+    ///
+    /// Fails with `UnexpectedEof` once the server closed the connection.
+    pub fn read_frame(&self) -> io::Result<CanFrame> {
+        self.reader
+            .lock()
+            .expect("reader lock poisoned")
+            .read_frame(&self.stream)
+    }
+
+    pub fn write_frame(&self, frame: &CanFrame) -> io::Result<()> {
+        let _guard = self.writer.lock().expect("writer lock poisoned");
+        (&self.stream).write_all(&encode(frame))
+    }
+}
+
+fn non_zero(duration: Duration) -> Option<Duration> {
+    if duration == Duration::from_millis(0) { None } else { Some(duration) }
+}
+
+impl CanTransport for RemoteSocket {
+    fn read_frame(&self) -> io::Result<CanFrame> {
+        RemoteSocket::read_frame(self)
+    }
+
+    fn write_frame(&self, frame: &CanFrame) -> io::Result<()> {
+        RemoteSocket::write_frame(self, frame)
+    }
+
+    fn set_read_timeout(&self, duration: Duration) -> io::Result<()> {
+        RemoteSocket::set_read_timeout(self, duration)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Clients {
+    next_id: usize,
+    streams: Vec<(usize, TcpStream)>,
+}
+
+impl Clients {
+    /// Send `frame` to all clients but `except`, dropping those that fail.
+    fn broadcast(&mut self, frame: &CanFrame, except: Option<usize>) {
+        let msg = encode(frame);
+        self.streams.retain(|&(id, ref stream)| {
+            if Some(id) == except {
+                return true;
+            }
+            let ok = (&*stream).write_all(&msg).is_ok();
+            if !ok {
+                let _ = stream.shutdown(Shutdown::Both);
+            }
+            ok
+        });
+    }
+}
+
+/// TCP server exposing a transport to `RemoteSocket` clients
+#[derive(Debug)]
+pub struct RemoteServer {
+    listener: TcpListener,
+    client_timeout: Duration,
+}
+
+impl RemoteServer {
+    /// Listen for clients on `addr`.
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<RemoteServer> {
+        Ok(RemoteServer {
+            listener: TcpListener::bind(addr)?,
+            client_timeout: Duration::from_secs(1),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Disconnect clients that do not accept a frame within `timeout`, so
+    /// a stalled client cannot hold up the others. Defaults to one second.
+    pub fn client_timeout(mut self, timeout: Duration) -> RemoteServer {
+        self.client_timeout = timeout;
+        self
+    }
+
+    /// Serve `transport` to clients.
+    ///
+    /// Clients are accepted on a background thread; every client gets a
+    /// thread of its own writing its frames to `transport`. The calling
+    /// thread forwards frames read from `transport` to all clients and
+    /// only returns when reading fails with an error that is not a
+    /// timeout.
+    pub fn run<T>(self, transport: T) -> io::Result<()>
+        where T: CanTransport + Send + Sync + 'static
+    {
+        let transport = Arc::new(transport);
+        let clients = Arc::new(Mutex::new(Clients::default()));
+
+        {
+            let transport = transport.clone();
+            let clients = clients.clone();
+            let listener = self.listener;
+            let timeout = self.client_timeout;
+
+            thread::spawn(move || for stream in listener.incoming() {
+                if let Ok(stream) = stream {
+                    let _ = accept(stream, timeout, transport.clone(), clients.clone());
+                }
+            });
+        }
+
+        loop {
+            match transport.read_frame() {
+                Ok(frame) => clients.lock().expect("clients lock poisoned").broadcast(&frame, None),
+                Err(ref e) if e.should_retry() => {}
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+fn accept<T>(stream: TcpStream,
+             timeout: Duration,
+             transport: Arc<T>,
+             clients: Arc<Mutex<Clients>>)
+             -> io::Result<()>
+    where T: CanTransport + Send + Sync + 'static
+{
+    stream.set_nodelay(true)?;
+    stream.set_write_timeout(non_zero(timeout))?;
+    let reader = stream.try_clone()?;
+
+    let id = {
+        let mut clients = clients.lock().expect("clients lock poisoned");
+        let id = clients.next_id;
+        clients.next_id += 1;
+        clients.streams.push((id, stream));
+        id
+    };
+
+    thread::spawn(move || {
+        let mut messages = MessageReader::default();
+        while let Ok(frame) = messages.read_frame(&reader) {
+            if transport.write_frame_insist(&frame).is_err() {
+                break;
+            }
+            clients.lock().expect("clients lock poisoned").broadcast(&frame, Some(id));
+        }
+
+        let _ = reader.shutdown(Shutdown::Both);
+        clients.lock().expect("clients lock poisoned").streams.retain(|&(i, _)| i != id);
+    });
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use CanFrame;
+    use testing::MockBus;
+    use super::{decode, encode, RemoteServer, RemoteSocket};
+
+    #[test]
+    fn test_codec() {
+        let frame = CanFrame::new(0x1234567, &[1, 2, 3], false, false).unwrap();
+        let msg = encode(&frame);
+        assert_eq!(&msg[..10], &[0, 16, 0x81, 0x23, 0x45, 0x67, 3, 0, 0, 0]);
+
+        let decoded = decode(&msg[2..]).unwrap();
+        assert_eq!(decoded.id(), 0x1234567);
+        assert!(decoded.is_extended());
+        assert_eq!(decoded.data(), &[1, 2, 3]);
+
+        assert!(decode(&msg[2..10]).is_err());
+    }
+
+    #[test]
+    fn test_bridge() {
+        let bus = MockBus::new();
+        let (local, served) = (bus.endpoint(), bus.endpoint());
+
+        let server = RemoteServer::bind("127.0.0.1:0").unwrap();
+        let addr = server.local_addr().unwrap();
+        thread::spawn(move || server.run(served));
+
+        let client = RemoteSocket::connect(addr).unwrap();
+        client.write_frame(&CanFrame::new(0x100, &[1], false, false).unwrap()).unwrap();
+        assert_eq!(local.read_frame().unwrap().id(), 0x100);
+
+        local.write_frame(&CanFrame::new(0x200, &[2], false, false).unwrap()).unwrap();
+        assert_eq!(client.read_frame().unwrap().id(), 0x200);
+    }
+}
@@ -0,0 +1,903 @@
+//! Frame routing to multiple consumers
+//!
+//! A `Router` owns a transport and reads from it on a background thread,
+//! dispatching every frame to the subscribers whose ID range contains it.
+//! Each subscriber has a bounded queue and decides what happens when it
+//! cannot keep up:
+//!
+//! ```
+//! use socketcan::CanFrame;
+//! use socketcan::router::{Overflow, Router};
+//! use socketcan::testing::MockBus;
+//!
+//! let bus = MockBus::new();
+//! let peer = bus.endpoint();
+//! let router = Router::new(bus.endpoint()).unwrap();
+//! let diag = router.subscribe(0x7e0, 0x7ef, 16, Overflow::Block);
+//!
+//! peer.write_frame(&CanFrame::new(0x7e8, &[1], false, false).unwrap()).unwrap();
+//! assert_eq!(diag.recv().unwrap().id(), 0x7e8);
+//! ```
+//!
+//! Frames discarded because a subscriber fell behind are counted, per
+//! subscriber and in total, so applications can tell how much they lost.
+//!
+//! With the `tokio` feature, an `AsyncRouter` routes the frames of an
+//! `AsyncCanTransport` on a tokio task instead. Subscribers can be read
+//! from tasks with `Subscriber::recv_async` in either case.
+
+use std::{io, thread};
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard, Weak};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant};
+#[cfg(feature = "tokio")]
+use tokio::task::JoinHandle;
+#[cfg(feature = "tokio")]
+use tokio_util::sync::{CancellationToken, WaitForCancellationFutureOwned};
+use {CanFilter, CanFrame, CanTransport, ShouldRetry, ERR_MASK};
+#[cfg(feature = "tokio")]
+use async_transport::AsyncCanTransport;
+use filter::filter_matches;
+
+/// What to do with a frame when a subscriber's queue is full
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Overflow {
+    /// Discard the oldest queued frame to make room
+    DropOldest,
+
+    /// Discard the new frame
+    DropNewest,
+
+    /// Wait until the subscriber made room, stalling all other
+    /// subscribers in the meantime
+    Block,
+}
+
+#[derive(Debug)]
+struct Queue {
+    frames: VecDeque<CanFrame>,
+    capacity: usize,
+    overflow: Overflow,
+    dropped: usize,
+    closed: bool,
+    // tasks waiting to receive and to push, the counterparts of the
+    // condition variables
+    reader: Option<Waker>,
+    writer: Option<Waker>,
+}
+
+fn wake(waker: &mut Option<Waker>) {
+    if let Some(waker) = waker.take() {
+        waker.wake();
+    }
+}
+
+#[derive(Debug)]
+struct Shared {
+    queue: Mutex<Queue>,
+    readable: Condvar,
+    writable: Condvar,
+    total_dropped: Arc<AtomicUsize>,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<Queue> {
+        self.queue.lock().expect("subscriber queue lock poisoned")
+    }
+
+    /// Queue `frame` as far as the overflow policy allows, returning
+    /// `false` if it has to wait for room.
+    fn offer(&self, queue: &mut Queue, frame: CanFrame) -> bool {
+        while queue.frames.len() >= queue.capacity && !queue.closed {
+            match queue.overflow {
+                Overflow::DropOldest => {
+                    queue.frames.pop_front();
+                    self.count_drop(queue);
+                }
+                Overflow::DropNewest => {
+                    self.count_drop(queue);
+                    return true;
+                }
+                Overflow::Block => return false,
+            }
+        }
+
+        if !queue.closed {
+            queue.frames.push_back(frame);
+            self.readable.notify_one();
+            wake(&mut queue.reader);
+        }
+        true
+    }
+
+    fn push(&self, frame: CanFrame) {
+        let mut queue = self.lock();
+        while !self.offer(&mut queue, frame) {
+            queue = self.writable.wait(queue).expect("subscriber queue lock poisoned");
+        }
+    }
+
+    /// Like `push`, but registers the task of `cx` instead of blocking.
+    fn poll_push(&self, cx: &mut Context, frame: CanFrame) -> Poll<()> {
+        let mut queue = self.lock();
+        if self.offer(&mut queue, frame) {
+            Poll::Ready(())
+        } else {
+            queue.writer = Some(cx.waker().clone());
+            Poll::Pending
+        }
+    }
+
+    fn count_drop(&self, queue: &mut Queue) {
+        queue.dropped += 1;
+        self.total_dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn notify_writer(&self, queue: &mut Queue) {
+        self.writable.notify_all();
+        wake(&mut queue.writer);
+    }
+
+    fn close(&self) {
+        let mut queue = self.lock();
+        queue.closed = true;
+        self.readable.notify_all();
+        wake(&mut queue.reader);
+        self.notify_writer(&mut queue);
+    }
+}
+
+/// Receiving end of a route
+///
+/// Dropping a subscriber removes its route.
+#[derive(Debug)]
+pub struct Subscriber {
+    shared: Arc<Shared>,
+}
+
+impl Subscriber {
+    fn pop(&self, queue: &mut Queue) -> Option<CanFrame> {
+        let frame = queue.frames.pop_front();
+        if frame.is_some() {
+            self.shared.notify_writer(queue);
+        }
+        frame
+    }
+
+    /// Wait for the next frame.
+    ///
+    /// Returns `None` once the router stopped and all queued frames were
+    /// received.
+    pub fn recv(&self) -> Option<CanFrame> {
+        let mut queue = self.shared.lock();
+        while queue.frames.is_empty() && !queue.closed {
+            queue = self.shared.readable.wait(queue).expect("subscriber queue lock poisoned");
+        }
+        self.pop(&mut queue)
+    }
+
+    /// Wait up to `timeout` for the next frame.
+    pub fn recv_timeout(&self, timeout: Duration) -> Option<CanFrame> {
+        let deadline = Instant::now() + timeout;
+        let mut queue = self.shared.lock();
+        while queue.frames.is_empty() && !queue.closed {
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            queue = self.shared
+                .readable
+                .wait_timeout(queue, deadline - now)
+                .expect("subscriber queue lock poisoned")
+                .0;
+        }
+        self.pop(&mut queue)
+    }
+
+    /// Take the next frame if one is queued.
+    pub fn try_recv(&self) -> Option<CanFrame> {
+        let mut queue = self.shared.lock();
+        self.pop(&mut queue)
+    }
+
+    /// Take the next frame if one is queued, like `recv` once the router
+    /// stopped.
+    ///
+    /// Otherwise returns `Poll::Pending` and wakes the task of `cx` once a
+    /// frame arrives. Only the task of the latest call is woken.
+    pub fn poll_recv(&self, cx: &mut Context) -> Poll<Option<CanFrame>> {
+        let mut queue = self.shared.lock();
+        if queue.frames.is_empty() && !queue.closed {
+            queue.reader = Some(cx.waker().clone());
+            return Poll::Pending;
+        }
+        Poll::Ready(self.pop(&mut queue))
+    }
+
+    /// Wait for the next frame without blocking the thread, see `recv`.
+    pub fn recv_async(&self) -> Recv {
+        Recv { subscriber: self }
+    }
+
+    /// Number of queued frames
+    pub fn len(&self) -> usize {
+        self.shared.lock().frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of frames discarded because the queue was full
+    pub fn dropped(&self) -> usize {
+        self.shared.lock().dropped
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.shared.lock().capacity
+    }
+
+    /// Change the queue depth.
+    ///
+    /// Frames already queued beyond a reduced capacity are kept.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is zero.
+    pub fn set_capacity(&self, capacity: usize) {
+        assert!(capacity > 0, "subscriber capacity must not be zero");
+        let mut queue = self.shared.lock();
+        queue.capacity = capacity;
+        self.shared.notify_writer(&mut queue);
+    }
+
+    pub fn overflow(&self) -> Overflow {
+        self.shared.lock().overflow
+    }
+
+    /// Change what happens when the queue is full.
+    pub fn set_overflow(&self, overflow: Overflow) {
+        let mut queue = self.shared.lock();
+        queue.overflow = overflow;
+        self.shared.notify_writer(&mut queue);
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        self.shared.close();
+    }
+}
+
+impl Iterator for Subscriber {
+    type Item = CanFrame;
+
+    fn next(&mut self) -> Option<CanFrame> {
+        self.recv()
+    }
+}
+
+/// Future returned by `Subscriber::recv_async`
+#[derive(Debug)]
+pub struct Recv<'a> {
+    subscriber: &'a Subscriber,
+}
+
+impl<'a> Future for Recv<'a> {
+    type Output = Option<CanFrame>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<CanFrame>> {
+        self.subscriber.poll_recv(cx)
+    }
+}
+
+/// The frames a route passes on
+#[derive(Debug)]
+enum Selector {
+    /// Data or error frames with IDs in `first..=last`
+    Range { first: u32, last: u32, errors: bool },
+
+    /// Data frames passing any of the filters
+    Filters(Vec<CanFilter>),
+}
+
+impl Selector {
+    fn matches(&self, frame: &CanFrame) -> bool {
+        match *self {
+            Selector::Range { first, last, errors } => {
+                errors == frame.is_error() && first <= frame.id() && frame.id() <= last
+            }
+            Selector::Filters(ref filters) => {
+                !frame.is_error() && filters.iter().any(|filter| filter_matches(filter, frame))
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Route {
+    selector: Selector,
+    subscriber: Weak<Shared>,
+}
+
+/// The routes of a router, shared with the thread or task routing frames
+#[derive(Debug)]
+struct Routes {
+    routes: Mutex<Vec<Route>>,
+    running: AtomicBool,
+    dropped: Arc<AtomicUsize>,
+}
+
+impl Routes {
+    fn new() -> Routes {
+        Routes {
+            routes: Mutex::new(Vec::new()),
+            running: AtomicBool::new(true),
+            dropped: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<Vec<Route>> {
+        self.routes.lock().expect("routes lock poisoned")
+    }
+
+    fn is_running(&self) -> bool {
+        self.running.load(Ordering::SeqCst)
+    }
+
+    fn add(&self, selector: Selector, capacity: usize, overflow: Overflow) -> Subscriber {
+        assert!(capacity > 0, "subscriber capacity must not be zero");
+
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                frames: VecDeque::with_capacity(capacity),
+                capacity: capacity,
+                overflow: overflow,
+                dropped: 0,
+                closed: !self.is_running(),
+                reader: None,
+                writer: None,
+            }),
+            readable: Condvar::new(),
+            writable: Condvar::new(),
+            total_dropped: self.dropped.clone(),
+        });
+
+        self.lock().push(Route {
+            selector: selector,
+            subscriber: Arc::downgrade(&shared),
+        });
+
+        Subscriber { shared: shared }
+    }
+
+    /// The subscribers `frame` is routed to, removing the routes of
+    /// dropped subscribers
+    fn targets(&self, frame: &CanFrame) -> Vec<Arc<Shared>> {
+        // collect the subscribers first, a blocking one must not hold up
+        // subscribing
+        let mut targets = Vec::new();
+        self.lock().retain(|route| {
+            match route.subscriber.upgrade() {
+                Some(shared) => {
+                    if route.selector.matches(frame) {
+                        targets.push(shared);
+                    }
+                    true
+                }
+                None => false,
+            }
+        });
+        targets
+    }
+
+    /// Stop routing and remove all routes, closing their subscribers.
+    fn close(&self) {
+        self.running.store(false, Ordering::SeqCst);
+        for route in self.lock().drain(..) {
+            if let Some(shared) = route.subscriber.upgrade() {
+                shared.close();
+            }
+        }
+    }
+}
+
+/// Reads frames from a transport and dispatches them to subscribers
+///
+/// Stops when dropped.
+#[derive(Debug)]
+pub struct Router<T> {
+    transport: Arc<T>,
+    routes: Arc<Routes>,
+    thread: Option<thread::JoinHandle<io::Result<()>>>,
+}
+
+/// Read timeout of the routing thread, which bounds how long `stop` waits
+const POLL_INTERVAL_MS: u64 = 100;
+
+impl<T: CanTransport + Send + Sync + 'static> Router<T> {
+    /// Start routing frames read from `transport`.
+    ///
+    /// Sets the read timeout of the transport, so the routing thread
+    /// notices when the router is stopped.
+    pub fn new(transport: T) -> io::Result<Router<T>> {
+        transport.set_read_timeout(Duration::from_millis(POLL_INTERVAL_MS))?;
+
+        let transport = Arc::new(transport);
+        let routes = Arc::new(Routes::new());
+
+        let thread = {
+            let (transport, routes) = (transport.clone(), routes.clone());
+            thread::spawn(move || {
+                let result = route(&*transport, &routes);
+                routes.close();
+                result
+            })
+        };
+
+        Ok(Router {
+            transport: transport,
+            routes: routes,
+            thread: Some(thread),
+        })
+    }
+
+    /// Receive frames with IDs in `first..=last`.
+    ///
+    /// Up to `capacity` frames are queued for the subscriber; `overflow`
+    /// decides what happens beyond that. Routes may overlap, a frame is
+    /// delivered to every matching subscriber. Error frames are only
+    /// delivered to `subscribe_errors` subscribers.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is zero.
+    pub fn subscribe(&self,
+                     first: u32,
+                     last: u32,
+                     capacity: usize,
+                     overflow: Overflow)
+                     -> Subscriber {
+        self.routes.add(Selector::Range {
+                            first: first,
+                            last: last,
+                            errors: false,
+                        },
+                        capacity,
+                        overflow)
+    }
+
+    /// Receive error frames.
+    ///
+    /// The transport only reports errors included in its error mask.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is zero.
+    pub fn subscribe_errors(&self, capacity: usize, overflow: Overflow) -> Subscriber {
+        self.routes.add(Selector::Range {
+                            first: 0,
+                            last: ERR_MASK,
+                            errors: true,
+                        },
+                        capacity,
+                        overflow)
+    }
+
+    /// Receive data frames passing any of `filters`, evaluated like
+    /// kernel filters.
+    ///
+    /// For selections an ID range does not cover, e.g. the services of a
+    /// CANopen node. Otherwise like `subscribe`.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is zero.
+    pub fn subscribe_filtered(&self,
+                              filters: &[CanFilter],
+                              capacity: usize,
+                              overflow: Overflow)
+                              -> Subscriber {
+        self.routes.add(Selector::Filters(filters.to_vec()), capacity, overflow)
+    }
+
+    /// Number of frames discarded for all subscribers, including ones
+    /// already dropped
+    pub fn dropped(&self) -> usize {
+        self.routes.dropped.load(Ordering::Relaxed)
+    }
+
+    /// The routed transport, e.g. to send frames
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Stop routing and wait for the routing thread to finish.
+    ///
+    /// Subscribers are closed right away, so the routing thread does not
+    /// wait for a full `Block` subscriber that is not read anymore.
+    /// Returns the error that made the routing thread stop early, if any.
+    pub fn stop(mut self) -> io::Result<()> {
+        self.shutdown()
+    }
+
+    fn shutdown(&mut self) -> io::Result<()> {
+        self.routes.close();
+        match self.thread.take() {
+            Some(thread) => thread.join().expect("routing thread panicked"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<T> Drop for Router<T> {
+    fn drop(&mut self) {
+        self.routes.close();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn route<T: CanTransport>(transport: &T, routes: &Routes) -> io::Result<()> {
+    while routes.is_running() {
+        let frame = match transport.read_frame() {
+            Ok(frame) => frame,
+            Err(ref e) if e.should_retry() => continue,
+            Err(e) => return Err(e),
+        };
+
+        for shared in routes.targets(&frame) {
+            shared.push(frame);
+        }
+    }
+    Ok(())
+}
+
+/// Reads frames from an asynchronous transport on a tokio task and
+/// dispatches them to subscribers
+///
+/// Subscribing works as with `Router`. Stops when dropped.
+#[cfg(feature = "tokio")]
+#[derive(Debug)]
+pub struct AsyncRouter<T> {
+    transport: Arc<T>,
+    routes: Arc<Routes>,
+    token: CancellationToken,
+    task: Option<JoinHandle<io::Result<()>>>,
+}
+
+#[cfg(feature = "tokio")]
+impl<T: AsyncCanTransport + Send + Sync + 'static> AsyncRouter<T> {
+    /// Start routing frames read from `transport` on a task of the current
+    /// tokio runtime.
+    ///
+    /// # Panics
+    ///
+    /// If called outside of a tokio runtime.
+    pub fn spawn(transport: T) -> AsyncRouter<T> {
+        let transport = Arc::new(transport);
+        let routes = Arc::new(Routes::new());
+        let token = CancellationToken::new();
+
+        let task = ::tokio::spawn(RouteTask {
+            transport: transport.clone(),
+            routes: routes.clone(),
+            cancelled: Box::pin(token.clone().cancelled_owned()),
+            frame: None,
+            targets: Vec::new(),
+        });
+
+        AsyncRouter {
+            transport: transport,
+            routes: routes,
+            token: token,
+            task: Some(task),
+        }
+    }
+
+    /// Receive frames with IDs in `first..=last`, see `Router::subscribe`.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is zero.
+    pub fn subscribe(&self,
+                     first: u32,
+                     last: u32,
+                     capacity: usize,
+                     overflow: Overflow)
+                     -> Subscriber {
+        self.routes.add(Selector::Range {
+                            first: first,
+                            last: last,
+                            errors: false,
+                        },
+                        capacity,
+                        overflow)
+    }
+
+    /// Receive error frames, see `Router::subscribe_errors`.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is zero.
+    pub fn subscribe_errors(&self, capacity: usize, overflow: Overflow) -> Subscriber {
+        self.routes.add(Selector::Range {
+                            first: 0,
+                            last: ERR_MASK,
+                            errors: true,
+                        },
+                        capacity,
+                        overflow)
+    }
+
+    /// Receive data frames passing any of `filters`, see
+    /// `Router::subscribe_filtered`.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is zero.
+    pub fn subscribe_filtered(&self,
+                              filters: &[CanFilter],
+                              capacity: usize,
+                              overflow: Overflow)
+                              -> Subscriber {
+        self.routes.add(Selector::Filters(filters.to_vec()), capacity, overflow)
+    }
+
+    /// Number of frames discarded for all subscribers, including ones
+    /// already dropped
+    pub fn dropped(&self) -> usize {
+        self.routes.dropped.load(Ordering::Relaxed)
+    }
+
+    /// The routed transport, e.g. to send frames
+    pub fn transport(&self) -> &T {
+        &self.transport
+    }
+
+    /// Stop routing.
+    ///
+    /// Subscribers are closed right away, as with `Router::stop`. The
+    /// returned handle resolves once the routing task finished, to the
+    /// error that made it stop early, if any.
+    pub fn stop(mut self) -> JoinHandle<io::Result<()>> {
+        self.token.cancel();
+        self.routes.close();
+        self.task.take().expect("routing task already taken")
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T> Drop for AsyncRouter<T> {
+    fn drop(&mut self) {
+        self.token.cancel();
+        self.routes.close();
+    }
+}
+
+/// Number of frames `RouteTask` routes before yielding to other tasks
+#[cfg(feature = "tokio")]
+const FRAMES_PER_POLL: u32 = 64;
+
+/// The routing task of an `AsyncRouter`
+#[cfg(feature = "tokio")]
+struct RouteTask<T> {
+    transport: Arc<T>,
+    routes: Arc<Routes>,
+    cancelled: Pin<Box<WaitForCancellationFutureOwned>>,
+    // frame being delivered, and the subscribers it still has to be
+    // delivered to
+    frame: Option<CanFrame>,
+    targets: Vec<Arc<Shared>>,
+}
+
+#[cfg(feature = "tokio")]
+impl<T: AsyncCanTransport> RouteTask<T> {
+    fn poll_route(&mut self, cx: &mut Context) -> Poll<io::Result<()>> {
+        for _ in 0..FRAMES_PER_POLL {
+            if self.cancelled.as_mut().poll(cx).is_ready() {
+                return Poll::Ready(Ok(()));
+            }
+
+            if let Some(frame) = self.frame {
+                while let Some(shared) = self.targets.pop() {
+                    if shared.poll_push(cx, frame).is_pending() {
+                        self.targets.push(shared);
+                        return Poll::Pending;
+                    }
+                }
+                self.frame = None;
+            }
+
+            let frame = match self.transport.poll_read_frame(cx) {
+                Poll::Ready(Ok(frame)) => frame,
+                Poll::Ready(Err(ref e)) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Poll::Ready(Err(e)) => return Poll::Ready(Err(e)),
+                Poll::Pending => return Poll::Pending,
+            };
+
+            // delivered from the back, in the order of subscribing
+            self.targets = self.routes.targets(&frame);
+            self.targets.reverse();
+            self.frame = Some(frame);
+        }
+
+        // a busy bus must not starve the other tasks of the runtime
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+#[cfg(feature = "tokio")]
+impl<T: AsyncCanTransport> Future for RouteTask<T> {
+    type Output = io::Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context) -> Poll<io::Result<()>> {
+        let task = self.get_mut();
+        let result = task.poll_route(cx);
+        if result.is_ready() {
+            task.routes.close();
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::mpsc;
+    use std::thread;
+    use std::time::Duration;
+    use {CanFilter, CanFrame, ERR_MASK_ALL};
+    use testing::MockBus;
+    use super::{Overflow, Router};
+
+    #[test]
+    fn test_routing() {
+        let bus = MockBus::new();
+        let peer = bus.endpoint();
+        let router = Router::new(bus.endpoint()).unwrap();
+
+        let low = router.subscribe(0x000, 0x0ff, 2, Overflow::DropOldest);
+        let high = router.subscribe(0x100, 0x7ff, 2, Overflow::DropNewest);
+        let filters = [CanFilter::new(0x003, 0x7ff).unwrap(),
+                       CanFilter::new(0x101, 0x7fd).unwrap()];
+        let filtered = router.subscribe_filtered(&filters, 16, Overflow::Block);
+        let mut all = router.subscribe(0, 0x1fffffff, 16, Overflow::Block);
+
+        for &id in &[0x001, 0x002, 0x003, 0x101, 0x102, 0x103] {
+            peer.write_frame(&CanFrame::new(id, &[], false, false).unwrap()).unwrap();
+        }
+
+        // wait until everything was routed
+        let ids: Vec<_> = all.by_ref().take(6).map(|f| f.id()).collect();
+        assert_eq!(ids, vec![0x001, 0x002, 0x003, 0x101, 0x102, 0x103]);
+
+        assert_eq!(low.try_recv().unwrap().id(), 0x002);
+        assert_eq!(low.try_recv().unwrap().id(), 0x003);
+        assert!(low.try_recv().is_none());
+
+        assert_eq!(high.try_recv().unwrap().id(), 0x101);
+        assert_eq!(high.try_recv().unwrap().id(), 0x102);
+        assert!(high.try_recv().is_none());
+
+        let ids: Vec<_> = (0..3).map(|_| filtered.try_recv().unwrap().id()).collect();
+        assert_eq!(ids, vec![0x003, 0x101, 0x103]);
+        assert!(filtered.try_recv().is_none());
+
+        assert_eq!(low.dropped(), 1);
+        assert_eq!(high.dropped(), 1);
+        assert_eq!(all.dropped(), 0);
+        assert_eq!(router.dropped(), 2);
+
+        router.stop().unwrap();
+        assert!(all.recv().is_none());
+    }
+
+    #[test]
+    fn test_stop_blocked() {
+        let bus = MockBus::new();
+        let peer = bus.endpoint();
+        let router = Router::new(bus.endpoint()).unwrap();
+
+        // full and never read, the routing thread blocks on it
+        let mut stuck = router.subscribe(0, 0x7ff, 16, Overflow::Block);
+        for id in 0..17 {
+            peer.write_frame(&CanFrame::new(id, &[], false, false).unwrap()).unwrap();
+        }
+        while stuck.len() < 16 {
+            thread::yield_now();
+        }
+
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || tx.send(router.stop().is_ok()).unwrap());
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)), Ok(true));
+
+        // queued frames are still received after the stop
+        assert_eq!(stuck.by_ref().count(), 16);
+    }
+
+    #[test]
+    fn test_reconfigure() {
+        let bus = MockBus::new();
+        let peer = bus.endpoint();
+        let router = Router::new(bus.endpoint()).unwrap();
+
+        // frames reach subscribers in order, so `sub` has them before `all`
+        let sub = router.subscribe(0, 0x7ff, 1, Overflow::DropNewest);
+        let mut all = router.subscribe(0, 0x7ff, 16, Overflow::Block);
+        sub.set_capacity(2);
+        sub.set_overflow(Overflow::DropOldest);
+        assert_eq!(sub.capacity(), 2);
+        assert_eq!(sub.overflow(), Overflow::DropOldest);
+
+        for id in 1..4 {
+            peer.write_frame(&CanFrame::new(id, &[], false, false).unwrap()).unwrap();
+        }
+        assert_eq!(all.by_ref().take(3).count(), 3);
+
+        assert_eq!(sub.dropped(), 1);
+        assert_eq!(sub.try_recv().unwrap().id(), 2);
+        assert_eq!(sub.try_recv().unwrap().id(), 3);
+    }
+
+    #[test]
+    fn test_error_routing() {
+        let bus = MockBus::new();
+        let peer = bus.endpoint();
+        let socket = bus.endpoint();
+        socket.set_error_mask(ERR_MASK_ALL).unwrap();
+        let router = Router::new(socket).unwrap();
+
+        let mut all = router.subscribe(0, 0x1fffffff, 16, Overflow::Block);
+        let errors = router.subscribe_errors(16, Overflow::Block);
+
+        peer.write_frame(&CanFrame::new(0x004, &[0; 8], false, true).unwrap()).unwrap();
+        peer.write_frame(&CanFrame::new(0x004, &[], false, false).unwrap()).unwrap();
+
+        assert!(!all.next().unwrap().is_error());
+        assert!(errors.try_recv().unwrap().is_error());
+        assert!(all.try_recv().is_none());
+    }
+
+    #[cfg(feature = "tokio")]
+    #[test]
+    fn test_async_router() {
+        use tokio::runtime::Builder;
+        use super::AsyncRouter;
+
+        let rt = Builder::new_current_thread().build().unwrap();
+        let _runtime = rt.enter();
+        let bus = MockBus::new();
+        let peer = bus.endpoint();
+        let router = AsyncRouter::spawn(bus.endpoint());
+
+        let diag = router.subscribe(0x7e0, 0x7ef, 16, Overflow::Block);
+        let latest = router.subscribe(0, 0x7ff, 1, Overflow::DropOldest);
+        for &id in &[0x7e8, 0x123, 0x7e9] {
+            peer.write_frame(&CanFrame::new(id, &[], false, false).unwrap()).unwrap();
+        }
+
+        assert_eq!(rt.block_on(diag.recv_async()).unwrap().id(), 0x7e8);
+        assert_eq!(rt.block_on(diag.recv_async()).unwrap().id(), 0x7e9);
+        assert_eq!(latest.try_recv().unwrap().id(), 0x7e9);
+        assert_eq!(router.dropped(), 2);
+
+        // full and never read, the routing task waits for it until stopped
+        let stuck = router.subscribe(0, 0x7ff, 1, Overflow::Block);
+        for id in 0..3 {
+            peer.write_frame(&CanFrame::new(id, &[], false, false).unwrap()).unwrap();
+        }
+        // frame 1 still reaches `latest`, subscribed before `stuck`
+        assert_eq!(rt.block_on(latest.recv_async()).unwrap().id(), 1);
+        assert!(latest.try_recv().is_none());
+
+        rt.block_on(router.stop()).unwrap().unwrap();
+        assert_eq!(rt.block_on(stuck.recv_async()).unwrap().id(), 0);
+        assert!(rt.block_on(stuck.recv_async()).is_none());
+    }
+}
@@ -0,0 +1,311 @@
+//! Tables of cyclic frames
+//!
+//! A `CyclicTable` lists frames to be sent periodically, each with its
+//! period and an offset of the first transmission, so the cyclic traffic
+//! of a test bench can be kept in a file instead of code. `CanBus` runs a
+//! table with `send_table`.
+//!
+//! Tables are written one frame per line in `cansend` syntax, followed by
+//! the period and an optional start offset. Durations take the units `us`,
+//! `ms` or `s`:
+//!
+//! ```text
+//! # frame          period  offset
+//! 701#05           100ms
+//! 18FF0001#0102    1s      250ms
+//! 7DF#R            20ms    5ms
+//! ```
+//!
+//! ```no_run
+//! use socketcan::CanBus;
+//! use socketcan::schedule::CyclicTable;
+//!
+//! let table = CyclicTable::from_file("bench.cyclic").unwrap();
+//! let bus = CanBus::open("vcan0").unwrap();
+//!
+//! // runs until `cyclic` is dropped
+//! let cyclic = bus.send_table(&table);
+//! ```
+//!
+//! With the `serde` feature, `CyclicTable` can also be deserialized, e.g.
+//! from TOML. Frames are given in `cansend` syntax and durations either as
+//! strings in the units above or as a number of microseconds:
+//!
+//! ```toml
+//! [[frames]]
+//! frame = "701#05"
+//! period = "100ms"
+//!
+//! [[frames]]
+//! frame = "18FF0001#0102"
+//! period = 1000000
+//! offset = "250ms"
+//! ```
+
+use std::{error, fmt, io, path};
+use std::fs::File;
+use std::io::Read;
+use std::str::FromStr;
+use std::time::Duration;
+use CanFrame;
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+
+/// Error building a `CyclicTable`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ScheduleError {
+    /// A period of zero, which would flood the bus
+    ZeroPeriod,
+
+    /// The frame could not be parsed; carries the line number
+    InvalidFrame(usize),
+
+    /// A line could not be parsed; carries the line number
+    Syntax(usize),
+}
+
+impl fmt::Display for ScheduleError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ScheduleError::InvalidFrame(line) => write!(f, "invalid frame in line {}", line),
+            ScheduleError::Syntax(line) => write!(f, "invalid entry in line {}", line),
+            _ => write!(f, "{}", error::Error::description(self)),
+        }
+    }
+}
+
+impl error::Error for ScheduleError {
+    fn description(&self) -> &str {
+        match *self {
+            ScheduleError::ZeroPeriod => "zero period",
+            ScheduleError::InvalidFrame(_) => "invalid frame",
+            ScheduleError::Syntax(_) => "invalid entry",
+        }
+    }
+}
+
+/// A frame sent every `period`, the first time `offset` after the start
+#[derive(Copy, Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct CyclicFrame {
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "de::frame"))]
+    pub frame: CanFrame,
+    #[cfg_attr(feature = "serde", serde(deserialize_with = "de::period"))]
+    pub period: Duration,
+    #[cfg_attr(feature = "serde", serde(default, deserialize_with = "de::duration"))]
+    pub offset: Duration,
+}
+
+/// Frames to be sent periodically
+#[derive(Clone, Debug, Default)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(feature = "serde", serde(deny_unknown_fields))]
+pub struct CyclicTable {
+    #[cfg_attr(feature = "serde", serde(default))]
+    frames: Vec<CyclicFrame>,
+}
+
+impl CyclicTable {
+    pub fn new() -> CyclicTable {
+        CyclicTable::default()
+    }
+
+    /// Read a table from a file, see the module documentation for the
+    /// format.
+    pub fn from_file<P: AsRef<path::Path>>(path: P) -> io::Result<CyclicTable> {
+        let mut s = String::new();
+        File::open(path)?.read_to_string(&mut s)?;
+        s.parse().map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+
+    /// Send `frame` every `period`, the first time `offset` after the
+    /// start.
+    pub fn add(&mut self,
+               frame: CanFrame,
+               period: Duration,
+               offset: Duration)
+               -> Result<(), ScheduleError> {
+        if period == Duration::from_secs(0) {
+            return Err(ScheduleError::ZeroPeriod);
+        }
+
+        self.frames.push(CyclicFrame {
+                             frame: frame,
+                             period: period,
+                             offset: offset,
+                         });
+        Ok(())
+    }
+
+    /// The frames in the order they were added
+    pub fn frames(&self) -> &[CyclicFrame] {
+        &self.frames
+    }
+}
+
+/// Parse a duration such as `100ms`, `1s` or `500us`.
+fn parse_duration(s: &str, line: usize) -> Result<Duration, ScheduleError> {
+    let split = s.find(|c: char| !c.is_digit(10)).unwrap_or(s.len());
+    let value = s[..split].parse::<u64>().map_err(|_| ScheduleError::Syntax(line))?;
+
+    match &s[split..] {
+        "us" => Ok(Duration::from_micros(value)),
+        "ms" => Ok(Duration::from_millis(value)),
+        "s" => Ok(Duration::from_secs(value)),
+        _ => Err(ScheduleError::Syntax(line)),
+    }
+}
+
+impl FromStr for CyclicTable {
+    type Err = ScheduleError;
+
+    /// Parse one frame per line, `<frame> <period> [<offset>]`. Empty
+    /// lines and lines starting with `#` are ignored.
+    fn from_str(s: &str) -> Result<CyclicTable, ScheduleError> {
+        let mut table = CyclicTable::new();
+
+        for (n, line) in s.lines().enumerate().map(|(i, l)| (i + 1, l.trim())) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            if fields.len() < 2 || fields.len() > 3 {
+                return Err(ScheduleError::Syntax(n));
+            }
+
+            let frame = fields[0].parse().map_err(|_| ScheduleError::InvalidFrame(n))?;
+            let period = parse_duration(fields[1], n)?;
+            let offset = match fields.get(2) {
+                Some(offset) => parse_duration(offset, n)?,
+                None => Duration::from_secs(0),
+            };
+            table.add(frame, period, offset)?;
+        }
+
+        Ok(table)
+    }
+}
+
+/// Deserialization of the fields of `CyclicFrame`
+#[cfg(feature = "serde")]
+mod de {
+    use std::fmt;
+    use std::time::Duration;
+    use serde::{Deserialize, Deserializer};
+    use serde::de::{Error, Unexpected, Visitor};
+    use CanFrame;
+    use super::{parse_duration, ScheduleError};
+
+    struct DurationVisitor;
+
+    impl<'de> Visitor<'de> for DurationVisitor {
+        type Value = Duration;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "a duration such as \"100ms\" or a number of microseconds")
+        }
+
+        fn visit_u64<E: Error>(self, us: u64) -> Result<Duration, E> {
+            Ok(Duration::from_micros(us))
+        }
+
+        fn visit_i64<E: Error>(self, us: i64) -> Result<Duration, E> {
+            if us < 0 {
+                return Err(E::invalid_value(Unexpected::Signed(us), &self));
+            }
+            self.visit_u64(us as u64)
+        }
+
+        fn visit_str<E: Error>(self, s: &str) -> Result<Duration, E> {
+            parse_duration(s, 0).map_err(|_| E::invalid_value(Unexpected::Str(s), &self))
+        }
+    }
+
+    pub fn frame<'de, D: Deserializer<'de>>(d: D) -> Result<CanFrame, D::Error> {
+        let s = String::deserialize(d)?;
+        s.parse()
+            .map_err(|_| D::Error::invalid_value(Unexpected::Str(&s), &"a frame in cansend syntax"))
+    }
+
+    pub fn duration<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        d.deserialize_any(DurationVisitor)
+    }
+
+    pub fn period<'de, D: Deserializer<'de>>(d: D) -> Result<Duration, D::Error> {
+        let period = duration(d)?;
+        if period == Duration::from_secs(0) {
+            return Err(D::Error::custom(ScheduleError::ZeroPeriod));
+        }
+        Ok(period)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::Duration;
+    use super::{CyclicTable, ScheduleError};
+
+    #[test]
+    fn test_parse_table() {
+        let table: CyclicTable = "# comment\n\
+                                  701#05 100ms\n\
+                                  \n\
+                                  18FF0001#0102  1s  250ms\n\
+                                  7DF#R 500us 5ms"
+            .parse()
+            .unwrap();
+
+        let frames = table.frames();
+        assert_eq!(frames.len(), 3);
+        assert_eq!((frames[0].frame.id(), frames[0].frame.data()), (0x701, &[5][..]));
+        assert_eq!((frames[0].period, frames[0].offset),
+                   (Duration::from_millis(100), Duration::from_secs(0)));
+        assert!(frames[1].frame.is_extended());
+        assert_eq!((frames[1].period, frames[1].offset),
+                   (Duration::from_secs(1), Duration::from_millis(250)));
+        assert!(frames[2].frame.is_rtr());
+        assert_eq!(frames[2].period, Duration::from_micros(500));
+
+        assert_eq!("701#05".parse::<CyclicTable>().unwrap_err(),
+                   ScheduleError::Syntax(1));
+        assert_eq!("701#05 10ms\n701#05 10min".parse::<CyclicTable>().unwrap_err(),
+                   ScheduleError::Syntax(2));
+        assert_eq!("701#5X 10ms".parse::<CyclicTable>().unwrap_err(),
+                   ScheduleError::InvalidFrame(1));
+        assert_eq!("701#05 0ms".parse::<CyclicTable>().unwrap_err(),
+                   ScheduleError::ZeroPeriod);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_deserialize_table() {
+        use toml;
+
+        let table: CyclicTable = toml::from_str("[[frames]]\n\
+                                                 frame = \"701#05\"\n\
+                                                 period = \"100ms\"\n\
+                                                 [[frames]]\n\
+                                                 frame = \"18FF0001#0102\"\n\
+                                                 period = 1000000\n\
+                                                 offset = \"250ms\"")
+            .unwrap();
+
+        let frames = table.frames();
+        assert_eq!(frames.len(), 2);
+        assert_eq!((frames[0].frame.id(), frames[0].frame.data()), (0x701, &[5][..]));
+        assert_eq!((frames[0].period, frames[0].offset),
+                   (Duration::from_millis(100), Duration::from_secs(0)));
+        assert!(frames[1].frame.is_extended());
+        assert_eq!((frames[1].period, frames[1].offset),
+                   (Duration::from_secs(1), Duration::from_millis(250)));
+
+        for bad in &["[[frames]]\nframe = \"701#05\"\nperiod = \"0ms\"",
+                     "[[frames]]\nframe = \"701#05\"\nperiod = -1",
+                     "[[frames]]\nframe = \"701#5X\"\nperiod = \"10ms\"",
+                     "[[frames]]\nframe = \"701#05\"\nperiod = \"10min\""] {
+            assert!(toml::from_str::<CyclicTable>(bad).is_err(), "accepted {:?}", bad);
+        }
+    }
+}
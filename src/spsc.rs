@@ -0,0 +1,293 @@
+//! Lock-free single-producer/single-consumer frame queue
+//!
+//! A bounded ring buffer for handing frames from one thread to exactly one
+//! other, typically from a dedicated reader thread (see `reader`) to a
+//! control loop. Neither side ever takes a lock, so a slow consumer can
+//! not delay the producer beyond the queue filling up.
+//!
+//! ```no_run
+//! use socketcan::{spsc, CanSocket};
+//! use socketcan::reader::ReaderOptions;
+//!
+//! let (producer, consumer) = spsc::channel(1024);
+//! let socket = CanSocket::open("can0").unwrap();
+//! let _reader = socket.spawn_reader(ReaderOptions::new().cpu(3), producer).unwrap();
+//!
+//! loop {
+//!     // control loop cycle, taking whatever arrived since the last one
+//!     while let Ok(frame) = consumer.try_recv() {
+//!         println!("{:X}", frame);
+//!     }
+//! }
+//! ```
+//!
+//! Each side belongs to exactly one thread at a time. Handles can be moved
+//! to another thread, but not shared between threads:
+//!
+//! ```compile_fail
+//! use std::sync::Arc;
+//! use std::thread;
+//! use socketcan::spsc;
+//!
+//! let (producer, _consumer) = spsc::channel(16);
+//! let producer = Arc::new(producer);
+//! let shared = producer.clone();
+//! thread::spawn(move || shared.capacity());
+//! ```
+
+use std::{cmp, fmt, io, thread};
+use std::cell::{Cell, UnsafeCell};
+use std::marker::PhantomData;
+use std::sync::Arc;
+use std::sync::atomic::{self, AtomicUsize, Ordering};
+use std::sync::mpsc::{RecvTimeoutError, TryRecvError, TrySendError};
+use std::time::{Duration, Instant};
+use CanFrame;
+use reader::FrameSink;
+
+struct Ring {
+    slots: Box<[UnsafeCell<CanFrame>]>,
+    mask: usize,
+
+    /// index of the next frame to receive, only written by the consumer
+    head: AtomicUsize,
+
+    /// index of the next free slot, only written by the producer
+    tail: AtomicUsize,
+}
+
+// a slot is only accessed by the producer before publishing it through
+// `tail`, and only by the consumer after that until it releases the slot
+// through `head`. There is only one of each, as neither handle is `Sync`
+// or `Clone`.
+unsafe impl Sync for Ring {}
+
+/// Create a queue holding at least `capacity` frames.
+///
+/// The capacity is rounded up to the next power of two.
+pub fn channel(capacity: usize) -> (Producer, Consumer) {
+    let capacity = cmp::max(capacity, 1).next_power_of_two();
+    let empty = CanFrame::new_const(0, &[], false, false);
+    let ring = Arc::new(Ring {
+                            slots: (0..capacity).map(|_| UnsafeCell::new(empty)).collect(),
+                            mask: capacity - 1,
+                            head: AtomicUsize::new(0),
+                            tail: AtomicUsize::new(0),
+                        });
+
+    let producer = Producer {
+        ring: ring.clone(),
+        _not_sync: PhantomData,
+    };
+    let consumer = Consumer {
+        ring: ring,
+        _not_sync: PhantomData,
+    };
+    (producer, consumer)
+}
+
+/// Sending side of a queue
+///
+/// `Send` but not `Sync`, so only one thread at a time can send.
+pub struct Producer {
+    ring: Arc<Ring>,
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+impl Producer {
+    /// Queue `frame` without blocking.
+    ///
+    /// Fails with `Full` if the consumer fell behind and with
+    /// `Disconnected` if it was dropped.
+    pub fn try_send(&self, frame: CanFrame) -> Result<(), TrySendError<CanFrame>> {
+        if Arc::strong_count(&self.ring) == 1 {
+            return Err(TrySendError::Disconnected(frame));
+        }
+
+        let tail = self.ring.tail.load(Ordering::Relaxed);
+        let head = self.ring.head.load(Ordering::Acquire);
+        if tail.wrapping_sub(head) > self.ring.mask {
+            return Err(TrySendError::Full(frame));
+        }
+
+        unsafe {
+            *self.ring.slots[tail & self.ring.mask].get() = frame;
+        }
+        self.ring.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Ok(())
+    }
+
+    /// Number of frames the queue holds
+    pub fn capacity(&self) -> usize {
+        self.ring.slots.len()
+    }
+}
+
+/// Blocks while the queue is full, by yielding the thread.
+impl FrameSink for Producer {
+    fn send_frame(&mut self, mut frame: CanFrame) -> io::Result<()> {
+        loop {
+            match self.try_send(frame) {
+                Ok(()) => return Ok(()),
+                Err(TrySendError::Full(f)) => frame = f,
+                Err(TrySendError::Disconnected(_)) => {
+                    return Err(io::Error::new(io::ErrorKind::BrokenPipe,
+                                              "frame receiver disconnected"))
+                }
+            }
+            thread::yield_now();
+        }
+    }
+
+    fn try_send_frame(&mut self, frame: CanFrame) -> io::Result<Option<CanFrame>> {
+        match self.try_send(frame) {
+            Ok(()) => Ok(None),
+            Err(TrySendError::Full(frame)) => Ok(Some(frame)),
+            Err(TrySendError::Disconnected(_)) => {
+                Err(io::Error::new(io::ErrorKind::BrokenPipe, "frame receiver disconnected"))
+            }
+        }
+    }
+}
+
+impl fmt::Debug for Producer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Producer").field("capacity", &self.capacity()).finish()
+    }
+}
+
+/// Receiving side of a queue
+///
+/// `Send` but not `Sync`, so only one thread at a time can receive.
+pub struct Consumer {
+    ring: Arc<Ring>,
+    _not_sync: PhantomData<Cell<()>>,
+}
+
+impl Consumer {
+    /// Take the oldest queued frame without blocking.
+    ///
+    /// Fails with `Empty` if no frame is queued and with `Disconnected` if
+    /// additionally the producer was dropped.
+    pub fn try_recv(&self) -> Result<CanFrame, TryRecvError> {
+        let head = self.ring.head.load(Ordering::Relaxed);
+        let mut tail = self.ring.tail.load(Ordering::Acquire);
+        if head == tail {
+            if Arc::strong_count(&self.ring) > 1 {
+                return Err(TryRecvError::Empty);
+            }
+            // frames sent right before the producer was dropped, made
+            // visible by synchronizing with the release of its reference
+            atomic::fence(Ordering::Acquire);
+            tail = self.ring.tail.load(Ordering::Acquire);
+            if head == tail {
+                return Err(TryRecvError::Disconnected);
+            }
+        }
+
+        let frame = unsafe { *self.ring.slots[head & self.ring.mask].get() };
+        self.ring.head.store(head.wrapping_add(1), Ordering::Release);
+        Ok(frame)
+    }
+
+    /// Take the oldest queued frame, waiting at most `timeout`.
+    ///
+    /// Waits by spinning and yielding the thread, which keeps latency low
+    /// but a core busy.
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<CanFrame, RecvTimeoutError> {
+        let start = Instant::now();
+        loop {
+            match self.try_recv() {
+                Ok(frame) => return Ok(frame),
+                Err(TryRecvError::Disconnected) => return Err(RecvTimeoutError::Disconnected),
+                Err(TryRecvError::Empty) if start.elapsed() >= timeout => {
+                    return Err(RecvTimeoutError::Timeout)
+                }
+                Err(TryRecvError::Empty) => thread::yield_now(),
+            }
+        }
+    }
+
+    /// Number of queued frames
+    pub fn len(&self) -> usize {
+        let tail = self.ring.tail.load(Ordering::Acquire);
+        tail.wrapping_sub(self.ring.head.load(Ordering::Relaxed))
+    }
+
+    /// Check if no frame is queued
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl fmt::Debug for Consumer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Consumer").field("len", &self.len()).finish()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::thread;
+    use std::sync::mpsc::{TryRecvError, TrySendError};
+    use std::time::Duration;
+    use CanFrame;
+    use reader::{spawn_reader, FrameSink, ReaderOptions};
+    use testing::MockBus;
+    use super::channel;
+
+    fn frame(id: u32) -> CanFrame {
+        CanFrame::new(id, &[], false, false).unwrap()
+    }
+
+    #[test]
+    fn test_queue() {
+        let (producer, consumer) = channel(3);
+        assert_eq!(producer.capacity(), 4);
+        assert_eq!(consumer.try_recv().unwrap_err(), TryRecvError::Empty);
+
+        for id in 0..4 {
+            producer.try_send(frame(id)).unwrap();
+        }
+        match producer.try_send(frame(4)) {
+            Err(TrySendError::Full(f)) => assert_eq!(f.id(), 4),
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(consumer.len(), 4);
+
+        assert_eq!(consumer.try_recv().unwrap().id(), 0);
+        producer.try_send(frame(4)).unwrap();
+        drop(producer);
+        let ids: Vec<u32> = (0..4).map(|_| consumer.try_recv().unwrap().id()).collect();
+        assert_eq!(ids, vec![1, 2, 3, 4]);
+        assert_eq!(consumer.try_recv().unwrap_err(), TryRecvError::Disconnected);
+    }
+
+    #[test]
+    fn test_threads() {
+        let (producer, consumer) = channel(16);
+        let sender = thread::spawn(move || {
+            let mut producer = producer;
+            for id in 0..10000 {
+                producer.send_frame(frame(id)).unwrap();
+            }
+        });
+
+        for id in 0..10000 {
+            assert_eq!(consumer.recv_timeout(Duration::from_secs(5)).unwrap().id(), id);
+        }
+        sender.join().unwrap();
+    }
+
+    #[test]
+    fn test_reader_thread() {
+        let bus = MockBus::new();
+        let peer = bus.endpoint();
+        let (producer, consumer) = channel(8);
+
+        let reader = spawn_reader(bus.endpoint(), ReaderOptions::new(), producer).unwrap();
+        peer.write_frame(&frame(0x123)).unwrap();
+        assert_eq!(consumer.recv_timeout(Duration::from_secs(5)).unwrap().id(), 0x123);
+        assert_eq!(reader.stop().unwrap(), 1);
+    }
+}
@@ -0,0 +1,210 @@
+//! Controller state tracking
+//!
+//! CAN controllers report changes of their error state through error
+//! frames, but only as individual problems: an error frame tells that the
+//! transmit error counter reached the warning level, not that the bus went
+//! from error active to error warning. `BusStateTracker` keeps the current
+//! state and turns error frames into transitions, reporting each change
+//! once.
+//!
+//! ```no_run
+//! use socketcan::{CanSocket, ERR_MASK_ALL};
+//! use socketcan::state::BusStateTracker;
+//!
+//! let mut socket = CanSocket::open("can0").unwrap();
+//! socket.set_error_mask(ERR_MASK_ALL).unwrap();
+//!
+//! let mut tracker = BusStateTracker::new();
+//! loop {
+//!     let (frame, t) = socket.read_frame_with_timestamp().unwrap();
+//!     if let Some(change) = tracker.process_frame(&frame, t) {
+//!         println!("{} -> {}", change.from, change.to);
+//!     }
+//! }
+//! ```
+
+use std::fmt;
+use std::time::SystemTime;
+use CanFrame;
+use constants::{CAN_ERR_BUSOFF, CAN_ERR_CRTL, CAN_ERR_RESTARTED};
+
+// linux/can/error.h, controller problems in data[1]
+const CAN_ERR_CRTL_RX_WARNING: u8 = 0x04;
+const CAN_ERR_CRTL_TX_WARNING: u8 = 0x08;
+const CAN_ERR_CRTL_RX_PASSIVE: u8 = 0x10;
+const CAN_ERR_CRTL_TX_PASSIVE: u8 = 0x20;
+const CAN_ERR_CRTL_ACTIVE: u8 = 0x40;
+
+/// Error state of a CAN controller, in order of severity
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum BusState {
+    /// Normal operation
+    ErrorActive,
+    /// An error counter reached the warning level of 96
+    ErrorWarning,
+    /// An error counter exceeded 127, the controller no longer sends
+    /// active error flags
+    ErrorPassive,
+    /// The transmit error counter exceeded 255, the controller left the
+    /// bus
+    BusOff,
+}
+
+impl BusState {
+    /// The state an error frame reports, if any.
+    ///
+    /// A restart reports `ErrorActive`.
+    pub fn from_frame(frame: &CanFrame) -> Option<BusState> {
+        if !frame.is_error() {
+            return None;
+        }
+
+        let class = frame.err();
+        if class & CAN_ERR_BUSOFF != 0 {
+            return Some(BusState::BusOff);
+        }
+        if class & CAN_ERR_RESTARTED != 0 {
+            return Some(BusState::ErrorActive);
+        }
+        if class & CAN_ERR_CRTL == 0 {
+            return None;
+        }
+
+        let problem = frame.data().get(1).cloned().unwrap_or(0);
+        if problem & (CAN_ERR_CRTL_RX_PASSIVE | CAN_ERR_CRTL_TX_PASSIVE) != 0 {
+            Some(BusState::ErrorPassive)
+        } else if problem & (CAN_ERR_CRTL_RX_WARNING | CAN_ERR_CRTL_TX_WARNING) != 0 {
+            Some(BusState::ErrorWarning)
+        } else if problem & CAN_ERR_CRTL_ACTIVE != 0 {
+            Some(BusState::ErrorActive)
+        } else {
+            None
+        }
+    }
+}
+
+impl fmt::Display for BusState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match *self {
+            BusState::ErrorActive => "error active",
+            BusState::ErrorWarning => "error warning",
+            BusState::ErrorPassive => "error passive",
+            BusState::BusOff => "bus off",
+        })
+    }
+}
+
+/// A transition between two bus states
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BusStateChange {
+    pub from: BusState,
+    pub to: BusState,
+    /// Time of the error frame reporting the change
+    pub timestamp: SystemTime,
+}
+
+/// Tracker of the bus state, fed with error frames
+#[derive(Clone, Debug)]
+pub struct BusStateTracker {
+    state: BusState,
+    since: Option<SystemTime>,
+}
+
+impl Default for BusStateTracker {
+    fn default() -> BusStateTracker {
+        BusStateTracker::new()
+    }
+}
+
+impl BusStateTracker {
+    /// Create a tracker assuming the bus is error active.
+    pub fn new() -> BusStateTracker {
+        BusStateTracker::with_state(BusState::ErrorActive)
+    }
+
+    /// Create a tracker starting from `state`, e.g. as read from the
+    /// interface.
+    pub fn with_state(state: BusState) -> BusStateTracker {
+        BusStateTracker {
+            state: state,
+            since: None,
+        }
+    }
+
+    /// The current state
+    pub fn state(&self) -> BusState {
+        self.state
+    }
+
+    /// Time of the last change, `None` if the state did not change yet
+    pub fn since(&self) -> Option<SystemTime> {
+        self.since
+    }
+
+    /// Account for `frame` received at `timestamp`.
+    ///
+    /// Returns the transition if the frame reports a state other than the
+    /// current one. Other frames, including repeated reports of the
+    /// current state, are ignored.
+    pub fn process_frame(&mut self,
+                         frame: &CanFrame,
+                         timestamp: SystemTime)
+                         -> Option<BusStateChange> {
+        let state = match BusState::from_frame(frame) {
+            Some(state) if state != self.state => state,
+            _ => return None,
+        };
+
+        let change = BusStateChange {
+            from: self.state,
+            to: state,
+            timestamp: timestamp,
+        };
+        self.state = state;
+        self.since = Some(timestamp);
+        Some(change)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, UNIX_EPOCH};
+    use CanFrame;
+    use constants::{CAN_ERR_BUSOFF, CAN_ERR_CRTL, CAN_ERR_PROT, CAN_ERR_RESTARTED};
+    use super::{BusState, BusStateTracker};
+
+    fn error(class: u32, problem: u8) -> CanFrame {
+        CanFrame::new(class, &[0, problem, 0, 0, 0, 0, 0, 0], false, true).unwrap()
+    }
+
+    #[test]
+    fn test_transitions() {
+        let mut tracker = BusStateTracker::new();
+        let t = |ms| UNIX_EPOCH + Duration::from_millis(ms);
+
+        let frames = [(error(CAN_ERR_CRTL, 0x08), BusState::ErrorWarning),
+                      (error(CAN_ERR_CRTL, 0x08), BusState::ErrorWarning),
+                      (error(CAN_ERR_PROT, 0), BusState::ErrorWarning),
+                      (error(CAN_ERR_CRTL, 0x20), BusState::ErrorPassive),
+                      (error(CAN_ERR_BUSOFF, 0), BusState::BusOff),
+                      (error(CAN_ERR_RESTARTED, 0), BusState::ErrorActive)];
+
+        let mut changes = Vec::new();
+        for (n, &(ref frame, state)) in frames.iter().enumerate() {
+            changes.extend(tracker.process_frame(frame, t(n as u64)));
+            assert_eq!(tracker.state(), state);
+        }
+
+        let states: Vec<_> = changes.iter().map(|c| (c.from, c.to)).collect();
+        assert_eq!(states,
+                   vec![(BusState::ErrorActive, BusState::ErrorWarning),
+                        (BusState::ErrorWarning, BusState::ErrorPassive),
+                        (BusState::ErrorPassive, BusState::BusOff),
+                        (BusState::BusOff, BusState::ErrorActive)]);
+        assert_eq!(changes[1].timestamp, t(3));
+        assert_eq!(tracker.since(), Some(t(5)));
+
+        let data = CanFrame::new(0x123, &[0, 0x20], false, false).unwrap();
+        assert_eq!(tracker.process_frame(&data, t(6)), None);
+    }
+}
@@ -0,0 +1,118 @@
+//! Instrumentation hooks
+//!
+//! Called at the relevant points of the socket layer. Without the
+//! `metrics` and `tracing` features, all hooks compile to nothing.
+//!
+//! With the `tracing` feature, events are emitted for opening sockets
+//! (`debug`), reading and writing frames (`trace`, with the frame ID as
+//! field), changing filters (`debug`) and IO errors (`warn`, timeouts are
+//! not reported). All events carry the socket's file descriptor as `fd`.
+//!
+//! Metrics emitted:
+//!
+//! * `socketcan_frames_rx_total`, `socketcan_frames_tx_total`: frames read
+//!   and written
+//! * `socketcan_bytes_rx_total`, `socketcan_bytes_tx_total`: payload bytes
+//!   read and written
+//! * `socketcan_error_frames_total`: error frames received
+//! * `socketcan_io_errors_total`: failed reads and writes, not counting
+//!   timeouts
+//! * `socketcan_queue_full_total`: writes rejected because the transmit
+//!   queue was full (`ENOBUFS`)
+//! * `socketcan_bus_load_percent`: the latest bus load sampled by
+//!   `analysis::BusLoad::sample`
+
+#![allow(unused_variables)]
+
+use std::io;
+use libc::{c_int, c_uint};
+use CanFrame;
+
+#[inline]
+pub fn socket_opened(if_index: c_uint, fd: c_int) {
+    #[cfg(feature = "tracing")]
+    ::tracing::debug!(if_index = if_index, fd = fd, "CAN socket opened");
+}
+
+#[inline]
+pub fn open_failed(if_index: c_uint, err: &io::Error) {
+    #[cfg(feature = "tracing")]
+    ::tracing::warn!(if_index = if_index, error = %err, "failed to open CAN socket");
+}
+
+#[inline]
+pub fn filters_set(fd: c_int, count: usize) {
+    #[cfg(feature = "tracing")]
+    ::tracing::debug!(fd = fd, count = count, "CAN filters set");
+}
+
+#[inline]
+pub fn error_mask_set(fd: c_int, mask: u32) {
+    #[cfg(feature = "tracing")]
+    ::tracing::debug!(fd = fd, mask = mask, "CAN error mask set");
+}
+
+#[inline]
+pub fn frame_received(fd: c_int, frame: &CanFrame) {
+    #[cfg(feature = "tracing")]
+    ::tracing::trace!(fd = fd,
+                      id = frame.id(),
+                      extended = frame.is_extended(),
+                      len = frame.data().len(),
+                      error = frame.is_error(),
+                      "frame received");
+
+    #[cfg(feature = "metrics")]
+    {
+        ::metrics::counter!("socketcan_frames_rx_total").increment(1);
+        ::metrics::counter!("socketcan_bytes_rx_total").increment(frame.data().len() as u64);
+        if frame.is_error() {
+            ::metrics::counter!("socketcan_error_frames_total").increment(1);
+        }
+    }
+}
+
+#[inline]
+pub fn frame_sent(fd: c_int, frame: &CanFrame) {
+    #[cfg(feature = "tracing")]
+    ::tracing::trace!(fd = fd,
+                      id = frame.id(),
+                      extended = frame.is_extended(),
+                      len = frame.data().len(),
+                      "frame sent");
+
+    #[cfg(feature = "metrics")]
+    {
+        ::metrics::counter!("socketcan_frames_tx_total").increment(1);
+        ::metrics::counter!("socketcan_bytes_tx_total").increment(frame.data().len() as u64);
+    }
+}
+
+#[inline]
+pub fn io_error(fd: c_int, err: &io::Error) {
+    #[cfg(feature = "tracing")]
+    {
+        use ShouldRetry;
+
+        if !err.should_retry() {
+            ::tracing::warn!(fd = fd, error = %err, "CAN socket IO error");
+        }
+    }
+
+    #[cfg(feature = "metrics")]
+    {
+        use ShouldRetry;
+
+        if err.raw_os_error() == Some(::libc::ENOBUFS) {
+            ::metrics::counter!("socketcan_queue_full_total").increment(1);
+        } else if !err.should_retry() {
+            ::metrics::counter!("socketcan_io_errors_total").increment(1);
+        }
+    }
+}
+
+#[inline]
+pub fn bus_load(percent: f64) {
+    #[cfg(feature = "metrics")]
+    ::metrics::gauge!("socketcan_bus_load_percent").set(percent);
+}
@@ -0,0 +1,961 @@
+//! In-process CAN bus for unit tests
+//!
+//! A `MockBus` connects any number of `MockSocket` endpoints in memory, so
+//! code using CAN can be tested without `vcan` interfaces or root
+//! privileges. `MockSocket` offers the same reading, writing and
+//! configuration methods as `CanSocket`.
+//!
+//! Time on the bus is simulated: frames are stamped with the bus clock,
+//! which only moves when `MockBus::advance` is called. Reads on an empty
+//! queue block until a frame is written by another thread or, with a read
+//! timeout, fail with `WouldBlock` once the timeout expired in real time.
+//! The bus clock is also available as a `Clock`, to drive timed components
+//! in simulated time. Tasks read without blocking with
+//! `MockSocket::poll_read_frame`.
+//!
+//! ```
+//! use socketcan::CanFrame;
+//! use socketcan::testing::MockBus;
+//!
+//! let bus = MockBus::new();
+//! let a = bus.endpoint();
+//! let b = bus.endpoint();
+//!
+//! a.write_frame(&CanFrame::new(0x123, &[1, 2], false, false).unwrap()).unwrap();
+//! assert_eq!(b.read_frame().unwrap().data(), &[1, 2]);
+//! ```
+//!
+//! Error handling is exercised by injecting faults into individual writes
+//! with `MockSocket::inject_fault`, e.g. `ENOBUFS` on the third write or a
+//! delayed or corrupted frame, and bus-off error frames with
+//! `MockBus::inject_bus_off`.
+//!
+//! `expect()` asserts that a sequence of frames arrives on either kind of
+//! socket, with a timeout per step.
+//!
+//! Tests that need a real kernel interface can use a `VcanGuard`, which
+//! provisions a uniquely named `vcan` interface for the duration of the
+//! test.
+
+use std::{cmp, error, fmt, io, process};
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::task::{Context, Poll, Waker};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use libc::{EOPNOTSUPP, EPERM};
+use {CanFilter, CanFrame, CanInterface, CanSocket, CanSocketOpenError, CanTransport, RecvMeta,
+     ShouldRetry, ERR_MASK_NONE};
+use clock::Clock;
+use constants::CAN_ERR_BUSOFF;
+use filter::filter_matches;
+
+#[derive(Debug)]
+struct Endpoint {
+    queue: VecDeque<(CanFrame, Duration, RecvMeta)>,
+    nonblocking: bool,
+    read_timeout: Option<Duration>,
+    loopback: bool,
+    recv_own_msgs: bool,
+    filters: Vec<CanFilter>,
+    join_filters: bool,
+    error_mask: u32,
+    read_errors: VecDeque<io::Error>,
+    write_errors: VecDeque<io::Error>,
+    // number of write attempts so far, and faults keyed by attempt number
+    writes: usize,
+    faults: Vec<(usize, Fault)>,
+    // task waiting in `poll_read_frame`
+    reader: Option<Waker>,
+}
+
+impl Endpoint {
+    fn new() -> Endpoint {
+        Endpoint {
+            queue: VecDeque::new(),
+            nonblocking: false,
+            read_timeout: None,
+            loopback: true,
+            recv_own_msgs: false,
+            filters: Vec::new(),
+            join_filters: false,
+            error_mask: ERR_MASK_NONE,
+            read_errors: VecDeque::new(),
+            write_errors: VecDeque::new(),
+            writes: 0,
+            faults: Vec::new(),
+            reader: None,
+        }
+    }
+
+    fn wake_reader(&mut self) {
+        if let Some(waker) = self.reader.take() {
+            waker.wake();
+        }
+    }
+
+    /// Whether the endpoint's filters let `frame` pass
+    fn accepts(&self, frame: &CanFrame) -> bool {
+        if frame.is_error() {
+            return frame.err() & self.error_mask != 0;
+        }
+
+        // without filters installed, sockets receive everything
+        if self.filters.is_empty() {
+            return true;
+        }
+
+        let mut results = self.filters.iter().map(|f| filter_matches(f, frame));
+        if self.join_filters {
+            results.all(|m| m)
+        } else {
+            results.any(|m| m)
+        }
+    }
+}
+
+#[derive(Debug)]
+struct BusState {
+    clock: Duration,
+    endpoints: Vec<Endpoint>,
+    // frames held back by `Fault::Delay`, with their delivery time
+    delayed: Vec<(Duration, usize, CanFrame)>,
+}
+
+/// A fault to inject into a write on the mock bus
+#[derive(Debug)]
+pub enum Fault {
+    /// Fail the write with the error, without sending the frame, e.g. with
+    /// `ENOBUFS` like a full transmit queue
+    WriteError(io::Error),
+    /// Deliver the frame once the bus clock has advanced by the duration
+    Delay(Duration),
+    /// Flip the bits set in the mask in the frame's data bytes
+    Corrupt([u8; 8]),
+    /// Report success, but never deliver the frame
+    Drop,
+}
+
+#[derive(Debug)]
+struct Shared {
+    state: Mutex<BusState>,
+    frame_written: Condvar,
+    // `Instant` corresponding to the start of the bus clock
+    epoch: Instant,
+}
+
+/// An in-memory CAN bus
+#[derive(Clone, Debug)]
+pub struct MockBus {
+    shared: Arc<Shared>,
+}
+
+impl MockBus {
+    /// Create a bus without endpoints, with the clock at the UNIX epoch.
+    pub fn new() -> MockBus {
+        MockBus {
+            shared: Arc::new(Shared {
+                state: Mutex::new(BusState {
+                    clock: Duration::from_millis(0),
+                    endpoints: Vec::new(),
+                    delayed: Vec::new(),
+                }),
+                frame_written: Condvar::new(),
+                epoch: Instant::now(),
+            }),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<BusState> {
+        self.shared.state.lock().expect("mock bus lock poisoned")
+    }
+
+    /// Connect a new endpoint to the bus.
+    pub fn endpoint(&self) -> MockSocket {
+        let mut state = self.lock();
+        state.endpoints.push(Endpoint::new());
+
+        MockSocket {
+            bus: self.clone(),
+            idx: state.endpoints.len() - 1,
+        }
+    }
+
+    /// Advance the bus clock, delivering delayed frames that became due.
+    pub fn advance(&self, duration: Duration) {
+        let mut state = self.lock();
+        let end = state.clock + duration;
+
+        // deliver in order of due time, each stamped with its due time
+        state.delayed.sort_by_key(|&(due, _, _)| due);
+        let due = state.delayed.iter().take_while(|&&(due, _, _)| due <= end).count();
+        let frames: Vec<_> = state.delayed.drain(..due).collect();
+        for (due, sender, frame) in frames {
+            state.clock = due;
+            MockBus::deliver_locked(&mut state, Some(sender), &frame);
+        }
+
+        state.clock = end;
+        self.shared.frame_written.notify_all();
+    }
+
+    /// Current time of the bus clock
+    pub fn now(&self) -> SystemTime {
+        UNIX_EPOCH + self.lock().clock
+    }
+
+    /// Deliver a frame to all endpoints as if sent by a node that is not
+    /// part of the test, e.g. to inject error frames.
+    pub fn inject_frame(&self, frame: &CanFrame) {
+        self.deliver(None, frame);
+    }
+
+    /// Deliver a bus-off error frame to all endpoints, as sent by the
+    /// controller when it stops participating in bus traffic.
+    pub fn inject_bus_off(&self) {
+        self.inject_frame(&CanFrame::new(CAN_ERR_BUSOFF, &[0; 8], false, true)
+            .expect("bus-off error frame is valid"));
+    }
+
+    fn deliver(&self, sender: Option<usize>, frame: &CanFrame) {
+        MockBus::deliver_locked(&mut self.lock(), sender, frame);
+        self.shared.frame_written.notify_all();
+    }
+
+    fn deliver_locked(state: &mut BusState, sender: Option<usize>, frame: &CanFrame) {
+        let clock = state.clock;
+        let loopback = sender.map_or(true, |idx| state.endpoints[idx].loopback);
+
+        for (idx, endpoint) in state.endpoints.iter_mut().enumerate() {
+            let own = Some(idx) == sender;
+            if !loopback || own && !endpoint.recv_own_msgs {
+                continue;
+            }
+            if endpoint.accepts(frame) {
+                let meta = RecvMeta {
+                    own: own,
+                    local: sender.is_some(),
+                };
+                endpoint.queue.push_back((*frame, clock, meta));
+                endpoint.wake_reader();
+            }
+        }
+    }
+}
+
+/// The bus clock as a `Clock`
+///
+/// Sleeping advances the bus clock instead of blocking, so components
+/// driven by the bus clock run as fast as possible while observing exact
+/// simulated timing.
+impl Clock for MockBus {
+    fn now(&self) -> Instant {
+        self.shared.epoch + self.lock().clock
+    }
+
+    fn sleep(&self, duration: Duration) {
+        self.advance(duration)
+    }
+}
+
+impl Default for MockBus {
+    fn default() -> MockBus {
+        MockBus::new()
+    }
+}
+
+/// An endpoint of a `MockBus`
+///
+/// Every endpoint receives the frames written by all other endpoints that
+/// pass its filters, unless the writer disabled loopback.
+#[derive(Debug)]
+pub struct MockSocket {
+    bus: MockBus,
+    idx: usize,
+}
+
+impl MockSocket {
+    fn with_endpoint<T, F: FnOnce(&mut Endpoint) -> T>(&self, f: F) -> T {
+        f(&mut self.bus.lock().endpoints[self.idx])
+    }
+
+    /// The bus this endpoint is connected to
+    pub fn bus(&self) -> &MockBus {
+        &self.bus
+    }
+
+    /// Make the next read fail with `err`.
+    pub fn inject_read_error(&self, err: io::Error) {
+        self.with_endpoint(|e| {
+            e.read_errors.push_back(err);
+            e.wake_reader();
+        });
+    }
+
+    /// Make the next write fail with `err`, without sending the frame.
+    pub fn inject_write_error(&self, err: io::Error) {
+        self.with_endpoint(|e| e.write_errors.push_back(err));
+    }
+
+    /// Inject `fault` into the `n`th write from now on, counting from one
+    /// for the next write.
+    ///
+    /// Errors injected with `inject_write_error` take precedence, but count
+    /// as writes.
+    pub fn inject_fault(&self, n: usize, fault: Fault) {
+        assert!(n > 0, "writes are counted from one");
+        self.with_endpoint(|e| {
+            let at = e.writes + n;
+            e.faults.push((at, fault))
+        });
+    }
+
+    /// Number of frames waiting to be read
+    pub fn pending(&self) -> usize {
+        self.with_endpoint(|e| e.queue.len())
+    }
+
+    pub fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> {
+        self.with_endpoint(|e| e.nonblocking = nonblocking);
+        Ok(())
+    }
+
+    /// Set the read timeout. A zero duration disables the timeout, like on
+    /// a real socket.
+    pub fn set_read_timeout(&self, duration: Duration) -> io::Result<()> {
+        let timeout = if duration == Duration::from_millis(0) { None } else { Some(duration) };
+        self.with_endpoint(|e| e.read_timeout = timeout);
+        Ok(())
+    }
+
+    /// Writes never block on the mock bus, the timeout is ignored.
+    pub fn set_write_timeout(&self, _duration: Duration) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Writes never block on the mock bus, the endpoint is always
+    /// writable.
+    pub fn poll_writable(&self, _timeout: Duration) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    pub fn is_writable(&self) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    fn read(&self) -> io::Result<(CanFrame, Duration, RecvMeta)> {
+        let mut state = self.bus.lock();
+        let mut deadline = None;
+        loop {
+            let timeout = {
+                let endpoint = &mut state.endpoints[self.idx];
+                if let Some(err) = endpoint.read_errors.pop_front() {
+                    return Err(err);
+                }
+                if let Some(entry) = endpoint.queue.pop_front() {
+                    return Ok(entry);
+                }
+                if endpoint.nonblocking {
+                    return Err(io::Error::new(io::ErrorKind::WouldBlock, "no frame available"));
+                }
+                endpoint.read_timeout
+            };
+
+            let frame_written = &self.bus.shared.frame_written;
+            state = match timeout {
+                Some(timeout) => {
+                    // the timeout runs on the wall clock, like on a real socket
+                    let deadline = *deadline.get_or_insert_with(|| Instant::now() + timeout);
+                    let now = Instant::now();
+                    if now >= deadline {
+                        return Err(io::Error::new(io::ErrorKind::WouldBlock,
+                                                  "no frame available"));
+                    }
+                    frame_written.wait_timeout(state, deadline - now)
+                        .expect("mock bus lock poisoned")
+                        .0
+                }
+                None => frame_written.wait(state).expect("mock bus lock poisoned"),
+            };
+        }
+    }
+
+    pub fn read_frame(&self) -> io::Result<CanFrame> {
+        self.read().map(|(frame, _, _)| frame)
+    }
+
+    /// Read a frame if one is queued, without blocking.
+    ///
+    /// Otherwise returns `Poll::Pending` and wakes the task of `cx` once a
+    /// frame arrives. Only the task of the latest call is woken.
+    pub fn poll_read_frame(&self, cx: &mut Context) -> Poll<io::Result<CanFrame>> {
+        self.with_endpoint(|e| {
+            if let Some(err) = e.read_errors.pop_front() {
+                return Poll::Ready(Err(err));
+            }
+            match e.queue.pop_front() {
+                Some((frame, _, _)) => Poll::Ready(Ok(frame)),
+                None => {
+                    e.reader = Some(cx.waker().clone());
+                    Poll::Pending
+                }
+            }
+        })
+    }
+
+    /// Read a frame along with where it came from.
+    ///
+    /// Frames of other endpoints count as local, frames injected through
+    /// `MockBus::inject_frame` as received from the bus.
+    pub fn read_frame_with_meta(&self) -> io::Result<(CanFrame, RecvMeta)> {
+        self.read().map(|(frame, _, meta)| (frame, meta))
+    }
+
+    /// Read as many queued frames as fit into `frames`, blocking only for
+    /// the first.
+    pub fn read_into(&self, frames: &mut [CanFrame]) -> io::Result<usize> {
+        if frames.is_empty() {
+            return Ok(0);
+        }
+
+        frames[0] = self.read_frame()?;
+        let mut state = self.bus.lock();
+        let queue = &mut state.endpoints[self.idx].queue;
+        let mut n = 1;
+        while n < frames.len() {
+            match queue.pop_front() {
+                Some((frame, _, _)) => frames[n] = frame,
+                None => break,
+            }
+            n += 1;
+        }
+        Ok(n)
+    }
+
+    /// Read a frame together with the bus time it was written at.
+    pub fn read_frame_with_timestamp(&mut self) -> io::Result<(CanFrame, SystemTime)> {
+        self.read().map(|(frame, t, _)| (frame, UNIX_EPOCH + t))
+    }
+
+    pub fn write_frame(&self, frame: &CanFrame) -> io::Result<()> {
+        let fault = self.with_endpoint(|e| {
+            e.writes += 1;
+            let fault = match e.faults.iter().position(|&(at, _)| at == e.writes) {
+                Some(i) => Some(e.faults.remove(i).1),
+                None => None,
+            };
+            match e.write_errors.pop_front() {
+                Some(err) => Err(err),
+                None => Ok(fault),
+            }
+        })?;
+
+        let mut frame = *frame;
+        match fault {
+            None => {}
+            Some(Fault::WriteError(err)) => return Err(err),
+            Some(Fault::Delay(delay)) => {
+                let mut state = self.bus.lock();
+                let due = state.clock + delay;
+                state.delayed.push((due, self.idx, frame));
+                return Ok(());
+            }
+            Some(Fault::Corrupt(mask)) => {
+                for (byte, mask) in frame._data.iter_mut().zip(&mask) {
+                    *byte ^= *mask;
+                }
+            }
+            Some(Fault::Drop) => return Ok(()),
+        }
+
+        self.bus.deliver(Some(self.idx), &frame);
+        Ok(())
+    }
+
+    pub fn write_frame_insist(&self, frame: &CanFrame) -> io::Result<()> {
+        loop {
+            match self.write_frame(frame) {
+                Ok(v) => return Ok(v),
+                Err(e) => {
+                    if !e.should_retry() {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn set_filters(&self, filters: &[CanFilter]) -> io::Result<()> {
+        self.with_endpoint(|e| e.filters = filters.to_vec());
+        Ok(())
+    }
+
+    /// Replace the filters and the join setting in one step, see
+    /// `CanSocket::update_filters`.
+    pub fn update_filters(&self, filters: &[CanFilter], join: bool) -> io::Result<()> {
+        self.with_endpoint(|e| {
+            e.filters = filters.to_vec();
+            e.join_filters = join;
+        });
+        Ok(())
+    }
+
+    pub fn set_error_mask(&self, mask: u32) -> io::Result<()> {
+        self.with_endpoint(|e| e.error_mask = mask);
+        Ok(())
+    }
+
+    pub fn set_loopback(&self, enabled: bool) -> io::Result<()> {
+        self.with_endpoint(|e| e.loopback = enabled);
+        Ok(())
+    }
+
+    pub fn set_recv_own_msgs(&self, enabled: bool) -> io::Result<()> {
+        self.with_endpoint(|e| e.recv_own_msgs = enabled);
+        Ok(())
+    }
+
+    pub fn set_join_filters(&self, enabled: bool) -> io::Result<()> {
+        self.with_endpoint(|e| e.join_filters = enabled);
+        Ok(())
+    }
+}
+
+static VCAN_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A temporary `vcan` interface
+///
+/// Created and brought up on construction, deleted again on drop. The name
+/// is unique per process and guard, so tests can run in parallel.
+///
+/// ```no_run
+/// use socketcan::testing::VcanGuard;
+///
+/// let vcan = VcanGuard::new().unwrap();
+/// let socket = vcan.open().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct VcanGuard {
+    name: String,
+    interface: Option<CanInterface>,
+}
+
+impl VcanGuard {
+    /// Create a new interface.
+    ///
+    /// Fails with `PermissionDenied` without `CAP_NET_ADMIN` and with
+    /// `Other` if the `vcan` kernel module is not available.
+    pub fn new() -> io::Result<VcanGuard> {
+        let name = format!("vt{}x{}",
+                           process::id(),
+                           VCAN_COUNTER.fetch_add(1, Ordering::SeqCst));
+
+        let interface = CanInterface::create_vcan(&name).map_err(|e| match e.raw_os_error() {
+            Some(EPERM) => {
+                io::Error::new(io::ErrorKind::PermissionDenied,
+                               "creating a vcan interface requires CAP_NET_ADMIN")
+            }
+            Some(EOPNOTSUPP) => {
+                io::Error::new(io::ErrorKind::Other, "vcan kernel module not available")
+            }
+            _ => e,
+        })?;
+
+        let guard = VcanGuard {
+            name: name,
+            interface: Some(interface),
+        };
+        guard.interface().bring_up()?;
+        Ok(guard)
+    }
+
+    /// Name of the interface
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn interface(&self) -> &CanInterface {
+        self.interface.as_ref().expect("interface is only taken on drop")
+    }
+
+    /// Open a socket on the interface.
+    pub fn open(&self) -> Result<CanSocket, CanSocketOpenError> {
+        CanSocket::open(&self.name)
+    }
+}
+
+impl Drop for VcanGuard {
+    fn drop(&mut self) {
+        if let Some(interface) = self.interface.take() {
+            let _ = interface.delete();
+        }
+    }
+}
+
+/// Per step timeout of `expect()` sequences, unless set with `within`
+pub const DEFAULT_EXPECT_TIMEOUT_MS: u64 = 1000;
+
+/// Start a sequence of expected frames.
+///
+/// Each step matches the next frame that satisfies its conditions, frames
+/// in between are ignored. A step fails if no such frame arrives within
+/// its timeout, counted from the previous match.
+///
+/// ```
+/// use std::time::Duration;
+/// use socketcan::CanFrame;
+/// use socketcan::testing::{self, MockBus};
+///
+/// let bus = MockBus::new();
+/// let (node, socket) = (bus.endpoint(), bus.endpoint());
+/// node.write_frame(&CanFrame::new(0x181, &[1, 0], false, false).unwrap()).unwrap();
+/// node.write_frame(&CanFrame::new(0x701, &[5], false, false).unwrap()).unwrap();
+///
+/// testing::expect()
+///     .frame(0x181).byte(0, 1).within(Duration::from_millis(100))
+///     .frame(0x701)
+///     .run(&socket)
+///     .unwrap();
+/// ```
+pub fn expect() -> Expect {
+    Expect { steps: Vec::new() }
+}
+
+#[derive(Clone, Debug)]
+struct Step {
+    id: Option<u32>,
+    len: Option<usize>,
+    bytes: Vec<(usize, u8)>,
+    timeout: Duration,
+}
+
+impl Step {
+    fn matches(&self, frame: &CanFrame) -> bool {
+        match self.id {
+            Some(id) if frame.is_error() || frame.id() != id => return false,
+            _ => {}
+        }
+        if self.len.map_or(false, |len| frame.data().len() != len) {
+            return false;
+        }
+        self.bytes.iter().all(|&(i, value)| frame.data().get(i) == Some(&value))
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.id {
+            Some(id) => write!(f, "{:X}", id)?,
+            None => write!(f, "any frame")?,
+        }
+        if let Some(len) = self.len {
+            write!(f, " with {} bytes", len)?;
+        }
+        for &(i, value) in &self.bytes {
+            write!(f, ", byte {} == {:02X}", i, value)?;
+        }
+        Ok(())
+    }
+}
+
+/// A sequence of expected frames, see `expect()`
+#[derive(Clone, Debug)]
+pub struct Expect {
+    steps: Vec<Step>,
+}
+
+impl Expect {
+    fn add(mut self, id: Option<u32>) -> Expect {
+        self.steps.push(Step {
+            id: id,
+            len: None,
+            bytes: Vec::new(),
+            timeout: Duration::from_millis(DEFAULT_EXPECT_TIMEOUT_MS),
+        });
+        self
+    }
+
+    fn last(&mut self) -> &mut Step {
+        self.steps.last_mut().expect("no frame expected yet, start with `frame` or `any_frame`")
+    }
+
+    /// Expect a frame with `id`. Error frames never match.
+    pub fn frame(self, id: u32) -> Expect {
+        self.add(Some(id))
+    }
+
+    /// Expect a frame with any ID.
+    pub fn any_frame(self) -> Expect {
+        self.add(None)
+    }
+
+    /// Require byte `index` of the current step's frame to be `value`.
+    ///
+    /// Bytes without a requirement match any value.
+    ///
+    /// # Panics
+    ///
+    /// If no step was started with `frame` or `any_frame`.
+    pub fn byte(mut self, index: usize, value: u8) -> Expect {
+        self.last().bytes.push((index, value));
+        self
+    }
+
+    /// Require the current step's frame to carry exactly `data`.
+    pub fn data(mut self, data: &[u8]) -> Expect {
+        {
+            let step = self.last();
+            step.len = Some(data.len());
+            step.bytes = data.iter().cloned().enumerate().collect();
+        }
+        self
+    }
+
+    /// Require the current step's frame to carry `len` bytes.
+    pub fn len(mut self, len: usize) -> Expect {
+        self.last().len = Some(len);
+        self
+    }
+
+    /// Fail the current step if it does not match within `timeout`.
+    pub fn within(mut self, timeout: Duration) -> Expect {
+        self.last().timeout = timeout;
+        self
+    }
+
+    /// Read from `transport` until all steps matched, returning the
+    /// matching frames.
+    ///
+    /// The read timeout of `transport` is changed.
+    pub fn run<T>(&self, transport: &T) -> Result<Vec<CanFrame>, ExpectError>
+        where T: CanTransport + ?Sized
+    {
+        let mut matched = Vec::with_capacity(self.steps.len());
+
+        for (n, step) in self.steps.iter().enumerate() {
+            let deadline = Instant::now() + step.timeout;
+            loop {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(ExpectError::Timeout {
+                        step: n,
+                        expected: step.to_string(),
+                        timeout: step.timeout,
+                    });
+                }
+
+                // a zero timeout would block indefinitely
+                let remaining = cmp::max(deadline - now, Duration::from_millis(1));
+                transport.set_read_timeout(remaining)?;
+                match transport.read_frame() {
+                    Ok(ref frame) if step.matches(frame) => {
+                        matched.push(*frame);
+                        break;
+                    }
+                    Ok(_) => {}
+                    Err(ref e) if e.should_retry() => {}
+                    Err(e) => return Err(ExpectError::Io(e)),
+                }
+            }
+        }
+
+        Ok(matched)
+    }
+}
+
+/// Failure of an `expect()` sequence
+#[derive(Debug)]
+pub enum ExpectError {
+    /// Step `step`, counted from zero, did not match in time
+    Timeout {
+        step: usize,
+        expected: String,
+        timeout: Duration,
+    },
+    /// Reading failed
+    Io(io::Error),
+}
+
+impl fmt::Display for ExpectError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExpectError::Timeout { step, ref expected, timeout } => {
+                write!(f,
+                       "step {}: expected {} within {} ms",
+                       step,
+                       expected,
+                       timeout.as_secs() * 1000 + (timeout.subsec_nanos() / 1_000_000) as u64)
+            }
+            ExpectError::Io(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl error::Error for ExpectError {
+    fn description(&self) -> &str {
+        match *self {
+            ExpectError::Timeout { .. } => "expected frame not received in time",
+            ExpectError::Io(ref e) => e.description(),
+        }
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        match *self {
+            ExpectError::Io(ref e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ExpectError {
+    fn from(e: io::Error) -> ExpectError {
+        ExpectError::Io(e)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{io, thread};
+    use std::time::{Duration, UNIX_EPOCH};
+    use {CanFilter, CanFrame, ShouldRetry, ERR_MASK_ALL};
+    use libc::ENOBUFS;
+    use super::{expect, ExpectError, Fault, MockBus};
+
+    #[test]
+    fn test_exchange_and_filters() {
+        let bus = MockBus::new();
+        let (a, mut b) = (bus.endpoint(), bus.endpoint());
+        b.set_read_timeout(Duration::from_millis(100)).unwrap();
+        b.set_filters(&[CanFilter::new(0x100, 0x700).unwrap()]).unwrap();
+
+        bus.advance(Duration::from_secs(2));
+        a.write_frame(&CanFrame::new(0x123, &[1], false, false).unwrap()).unwrap();
+        a.write_frame(&CanFrame::new(0x223, &[2], false, false).unwrap()).unwrap();
+
+        let (frame, t) = b.read_frame_with_timestamp().unwrap();
+        assert_eq!(frame.id(), 0x123);
+        assert_eq!(t, UNIX_EPOCH + Duration::from_secs(2));
+        assert!(b.read_frame().should_retry());
+
+        // not received by the sender itself unless requested
+        assert_eq!(a.pending(), 0);
+        a.set_recv_own_msgs(true).unwrap();
+        a.write_frame(&CanFrame::new(0x123, &[], false, false).unwrap()).unwrap();
+        assert_eq!(a.pending(), 1);
+        let (_, meta) = a.read_frame_with_meta().unwrap();
+        assert!(meta.is_own_frame() && meta.is_local());
+        let (_, meta) = b.read_frame_with_meta().unwrap();
+        assert!(!meta.is_own_frame() && meta.is_local());
+
+        // without loopback, no other endpoint sees the frames of `a`
+        a.set_loopback(false).unwrap();
+        a.write_frame(&CanFrame::new(0x123, &[], false, false).unwrap()).unwrap();
+        assert_eq!((a.pending(), b.pending()), (0, 0));
+        a.set_loopback(true).unwrap();
+
+        // error frames only pass the error mask
+        bus.inject_frame(&CanFrame::new(0x4, &[0; 8], false, true).unwrap());
+        assert_eq!(b.pending(), 0);
+        b.set_error_mask(ERR_MASK_ALL).unwrap();
+        bus.inject_frame(&CanFrame::new(0x4, &[0; 8], false, true).unwrap());
+        assert_eq!(b.pending(), 1);
+        assert!(!b.read_frame_with_meta().unwrap().1.is_local());
+    }
+
+    #[test]
+    fn test_blocking_read_and_errors() {
+        let bus = MockBus::new();
+        let (a, b) = (bus.endpoint(), bus.endpoint());
+
+        a.inject_write_error(io::Error::from_raw_os_error(ENOBUFS));
+        assert!(a.write_frame(&CanFrame::new(0x1, &[], false, false).unwrap()).is_err());
+
+        let writer = thread::spawn(move || {
+            a.write_frame(&CanFrame::new(0x2, &[], false, false).unwrap()).unwrap();
+            a
+        });
+        assert_eq!(b.read_frame().unwrap().id(), 0x2);
+        let a = writer.join().unwrap();
+
+        // a read timeout waits in real time for frames of other threads
+        b.set_read_timeout(Duration::from_secs(5)).unwrap();
+        let writer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            a.write_frame(&CanFrame::new(0x3, &[], false, false).unwrap()).unwrap();
+        });
+        assert_eq!(b.read_frame().unwrap().id(), 0x3);
+        writer.join().unwrap();
+
+        b.set_read_timeout(Duration::from_millis(20)).unwrap();
+        assert_eq!(b.read_frame().unwrap_err().kind(), io::ErrorKind::WouldBlock);
+
+        b.inject_read_error(io::Error::new(io::ErrorKind::Other, "bus off"));
+        assert!(b.read_frame().is_err());
+    }
+
+    #[test]
+    fn test_expect() {
+        let bus = MockBus::new();
+        let (node, socket) = (bus.endpoint(), bus.endpoint());
+
+        let writer = thread::spawn(move || {
+            node.write_frame(&CanFrame::new(0x181, &[0, 7], false, false).unwrap()).unwrap();
+            node.write_frame(&CanFrame::new(0x181, &[1, 7], false, false).unwrap()).unwrap();
+            thread::sleep(Duration::from_millis(10));
+            node.write_frame(&CanFrame::new(0x701, &[5], false, false).unwrap()).unwrap();
+        });
+
+        let frames = expect()
+            .frame(0x181).byte(0, 1).within(Duration::from_millis(500))
+            .frame(0x701).data(&[5])
+            .run(&socket)
+            .unwrap();
+        assert_eq!(frames.iter().map(|f| f.data()[0]).collect::<Vec<_>>(), vec![1, 5]);
+        writer.join().unwrap();
+
+        let err = expect().any_frame().within(Duration::from_millis(5)).run(&socket).unwrap_err();
+        match err {
+            ExpectError::Timeout { step: 0, .. } => {}
+            ref e => panic!("unexpected error: {}", e),
+        }
+        assert_eq!(err.to_string(), "step 0: expected any frame within 5 ms");
+    }
+
+    #[test]
+    fn test_faults() {
+        let bus = MockBus::new();
+        let (a, mut b) = (bus.endpoint(), bus.endpoint());
+        b.set_read_timeout(Duration::from_millis(100)).unwrap();
+        b.set_error_mask(ERR_MASK_ALL).unwrap();
+        let frame = |id| CanFrame::new(id, &[0x0f, 0xf0], false, false).unwrap();
+
+        a.inject_fault(2, Fault::WriteError(io::Error::from_raw_os_error(ENOBUFS)));
+        a.inject_fault(3, Fault::Corrupt([0xff, 0, 0, 0, 0, 0, 0, 0]));
+        a.inject_fault(4, Fault::Delay(Duration::from_millis(10)));
+        a.inject_fault(5, Fault::Drop);
+
+        a.write_frame(&frame(1)).unwrap();
+        let err = a.write_frame(&frame(2)).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(ENOBUFS));
+        a.write_frame(&frame(3)).unwrap();
+        a.write_frame(&frame(4)).unwrap();
+        a.write_frame(&frame(5)).unwrap();
+        a.write_frame(&frame(6)).unwrap();
+
+        assert_eq!(b.read_frame().unwrap().id(), 1);
+        assert_eq!(b.read_frame().unwrap().data(), &[0xf0, 0xf0]);
+        assert_eq!(b.read_frame().unwrap().id(), 6);
+        assert!(b.read_frame().should_retry());
+
+        bus.advance(Duration::from_millis(15));
+        let (frame, t) = b.read_frame_with_timestamp().unwrap();
+        assert_eq!(frame.id(), 4);
+        assert_eq!(t, UNIX_EPOCH + Duration::from_millis(10));
+        assert_eq!(bus.now(), UNIX_EPOCH + Duration::from_millis(15));
+
+        bus.inject_bus_off();
+        let frame = b.read_frame().unwrap();
+        assert!(frame.is_error());
+        assert_eq!(frame.err(), ::constants::CAN_ERR_BUSOFF);
+    }
+}
@@ -1,35 +1,239 @@
-use CanSocket;
+use std::io;
+use std::os::unix::io::FromRawFd;
+use libc::{socketpair, write, AF_UNIX, SOCK_DGRAM};
+use {CanFrame, CanSocket, FrameReadError, LengthPolicy};
 
 #[test]
 fn test_nonexistant_device() {
     assert!(CanSocket::open("invalid").is_err());
 }
 
+#[test]
+fn test_validate_received_frame() {
+    let mut frame = CanFrame::new(0x123, &[1, 2, 3, 4, 5, 6, 7, 8], false, false).unwrap();
+    assert!(frame.validate().is_ok());
+    assert_eq!(frame.len8_dlc(), None);
+    assert_eq!(frame.dlc(), 8);
+
+    frame._data_len = 12;
+    assert!(frame.validate().is_ok());
+    assert_eq!(frame.data().len(), 8);
+    assert_eq!(frame.len8_dlc(), Some(12));
+    assert_eq!((frame.len(), frame.dlc()), (8, 12));
+
+    frame._data_len = 200;
+    assert_eq!(frame.validate(), Err(FrameReadError::InvalidLength(200)));
+    assert_eq!(frame.data().len(), 8);
+}
+
+#[test]
+fn test_read_oversized_frame() {
+    // a datagram socket delivers whole messages like a CAN socket
+    let mut fds = [0; 2];
+    assert_eq!(unsafe { socketpair(AF_UNIX, SOCK_DGRAM, 0, fds.as_mut_ptr()) }, 0);
+    let socket = unsafe { CanSocket::from_raw_fd(fds[0]) };
+    let peer = unsafe { CanSocket::from_raw_fd(fds[1]) };
+
+    // an FD frame of 12 bytes, whose first 16 bytes look like a classic frame
+    let mut fd_frame = [0u8; 72];
+    fd_frame[..4].copy_from_slice(&0x123u32.to_ne_bytes());
+    fd_frame[4] = 12;
+    let written = unsafe { write(fds[1], fd_frame.as_ptr() as *const _, fd_frame.len()) };
+    assert_eq!(written, 72);
+
+    let err = socket.read_frame().unwrap_err();
+    assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    assert_eq!(err.into_inner().unwrap().downcast_ref::<FrameReadError>(),
+               Some(&FrameReadError::Oversized));
+
+    peer.write_frame(&CanFrame::new(0x123, &[1], false, false).unwrap()).unwrap();
+    assert_eq!(socket.read_frame().unwrap().data(), &[1]);
+}
+
+#[test]
+fn test_length_policy() {
+    let mut frame = CanFrame::new(0x123, &[1, 2, 3, 4, 5, 6, 7, 8], false, false).unwrap();
+    frame._data_len = 12;
+    assert_eq!(frame.validate_with(LengthPolicy::Strict),
+               Err(FrameReadError::InvalidLength(12)));
+
+    frame._data_len = 200;
+    assert_eq!(frame.validate_with(LengthPolicy::Len8Dlc),
+               Err(FrameReadError::InvalidLength(200)));
+    assert!(frame.validate_with(LengthPolicy::Truncate).is_ok());
+    assert!(frame.is_truncated());
+    assert_eq!((frame.len(), frame.dlc(), frame.len8_dlc()), (8, 8, None));
+
+    let mut frame = CanFrame::new(0x123, &[1, 2, 3, 4, 5, 6, 7, 8], false, false).unwrap();
+    frame._data_len = 12;
+    assert!(frame.validate_with(LengthPolicy::Truncate).is_ok());
+    assert!(!frame.is_truncated());
+    assert_eq!(frame.len8_dlc(), Some(12));
+}
+
+#[test]
+fn test_frame_bytes() {
+    let frame = CanFrame::new(0x18ff0001, &[1, 2, 3], false, false).unwrap();
+    let bytes = frame.as_bytes();
+    assert_eq!(bytes.len(), 16);
+    assert_eq!(&bytes[4..11], &[3, 0, 0, 0, 1, 2, 3]);
+
+    let copy = CanFrame::from_bytes(bytes).unwrap();
+    assert_eq!(copy.as_bytes(), bytes);
+    assert!(copy.is_extended());
+    assert_eq!((copy.id(), copy.data()), (0x18ff0001, &[1, 2, 3][..]));
+
+    assert_eq!(CanFrame::from_bytes(&bytes[..8]).unwrap_err(),
+               FrameReadError::ShortRead(8));
+    let mut invalid = bytes.to_vec();
+    invalid[4] = 16;
+    assert_eq!(CanFrame::from_bytes(&invalid).unwrap_err(),
+               FrameReadError::InvalidLength(16));
+}
+
+#[test]
+fn test_const_frame() {
+    const FRAMES: [CanFrame; 2] = [CanFrame::new_const(0x123, &[1, 2], true, false),
+                                   can_frame!(0x1fffffff, [0xde, 0xad, 0xbe, 0xef])];
+    assert!(FRAMES[0].is_rtr() && !FRAMES[0].is_extended());
+    assert_eq!((FRAMES[0].id(), FRAMES[0].data()), (0x123, &[1, 2][..]));
+    assert!(FRAMES[1].is_extended() && !FRAMES[1].is_rtr());
+    assert_eq!(FRAMES[1].as_bytes(),
+               CanFrame::new(0x1fffffff, &[0xde, 0xad, 0xbe, 0xef], false, false)
+                   .unwrap()
+                   .as_bytes());
+}
 
 #[cfg(feature = "vcan_tests")]
 mod vcan_tests {
-    use {CanFrame, CanInterface, CanSocket, ERR_MASK_ALL, ERR_MASK_NONE};
+    use {CanFilter, CanFrame, CanInterface, CanSocket, ERR_MASK_ALL, ERR_MASK_NONE};
     use std::time;
+    use fd::CanFdFrame;
+    use testing::VcanGuard;
+    use timestamp::TimestampMode;
     use ShouldRetry;
 
+    fn vcan() -> VcanGuard {
+        VcanGuard::new().expect("could not provision a vcan interface")
+    }
+
     #[test]
-    fn vcan0_timeout() {
-        let cs = CanSocket::open("vcan0").unwrap();
+    fn vcan_timeout() {
+        let vcan = vcan();
+        let cs = vcan.open().unwrap();
         cs.set_read_timeout(time::Duration::from_millis(100))
             .unwrap();
         assert!(cs.read_frame().should_retry());
     }
 
     #[test]
-    fn vcan0_set_error_mask() {
-        let cs = CanSocket::open("vcan0").unwrap();
+    fn vcan_read_opt() {
+        let vcan = vcan();
+        let (tx, rx) = (vcan.open().unwrap(), vcan.open().unwrap());
+        assert!(rx.read_opt(time::Duration::from_millis(0)).unwrap().is_none());
+        assert!(rx.read_opt(time::Duration::from_millis(10)).unwrap().is_none());
+
+        tx.write_frame(&CanFrame::new(0x123, &[], false, false).unwrap()).unwrap();
+        assert_eq!(rx.read_opt(time::Duration::from_millis(0)).unwrap().unwrap().id(), 0x123);
+    }
+
+    #[test]
+    fn vcan_busy_poll() {
+        let vcan = vcan();
+        let (tx, rx) = (vcan.open().unwrap(), vcan.open().unwrap());
+        rx.set_busy_poll(time::Duration::from_millis(0)).unwrap();
+
+        tx.write_frame(&CanFrame::new(0x123, &[], false, false).unwrap()).unwrap();
+        let frame = rx.read_frame_spin(time::Duration::from_millis(1)).unwrap();
+        assert_eq!(frame.id(), 0x123);
+
+        // falls back to blocking once the spin time is up
+        rx.set_read_timeout(time::Duration::from_millis(10)).unwrap();
+        assert!(rx.read_frame_spin(time::Duration::from_millis(1)).unwrap_err().should_retry());
+    }
+
+    #[test]
+    fn vcan_timestamping() {
+        let vcan = vcan();
+        let (tx, rx) = (vcan.open().unwrap(), vcan.open().unwrap());
+        rx.set_timestamping(TimestampMode::Software).unwrap();
+
+        let before = time::SystemTime::now();
+        tx.write_frame(&CanFrame::new(0x123, &[1], false, false).unwrap()).unwrap();
+        let (frame, ts) = rx.read_with_timestamp().unwrap();
+        assert_eq!(frame.id(), 0x123);
+        assert!(ts.software.unwrap() >= before);
+        assert_eq!(ts.hardware, None);
+
+        rx.set_timestamping(TimestampMode::Off).unwrap();
+        tx.write_frame(&CanFrame::new(0x123, &[2], false, false).unwrap()).unwrap();
+        assert_eq!(rx.read_with_timestamp().unwrap().1.software, None);
+    }
+
+    #[test]
+    fn vcan_writable() {
+        let vcan = vcan();
+        let cs = vcan.open().unwrap();
+        assert!(cs.is_writable().unwrap());
+        assert!(cs.poll_writable(time::Duration::from_millis(10)).unwrap());
+    }
+
+    #[test]
+    fn vcan_fd_frames() {
+        let vcan = vcan();
+        let (tx, rx) = (vcan.open().unwrap(), CanSocket::open_fd(vcan.name()).unwrap());
+        assert!(!tx.fd_frames().unwrap() && rx.fd_frames().unwrap());
+        tx.set_fd_frames(true).unwrap();
+
+        tx.write_fd_frame(&CanFdFrame::new(0x123, &[0xaa; 20], true, true).unwrap()).unwrap();
+        tx.write_frame(&CanFrame::new(0x124, &[1, 2], false, false).unwrap()).unwrap();
+
+        let fd = rx.read_fd_frame().unwrap();
+        assert!(fd.is_fd() && fd.is_brs() && fd.is_esi());
+        assert_eq!(fd.len(), 20);
+
+        let classic = rx.read_fd_frame().unwrap();
+        assert!(!classic.is_fd());
+        assert_eq!(classic.to_classic().unwrap().data(), &[1, 2]);
+    }
+
+    #[test]
+    fn vcan_set_error_mask() {
+        let vcan = vcan();
+        let cs = vcan.open().unwrap();
         cs.set_error_mask(ERR_MASK_ALL).unwrap();
         cs.set_error_mask(ERR_MASK_NONE).unwrap();
     }
 
     #[test]
-    fn vcan0_enable_own_loopback() {
-        let cs = CanSocket::open("vcan0").unwrap();
+    fn vcan_error_filter() {
+        let vcan = vcan();
+        let cs = vcan.open().unwrap();
+        let filter = cs.error_filter().bus_off().no_ack();
+        assert_eq!(filter.mask(), 0x60);
+        filter.apply().unwrap();
+        cs.error_filter().all().apply().unwrap();
+    }
+
+    #[test]
+    fn vcan_disable_loopback() {
+        let vcan = vcan();
+        let (tx, rx) = (vcan.open().unwrap(), vcan.open().unwrap());
+        rx.set_read_timeout(time::Duration::from_millis(10)).unwrap();
+        tx.set_loopback(false).unwrap();
+
+        tx.write_frame(&CanFrame::new(0x123, &[], false, false).unwrap()).unwrap();
+        assert!(rx.read_frame().unwrap_err().should_retry());
+
+        tx.set_loopback(true).unwrap();
+        tx.write_frame(&CanFrame::new(0x123, &[], false, false).unwrap()).unwrap();
+        assert_eq!(rx.read_frame().unwrap().id(), 0x123);
+    }
+
+    #[test]
+    fn vcan_enable_own_loopback() {
+        let vcan = vcan();
+        let cs = vcan.open().unwrap();
         cs.set_loopback(true).unwrap();
         cs.set_recv_own_msgs(true).unwrap();
 
@@ -37,22 +241,75 @@ mod vcan_tests {
 
         cs.write_frame(&frame).unwrap();
 
-        cs.read_frame().unwrap();
+        let (_, meta) = cs.read_frame_with_meta().unwrap();
+        assert!(meta.is_own_frame());
+        assert!(meta.is_local());
     }
 
     #[test]
-    fn vcan0_set_down() {
-        let can_if = CanInterface::open("vcan0").unwrap();
-        can_if.bring_down().unwrap();
+    fn vcan_set_down() {
+        let vcan = vcan();
+        vcan.interface().bring_down().unwrap();
     }
 
     #[test]
-    fn vcan0_test_nonblocking() {
-        let cs = CanSocket::open("vcan0").unwrap();
+    fn vcan_test_nonblocking() {
+        let vcan = vcan();
+        let cs = vcan.open().unwrap();
         cs.set_nonblocking(true);
 
         // no timeout set, but should return immediately
         assert!(cs.read_frame().should_retry());
     }
 
+    #[test]
+    fn vcan_list_matching() {
+        let vcan = vcan();
+        assert!(CanInterface::list().unwrap().contains(&vcan.name().to_owned()));
+        assert_eq!(CanInterface::list_matching(vcan.name()).unwrap(), vec![vcan.name()]);
+        assert!(CanSocket::open_first_matching(vcan.name()).is_ok());
+
+        vcan.interface().bring_down().unwrap();
+        assert!(CanInterface::list_matching(vcan.name()).unwrap().is_empty());
+        assert!(CanSocket::open_first_matching(vcan.name()).is_err());
+    }
+
+    #[test]
+    fn vcan_update_filters() {
+        let vcan = vcan();
+        let (tx, rx) = (vcan.open().unwrap(), vcan.open().unwrap());
+        rx.set_read_timeout(time::Duration::from_millis(100)).unwrap();
+        rx.set_filters(&[CanFilter::new(0x100, 0x7ff).unwrap()]).unwrap();
+
+        let filters = [CanFilter::new(0x123, 0x7ff).unwrap(),
+                       CanFilter::new(0x100, 0x700).unwrap()];
+        rx.update_filters(&filters, true).unwrap();
+
+        for &id in &[0x100, 0x124, 0x123] {
+            tx.write_frame(&CanFrame::new(id, &[], false, false).unwrap()).unwrap();
+        }
+        assert_eq!(rx.read_frame().unwrap().id(), 0x123);
+        assert!(rx.read_frame().should_retry());
+
+        rx.update_filters(&filters, false).unwrap();
+        tx.write_frame(&CanFrame::new(0x124, &[], false, false).unwrap()).unwrap();
+        assert_eq!(rx.read_frame().unwrap().id(), 0x124);
+    }
+
+    #[test]
+    fn vcan_stats() {
+        let vcan = vcan();
+        let cs = vcan.open().unwrap();
+        let before = cs.stats().unwrap();
+
+        for _ in 0..3 {
+            cs.write_frame(&CanFrame::new(0x123, &[1, 2], false, false).unwrap()).unwrap();
+        }
+
+        let after = cs.stats().unwrap();
+        assert_eq!(after.tx_packets - before.tx_packets, 3);
+        assert_eq!(after.tx_bytes - before.tx_bytes, 6);
+        assert_eq!(after.device, None);
+    }
+
 }
@@ -0,0 +1,189 @@
+//! Receive timestamps
+//!
+//! Once enabled with `set_timestamping`, the kernel attaches the time a
+//! frame was received to each frame, which `read_with_timestamp` returns
+//! along with the frame in a single syscall. Devices with a hardware clock
+//! additionally report when the frame was seen on the wire.
+//!
+//! ```no_run
+//! use socketcan::CanSocket;
+//! use socketcan::timestamp::TimestampMode;
+//!
+//! let socket = CanSocket::open("can0").unwrap();
+//! socket.set_timestamping(TimestampMode::Hardware).unwrap();
+//!
+//! let (frame, ts) = socket.read_with_timestamp().unwrap();
+//! println!("{:X} software={:?} hardware={:?}", frame, ts.software, ts.hardware);
+//! ```
+
+use std::{cmp, io, mem, ptr};
+use std::mem::size_of;
+use std::time::SystemTime;
+use libc::{c_int, c_void, msghdr, timespec, CMSG_DATA, CMSG_FIRSTHDR, CMSG_NXTHDR, SOL_SOCKET};
+use {CanFrame, CanSocket};
+use constants::{SCM_TIMESTAMPING, SCM_TIMESTAMPNS, SOF_TIMESTAMPING_RAW_HARDWARE,
+                SOF_TIMESTAMPING_RX_HARDWARE, SOF_TIMESTAMPING_RX_SOFTWARE,
+                SOF_TIMESTAMPING_SOFTWARE, SO_TIMESTAMPING, SO_TIMESTAMPNS};
+use util::{set_socket_option, system_time_from_timespec};
+
+/// Size of the control buffer, in `u64`s. Fits both timestamp messages.
+const CONTROL_LEN: usize = 16;
+
+/// Which receive timestamps the kernel attaches to frames
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum TimestampMode {
+    /// No timestamps
+    Off,
+
+    /// Software timestamps, taken by the kernel on receive
+    Software,
+
+    /// Hardware timestamps where the device supports them, and software
+    /// timestamps
+    Hardware,
+}
+
+/// Receive timestamps of a frame
+///
+/// A timestamp is `None` if it was not enabled or the device does not
+/// provide it.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct RxTimestamp {
+    /// Time the kernel received the frame
+    pub software: Option<SystemTime>,
+
+    /// Time the device received the frame, on the clock of the device.
+    /// That clock is not necessarily synchronized to the system clock.
+    pub hardware: Option<SystemTime>,
+}
+
+fn non_zero(ts: timespec) -> Option<SystemTime> {
+    if ts.tv_sec == 0 && ts.tv_nsec == 0 {
+        None
+    } else {
+        Some(system_time_from_timespec(ts))
+    }
+}
+
+/// Collect the timestamps from the first `len` bytes of control messages.
+fn parse_control(control: &mut [u64], len: usize) -> RxTimestamp {
+    let mut ts = RxTimestamp::default();
+
+    let mut msg: msghdr = unsafe { mem::zeroed() };
+    msg.msg_control = control.as_mut_ptr() as *mut c_void;
+    msg.msg_controllen = len as _;
+
+    unsafe {
+        let mut cmsg = CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            let data = CMSG_DATA(cmsg);
+            match ((*cmsg).cmsg_level, (*cmsg).cmsg_type) {
+                (SOL_SOCKET, SCM_TIMESTAMPNS) => {
+                    ts.software = non_zero(ptr::read_unaligned(data as *const timespec));
+                }
+                (SOL_SOCKET, SCM_TIMESTAMPING) => {
+                    // software, deprecated, raw hardware
+                    let stamps = ptr::read_unaligned(data as *const [timespec; 3]);
+                    ts.software = ts.software.or_else(|| non_zero(stamps[0]));
+                    ts.hardware = non_zero(stamps[2]);
+                }
+                _ => {}
+            }
+            cmsg = CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    ts
+}
+
+impl CanSocket {
+    /// Select the receive timestamps returned by `read_with_timestamp`.
+    ///
+    /// Hardware timestamps must also be enabled on the device, which
+    /// requires `CAP_NET_ADMIN` (`hwstamp_ctl` or the `SIOCSHWTSTAMP`
+    /// ioctl).
+    pub fn set_timestamping(&self, mode: TimestampMode) -> io::Result<()> {
+        let (ns, flags): (c_int, c_int) = match mode {
+            TimestampMode::Off => (0, 0),
+            TimestampMode::Software => (1, 0),
+            TimestampMode::Hardware => {
+                (0,
+                 SOF_TIMESTAMPING_RX_HARDWARE | SOF_TIMESTAMPING_RAW_HARDWARE |
+                 SOF_TIMESTAMPING_RX_SOFTWARE | SOF_TIMESTAMPING_SOFTWARE)
+            }
+        };
+
+        set_socket_option(self.fd, SOL_SOCKET, SO_TIMESTAMPNS, &ns)?;
+        set_socket_option(self.fd, SOL_SOCKET, SO_TIMESTAMPING, &flags)
+    }
+
+    /// Blocking read a single frame with its receive timestamps.
+    ///
+    /// Timestamps are only present once enabled with `set_timestamping`.
+    /// Unlike `read_frame_with_timestamp`, frame and timestamps are read in
+    /// a single syscall.
+    pub fn read_with_timestamp(&self) -> io::Result<(CanFrame, RxTimestamp)> {
+        let mut control = [0u64; CONTROL_LEN];
+        let (frame, _, len) = self.recv_frame_cmsg(0, &mut control)?;
+        let len = cmp::min(len, CONTROL_LEN * size_of::<u64>());
+        Ok((frame, parse_control(&mut control, len)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::mem::size_of;
+    use std::time::{Duration, UNIX_EPOCH};
+    use libc::{cmsghdr, timespec, CMSG_DATA, CMSG_LEN, CMSG_SPACE, SOL_SOCKET};
+    use constants::{SCM_TIMESTAMPING, SCM_TIMESTAMPNS};
+    use super::{parse_control, CONTROL_LEN};
+
+    fn timespec(sec: i64, nsec: i64) -> timespec {
+        timespec {
+            tv_sec: sec as _,
+            tv_nsec: nsec as _,
+        }
+    }
+
+    /// Append a control message carrying `stamps` at byte `offset`.
+    fn push(control: &mut [u64], offset: usize, kind: i32, stamps: &[timespec]) -> usize {
+        let len = stamps.len() * size_of::<timespec>();
+        unsafe {
+            let cmsg = (control.as_mut_ptr() as *mut u8).offset(offset as isize) as *mut cmsghdr;
+            (*cmsg).cmsg_len = CMSG_LEN(len as u32) as _;
+            (*cmsg).cmsg_level = SOL_SOCKET;
+            (*cmsg).cmsg_type = kind;
+            let data = CMSG_DATA(cmsg) as *mut timespec;
+            for (i, ts) in stamps.iter().enumerate() {
+                *data.offset(i as isize) = *ts;
+            }
+            offset + CMSG_SPACE(len as u32) as usize
+        }
+    }
+
+    #[test]
+    fn test_parse_control() {
+        let mut control = [0u64; CONTROL_LEN];
+        assert_eq!(parse_control(&mut control, 0).software, None);
+
+        let len = push(&mut control, 0, SCM_TIMESTAMPNS, &[timespec(10, 500)]);
+        let ts = parse_control(&mut control, len);
+        assert_eq!(ts.software, Some(UNIX_EPOCH + Duration::new(10, 500)));
+        assert_eq!(ts.hardware, None);
+
+        let mut control = [0u64; CONTROL_LEN];
+        let stamps = [timespec(0, 0), timespec(0, 0), timespec(3, 7)];
+        let len = push(&mut control, 0, SCM_TIMESTAMPING, &stamps);
+        let ts = parse_control(&mut control, len);
+        assert_eq!(ts.software, None);
+        assert_eq!(ts.hardware, Some(UNIX_EPOCH + Duration::new(3, 7)));
+
+        let mut control = [0u64; CONTROL_LEN];
+        let stamps = [timespec(20, 1), timespec(0, 0), timespec(4, 2)];
+        let len = push(&mut control, 0, SCM_TIMESTAMPING, &stamps);
+        let len = push(&mut control, len, SCM_TIMESTAMPNS, &[timespec(20, 0)]);
+        let ts = parse_control(&mut control, len);
+        assert_eq!(ts.software, Some(UNIX_EPOCH + Duration::new(20, 0)));
+        assert_eq!(ts.hardware, Some(UNIX_EPOCH + Duration::new(4, 2)));
+    }
+}
@@ -0,0 +1,239 @@
+//! Transport abstraction
+//!
+//! Higher layers such as the CANopen services and the analysis tools only
+//! need to read and write frames. They are generic over `CanTransport`, so
+//! they run on a `CanSocket` as well as on a `testing::MockSocket` or any
+//! other backend. With the `tokio` feature, `AsyncCanTransport` in the
+//! `async_transport` module is the asynchronous flavor.
+//!
+//! `run_read_loop` is the receive loop most consumers need, stopping
+//! cleanly once a shutdown flag is set:
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//! use std::sync::atomic::{AtomicBool, Ordering};
+//! use std::thread;
+//! use socketcan::{run_read_loop, CanSocket};
+//!
+//! let shutdown = Arc::new(AtomicBool::new(false));
+//! let reader = {
+//!     let shutdown = shutdown.clone();
+//!     thread::spawn(move || {
+//!         let socket = CanSocket::open("vcan0").unwrap();
+//!         run_read_loop(&socket, |frame| Ok(println!("{}", frame.id())), &shutdown)
+//!     })
+//! };
+//!
+//! shutdown.store(true, Ordering::SeqCst);
+//! reader.join().unwrap().unwrap();
+//! ```
+//!
+//! On a tokio runtime, `async_transport::run_read_loop` does the same,
+//! stopping on a `CancellationToken`.
+
+use std::{cmp, io};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use {CanFrame, CanSocket, ShouldRetry};
+use testing::MockSocket;
+
+/// A blocking frame transport
+pub trait CanTransport {
+    /// Blocking read of a single frame.
+    fn read_frame(&self) -> io::Result<CanFrame>;
+
+    /// Write a single frame.
+    fn write_frame(&self, frame: &CanFrame) -> io::Result<()>;
+
+    /// Set the timeout of `read_frame`, after which it fails with an error
+    /// for which `should_retry` returns `true`.
+    ///
+    /// A zero duration disables the timeout.
+    fn set_read_timeout(&self, duration: Duration) -> io::Result<()>;
+
+    /// Read a single frame, waiting at most `timeout`.
+    ///
+    /// Returns `Ok(None)` if no frame arrived in time. Changes the read
+    /// timeout. The default implementation waits at least a microsecond,
+    /// as a zero timeout would block.
+    fn read_opt(&self, timeout: Duration) -> io::Result<Option<CanFrame>> {
+        self.set_read_timeout(cmp::max(timeout, Duration::new(0, 1000)))?;
+        match self.read_frame() {
+            Ok(frame) => Ok(Some(frame)),
+            Err(ref e) if e.should_retry() => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Read available frames into `frames`, blocking for the first one.
+    ///
+    /// Returns the number of frames read. The default implementation reads
+    /// a single frame.
+    fn read_into(&self, frames: &mut [CanFrame]) -> io::Result<usize> {
+        match frames.first_mut() {
+            Some(first) => {
+                *first = self.read_frame()?;
+                Ok(1)
+            }
+            None => Ok(0),
+        }
+    }
+
+    /// Write a single frame, retrying until it gets sent successfully.
+    fn write_frame_insist(&self, frame: &CanFrame) -> io::Result<()> {
+        loop {
+            match self.write_frame(frame) {
+                Ok(v) => return Ok(v),
+                Err(e) => {
+                    if !e.should_retry() {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl CanTransport for CanSocket {
+    fn read_frame(&self) -> io::Result<CanFrame> {
+        CanSocket::read_frame(self)
+    }
+
+    fn write_frame(&self, frame: &CanFrame) -> io::Result<()> {
+        CanSocket::write_frame(self, frame)
+    }
+
+    fn set_read_timeout(&self, duration: Duration) -> io::Result<()> {
+        CanSocket::set_read_timeout(self, duration)
+    }
+
+    fn read_opt(&self, timeout: Duration) -> io::Result<Option<CanFrame>> {
+        CanSocket::read_opt(self, timeout)
+    }
+
+    fn read_into(&self, frames: &mut [CanFrame]) -> io::Result<usize> {
+        CanSocket::read_into(self, frames)
+    }
+}
+
+impl CanTransport for MockSocket {
+    fn read_frame(&self) -> io::Result<CanFrame> {
+        MockSocket::read_frame(self)
+    }
+
+    fn write_frame(&self, frame: &CanFrame) -> io::Result<()> {
+        MockSocket::write_frame(self, frame)
+    }
+
+    fn set_read_timeout(&self, duration: Duration) -> io::Result<()> {
+        MockSocket::set_read_timeout(self, duration)
+    }
+
+    fn read_into(&self, frames: &mut [CanFrame]) -> io::Result<usize> {
+        MockSocket::read_into(self, frames)
+    }
+}
+
+impl<'a, T: CanTransport + ?Sized> CanTransport for &'a T {
+    fn read_frame(&self) -> io::Result<CanFrame> {
+        (**self).read_frame()
+    }
+
+    fn write_frame(&self, frame: &CanFrame) -> io::Result<()> {
+        (**self).write_frame(frame)
+    }
+
+    fn set_read_timeout(&self, duration: Duration) -> io::Result<()> {
+        (**self).set_read_timeout(duration)
+    }
+
+    fn read_opt(&self, timeout: Duration) -> io::Result<Option<CanFrame>> {
+        (**self).read_opt(timeout)
+    }
+
+    fn read_into(&self, frames: &mut [CanFrame]) -> io::Result<usize> {
+        (**self).read_into(frames)
+    }
+}
+
+/// Interval at which `run_read_loop` checks the shutdown flag
+pub const SHUTDOWN_POLL_INTERVAL_MS: u64 = 100;
+
+/// Pass frames read from `transport` to `handler` until `shutdown` is set.
+///
+/// Read timeouts and interrupted reads are retried, any other read error
+/// ends the loop, as does an error returned by `handler`. The shutdown flag
+/// is checked at least every `SHUTDOWN_POLL_INTERVAL_MS`, for which the
+/// transport's read timeout is changed. Returns the number of frames
+/// handled.
+pub fn run_read_loop<T, F>(transport: &T, mut handler: F, shutdown: &AtomicBool) -> io::Result<u64>
+    where T: CanTransport + ?Sized,
+          F: FnMut(CanFrame) -> io::Result<()>
+{
+    transport.set_read_timeout(Duration::from_millis(SHUTDOWN_POLL_INTERVAL_MS))?;
+
+    let mut count = 0;
+    while !shutdown.load(Ordering::SeqCst) {
+        let frame = match transport.read_frame() {
+            Ok(frame) => frame,
+            Err(ref e) if e.should_retry() || e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+
+        handler(frame)?;
+        count += 1;
+    }
+    Ok(count)
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::time::Duration;
+    use {CanFrame, CanTransport};
+    use testing::MockBus;
+    use super::run_read_loop;
+
+    #[test]
+    fn test_read_opt() {
+        let bus = MockBus::new();
+        let (socket, peer) = (bus.endpoint(), bus.endpoint());
+        let transport: &CanTransport = &socket;
+
+        assert!(transport.read_opt(Duration::from_millis(0)).unwrap().is_none());
+        peer.write_frame(&CanFrame::new(0x123, &[], false, false).unwrap()).unwrap();
+        assert_eq!(transport.read_opt(Duration::from_millis(10)).unwrap().unwrap().id(), 0x123);
+
+        socket.inject_read_error(io::Error::from_raw_os_error(::libc::ENETDOWN));
+        assert!(transport.read_opt(Duration::from_millis(10)).is_err());
+    }
+
+    #[test]
+    fn test_read_loop() {
+        let bus = MockBus::new();
+        let (socket, peer) = (bus.endpoint(), bus.endpoint());
+        for id in 1..5 {
+            peer.write_frame(&CanFrame::new(id, &[], false, false).unwrap()).unwrap();
+        }
+
+        // the mock times out at once when empty, the loop keeps polling
+        let shutdown = AtomicBool::new(false);
+        let mut ids = Vec::new();
+        let count = run_read_loop(&socket,
+                                  |frame| {
+                                      ids.push(frame.id());
+                                      shutdown.store(ids.len() == 3, Ordering::SeqCst);
+                                      Ok(())
+                                  },
+                                  &shutdown)
+            .unwrap();
+        assert_eq!((count, ids), (3, vec![1, 2, 3]));
+
+        let err = run_read_loop(&socket,
+                                |_| Err(io::Error::new(io::ErrorKind::Other, "stop")),
+                                &AtomicBool::new(false))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "stop");
+    }
+}
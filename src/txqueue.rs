@@ -0,0 +1,240 @@
+//! Software transmit queue
+//!
+//! A `TxQueue` sends frames on a background thread, in the order they were
+//! queued, and retries writes while the bus is congested. Frames can be
+//! given a time to live: a control command that could not be sent within
+//! its useful lifetime is discarded instead of being sent late.
+//!
+//! ```no_run
+//! use std::time::Duration;
+//! use socketcan::{CanFrame, CanSocket};
+//! use socketcan::txqueue::TxQueue;
+//!
+//! let queue = TxQueue::new(CanSocket::open("can0").unwrap());
+//!
+//! // a setpoint is worthless after 20 ms, a newer one will follow
+//! let setpoint = CanFrame::new(0x210, &[0x01, 0xf4], false, false).unwrap();
+//! queue.send_with_ttl(setpoint, Duration::from_millis(20));
+//!
+//! // diagnostics must get through eventually
+//! queue.send(CanFrame::new(0x7df, &[0x02, 0x01, 0x00], false, false).unwrap());
+//! ```
+
+use std::{io, thread};
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use libc::ENOBUFS;
+use {CanFrame, CanTransport, ShouldRetry};
+use clock::{Clock, SystemClock};
+
+/// Time to wait before retrying a write that failed because the bus or
+/// the device queue is congested
+pub const CONGESTION_BACKOFF_MS: u64 = 1;
+
+#[derive(Debug)]
+struct Entry {
+    frame: CanFrame,
+    deadline: Option<Instant>,
+}
+
+#[derive(Debug, Default)]
+struct State {
+    frames: VecDeque<Entry>,
+    expired: u64,
+    closed: bool,
+}
+
+#[derive(Debug, Default)]
+struct Shared {
+    state: Mutex<State>,
+    available: Condvar,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<State> {
+        self.state.lock().expect("tx queue lock poisoned")
+    }
+}
+
+/// Check if a write failed because the bus or device queue is full.
+fn is_congested(err: &io::Error) -> bool {
+    err.should_retry() || err.raw_os_error() == Some(ENOBUFS)
+}
+
+/// Frames waiting to be sent, with optional time to live
+///
+/// Dropping the queue stops the sender thread, discarding frames that
+/// were not sent yet.
+#[derive(Debug)]
+pub struct TxQueue<C = SystemClock> {
+    shared: Arc<Shared>,
+    clock: C,
+    thread: Option<thread::JoinHandle<io::Result<()>>>,
+}
+
+impl TxQueue {
+    /// Send the queued frames on `transport`.
+    pub fn new<T: CanTransport + Send + 'static>(transport: T) -> TxQueue {
+        TxQueue::with_clock(transport, SystemClock)
+    }
+}
+
+impl<C: Clock + Clone + Send + 'static> TxQueue<C> {
+    /// Send the queued frames on `transport`, timing time to live and
+    /// retries with `clock`.
+    pub fn with_clock<T: CanTransport + Send + 'static>(transport: T, clock: C) -> TxQueue<C> {
+        let shared = Arc::new(Shared::default());
+
+        let thread = {
+            let (shared, clock) = (shared.clone(), clock.clone());
+            thread::spawn(move || {
+                while let Some(entry) = next_entry(&shared) {
+                    send_entry(&transport, &shared, &clock, &entry)?;
+                }
+                Ok(())
+            })
+        };
+
+        TxQueue {
+            shared: shared,
+            clock: clock,
+            thread: Some(thread),
+        }
+    }
+
+    /// Queue `frame` to be sent, however long that takes.
+    pub fn send(&self, frame: CanFrame) {
+        self.push(Entry {
+                      frame: frame,
+                      deadline: None,
+                  });
+    }
+
+    /// Queue `frame`, discarding it if it can not be sent within `ttl`.
+    pub fn send_with_ttl(&self, frame: CanFrame, ttl: Duration) {
+        self.push(Entry {
+                      frame: frame,
+                      deadline: Some(self.clock.now() + ttl),
+                  });
+    }
+
+    fn push(&self, entry: Entry) {
+        self.shared.lock().frames.push_back(entry);
+        self.shared.available.notify_one();
+    }
+
+    /// Number of frames waiting to be sent
+    pub fn len(&self) -> usize {
+        self.shared.lock().frames.len()
+    }
+
+    /// Check if all queued frames were sent or discarded
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Number of frames discarded because their time to live ran out
+    pub fn expired(&self) -> u64 {
+        self.shared.lock().expired
+    }
+
+    /// Stop sending, discarding frames that were not sent yet.
+    ///
+    /// Returns the write error that stopped the sender early, if any.
+    pub fn stop(mut self) -> io::Result<()> {
+        self.close()
+    }
+
+    fn close(&mut self) -> io::Result<()> {
+        self.shared.lock().closed = true;
+        self.shared.available.notify_one();
+        match self.thread.take() {
+            Some(thread) => thread.join().expect("tx queue sender panicked"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<C> Drop for TxQueue<C> {
+    fn drop(&mut self) {
+        self.shared.lock().closed = true;
+        self.shared.available.notify_one();
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+/// Wait for the next frame to send, `None` once the queue is closed.
+fn next_entry(shared: &Shared) -> Option<Entry> {
+    let mut state = shared.lock();
+    loop {
+        if state.closed {
+            return None;
+        }
+        if let Some(entry) = state.frames.pop_front() {
+            return Some(entry);
+        }
+        state = shared.available.wait(state).expect("tx queue lock poisoned");
+    }
+}
+
+/// Write `entry`, retrying while congested until it is sent or expired.
+fn send_entry<T, C>(transport: &T, shared: &Shared, clock: &C, entry: &Entry) -> io::Result<()>
+    where T: CanTransport,
+          C: Clock
+{
+    loop {
+        if let Some(deadline) = entry.deadline {
+            if clock.now() > deadline {
+                shared.lock().expired += 1;
+                return Ok(());
+            }
+        }
+
+        match transport.write_frame(&entry.frame) {
+            Err(ref e) if is_congested(e) => {}
+            result => return result,
+        }
+        if shared.lock().closed {
+            return Ok(());
+        }
+        clock.sleep(Duration::from_millis(CONGESTION_BACKOFF_MS));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::io;
+    use std::time::Duration;
+    use libc::ENOBUFS;
+    use CanFrame;
+    use testing::MockBus;
+    use super::TxQueue;
+
+    #[test]
+    fn test_ttl() {
+        let bus = MockBus::new();
+        let peer = bus.endpoint();
+        let socket = bus.endpoint();
+        for _ in 0..5 {
+            socket.inject_write_error(io::Error::from_raw_os_error(ENOBUFS));
+        }
+
+        // the mock clock advances by the backoff with every failed write
+        let queue = TxQueue::with_clock(socket, bus.clone());
+        queue.send_with_ttl(CanFrame::new(0x210, &[1], false, false).unwrap(),
+                            Duration::from_millis(2));
+        queue.send(CanFrame::new(0x7df, &[2], false, false).unwrap());
+
+        assert_eq!(peer.read_frame().unwrap().id(), 0x7df);
+        assert_eq!(queue.expired(), 1);
+        assert!(queue.is_empty());
+
+        queue.send_with_ttl(CanFrame::new(0x210, &[3], false, false).unwrap(),
+                            Duration::from_millis(2));
+        assert_eq!(peer.read_frame().unwrap().data(), &[3]);
+        queue.stop().unwrap();
+    }
+}
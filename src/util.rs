@@ -1,7 +1,8 @@
-use libc::{c_int, c_void, setsockopt, socklen_t, timespec};
-use std::{io, ptr};
+use libc::{c_int, c_void, getsockopt, setsockopt, socklen_t, timespec};
+use std::{cmp, io, mem, ptr, thread};
 use std::mem::size_of;
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use {CanFrame, CanTransport, ShouldRetry};
 
 
 /// `setsockopt` wrapper
@@ -41,6 +42,19 @@ pub fn set_socket_option<T>(fd: c_int, level: c_int, name: c_int, val: &T) -> io
     Ok(())
 }
 
+/// `getsockopt` wrapper, the counterpart of `set_socket_option`
+pub fn get_socket_option<T: Copy>(fd: c_int, level: c_int, name: c_int) -> io::Result<T> {
+    let mut val: T = unsafe { mem::zeroed() };
+    let mut len = size_of::<T>() as socklen_t;
+    let rv = unsafe { getsockopt(fd, level, name, &mut val as *mut T as *mut c_void, &mut len) };
+
+    if rv != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(val)
+}
+
 pub fn set_socket_option_mult<T>(fd: c_int,
                                  level: c_int,
                                  name: c_int,
@@ -79,3 +93,93 @@ pub fn duration_from_timeval(ts: timespec) -> Duration {
 pub fn system_time_from_timespec(ts: timespec) -> SystemTime {
     UNIX_EPOCH + duration_from_timeval(ts)
 }
+
+/// Whole microseconds of `d`, as used by log timestamps
+#[inline]
+pub fn duration_to_us(d: Duration) -> u64 {
+    d.as_secs() * 1_000_000 + (d.subsec_nanos() / 1000) as u64
+}
+
+#[inline]
+pub fn duration_from_us(us: u64) -> Duration {
+    Duration::new(us / 1_000_000, (us % 1_000_000) as u32 * 1000)
+}
+
+/// Read frames until one matches `pred` or `timeout` expires.
+///
+/// Non-matching frames are discarded, `Ok(None)` is returned on timeout.
+/// `pred` sees every frame received meanwhile, so it can also collect
+/// several frames, returning `true` once done. Reads failing early, e.g.
+/// when interrupted, are retried until the timeout expires. The socket's
+/// read timeout is changed by this call.
+pub fn read_until<T, F>(socket: &T, timeout: Duration, mut pred: F) -> io::Result<Option<CanFrame>>
+    where T: CanTransport + ?Sized,
+          F: FnMut(&CanFrame) -> bool
+{
+    let deadline = Instant::now() + timeout;
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+
+        // a zero timeout would block forever
+        socket.set_read_timeout(cmp::max(deadline - now, Duration::from_millis(1)))?;
+        match socket.read_frame() {
+            Ok(frame) => {
+                if pred(&frame) {
+                    return Ok(Some(frame));
+                }
+            }
+            // transports may fail early, e.g. when interrupted
+            Err(ref e) if e.should_retry() => thread::sleep(Duration::from_millis(1)),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Match `name` against a shell-style pattern supporting `*` and `?`.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    // position after the last `*` and the name position it was tried at
+    let mut backtrack = None;
+    let (mut p, mut n) = (0, 0);
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            p += 1;
+            backtrack = Some((p, n));
+        } else if let Some((bp, bn)) = backtrack {
+            // let the last `*` swallow one more character
+            p = bp;
+            n = bn + 1;
+            backtrack = Some((bp, bn + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod test {
+    use super::glob_match;
+
+    #[test]
+    fn test_glob_match() {
+        assert!(glob_match("can*", "can0"));
+        assert!(glob_match("can*", "can"));
+        assert!(glob_match("*can?", "slcan1"));
+        assert!(glob_match("*", ""));
+        assert!(glob_match("v*n*0", "vcan0"));
+        assert!(!glob_match("can*", "vcan0"));
+        assert!(!glob_match("can?", "can10"));
+        assert!(!glob_match("can", "can0"));
+    }
+}
@@ -0,0 +1,492 @@
+//! CAN XL frames
+//!
+//! Groundwork for CAN XL (CiA 610), available with the `xl` feature. CAN XL
+//! frames carry up to 2048 bytes and replace the CAN ID with an 11 bit
+//! priority, an SDU type describing the payload and a 32 bit acceptance
+//! field. A `CanSocket` exchanges them once enabled with `set_xl_frames`,
+//! which requires an interface with the CAN XL MTU. Virtual CAN network IDs
+//! separating logical networks on one bus are configured with
+//! `set_xl_vcid_options`.
+//!
+//! ```no_run
+//! use socketcan::CanSocket;
+//! use socketcan::xl::{AnyFrame, CanXlFrame, SDT_CLASSIC_FRAME};
+//!
+//! let socket = CanSocket::open("xlcan0").unwrap();
+//! socket.set_xl_frames(true).unwrap();
+//!
+//! let frame = CanXlFrame::new(0x123, SDT_CLASSIC_FRAME, 0x1234, &[0xaa; 100]).unwrap();
+//! socket.write_xl_frame(&frame).unwrap();
+//!
+//! match socket.read_any_frame().unwrap() {
+//!     AnyFrame::Xl(frame) => println!("XL {:03X}: {} bytes", frame.priority(), frame.len()),
+//!     AnyFrame::Fd(frame) => println!("{:X}: {} bytes", frame.id(), frame.len()),
+//! }
+//! ```
+
+use std::{cmp, fmt, io, ptr, slice};
+use libc::{c_int, c_void, read, write};
+use {CanSocket, ConstructionError, FrameReadError};
+use constants::{CAN_RAW_XL_FRAMES, CAN_RAW_XL_VCID_OPTS, SOL_CAN_RAW};
+use fd::CanFdFrame;
+use util::{get_socket_option, set_socket_option};
+
+/// smallest payload of a CAN XL frame
+pub const CANXL_MIN_DLEN: usize = 1;
+
+/// largest payload of a CAN XL frame
+pub const CANXL_MAX_DLEN: usize = 2048;
+
+/// size of the header of a `struct canxl_frame`, preceding the data
+pub const CANXL_HDR_SIZE: usize = 12;
+
+/// size of a `struct canxl_frame`
+pub const CANXL_MTU: usize = CANXL_HDR_SIZE + CANXL_MAX_DLEN;
+
+/// mask of the priority in the `prio` field
+pub const CANXL_PRIO_MASK: u32 = 0x7ff;
+
+/// offset of the virtual CAN network ID in the `prio` field
+pub const CANXL_VCID_OFFSET: u32 = 16;
+
+/// mask of the virtual CAN network ID in the `prio` field
+pub const CANXL_VCID_MASK: u32 = 0xff << CANXL_VCID_OFFSET;
+
+/// simple extended content, the payload is protected by CADsec
+pub const CANXL_SEC: u8 = 0x01;
+
+/// remote request substitution, reserved
+pub const CANXL_RRS: u8 = 0x02;
+
+/// marks a CAN XL frame, set for all frames of this type
+pub const CANXL_XLF: u8 = 0x80;
+
+/// set the VCID of all sent frames to `tx_vcid`
+pub const CAN_RAW_XL_VCID_TX_SET: u8 = 0x01;
+
+/// send the VCID of the frame as is
+pub const CAN_RAW_XL_VCID_TX_PASS: u8 = 0x02;
+
+/// only receive frames matching `rx_vcid` under `rx_vcid_mask`
+pub const CAN_RAW_XL_VCID_RX_FILTER: u8 = 0x04;
+
+/// SDU type of content-based addressed data
+pub const SDT_CONTENT_BASED: u8 = 0x01;
+
+/// SDU type of a tunneled classic CAN frame
+pub const SDT_CLASSIC_FRAME: u8 = 0x03;
+
+/// SDU type of a tunneled CAN FD frame
+pub const SDT_FD_FRAME: u8 = 0x04;
+
+/// SDU type of a tunneled Ethernet frame
+pub const SDT_ETHERNET: u8 = 0x05;
+
+/// A CAN XL frame
+///
+/// Uses the memory layout of the kernel's `struct canxl_frame`.
+#[derive(Clone)]
+#[repr(C)]
+pub struct CanXlFrame {
+    /// 11 bit priority and the virtual CAN network ID
+    _prio: u32,
+
+    /// CANXL_* flags
+    _flags: u8,
+
+    /// SDU type
+    _sdt: u8,
+
+    /// payload length in bytes
+    _len: u16,
+
+    /// acceptance field
+    _af: u32,
+
+    _data: [u8; CANXL_MAX_DLEN],
+}
+
+impl CanXlFrame {
+    /// Create a frame with the given priority, SDU type and acceptance
+    /// field.
+    ///
+    /// Fails if the priority exceeds 11 bits or the payload is empty or
+    /// longer than `CANXL_MAX_DLEN`.
+    pub fn new(priority: u16,
+               sdt: u8,
+               af: u32,
+               data: &[u8])
+               -> Result<CanXlFrame, ConstructionError> {
+        if priority as u32 > CANXL_PRIO_MASK {
+            return Err(ConstructionError::IDTooLarge);
+        }
+        if data.len() < CANXL_MIN_DLEN {
+            return Err(ConstructionError::NotEnoughData);
+        }
+        if data.len() > CANXL_MAX_DLEN {
+            return Err(ConstructionError::TooMuchData);
+        }
+
+        let mut frame = CanXlFrame::empty();
+        frame._prio = priority as u32;
+        frame._flags = CANXL_XLF;
+        frame._sdt = sdt;
+        frame._len = data.len() as u16;
+        frame._af = af;
+        frame._data[..data.len()].copy_from_slice(data);
+        Ok(frame)
+    }
+
+    fn empty() -> CanXlFrame {
+        CanXlFrame {
+            _prio: 0,
+            _flags: 0,
+            _sdt: 0,
+            _len: 0,
+            _af: 0,
+            _data: [0; CANXL_MAX_DLEN],
+        }
+    }
+
+    /// Priority used in arbitration, lower values win
+    #[inline]
+    pub fn priority(&self) -> u16 {
+        (self._prio & CANXL_PRIO_MASK) as u16
+    }
+
+    /// Virtual CAN network ID, 0 if none is set
+    #[inline]
+    pub fn vcid(&self) -> u8 {
+        ((self._prio & CANXL_VCID_MASK) >> CANXL_VCID_OFFSET) as u8
+    }
+
+    /// Set the virtual CAN network ID.
+    ///
+    /// Only sent if the socket is configured to pass it, see the kernel's
+    /// `CAN_RAW_XL_VCID_OPTS`.
+    pub fn set_vcid(&mut self, vcid: u8) {
+        self._prio = self._prio & !CANXL_VCID_MASK | (vcid as u32) << CANXL_VCID_OFFSET;
+    }
+
+    /// SDU type, describing the payload
+    #[inline]
+    pub fn sdt(&self) -> u8 {
+        self._sdt
+    }
+
+    /// Acceptance field, e.g. the CAN ID of a tunneled frame
+    #[inline]
+    pub fn af(&self) -> u32 {
+        self._af
+    }
+
+    /// Check if the payload is protected by CADsec
+    #[inline]
+    pub fn is_sec(&self) -> bool {
+        self._flags & CANXL_SEC != 0
+    }
+
+    /// Set or clear the simple extended content flag.
+    pub fn set_sec(&mut self, sec: bool) {
+        if sec {
+            self._flags |= CANXL_SEC;
+        } else {
+            self._flags &= !CANXL_SEC;
+        }
+    }
+
+    /// Raw CANXL_* flags
+    #[inline]
+    pub fn flags(&self) -> u8 {
+        self._flags
+    }
+
+    /// The payload, up to 2048 bytes
+    #[inline]
+    pub fn data(&self) -> &[u8] {
+        &self._data[..cmp::min(self._len as usize, CANXL_MAX_DLEN)]
+    }
+
+    /// Number of data bytes
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.data().len()
+    }
+
+    /// Check if the frame carries no data, never the case for valid frames
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The frame as the kernel's `struct canxl_frame`, in host byte order
+    ///
+    /// Only the header and the used part of the payload are returned, the
+    /// same bytes `write_xl_frame` hands to the kernel.
+    #[inline]
+    pub fn as_bytes(&self) -> &[u8] {
+        let size = CANXL_HDR_SIZE + self.len();
+        unsafe { slice::from_raw_parts(self as *const CanXlFrame as *const u8, size) }
+    }
+
+    /// Read a frame from the kernel's `struct canxl_frame`, as returned by
+    /// `as_bytes`.
+    ///
+    /// Fails unless `bytes` holds the header and exactly the payload
+    /// length it announces.
+    pub fn from_bytes(bytes: &[u8]) -> Result<CanXlFrame, FrameReadError> {
+        if bytes.len() < CANXL_HDR_SIZE + CANXL_MIN_DLEN || bytes.len() > CANXL_MTU {
+            return Err(FrameReadError::ShortRead(bytes.len()));
+        }
+
+        let mut frame = CanXlFrame::empty();
+        unsafe {
+            ptr::copy_nonoverlapping(bytes.as_ptr(),
+                                     &mut frame as *mut CanXlFrame as *mut u8,
+                                     bytes.len());
+        }
+        if CANXL_HDR_SIZE + frame._len as usize != bytes.len() {
+            return Err(FrameReadError::ShortRead(bytes.len()));
+        }
+        Ok(frame)
+    }
+}
+
+impl fmt::Debug for CanXlFrame {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("CanXlFrame")
+            .field("priority", &self.priority())
+            .field("vcid", &self.vcid())
+            .field("flags", &self._flags)
+            .field("sdt", &self._sdt)
+            .field("af", &self._af)
+            .field("data", &self.data())
+            .finish()
+    }
+}
+
+/// A frame read from an XL enabled socket
+#[derive(Clone, Debug)]
+pub enum AnyFrame {
+    /// A classic or FD frame, told apart by `CanFdFrame::is_fd`
+    Fd(CanFdFrame),
+    Xl(CanXlFrame),
+}
+
+/// How the VCID of sent frames is chosen
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VcidTx {
+    /// Clear the VCID, frames go out untagged (kernel default)
+    Clear,
+    /// Send the VCID set on each frame with `CanXlFrame::set_vcid`
+    Pass,
+    /// Tag every frame with the given VCID, overriding the frame's own
+    Set(u8),
+}
+
+/// Which received frames are delivered, based on their VCID
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum VcidRx {
+    /// Only frames without a VCID (kernel default)
+    Untagged,
+    /// Only frames whose VCID matches `vcid` in the bits set in `mask`
+    ///
+    /// A mask of 0 receives frames of all virtual networks.
+    Filter { vcid: u8, mask: u8 },
+}
+
+/// Virtual CAN network ID handling of a socket, see `set_xl_vcid_options`
+///
+/// The default passes only untagged frames in both directions.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct VcidOptions {
+    pub tx: VcidTx,
+    pub rx: VcidRx,
+}
+
+impl VcidOptions {
+    /// Send and receive on a single virtual network only.
+    pub fn network(vcid: u8) -> VcidOptions {
+        VcidOptions {
+            tx: VcidTx::Set(vcid),
+            rx: VcidRx::Filter {
+                vcid: vcid,
+                mask: 0xff,
+            },
+        }
+    }
+
+    fn to_raw(self) -> RawVcidOptions {
+        let mut raw = RawVcidOptions::default();
+        match self.tx {
+            VcidTx::Clear => (),
+            VcidTx::Pass => raw.flags |= CAN_RAW_XL_VCID_TX_PASS,
+            VcidTx::Set(vcid) => {
+                raw.flags |= CAN_RAW_XL_VCID_TX_SET;
+                raw.tx_vcid = vcid;
+            }
+        }
+        if let VcidRx::Filter { vcid, mask } = self.rx {
+            raw.flags |= CAN_RAW_XL_VCID_RX_FILTER;
+            raw.rx_vcid = vcid;
+            raw.rx_vcid_mask = mask;
+        }
+        raw
+    }
+
+    fn from_raw(raw: &RawVcidOptions) -> VcidOptions {
+        let tx = if raw.flags & CAN_RAW_XL_VCID_TX_SET != 0 {
+            VcidTx::Set(raw.tx_vcid)
+        } else if raw.flags & CAN_RAW_XL_VCID_TX_PASS != 0 {
+            VcidTx::Pass
+        } else {
+            VcidTx::Clear
+        };
+        let rx = if raw.flags & CAN_RAW_XL_VCID_RX_FILTER != 0 {
+            VcidRx::Filter {
+                vcid: raw.rx_vcid,
+                mask: raw.rx_vcid_mask,
+            }
+        } else {
+            VcidRx::Untagged
+        };
+        VcidOptions { tx: tx, rx: rx }
+    }
+}
+
+impl Default for VcidOptions {
+    fn default() -> VcidOptions {
+        VcidOptions {
+            tx: VcidTx::Clear,
+            rx: VcidRx::Untagged,
+        }
+    }
+}
+
+/// The kernel's `struct can_raw_vcid_options`
+#[derive(Copy, Clone, Debug, Default)]
+#[repr(C)]
+struct RawVcidOptions {
+    flags: u8,
+    tx_vcid: u8,
+    rx_vcid: u8,
+    rx_vcid_mask: u8,
+}
+
+impl CanSocket {
+    /// Enable or disable CAN XL frames.
+    ///
+    /// Enabling XL frames also enables FD frames. Fails if the kernel does
+    /// not support CAN XL.
+    pub fn set_xl_frames(&self, enabled: bool) -> io::Result<()> {
+        let xl_frames: c_int = if enabled { 1 } else { 0 };
+        set_socket_option(self.fd, SOL_CAN_RAW, CAN_RAW_XL_FRAMES, &xl_frames)
+    }
+
+    /// Configure how virtual CAN network IDs are sent and filtered.
+    ///
+    /// Lets several logical networks share one physical XL bus, e.g. with
+    /// `VcidOptions::network` per socket. Only affects XL frames.
+    pub fn set_xl_vcid_options(&self, options: &VcidOptions) -> io::Result<()> {
+        let raw = options.to_raw();
+        set_socket_option(self.fd, SOL_CAN_RAW, CAN_RAW_XL_VCID_OPTS, &raw)
+    }
+
+    /// The current virtual CAN network ID handling.
+    pub fn xl_vcid_options(&self) -> io::Result<VcidOptions> {
+        let raw: RawVcidOptions = get_socket_option(self.fd, SOL_CAN_RAW, CAN_RAW_XL_VCID_OPTS)?;
+        Ok(VcidOptions::from_raw(&raw))
+    }
+
+    /// Blocking read a single classic, FD or XL frame.
+    ///
+    /// Requires XL frames to be enabled with `set_xl_frames` to receive XL
+    /// frames.
+    pub fn read_any_frame(&self) -> io::Result<AnyFrame> {
+        let mut buf = vec![0u8; CANXL_MTU];
+        let rv = unsafe { read(self.fd, buf.as_mut_ptr() as *mut c_void, buf.len()) };
+
+        if rv < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let buf = &buf[..rv as usize];
+
+        // the flags of an XL frame share their position with the length
+        // of classic and FD frames, which never has the top bit set
+        let frame = match buf.get(4) {
+            Some(&flags) if flags & CANXL_XLF != 0 => CanXlFrame::from_bytes(buf).map(AnyFrame::Xl),
+            _ => CanFdFrame::from_bytes(buf).map(AnyFrame::Fd),
+        };
+        frame.map_err(io::Error::from)
+    }
+
+    /// Write a single XL frame.
+    ///
+    /// Requires XL frames to be enabled with `set_xl_frames`.
+    pub fn write_xl_frame(&self, frame: &CanXlFrame) -> io::Result<()> {
+        let bytes = frame.as_bytes();
+        let rv = unsafe { write(self.fd, bytes.as_ptr() as *const c_void, bytes.len()) };
+
+        if rv < 0 || rv as usize != bytes.len() {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::mem::size_of;
+    use ConstructionError;
+    use super::{CanXlFrame, VcidOptions, VcidRx, VcidTx, CANXL_HDR_SIZE, CANXL_MTU,
+                SDT_CLASSIC_FRAME};
+
+    #[test]
+    fn test_xl_frame() {
+        assert_eq!(size_of::<CanXlFrame>(), CANXL_MTU);
+
+        let mut frame = CanXlFrame::new(0x123, SDT_CLASSIC_FRAME, 0x456, &[1, 2, 3]).unwrap();
+        assert_eq!((frame.priority(), frame.vcid(), frame.af()), (0x123, 0, 0x456));
+        assert_eq!(frame.data(), &[1, 2, 3]);
+
+        frame.set_vcid(0x42);
+        frame.set_sec(true);
+        assert_eq!((frame.priority(), frame.vcid()), (0x123, 0x42));
+        assert!(frame.is_sec());
+
+        let bytes = frame.as_bytes();
+        assert_eq!(bytes.len(), CANXL_HDR_SIZE + 3);
+        assert_eq!(&bytes[..8], &[0x23, 0x01, 0x42, 0x00, 0x81, 0x03, 0x03, 0x00]);
+        let read = CanXlFrame::from_bytes(bytes).unwrap();
+        assert_eq!((read.vcid(), read.data()), (0x42, &[1, 2, 3][..]));
+        assert!(CanXlFrame::from_bytes(&bytes[..CANXL_HDR_SIZE + 2]).is_err());
+
+        match CanXlFrame::new(0x800, 0, 0, &[1]) {
+            Err(ConstructionError::IDTooLarge) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+        match CanXlFrame::new(0x7ff, 0, 0, &[]) {
+            Err(ConstructionError::NotEnoughData) => (),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_vcid_options() {
+        let raw = VcidOptions::network(0x42).to_raw();
+        assert_eq!((raw.flags, raw.tx_vcid, raw.rx_vcid, raw.rx_vcid_mask),
+                   (0x05, 0x42, 0x42, 0xff));
+        assert_eq!(VcidOptions::default().to_raw().flags, 0);
+
+        let options = VcidOptions {
+            tx: VcidTx::Pass,
+            rx: VcidRx::Filter {
+                vcid: 0x10,
+                mask: 0xf0,
+            },
+        };
+        assert_eq!(VcidOptions::from_raw(&options.to_raw()), options);
+        assert_eq!(VcidOptions::from_raw(&VcidOptions::network(7).to_raw()),
+                   VcidOptions::network(7));
+    }
+}